@@ -0,0 +1,45 @@
+//! Benchmarks for full scans of a tree, decoding the values or only the keys.
+
+#![feature(test)]
+
+extern crate test;
+use test::Bencher;
+
+use lsm_tree::{Config, LSMTree, Result};
+use std::path::Path;
+use tempfile::tempdir;
+
+/// A tree of 10k keys with values of about 100 bytes, all in SSTables
+fn setup(dir: &Path) -> Result<LSMTree<u64, String>> {
+    let config = Config {
+        data_dir: dir.to_path_buf(),
+        compaction_threshold: None,
+        ..Config::default()
+    };
+    let mut lsm = LSMTree::with_config(config)?;
+    for i in 0..10_000 {
+        lsm.insert(i, format!("value_{}_{}", i, "x".repeat(90)))?;
+    }
+    lsm.flush()?;
+    Ok(lsm)
+}
+
+#[bench]
+fn bench_iter_10k(b: &mut Bencher) -> Result<()> {
+    let dir = tempdir()?;
+    let lsm = setup(dir.path())?;
+
+    b.iter(|| lsm.iter().unwrap().count());
+
+    Ok(())
+}
+
+#[bench]
+fn bench_keys_10k(b: &mut Bencher) -> Result<()> {
+    let dir = tempdir()?;
+    let lsm = setup(dir.path())?;
+
+    b.iter(|| lsm.keys().unwrap().count());
+
+    Ok(())
+}
@@ -6,26 +6,32 @@
 extern crate test;
 use test::Bencher;
 
-use lsm_tree::{memtable::MemTable, sstable::SSTable, Result};
+use lsm_tree::{memtable::MemTable, sstable::{SSTable, SSTableOptions}, Result};
 use tempfile::tempdir;
 
-#[bench]
-fn bench_sstable_creation_10k(b: &mut Bencher) -> Result<()> {
+fn bench_creation(b: &mut Bencher, count: i32, options: &SSTableOptions) -> Result<()> {
     let dir = tempdir()?;
+    let path = dir.path().join("bench_create.sst");
 
     let mut memtable = MemTable::new();
-    for i in 0..10_000 {
+    for i in 0..count {
         memtable.put(i, format!("value_{}", i))?;
     }
-    
+
+    // Tables are never overwritten, so each iteration removes the one it wrote
     b.iter(|| {
-        let path = dir.path().join("bench_create.sst").to_str().unwrap().to_string();
-        SSTable::from_memtable(&memtable, path).unwrap()
+        SSTable::from_memtable_with_options(&memtable, path.clone(), options).unwrap();
+        std::fs::remove_file(&path).unwrap();
     });
-    
+
     Ok(())
 }
 
+#[bench]
+fn bench_sstable_creation_10k(b: &mut Bencher) -> Result<()> {
+    bench_creation(b, 10_000, &SSTableOptions::default())
+}
+
 #[bench]
 fn bench_sstable_random_reads(b: &mut Bencher) -> Result<()> {
     let dir = tempdir()?;
@@ -69,19 +75,76 @@ fn bench_sstable_sequential_reads(b: &mut Bencher) -> Result<()> {
     Ok(())
 }
 
+fn bench_missing_reads(b: &mut Bencher, options: &SSTableOptions) -> Result<()> {
+    let dir = tempdir()?;
+    let path = dir.path().join("bench_missing_read.sst").to_str().unwrap().to_string();
+
+    // Only even keys are stored, so every odd key is a miss
+    let mut memtable = MemTable::new();
+    for i in 0..10_000 {
+        memtable.put(i * 2, format!("value_{}", i))?;
+    }
+    let sstable = SSTable::from_memtable_with_options(&memtable, path, options)?;
+
+    use rand::Rng;
+    let mut rng = rand::thread_rng();
+
+    b.iter(|| {
+        let key = rng.gen_range(0..10_000) * 2 + 1;
+        sstable.get(&key).unwrap()
+    });
+
+    Ok(())
+}
+
 #[bench]
-fn bench_sstable_creation_100k(b: &mut Bencher) -> Result<()> {
+fn bench_sstable_missing_reads_with_bloom(b: &mut Bencher) -> Result<()> {
+    bench_missing_reads(b, &SSTableOptions::default())
+}
+
+#[bench]
+fn bench_sstable_missing_reads_without_bloom(b: &mut Bencher) -> Result<()> {
+    bench_missing_reads(b, &SSTableOptions { bloom_bits_per_key: 0, ..SSTableOptions::default() })
+}
+
+fn bench_random_reads_with_interval(b: &mut Bencher, index_interval: u64) -> Result<()> {
     let dir = tempdir()?;
+    let path = dir.path().join("bench_interval_read.sst").to_str().unwrap().to_string();
 
     let mut memtable = MemTable::new();
-    for i in 0..100_000 {
+    for i in 0..10_000 {
         memtable.put(i, format!("value_{}", i))?;
     }
-    
+    let options = SSTableOptions { index_interval, ..SSTableOptions::default() };
+    let sstable = SSTable::from_memtable_with_options(&memtable, path, &options)?;
+
+    use rand::Rng;
+    let mut rng = rand::thread_rng();
+
     b.iter(|| {
-        let path = dir.path().join("bench_create_large.sst").to_str().unwrap().to_string();
-        SSTable::from_memtable(&memtable, path).unwrap()
+        let key = rng.gen_range(0..10_000);
+        sstable.get(&key).unwrap()
     });
-    
+
     Ok(())
+}
+
+#[bench]
+fn bench_sstable_random_reads_interval_1(b: &mut Bencher) -> Result<()> {
+    bench_random_reads_with_interval(b, 1)
+}
+
+#[bench]
+fn bench_sstable_random_reads_interval_100(b: &mut Bencher) -> Result<()> {
+    bench_random_reads_with_interval(b, 100)
+}
+
+#[bench]
+fn bench_sstable_creation_100k(b: &mut Bencher) -> Result<()> {
+    bench_creation(b, 100_000, &SSTableOptions::default())
+}
+
+#[bench]
+fn bench_sstable_creation_100k_1mb_buffer(b: &mut Bencher) -> Result<()> {
+    bench_creation(b, 100_000, &SSTableOptions { write_buffer_bytes: 1024 * 1024, ..SSTableOptions::default() })
 } 
\ No newline at end of file
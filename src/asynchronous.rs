@@ -0,0 +1,208 @@
+//! Async handle to an LSM tree, available with the `tokio` feature.
+//!
+//! `AsyncLSMTree` is the async counterpart of `ConcurrentLSMTree`: it can be cloned cheaply and
+//! shared between tasks. A `get` checks the memtable under a read lock, then searches the
+//! SSTables without holding it; the sparse index is searched in memory and the one block that
+//! may hold the key is read through `tokio::fs`, so a lookup that goes to disk yields instead
+//! of blocking the executor thread. The index of a table opened with the tree is only read
+//! from disk the first time a lookup gets past its bloom filter, and that read runs on the
+//! blocking thread pool.
+//!
+//! Writes append to the write-ahead log and may flush the memtable (and compact), which is
+//! blocking file I/O. They run on tokio's blocking thread pool while holding the write lock,
+//! the same way `tokio::fs` operations do.
+
+use crate::merge;
+use crate::sequence::Versions;
+use crate::{Config, LSMTree, Result};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+pub struct AsyncLSMTree<K, V> {
+    tree: Arc<RwLock<LSMTree<K, V>>>,
+}
+
+impl<K, V> Clone for AsyncLSMTree<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: Arc::clone(&self.tree),
+        }
+    }
+}
+
+impl<K, V> AsyncLSMTree<K, V>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone + Send + Sync + 'static,
+    V: serde::Serialize + serde::de::DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    pub async fn new() -> Result<Self> {
+        Self::with_config(Config::default()).await
+    }
+
+    /// Opens the tree on the blocking thread pool, loading its SSTables and replaying the
+    /// write-ahead log
+    pub async fn with_config(config: Config<K>) -> Result<Self> {
+        let tree = run_blocking(move || LSMTree::with_config(config)).await?;
+        Ok(Self {
+            tree: Arc::new(RwLock::new(tree)),
+        })
+    }
+
+    pub async fn insert(&self, key: K, value: V) -> Result<()> {
+        let mut tree = Arc::clone(&self.tree).write_owned().await;
+        run_blocking(move || tree.insert(key, value)).await
+    }
+
+    pub async fn delete(&self, key: K) -> Result<()> {
+        let mut tree = Arc::clone(&self.tree).write_owned().await;
+        run_blocking(move || tree.delete(key)).await
+    }
+
+    pub async fn delete_range(&self, start: K, end: K) -> Result<()> {
+        let mut tree = Arc::clone(&self.tree).write_owned().await;
+        run_blocking(move || tree.delete_range(start, end)).await
+    }
+
+    pub async fn get(&self, key: &K) -> Result<Option<V>> {
+        let tree = self.tree.read().await;
+        tree.counters.gets.add(1);
+        let now = tree.config.clock.now_millis();
+        match tree.memtable_entry(key) {
+            // Operands in the memtable are applied onto the older versions under the lock
+            Some(entry) if entry.is_merge() => {
+                let entry = tree.lookup(key)?;
+                return Ok(entry.and_then(|entry| entry.into_live_value(now)).map(Arc::unwrap_or_clone));
+            }
+            Some(entry) => return Ok(entry.live_value(now).map(|value| V::clone(value))),
+            None => {}
+        }
+        let sstables = tree.sstables.clone();
+        let operator = tree.merge_operator.clone();
+        drop(tree);
+
+        // The versions that matter, see `lookup_entry`
+        let mut versions = Versions::new();
+        for sstable in sstables.iter().rev() {
+            if !versions.wants(sstable.max_sequence()) {
+                continue;
+            }
+            if !sstable.index_is_loaded() && !sstable.bloom_rules_out(key)? {
+                let sstable = Arc::clone(sstable);
+                run_blocking(move || sstable.load_index()).await?;
+            }
+            if !sstable.may_contain_key(key)? {
+                continue;
+            }
+            if let Some(entry) = sstable.get_sequenced_async(key).await? {
+                versions.push(entry);
+            }
+        }
+
+        let entry = merge::collapse(operator.as_deref(), versions.newest_first().map(Ok))?;
+        Ok(entry.and_then(|entry| entry.into_live_value(now)))
+    }
+
+    /// Writes the memtable to a new SSTable, see `LSMTree::flush`
+    pub async fn flush(&self) -> Result<()> {
+        let mut tree = Arc::clone(&self.tree).write_owned().await;
+        run_blocking(move || tree.flush()).await
+    }
+}
+
+/// Runs `f` on the blocking thread pool, resuming any panic in the calling task
+async fn run_blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn config(temp_dir: &TempDir) -> Config<u32> {
+        Config {
+            memtable_size_threshold: 512,
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_basic() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let lsm = AsyncLSMTree::with_config(config(&temp_dir)).await?;
+
+        for i in 0..200 {
+            lsm.insert(i, format!("value{}", i)).await?;
+        }
+        lsm.delete(7).await?;
+        lsm.flush().await?;
+        assert!(!lsm.tree.read().await.sstables.is_empty());
+
+        assert_eq!(lsm.get(&5).await?, Some("value5".to_string()));
+        assert_eq!(lsm.get(&199).await?, Some("value199".to_string()));
+        assert_eq!(lsm.get(&7).await?, None);
+        assert_eq!(lsm.get(&1000).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_get_after_reopen() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let mut lsm = LSMTree::with_config(config(&temp_dir))?;
+        for i in 0..200 {
+            lsm.insert(i, format!("value{}", i))?;
+        }
+        lsm.close()?;
+
+        // On a single-threaded runtime, the indexes of the reopened tables are read off it
+        let lsm = AsyncLSMTree::<u32, String>::with_config(config(&temp_dir)).await?;
+        let loaded = || async { lsm.tree.read().await.stats().sstable_indexes_loaded };
+        assert_eq!(loaded().await, 0);
+        assert_eq!(lsm.get(&5).await?, Some("value5".to_string()));
+        assert_eq!(lsm.get(&150).await?, Some("value150".to_string()));
+        assert_eq!(lsm.get(&1000).await?, None);
+        assert!(loaded().await > 0);
+        let tree = lsm.tree.read().await;
+        assert!(tree.sstables.iter().any(|sstable| sstable.index_is_loaded()));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_async_concurrent_tasks() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let lsm = AsyncLSMTree::with_config(config(&temp_dir)).await?;
+
+        let tasks: Vec<_> = (0..4u32)
+            .map(|task| {
+                let lsm = lsm.clone();
+                tokio::spawn(async move {
+                    for i in 0..50 {
+                        let key = task * 100 + i;
+                        lsm.insert(key, key.to_string()).await?;
+                        assert_eq!(lsm.get(&key).await?, Some(key.to_string()));
+                    }
+                    Ok::<_, crate::LSMError>(())
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap()?;
+        }
+
+        drop(lsm);
+        let lsm = AsyncLSMTree::<u32, String>::with_config(config(&temp_dir)).await?;
+        assert_eq!(lsm.get(&349).await?, Some("349".to_string()));
+
+        Ok(())
+    }
+}
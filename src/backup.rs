@@ -0,0 +1,207 @@
+//! Backups of the tree as tar archives.
+//!
+//! A backup holds the manifest and the live SSTable files as they were right after flushing
+//! the memtable, so it needs no write-ahead log. SSTables are immutable and their files are only
+//! deleted once nothing holds them, so once the set of live tables has been captured the archive
+//! can be written while the tree keeps taking writes, flushes and compactions.
+
+use crate::manifest::{Manifest, MANIFEST_FILE};
+use crate::sstable::SSTable;
+use crate::{manifest_path, sync_parent_dir, tmp_path, Config, LSMError, LSMTree, Result};
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The manifest and the tables it lists, captured at one point in time
+pub(crate) struct Checkpoint<K, V> {
+    manifest: Manifest,
+    /// File name of the manifest, which carries the tree's namespace
+    manifest_name: String,
+    sstables: Vec<Arc<SSTable<K, V>>>,
+}
+
+impl<K, V> Checkpoint<K, V>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Writes the archive to a temporary file next to `out` and renames it into place, so
+    /// `out` never holds a partial backup
+    pub(crate) fn write_archive(&self, out: &Path) -> Result<()> {
+        let tmp_path = tmp_path(out);
+
+        let mut builder = tar::Builder::new(File::create(&tmp_path)?);
+        let manifest = bincode::serialize(&self.manifest)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest.len() as u64);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, &self.manifest_name, &manifest[..])?;
+        for sstable in &self.sstables {
+            let path = sstable.path();
+            let name = path.file_name().expect("SSTable paths end in a file name");
+            builder.append_path_with_name(path, name)?;
+        }
+        builder.into_inner()?.sync_all()?;
+
+        std::fs::rename(&tmp_path, out)?;
+        sync_parent_dir(out)
+    }
+}
+
+impl<K, V> LSMTree<K, V>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Writes a consistent image of the tree to a tar archive at `out`, flushing the memtable
+    /// first. `restore` turns the archive back into a tree.
+    pub fn backup(&mut self, out: &Path) -> Result<()> {
+        self.checkpoint()?.write_archive(out)
+    }
+
+    /// Flushes the memtable and captures the live tables
+    pub(crate) fn checkpoint(&mut self) -> Result<Checkpoint<K, V>> {
+        self.flush()?;
+        Ok(Checkpoint {
+            manifest: self.manifest.clone(),
+            manifest_name: self.config.file_prefix() + MANIFEST_FILE,
+            sstables: self.sstables.clone(),
+        })
+    }
+
+    /// Unpacks an archive written by `backup` into `config.data_dir`, which must be empty or
+    /// not exist yet, and opens the tree. The namespace must be the one the backup was taken with.
+    pub fn restore(archive: &Path, config: Config<K>) -> Result<Self>
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        let data_dir = &config.data_dir;
+        if data_dir.exists() && data_dir.read_dir()?.next().is_some() {
+            return Err(LSMError::InvalidConfig(format!(
+                "cannot restore into {}: the directory is not empty",
+                config.data_dir.display()
+            )));
+        }
+        std::fs::create_dir_all(data_dir)?;
+
+        tar::Archive::new(File::open(archive)?).unpack(data_dir)?;
+        if Manifest::load(&manifest_path(&config))?.is_none() {
+            return Err(LSMError::InvalidBackup {
+                path: archive.display().to_string(),
+                reason: "it has no manifest".to_string(),
+            });
+        }
+
+        Self::with_config(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::concurrent::ConcurrentLSMTree;
+    use tempfile::TempDir;
+
+    fn config(data_dir: &Path) -> Config<String> {
+        Config {
+            memtable_size_threshold: 1024,
+            data_dir: data_dir.to_path_buf(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_backup_and_restore() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let archive = temp_dir.path().join("backup.tar");
+        let mut lsm = LSMTree::with_config(config(&temp_dir.path().join("data")))?;
+
+        for i in 0..100 {
+            lsm.insert(format!("key{:03}", i), format!("value{}", i))?;
+        }
+        lsm.delete("key050".to_string())?;
+        // Still in the memtable, so the backup has to flush it
+        lsm.insert("latest".to_string(), "in memtable".to_string())?;
+        lsm.backup(&archive)?;
+
+        lsm.insert("after".to_string(), "not backed up".to_string())?;
+
+        let restored = LSMTree::<String, String>::restore(&archive, config(&temp_dir.path().join("restored")))?;
+        for i in (0..100).filter(|&i| i != 50) {
+            assert_eq!(restored.get(&format!("key{:03}", i))?, Some(format!("value{}", i)));
+        }
+        assert_eq!(restored.get(&"key050".to_string())?, None);
+        assert_eq!(restored.get(&"latest".to_string())?, Some("in memtable".to_string()));
+        assert_eq!(restored.get(&"after".to_string())?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_backup_survives_compaction() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let archive = temp_dir.path().join("backup.tar");
+        let mut lsm = LSMTree::with_config(Config {
+            compaction_threshold: None,
+            ..config(&temp_dir.path().join("data"))
+        })?;
+
+        for round in 0..3 {
+            lsm.insert("key".to_string(), format!("v{}", round))?;
+            lsm.flush()?;
+        }
+        let checkpoint = lsm.checkpoint()?;
+
+        // Compaction replaces the captured tables while the archive is still to be written
+        lsm.insert("key".to_string(), "newer".to_string())?;
+        lsm.flush()?;
+        lsm.compact()?;
+        checkpoint.write_archive(&archive)?;
+        drop(checkpoint);
+
+        let restored = LSMTree::<String, String>::restore(&archive, config(&temp_dir.path().join("restored")))?;
+        assert_eq!(restored.get(&"key".to_string())?, Some("v2".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_backup() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let archive = temp_dir.path().join("backup.tar");
+        let lsm = ConcurrentLSMTree::with_config(config(&temp_dir.path().join("data")))?;
+
+        lsm.insert("key".to_string(), "value".to_string())?;
+        lsm.backup(&archive)?;
+
+        let restored = ConcurrentLSMTree::<String, String>::restore(&archive, config(&temp_dir.path().join("restored")))?;
+        assert_eq!(restored.get(&"key".to_string())?, Some("value".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_rejects_bad_targets() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let archive = temp_dir.path().join("backup.tar");
+        let data_dir = temp_dir.path().join("data");
+        let mut lsm = LSMTree::<String, String>::with_config(config(&data_dir))?;
+        lsm.backup(&archive)?;
+
+        // The source directory is not empty
+        assert!(matches!(
+            LSMTree::<String, String>::restore(&archive, config(&data_dir)),
+            Err(LSMError::InvalidConfig(_))
+        ));
+
+        let not_a_backup = temp_dir.path().join("other.tar");
+        tar::Builder::new(File::create(&not_a_backup)?).finish()?;
+        assert!(matches!(
+            LSMTree::<String, String>::restore(&not_a_backup, config(&temp_dir.path().join("restored"))),
+            Err(LSMError::InvalidBackup { .. })
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+//! Groups of writes applied atomically with `LSMTree::commit`.
+
+use crate::memtable::Entry;
+
+/// Puts and deletes to apply together, in order: a later write to a key replaces an earlier
+/// one. The write-ahead log stores a batch as a single record, so after a crash either all of
+/// its writes are recovered or none are.
+#[derive(Clone, Debug)]
+pub struct WriteBatch<K, V> {
+    pub(crate) entries: Vec<(K, Entry<V>)>,
+}
+
+impl<K, V> WriteBatch<K, V> {
+    pub fn new() -> Self {
+        Self { entries: Vec::new() }
+    }
+
+    pub fn put(&mut self, key: K, value: V) {
+        self.entries.push((key, Entry::Value(value)));
+    }
+
+    pub fn delete(&mut self, key: K) {
+        self.entries.push((key, Entry::Tombstone));
+    }
+
+    /// Number of writes in the batch
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<K, V> Default for WriteBatch<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for WriteBatch<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self {
+            entries: iter.into_iter().map(|(key, value)| (key, Entry::Value(value))).collect(),
+        }
+    }
+}
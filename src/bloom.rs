@@ -0,0 +1,183 @@
+//! Bloom filter used by SSTables to skip lookups for keys they definitely don't contain.
+//!
+//! Keys are hashed over their serialized bytes, the ones the table writes for them anyway, with
+//! the `BloomHasher` configured when the table was written. Filters are persisted, so the hash
+//! functions must hash identically across builds, which rules out the ones of `std`. Each table
+//! records which one built its filter, and lookups hash with that one rather than the one
+//! configured now: lookups would otherwise miss keys that are there.
+
+use crate::encoding::Encoding;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// Builds a filter over the given key hashes, using `bits_per_key` bits for each key.
+    /// Ten bits per key gives a false-positive rate of roughly 1%.
+    pub fn from_hashes(hashes: &[u64], bits_per_key: usize) -> Self {
+        let num_bits = (hashes.len() * bits_per_key).max(64);
+        // The optimal number of hash functions is bits_per_key * ln(2)
+        let num_hashes = ((bits_per_key as f64 * 0.69) as u32).clamp(1, 30);
+
+        let mut filter = Self {
+            bits: vec![0; num_bits.div_ceil(64)],
+            num_hashes,
+        };
+        for &hash in hashes {
+            filter.insert_hash(hash);
+        }
+        filter
+    }
+
+    fn num_bits(&self) -> u64 {
+        self.bits.len() as u64 * 64
+    }
+
+    /// Bit positions for a key, derived from one 64-bit hash by double hashing
+    fn positions(&self, hash: u64) -> impl Iterator<Item = u64> {
+        let num_bits = self.num_bits();
+        let delta = hash.rotate_left(17) | 1;
+        (0..self.num_hashes as u64).map(move |i| hash.wrapping_add(i.wrapping_mul(delta)) % num_bits)
+    }
+
+    fn insert_hash(&mut self, hash: u64) {
+        for bit in self.positions(hash).collect::<Vec<_>>() {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Returns `false` if the key with this hash is definitely absent
+    pub fn may_contain_hash(&self, hash: u64) -> bool {
+        self.positions(hash)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+}
+
+/// Hash function of bloom filters, set with `Config::bloom_hasher`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BloomHasher {
+    /// 64-bit FNV-1a, see `hash_bytes`
+    #[default]
+    Fnv1a,
+    /// XXH3, faster than FNV-1a on keys longer than a few bytes
+    Xxh3,
+}
+
+impl BloomHasher {
+    pub fn hash(self, bytes: &[u8]) -> u64 {
+        match self {
+            BloomHasher::Fnv1a => hash_bytes(bytes),
+            BloomHasher::Xxh3 => xxhash_rust::xxh3::xxh3_64(bytes),
+        }
+    }
+
+    /// Hashes a key over its bytes in `encoding`
+    pub fn hash_key<K: Serialize>(self, key: &K, encoding: Encoding) -> crate::Result<u64> {
+        Ok(self.hash(&encoding.serialize(key)?))
+    }
+
+    /// Identifier stored in SSTables
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            BloomHasher::Fnv1a => 0,
+            BloomHasher::Xxh3 => 1,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(BloomHasher::Fnv1a),
+            1 => Some(BloomHasher::Xxh3),
+            _ => None,
+        }
+    }
+}
+
+/// 64-bit FNV-1a over `bytes`, finished with a splitmix64 round to spread the bits
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+/// Hashes a key over its bytes in `encoding`
+pub fn hash_key<K: Serialize>(key: &K, encoding: Encoding) -> crate::Result<u64> {
+    Ok(hash_bytes(&encoding.serialize(key)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_no_false_negatives() -> crate::Result<()> {
+        let hashes = (0..1000)
+            .map(|i| hash_key(&i, Encoding::default()))
+            .collect::<crate::Result<Vec<_>>>()?;
+        let filter = BloomFilter::from_hashes(&hashes, 10);
+
+        for i in 0..1000 {
+            assert!(filter.may_contain_hash(hash_key(&i, Encoding::default())?));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bloom_false_positive_rate() -> crate::Result<()> {
+        let hashes = (0..1000)
+            .map(|i| hash_key(&i, Encoding::default()))
+            .collect::<crate::Result<Vec<_>>>()?;
+        let filter = BloomFilter::from_hashes(&hashes, 10);
+
+        let mut false_positives = 0;
+        for i in 1000..11000 {
+            if filter.may_contain_hash(hash_key(&i, Encoding::default())?) {
+                false_positives += 1;
+            }
+        }
+        // ~1% expected with 10 bits per key
+        assert!(false_positives < 300, "too many false positives: {}", false_positives);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bloom_hashers() -> crate::Result<()> {
+        for hasher in [BloomHasher::Fnv1a, BloomHasher::Xxh3] {
+            assert_eq!(BloomHasher::from_id(hasher.id()), Some(hasher));
+            let hashes = (0..1000)
+                .map(|i| hasher.hash_key(&i, Encoding::default()))
+                .collect::<crate::Result<Vec<_>>>()?;
+            let filter = BloomFilter::from_hashes(&hashes, 10);
+            for i in 0..1000 {
+                assert!(filter.may_contain_hash(hasher.hash_key(&i, Encoding::default())?));
+            }
+            let false_positives = (1000..11000)
+                .filter(|i| filter.may_contain_hash(hasher.hash_key(i, Encoding::default()).unwrap()))
+                .count();
+            assert!(false_positives < 300, "too many false positives with {:?}: {}", hasher, false_positives);
+        }
+        assert_eq!(BloomHasher::Fnv1a.hash(b"key"), hash_bytes(b"key"));
+        assert_ne!(BloomHasher::Xxh3.hash(b"key"), hash_bytes(b"key"));
+        assert_eq!(BloomHasher::from_id(2), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bloom_empty() {
+        let filter = BloomFilter::from_hashes(&[], 10);
+        assert!(!filter.may_contain_hash(hash_bytes(b"anything")));
+    }
+}
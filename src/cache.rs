@@ -0,0 +1,167 @@
+//! Caches shared by the SSTables of a tree for point lookups.
+//!
+//! `BlockCache` holds decompressed blocks, keyed by the id of their table and their offset in
+//! the file, and evicted in least-recently-used order once their total size exceeds the
+//! capacity. Only lookups go through the cache: scans read every block once, and would evict
+//! the hot ones.
+//!
+//! `FilePool` bounds the number of file handles lookups keep open, for stores with more tables
+//! than the process may have open files. Handles are evicted in least-recently-used order and
+//! reopened on demand.
+
+use lru::LruCache;
+use std::fs::File;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, PoisonError};
+
+pub(crate) struct BlockCache {
+    capacity: usize,
+    state: Mutex<CacheState>,
+}
+
+struct CacheState {
+    /// Blocks by table id and offset
+    blocks: LruCache<(u64, u64), Arc<Vec<u8>>>,
+    /// Total size of the cached blocks in bytes
+    size: usize,
+}
+
+impl BlockCache {
+    /// Creates a cache holding at most `capacity` bytes of blocks
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(CacheState {
+                blocks: LruCache::unbounded(),
+                size: 0,
+            }),
+        }
+    }
+
+    pub(crate) fn get(&self, table: u64, offset: u64) -> Option<Arc<Vec<u8>>> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.blocks.get(&(table, offset)).cloned()
+    }
+
+    /// Caches a block, evicting the least recently used ones to make room. Blocks larger
+    /// than the whole cache are not cached.
+    pub(crate) fn insert(&self, table: u64, offset: u64, block: Arc<Vec<u8>>) {
+        if block.len() > self.capacity {
+            return;
+        }
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.size += block.len();
+        if let Some(old) = state.blocks.put((table, offset), block) {
+            state.size -= old.len();
+        }
+        while state.size > self.capacity {
+            let Some((_, evicted)) = state.blocks.pop_lru() else {
+                break;
+            };
+            state.size -= evicted.len();
+        }
+    }
+
+    /// Drops every block, for when table ids may be handed out again
+    pub(crate) fn clear(&self) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.blocks.clear();
+        state.size = 0;
+    }
+}
+
+/// Open handles of SSTable files by table id
+pub(crate) struct FilePool {
+    files: Mutex<LruCache<u64, Arc<Mutex<File>>>>,
+}
+
+impl FilePool {
+    /// Creates a pool keeping at most `capacity` files open. A handle evicted while a lookup
+    /// still reads through it is closed once the lookup is done.
+    pub(crate) fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            files: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// The handle of table `table`, opened with `open` if the pool has none, which evicts the
+    /// least recently used handle when the pool is full
+    pub(crate) fn acquire(
+        &self,
+        table: u64,
+        open: impl FnOnce() -> std::io::Result<File>,
+    ) -> std::io::Result<Arc<Mutex<File>>> {
+        let mut files = self.files.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(file) = files.get(&table) {
+            return Ok(Arc::clone(file));
+        }
+        let file = Arc::new(Mutex::new(open()?));
+        files.put(table, Arc::clone(&file));
+        Ok(file)
+    }
+
+    /// Closes the handle of a table that is gone
+    pub(crate) fn remove(&self, table: u64) {
+        self.files.lock().unwrap_or_else(PoisonError::into_inner).pop(&table);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_cache_evicts_least_recently_used() {
+        let cache = BlockCache::new(100);
+        let block = |len| Arc::new(vec![0u8; len]);
+
+        cache.insert(1, 0, block(40));
+        cache.insert(1, 40, block(40));
+        // Touching the first block makes the second one the eviction candidate
+        assert!(cache.get(1, 0).is_some());
+        cache.insert(2, 0, block(40));
+        assert!(cache.get(1, 0).is_some());
+        assert!(cache.get(1, 40).is_none());
+        assert!(cache.get(2, 0).is_some());
+
+        // Replacing a block only counts its new size
+        cache.insert(2, 0, block(20));
+        cache.insert(3, 0, block(40));
+        assert!(cache.get(1, 0).is_some());
+        assert!(cache.get(2, 0).is_some());
+
+        cache.insert(4, 0, block(101));
+        assert!(cache.get(4, 0).is_none());
+        assert!(cache.get(3, 0).is_some());
+    }
+    #[test]
+    fn test_file_pool_evicts_least_recently_used() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("table");
+        std::fs::write(&path, b"data")?;
+        let pool = FilePool::new(NonZeroUsize::new(2).unwrap());
+        let opened = std::cell::Cell::new(0);
+        let acquire = |table| {
+            pool.acquire(table, || {
+                opened.set(opened.get() + 1);
+                File::open(&path)
+            })
+        };
+
+        let first = acquire(1)?;
+        acquire(2)?;
+        // Touching the first handle makes the second one the eviction candidate
+        assert!(Arc::ptr_eq(&first, &acquire(1)?));
+        acquire(3)?;
+        assert_eq!(opened.get(), 3);
+        assert!(Arc::ptr_eq(&first, &acquire(1)?));
+        acquire(2)?;
+        assert_eq!(opened.get(), 4);
+
+        pool.remove(2);
+        acquire(2)?;
+        assert_eq!(opened.get(), 5);
+        assert!(pool.acquire(4, || File::open(dir.path().join("missing"))).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+//! Time source used to expire entries written with a TTL.
+//!
+//! Expiry times are stored as milliseconds since the UNIX epoch. The clock is part of `Config`,
+//! so tests can swap in a `ManualClock` and advance time without sleeping.
+
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub trait Clock: Debug + Send + Sync {
+    /// Current time in milliseconds since the UNIX epoch
+    fn now_millis(&self) -> u64;
+}
+
+/// The system wall clock
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64)
+    }
+}
+
+/// A clock that only moves when told to
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    millis: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(millis: u64) -> Self {
+        Self {
+            millis: AtomicU64::new(millis),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.millis.fetch_add(by.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    pub fn set(&self, millis: u64) {
+        self.millis.store(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        self.millis.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new(1000);
+        assert_eq!(clock.now_millis(), 1000);
+
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(clock.now_millis(), 3000);
+
+        clock.set(10);
+        assert_eq!(clock.now_millis(), 10);
+    }
+}
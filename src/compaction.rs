@@ -0,0 +1,1219 @@
+//! Compaction strategies.
+//!
+//! `LSMTree::sstables` is kept in search order, oldest first: tables of deeper levels come first,
+//! deepest level first, followed by level 0 in flush order. Every merge preserves that order, so
+//! point lookups and scans can always treat later tables as newer.
+//!
+//! Size-tiered compaction keeps every table at level 0 and merges runs of adjacent tables into
+//! one, picked by the configured `CompactionPolicy`. Leveled compaction merges level 0 into
+//! level 1, and a level that outgrows its size budget into the next one. Levels 1 and up hold
+//! tables with non-overlapping key ranges sorted by key, so a lookup reads at most one table
+//! per level. `Config::max_levels`, `l0_compaction_trigger` and `level_size_multiplier` set the
+//! number of levels, how many level 0 tables start a compaction and how much larger each
+//! level's budget is than the last.
+//!
+//! Merges are streaming k-way merges in which only the newest version of each key survives,
+//! with the merge operands above it applied (see the `merge` module), and entries under the
+//! range tombstones of newer inputs are dropped (see the `range_tombstone` module). Outputs get
+//! fresh ids. The inputs are deleted once the manifest no longer lists them and the last reader
+//! (for example a snapshot) has let go of them.
+
+use crate::iter::MergeIterator;
+use crate::manifest::ManifestEntry;
+use crate::memtable::Entry;
+use crate::merge::{self, MergeOperator};
+use crate::range_tombstone::{self, RangeTombstone};
+use crate::sequence::Sequenced;
+use crate::sstable::{SSTable, SSTableOptions};
+use crate::stats::{CompactionStats, Counters};
+use crate::{manifest_path, write_sstables, Config, LSMTree, Result};
+use std::ops::{Bound, Range};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Minimum number of tables worth merging
+const MIN_RUN_LEN: usize = 2;
+
+/// How SSTables are merged as they accumulate
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompactionStrategy {
+    /// Merge runs of adjacent tables of similar size. Cheap on writes, but a lookup may have
+    /// to consult every table.
+    #[default]
+    SizeTiered,
+    /// Keep tables in levels of non-overlapping key ranges. Level 1 may hold `fanout` times
+    /// `memtable_size_threshold` bytes and each further level `fanout` times more than the one
+    /// before, unless `Config::level_size_multiplier` says otherwise. `fanout` must be at least 2.
+    Leveled { fanout: usize },
+}
+
+/// Which level 0 tables a size-tiered compaction merges. Merging fewer, similar tables at a
+/// time keeps large tables from being rewritten by every compaction. Leveled compaction
+/// ignores it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompactionPolicy {
+    /// Merge every run of at least two adjacent tables in which each table's size is between
+    /// `low` and `high` times the average size of the tables before it in the run. `low` must
+    /// be in `(0, 1]` and `high` at least 1. This is the default, with `low` 0.5 and `high` 1.5.
+    SimilarSize { low: f64, high: f64 },
+    /// Merge the `count` oldest tables, or all of them if there are fewer. `count` must be at
+    /// least 2.
+    Oldest { count: usize },
+}
+
+impl Default for CompactionPolicy {
+    fn default() -> Self {
+        CompactionPolicy::SimilarSize { low: 0.5, high: 1.5 }
+    }
+}
+
+impl CompactionPolicy {
+    pub(crate) fn validate(&self) -> std::result::Result<(), String> {
+        match *self {
+            CompactionPolicy::SimilarSize { low, high } if !(low > 0.0 && low <= 1.0 && high >= 1.0) => {
+                Err("compaction_policy bounds must satisfy 0 < low <= 1 <= high".to_string())
+            }
+            CompactionPolicy::Oldest { count } if count < MIN_RUN_LEN => {
+                Err("compaction_policy must merge at least 2 tables".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The runs of tables (given as file sizes, oldest first) to merge
+    pub(crate) fn select(&self, sizes: &[u64]) -> Vec<Range<usize>> {
+        match *self {
+            CompactionPolicy::SimilarSize { low, high } => size_tiered_runs(sizes, low, high),
+            CompactionPolicy::Oldest { count } => {
+                let count = count.min(sizes.len());
+                (count >= MIN_RUN_LEN).then_some(0..count).into_iter().collect()
+            }
+        }
+    }
+}
+
+/// Splits tables (given as file sizes, oldest first) into contiguous runs of similar size
+/// and returns the runs long enough to be compacted. A table joins the current run if its
+/// size is within `low` and `high` times the run's average.
+fn size_tiered_runs(sizes: &[u64], low: f64, high: f64) -> Vec<Range<usize>> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut total = 0u64;
+
+    for (i, &size) in sizes.iter().enumerate() {
+        if i > start {
+            let average = total as f64 / (i - start) as f64;
+            let similar = size as f64 >= average * low && size as f64 <= average * high;
+            if !similar {
+                if i - start >= MIN_RUN_LEN {
+                    runs.push(start..i);
+                }
+                start = i;
+                total = 0;
+            }
+        }
+        total += size;
+    }
+    if sizes.len() - start >= MIN_RUN_LEN {
+        runs.push(start..sizes.len());
+    }
+
+    runs
+}
+
+fn file_size(path: &Path) -> Result<u64> {
+    Ok(std::fs::metadata(path)?.len())
+}
+
+/// A merge of some of the tree's tables into new tables for `level`. It is planned and
+/// installed with access to the tree, but written without it, so `ConcurrentLSMTree` can run
+/// it in the background.
+pub(crate) struct Merge<K, V> {
+    /// The tables replaced by the output, in search order
+    inputs: Vec<Arc<SSTable<K, V>>>,
+    level: u32,
+    drop_tombstones: bool,
+    max_table_size: Option<u64>,
+    counters: Arc<Counters>,
+    merge_operator: Option<Arc<dyn MergeOperator<V>>>,
+}
+
+impl<K, V> Merge<K, V>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Writes the merged inputs to new tables, taking their ids from `allocate_id`
+    pub(crate) fn write(
+        &self,
+        config: &Config<K>,
+        options: &SSTableOptions,
+        allocate_id: impl FnMut() -> u64,
+    ) -> Result<Vec<(u64, SSTable<K, V>)>> {
+        let operator = self.merge_operator.clone();
+        let (inputs, drop_tombstones) = (&self.inputs, self.drop_tombstones);
+        let keep_all = |_: &K, value| Some(value);
+        let entries = merged_entries(config, &self.counters, operator, inputs, drop_tombstones, keep_all)?;
+        let range_tombstones: Vec<RangeTombstone<K>> = match drop_tombstones {
+            true => Vec::new(),
+            false => inputs.iter().flat_map(|sstable| sstable.range_tombstones()).cloned().collect(),
+        };
+        let (level, max_size) = (self.level, self.max_table_size);
+        let outputs = write_sstables(config, options, entries, &range_tombstones, level, max_size, 0, allocate_id)?;
+        record_merge(&self.counters, &self.inputs, &outputs);
+        Ok(outputs)
+    }
+}
+
+/// Merges the entries of `inputs` (in search order), keeping only the newest version of each
+/// key, onto which the operands of newer merges are applied with `operator`, and dropping the
+/// entries under the range tombstones of newer inputs. Values are replaced by what `rewrite`
+/// returns for them, or become tombstones if it returns `None`, and tombstones are dropped if
+/// `drop_tombstones` is set: no older version of a key is left below the output then, so
+/// remaining operands are applied onto no value, as are those under a range tombstone. What
+/// is dropped is counted in `counters`.
+fn merged_entries<'a, K, V>(
+    config: &Config<K>,
+    counters: &Arc<Counters>,
+    operator: Option<Arc<dyn MergeOperator<V>>>,
+    inputs: &[Arc<SSTable<K, V>>],
+    drop_tombstones: bool,
+    rewrite: impl Fn(&K, V) -> Option<V> + 'a,
+) -> Result<impl Iterator<Item = Result<(K, Sequenced<Entry<V>>)>> + 'a>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone + 'a,
+    V: serde::Serialize + serde::de::DeserializeOwned + 'a,
+{
+    let now = config.clock.now_millis();
+    let counters = Arc::clone(counters);
+    let comparator = Arc::clone(&config.comparator);
+    let tombstones: Vec<_> = inputs.iter().rev().map(|sstable| sstable.range_tombstones()).collect();
+    let all_tombstones: Vec<RangeTombstone<K>> = tombstones.concat();
+    let sources = inputs
+        .iter()
+        .rev()
+        .zip(range_tombstone::newer_tombstones(&tombstones))
+        .map(|(sstable, newer)| {
+            Ok(range_tombstone::uncovered(sstable.sequenced_entries()?, newer, Arc::clone(&comparator)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let combine_operator = operator.clone();
+    let merged = MergeIterator::new(sources, Arc::clone(&comparator))?
+        .by_sequence(|entry: &Sequenced<Entry<V>>| entry.sequence)
+        .combining(move |newer, older| {
+            let entry = merge::combine(combine_operator.as_deref(), newer.entry, older.entry)?;
+            Ok(Sequenced::new(entry, newer.sequence))
+        });
+    Ok(merged.filter_map(move |item| {
+        let (key, Sequenced { mut entry, sequence }) = match item {
+            Ok(record) => record,
+            Err(e) => return Some(Err(e)),
+        };
+        counters.compaction_records_merged.add(1);
+        let range_deleted = entry.is_merge() && range_tombstone::is_covered(&all_tombstones, &key, &*comparator);
+        if drop_tombstones || range_deleted {
+            entry = match merge::resolve(operator.as_deref(), entry) {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+        }
+        // An expired or rewritten away value still has to shadow older versions of its key
+        let rewritten = match entry {
+            _ if entry.is_expired(now) => None,
+            Entry::Value(value) => rewrite(&key, value).map(Entry::Value),
+            Entry::Expiring { value, expires_at } => {
+                rewrite(&key, value).map(|value| Entry::Expiring { value, expires_at })
+            }
+            entry => Some(entry),
+        };
+        let dropped = rewritten.is_none();
+        let entry = rewritten.unwrap_or(Entry::Tombstone);
+        if drop_tombstones && matches!(entry, Entry::Tombstone) {
+            match dropped {
+                true => counters.compaction_expired_dropped.add(1),
+                false => counters.compaction_tombstones_dropped.add(1),
+            }
+            return None;
+        }
+        Some(Ok((key, Sequenced::new(entry, sequence))))
+    }))
+}
+
+/// Counts the tables read and written by a merge
+fn record_merge<K, V>(counters: &Counters, inputs: &[Arc<SSTable<K, V>>], outputs: &[(u64, SSTable<K, V>)])
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+{
+    for input in inputs {
+        counters.compaction_bytes_read.add(input.file_size());
+        counters.compaction_records_read.add(input.entry_count());
+    }
+    for (_, output) in outputs {
+        counters.compaction_bytes_written.add(output.file_size());
+        counters.compaction_records_written.add(output.entry_count());
+    }
+}
+
+impl<K, V> LSMTree<K, V>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Runs the configured compaction strategy, deleting the replaced files
+    pub fn compact(&mut self) -> Result<()> {
+        self.check_writable()?;
+        self.flushes_since_compaction = 0;
+        let mut first = true;
+        loop {
+            let merges = self.plan_merges(first)?;
+            if merges.is_empty() {
+                break;
+            }
+            for merge in merges {
+                self.run_merge(merge)?;
+            }
+            first = false;
+        }
+        self.counters.compactions.add(1);
+
+        Ok(())
+    }
+
+    /// Runs one step of compaction: the merges of level 0 if a compaction is due by the
+    /// configured trigger, and otherwise, for leveled compaction, one table pushed down from a
+    /// level over its size budget. Returns whether there was anything to do, so calling it
+    /// until it returns `false` leaves the tree as `compact` would once a compaction is due.
+    pub fn maybe_compact(&mut self) -> Result<bool> {
+        self.check_writable()?;
+        let due = self.compaction_due();
+        if due {
+            self.flushes_since_compaction = 0;
+        }
+        let merges = self.plan_merges(due)?;
+        if merges.is_empty() {
+            return Ok(false);
+        }
+        for merge in merges {
+            self.run_merge(merge)?;
+        }
+        self.counters.compactions.add(1);
+
+        Ok(true)
+    }
+
+    /// Bytes and records read and written and dropped by compactions since the tree was opened
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.counters.compaction_stats()
+    }
+
+    /// Whether a compaction is due and should run automatically
+    pub(crate) fn needs_compaction(&self) -> bool {
+        self.config.automatic_compaction && self.compaction_due()
+    }
+
+    /// Whether enough flushes accumulated for a compaction, or for leveled compaction enough
+    /// level 0 tables
+    fn compaction_due(&self) -> bool {
+        let Some(threshold) = self.config.compaction_threshold else {
+            return false;
+        };
+        match self.config.compaction_strategy {
+            CompactionStrategy::SizeTiered => self.flushes_since_compaction >= threshold,
+            CompactionStrategy::Leveled { .. } => self.level_range(0).len() >= self.config.l0_compaction_trigger,
+        }
+    }
+
+    /// Plans the next merges of a compaction: on the `first` call the runs of level 0 tables
+    /// picked by the compaction policy for size-tiered compaction, or all of level 0 merged
+    /// into level 1 for leveled compaction. After that, for leveled compaction only, one table
+    /// pushed down from the shallowest level above the last exceeding its size budget. The
+    /// merges planned by one call are independent of each other; no merges means the
+    /// compaction is done.
+    pub(crate) fn plan_merges(&self, first: bool) -> Result<Vec<Merge<K, V>>> {
+        match self.config.compaction_strategy {
+            CompactionStrategy::SizeTiered if first => {
+                let level0 = self.level_range(0);
+                let sizes = self.sstables[level0.clone()]
+                    .iter()
+                    .map(|sstable| file_size(sstable.path()))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(self
+                    .config
+                    .compaction_policy
+                    .select(&sizes)
+                    .into_iter()
+                    .map(|run| self.plan_run(run.start + level0.start..run.end + level0.start))
+                    .collect())
+            }
+            CompactionStrategy::SizeTiered => Ok(Vec::new()),
+            CompactionStrategy::Leveled { fanout } => {
+                let level0 = self.level_range(0);
+                if first && !level0.is_empty() {
+                    return Ok(vec![self.plan_next_level(0, level0.collect())?]);
+                }
+                // The last level has no budget
+                for level in (1..=self.max_level()).take_while(|&level| level + 1 < self.config.max_levels) {
+                    let tables = self.level_range(level);
+                    let size = self.sstables[tables.clone()]
+                        .iter()
+                        .map(|sstable| file_size(sstable.path()))
+                        .sum::<Result<u64>>()?;
+                    if !tables.is_empty() && size > self.level_budget(level, fanout) {
+                        return Ok(vec![self.plan_next_level(level, vec![tables.start])?]);
+                    }
+                }
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /// Merges the oldest level 0 tables into one if the tree holds more than `limit` tables,
+    /// taking just enough of them to get back to `limit`
+    pub(crate) fn merge_oldest(&mut self, limit: usize) -> Result<()> {
+        let excess = self.sstables.len().saturating_sub(limit);
+        let level0 = self.level_range(0);
+        let count = (excess + 1).min(level0.len());
+        if excess == 0 || count < 2 {
+            return Ok(());
+        }
+
+        self.run_merge(self.plan_run(level0.start..level0.start + count))
+    }
+
+    /// Plans merging a run of adjacent level 0 tables into one
+    fn plan_run(&self, run: Range<usize>) -> Merge<K, V> {
+        Merge {
+            // Tombstones only need to be kept while an older table might still hold the key
+            drop_tombstones: run.start == 0,
+            inputs: self.sstables[run].to_vec(),
+            level: 0,
+            max_table_size: None,
+            counters: Arc::clone(&self.counters),
+            merge_operator: self.merge_operator.clone(),
+        }
+    }
+
+    /// Writes and installs a merge
+    fn run_merge(&mut self, merge: Merge<K, V>) -> Result<()> {
+        let options = self.sstable_options();
+        let manifest = &mut self.manifest;
+        let outputs = merge.write(&self.config, &options, || manifest.allocate_id())?;
+        self.install_merge(merge, outputs)?;
+        Ok(())
+    }
+
+    /// Replaces the inputs of `merge` with its outputs. If the inputs are no longer all in the
+    /// tree, because another compaction or a `clear` replaced some of them since the merge was
+    /// planned, the outputs are discarded instead and false is returned.
+    pub(crate) fn install_merge(&mut self, merge: Merge<K, V>, outputs: Vec<(u64, SSTable<K, V>)>) -> Result<bool> {
+        let positions = merge
+            .inputs
+            .iter()
+            .map(|input| self.sstables.iter().position(|sstable| Arc::ptr_eq(sstable, input)))
+            .collect::<Option<Vec<_>>>();
+        let Some(mut positions) = positions else {
+            for (_, sstable) in outputs {
+                sstable.mark_obsolete();
+            }
+            return Ok(false);
+        };
+
+        positions.sort_unstable();
+        let mut removed = Vec::with_capacity(positions.len());
+        for &i in positions.iter().rev() {
+            removed.push(self.sstables.remove(i));
+            self.manifest.sstables.remove(i);
+        }
+
+        let position = match merge.level {
+            // A level 0 run is replaced in place
+            0 => positions[0],
+            // Outputs are sorted and don't overlap the rest of the level, so they go in as a block
+            level => {
+                let tables = self.level_range(level);
+                let mut position = tables.start;
+                if let Some((_, output)) = outputs.first() {
+                    if let Some((first, _)) = output.span()? {
+                        for sstable in &self.sstables[tables] {
+                            match sstable.span()? {
+                                Some((other, _)) if self.config.comparator.compare(other, first).is_lt() => {
+                                    position += 1
+                                }
+                                _ => break,
+                            }
+                        }
+                    }
+                }
+                position
+            }
+        };
+        self.insert_tables(position, merge.level, outputs);
+        self.manifest.store(&manifest_path(&self.config))?;
+
+        Self::retire(removed);
+        Ok(true)
+    }
+
+    /// Flushes the memtable and rewrites all SSTables into new ones without the entries for
+    /// which `keep` returns false, and without tombstones. See `rewrite_with`.
+    pub fn compact_with_filter<F: Fn(&K, &V) -> bool>(&mut self, keep: F) -> Result<()> {
+        self.rewrite_with(|key, value| keep(key, &value).then_some(value))
+    }
+
+    /// Flushes the memtable and rewrites all SSTables into new ones in which each live value is
+    /// replaced by what `f` returns for it, or deleted if that is `None`, e.g. to migrate
+    /// values to a new schema. Tombstones are dropped. The new tables replace the old ones in a
+    /// single manifest update, so an interrupted run leaves the tree as it was; its partial
+    /// output is removed on the next open. The output goes to the deepest level, split into
+    /// tables of about `memtable_size_threshold` bytes unless that is level 0.
+    pub fn rewrite_with<F: Fn(&K, V) -> Option<V>>(&mut self, f: F) -> Result<()> {
+        self.flush()?;
+
+        let level = self.max_level();
+        let max_table_size = (level > 0).then(|| self.config.memtable_size_threshold.max(1) as u64);
+        let operator = self.merge_operator.clone();
+        let entries = merged_entries(&self.config, &self.counters, operator, &self.sstables, true, f)?;
+        let outputs = self.write_tables(entries, &[], level, max_table_size, 0)?;
+        record_merge(&self.counters, &self.sstables, &outputs);
+
+        let inputs = std::mem::take(&mut self.sstables);
+        self.manifest.sstables.clear();
+        self.insert_tables(0, level, outputs);
+        self.manifest.store(&manifest_path(&self.config))?;
+        self.counters.compactions.add(1);
+
+        Self::retire(inputs);
+        Ok(())
+    }
+
+    /// Maximum size in bytes of a level ≥ 1
+    fn level_budget(&self, level: u32, fanout: usize) -> u64 {
+        let multiplier = self.config.level_size_multiplier.unwrap_or(fanout) as u64;
+        (self.config.memtable_size_threshold as u64).saturating_mul(multiplier.saturating_pow(level))
+    }
+
+    fn max_level(&self) -> u32 {
+        self.manifest.sstables.iter().map(|entry| entry.level).max().unwrap_or(0)
+    }
+
+    /// Indices of the tables at `level`, which are contiguous in search order
+    fn level_range(&self, level: u32) -> Range<usize> {
+        let start = self.manifest.sstables.iter().take_while(|entry| entry.level > level).count();
+        let len = self.manifest.sstables[start..].iter().take_while(|entry| entry.level == level).count();
+        start..start + len
+    }
+
+    /// Plans merging the tables at `inputs` (all at `level`) with the overlapping tables of
+    /// the next level, writing the result to the next level as tables of about
+    /// `memtable_size_threshold` bytes
+    fn plan_next_level(&self, level: u32, inputs: Vec<usize>) -> Result<Merge<K, V>> {
+        let target = level + 1;
+
+        let comparator = &*self.config.comparator;
+        let mut ranges = Vec::with_capacity(inputs.len());
+        for &i in &inputs {
+            ranges.extend(self.sstables[i].span()?);
+        }
+        let low = ranges.iter().map(|(first, _)| *first).min_by(|a, b| comparator.compare(a, b));
+        let high = ranges.iter().map(|(_, last)| *last).max_by(|a, b| comparator.compare(a, b));
+        let mut overlapping = Vec::new();
+        if let (Some(low), Some(high)) = (low, high) {
+            for i in self.level_range(target) {
+                if self.sstables[i].overlaps_range(Bound::Included(low), Bound::Included(high))? {
+                    overlapping.push(i);
+                }
+            }
+        }
+
+        // The next level comes first in search order, so the inputs are newest last
+        let mut replaced: Vec<_> = overlapping.into_iter().chain(inputs).collect();
+        replaced.sort_unstable();
+        Ok(Merge {
+            inputs: replaced.iter().map(|&i| Arc::clone(&self.sstables[i])).collect(),
+            level: target,
+            drop_tombstones: self.max_level() <= target,
+            max_table_size: Some(self.config.memtable_size_threshold.max(1) as u64),
+            counters: Arc::clone(&self.counters),
+            merge_operator: self.merge_operator.clone(),
+        })
+    }
+
+    pub(crate) fn insert_tables(&mut self, position: usize, level: u32, tables: Vec<(u64, SSTable<K, V>)>) {
+        for (offset, (id, sstable)) in tables.into_iter().enumerate() {
+            self.counters.sstable_bytes_written.add(sstable.file_size());
+            let sstable = sstable
+                .with_counters(Arc::clone(&self.counters))
+                .with_block_cache(self.block_cache.clone(), id)
+                .with_file_pool(self.file_pool.clone(), id)
+                .with_read_buffer_bytes(self.config.read_buffer_bytes);
+            self.sstables.insert(position + offset, Arc::new(sstable));
+            self.manifest.sstables.insert(position + offset, ManifestEntry { id, level });
+        }
+    }
+
+    /// Releases replaced tables; their files go away with the last reference
+    fn retire(sstables: Vec<Arc<SSTable<K, V>>>) {
+        for sstable in sstables {
+            sstable.mark_obsolete();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::Config;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn setup(compaction_threshold: Option<usize>) -> (LSMTree<String, String>, TempDir) {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            memtable_size_threshold: 1024 * 1024,
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold,
+            ..Config::default()
+        };
+        (LSMTree::with_config(config).unwrap(), temp_dir)
+    }
+
+    fn sstable_files(temp_dir: &TempDir) -> usize {
+        std::fs::read_dir(temp_dir.path())
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                let name = name.to_str().unwrap();
+                name.starts_with("sstable_") && name.ends_with(".db")
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_size_tiered_runs() {
+        let runs = |sizes: &[u64]| CompactionPolicy::default().select(sizes);
+        assert_eq!(runs(&[]), Vec::<Range<usize>>::new());
+        assert_eq!(runs(&[100]), Vec::<Range<usize>>::new());
+        assert_eq!(runs(&[100, 110, 90]), vec![0..3]);
+        assert_eq!(runs(&[1000, 100, 110, 5000]), vec![1..3]);
+        assert_eq!(runs(&[1000, 900, 100, 110]), vec![0..2, 2..4]);
+        assert_eq!(runs(&[1000, 100, 5000]), Vec::<Range<usize>>::new());
+
+        let wide = CompactionPolicy::SimilarSize { low: 0.1, high: 10.0 };
+        assert_eq!(wide.select(&[1000, 100, 5000]), vec![0..3]);
+        let oldest = CompactionPolicy::Oldest { count: 3 };
+        assert_eq!(oldest.select(&[1000, 100, 5000, 10]), vec![0..3]);
+        assert_eq!(oldest.select(&[1000, 100]), vec![0..2]);
+        assert_eq!(oldest.select(&[1000]), Vec::<Range<usize>>::new());
+    }
+
+    #[test]
+    fn test_compaction_stats() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let mut lsm = LSMTree::with_config(Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            compaction_policy: CompactionPolicy::Oldest { count: 10 },
+            ..Config::default()
+        })?;
+        assert_eq!(lsm.compaction_stats().write_amplification(), 1.0);
+
+        for i in 0..10 {
+            lsm.insert(format!("key{}", i), "old".to_string())?;
+        }
+        lsm.flush()?;
+        for i in 0..5 {
+            lsm.insert(format!("key{}", i), "new".to_string())?;
+        }
+        lsm.delete("key9".to_string())?;
+        lsm.flush()?;
+        let flushed: u64 = lsm.sstables.iter().map(|sstable| sstable.file_size()).sum();
+
+        lsm.compact()?;
+        let stats = lsm.compaction_stats();
+        let written = lsm.sstables[0].file_size();
+        assert_eq!(stats.compactions, 1);
+        assert_eq!((stats.flushed_bytes, stats.bytes_read, stats.bytes_written), (flushed, flushed, written));
+        assert_eq!((stats.records_read, stats.records_written), (16, 9));
+        // Six older versions, then key9's tombstone, which has nothing left to shadow
+        assert_eq!((stats.shadowed_dropped, stats.expired_dropped, stats.tombstones_dropped), (6, 0, 1));
+        assert_eq!(stats.bytes_written_per_compaction(), written);
+        assert_eq!(stats.write_amplification(), (flushed + written) as f64 / flushed as f64);
+
+        lsm.compact_with_filter(|key, _| key != "key0")?;
+        let stats = lsm.compaction_stats();
+        assert_eq!(stats.compactions, 2);
+        assert_eq!((stats.records_read, stats.records_written), (25, 17));
+        assert_eq!((stats.shadowed_dropped, stats.expired_dropped, stats.tombstones_dropped), (6, 1, 1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_policy() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = |compaction_policy| Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            compaction_policy,
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config(CompactionPolicy::Oldest { count: 3 }))?;
+        for round in 0..5 {
+            lsm.insert(format!("key{}", round), "value".to_string())?;
+            lsm.flush()?;
+        }
+
+        lsm.compact()?;
+        assert_eq!(sstable_files(&temp_dir), 3);
+        lsm.compact()?;
+        assert_eq!(sstable_files(&temp_dir), 1);
+        for round in 0..5 {
+            assert_eq!(lsm.get(&format!("key{}", round))?, Some("value".to_string()));
+        }
+        drop(lsm);
+
+        for invalid in [
+            CompactionPolicy::Oldest { count: 1 },
+            CompactionPolicy::SimilarSize { low: 0.0, high: 1.5 },
+            CompactionPolicy::SimilarSize { low: 0.5, high: 0.9 },
+        ] {
+            assert!(matches!(
+                LSMTree::<String, String>::with_config(config(invalid)),
+                Err(crate::LSMError::InvalidConfig(_))
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_keeps_newest_version() -> Result<()> {
+        let (mut lsm, temp_dir) = setup(None);
+
+        for round in 0..4 {
+            for i in 0..20 {
+                lsm.insert(format!("key{:02}", i), format!("value{}_{}", i, round))?;
+            }
+            lsm.flush()?;
+        }
+        assert_eq!(sstable_files(&temp_dir), 4);
+
+        lsm.compact()?;
+        assert_eq!(lsm.sstables.len(), 1);
+        assert_eq!(sstable_files(&temp_dir), 1);
+
+        for i in 0..20 {
+            assert_eq!(lsm.get(&format!("key{:02}", i))?, Some(format!("value{}_3", i)));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_drops_deleted_keys() -> Result<()> {
+        let (mut lsm, temp_dir) = setup(None);
+
+        lsm.insert("a".to_string(), "1".to_string())?;
+        lsm.insert("b".to_string(), "2".to_string())?;
+        lsm.flush()?;
+        lsm.delete("a".to_string())?;
+        lsm.delete("b".to_string())?;
+        lsm.flush()?;
+
+        lsm.compact()?;
+        assert_eq!(lsm.get(&"a".to_string())?, None);
+        assert_eq!(lsm.get(&"b".to_string())?, None);
+        assert!(lsm.sstables.is_empty());
+        assert_eq!(sstable_files(&temp_dir), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_survives_reopen() -> Result<()> {
+        let (mut lsm, temp_dir) = setup(None);
+
+        for round in 0..3 {
+            lsm.insert("key".to_string(), format!("value{}", round))?;
+            lsm.flush()?;
+        }
+        lsm.compact()?;
+        lsm.insert("key".to_string(), "latest".to_string())?;
+        lsm.flush()?;
+        drop(lsm);
+
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        let lsm = LSMTree::<String, String>::with_config(config)?;
+        assert_eq!(lsm.get(&"key".to_string())?, Some("latest".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_with_filter() -> Result<()> {
+        let (mut lsm, temp_dir) = setup(None);
+
+        for round in 0..3 {
+            for tenant in ["a", "b"] {
+                for i in 0..10 {
+                    lsm.insert(format!("{}:{}", tenant, i), format!("{}", round))?;
+                }
+            }
+            lsm.flush()?;
+        }
+        // Only the newest version of b:0 matches, and the memtable is filtered as well
+        lsm.insert("b:0".to_string(), "drop".to_string())?;
+        lsm.insert("a:0".to_string(), "drop".to_string())?;
+
+        lsm.compact_with_filter(|key, value| !key.starts_with("a:") && value != "drop")?;
+        assert_eq!(sstable_files(&temp_dir), 1);
+        assert_eq!(lsm.get(&"a:5".to_string())?, None);
+        assert_eq!(lsm.get(&"b:0".to_string())?, None);
+        assert_eq!(lsm.get(&"b:1".to_string())?, Some("2".to_string()));
+
+        let data_dir = lsm.config.data_dir.clone();
+        drop(lsm);
+        let lsm = LSMTree::<String, String>::with_config(Config {
+            data_dir,
+            compaction_threshold: None,
+            ..Config::default()
+        })?;
+        let keys: Vec<_> = lsm.iter()?.map(|item| item.map(|(key, _)| key)).collect::<Result<_>>()?;
+        assert_eq!(keys, (1..10).map(|i| format!("b:{}", i)).collect::<Vec<_>>());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrite_with() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let clock = Arc::new(ManualClock::new(0));
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            clock: clock.clone(),
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::<String, String>::with_config(config)?;
+
+        for i in 0..10 {
+            lsm.insert(format!("key{}", i), format!("v1:{}", i))?;
+        }
+        lsm.flush()?;
+        lsm.insert("key0".to_string(), "v1:new".to_string())?;
+        lsm.delete("key1".to_string())?;
+        lsm.insert_with_ttl("key2".to_string(), "v1:2".to_string(), Duration::from_secs(10))?;
+
+        // Migrate every value to a new format, dropping the odd keys
+        lsm.rewrite_with(|key, value| {
+            let odd = key.ends_with(|c: char| c.to_digit(10).is_some_and(|digit| digit % 2 == 1));
+            (!odd).then(|| value.replacen("v1:", "v2:", 1))
+        })?;
+        assert_eq!(sstable_files(&temp_dir), 1);
+        let pairs: Vec<_> = lsm.iter()?.collect::<Result<_>>()?;
+        let expected: Vec<_> = [
+            ("key0", "v2:new"),
+            ("key2", "v2:2"),
+            ("key4", "v2:4"),
+            ("key6", "v2:6"),
+            ("key8", "v2:8"),
+        ]
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+        assert_eq!(pairs, expected);
+
+        // The odd keys other than the deleted key1
+        assert_eq!(lsm.compaction_stats().expired_dropped, 4);
+
+        // A rewritten value keeps its expiry time
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(lsm.get(&"key2".to_string())?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_drops_expired_entries() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let clock = Arc::new(ManualClock::new(0));
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            clock: clock.clone(),
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::<String, String>::with_config(config)?;
+
+        lsm.insert("a".to_string(), "1".to_string())?;
+        lsm.insert_with_ttl("b".to_string(), "2".to_string(), Duration::from_secs(1))?;
+        lsm.flush()?;
+        lsm.insert_with_ttl("c".to_string(), "3".to_string(), Duration::from_secs(1))?;
+        lsm.insert_with_ttl("d".to_string(), "4".to_string(), Duration::from_secs(100))?;
+        lsm.flush()?;
+
+        clock.advance(Duration::from_secs(1));
+        lsm.compact()?;
+        assert_eq!(lsm.sstables.len(), 1);
+
+        let keys: Vec<_> = lsm.sstables[0].entries()?.map(|item| item.map(|(k, _)| k)).collect::<Result<_>>()?;
+        assert_eq!(keys, vec!["a".to_string(), "d".to_string()]);
+        assert_eq!(lsm.get(&"d".to_string())?, Some("4".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_merge_bounds_sstables() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            max_sstables_before_flush_merge: Some(3),
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::<String, String>::with_config(config.clone())?;
+
+        for round in 0..10 {
+            lsm.insert("shared".to_string(), format!("v{}", round))?;
+            lsm.insert(format!("key{}", round), round.to_string())?;
+            if round == 4 {
+                lsm.delete("key2".to_string())?;
+            }
+            lsm.flush()?;
+            assert!(lsm.sstables.len() <= 3, "round {}: {} tables", round, lsm.sstables.len());
+        }
+        // The newest table is never part of a merge
+        assert_eq!(lsm.sstables.last().unwrap().entry_count(), 2);
+
+        let lsm = LSMTree::<String, String>::with_config(config)?;
+        assert_eq!(lsm.get(&"shared".to_string())?, Some("v9".to_string()));
+        assert_eq!(lsm.get(&"key2".to_string())?, None);
+        for round in (0..10).filter(|&round| round != 2) {
+            assert_eq!(lsm.get(&format!("key{}", round))?, Some(round.to_string()));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_level_directories() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = |level_directories| Config {
+            memtable_size_threshold: 2048,
+            data_dir: temp_dir.path().to_path_buf(),
+            level_directories,
+            compaction_threshold: Some(2),
+            compaction_strategy: CompactionStrategy::Leveled { fanout: 2 },
+            l0_compaction_trigger: 2,
+            ..Config::default()
+        };
+        let table_paths = |lsm: &LSMTree<String, String>| -> Vec<(u32, PathBuf)> {
+            lsm.manifest
+                .sstables
+                .iter()
+                .zip(&lsm.sstables)
+                .map(|(entry, sstable)| (entry.level, sstable.path().to_path_buf()))
+                .collect()
+        };
+
+        let mut lsm = LSMTree::with_config(config(true))?;
+        assert!(temp_dir.path().join("L0").is_dir());
+        for i in 0..300 {
+            lsm.insert(format!("key{:04}", i), format!("value{}", i))?;
+        }
+        lsm.flush()?;
+        lsm.compact()?;
+        assert!(lsm.max_level() >= 1);
+        for (level, path) in table_paths(&lsm) {
+            let dir = temp_dir.path().join(format!("L{}", level));
+            assert_eq!(path.parent(), Some(dir.as_path()));
+            assert!(path.exists());
+        }
+        drop(lsm);
+
+        // Changing the setting moves the tables on the next open, in both directions
+        for level_directories in [false, true] {
+            let lsm = LSMTree::<String, String>::with_config(config(level_directories))?;
+            for (level, path) in table_paths(&lsm) {
+                let dir = match level_directories {
+                    true => temp_dir.path().join(format!("L{}", level)),
+                    false => temp_dir.path().to_path_buf(),
+                };
+                assert_eq!(path.parent(), Some(dir.as_path()));
+            }
+            for i in 0..300 {
+                assert_eq!(lsm.get(&format!("key{:04}", i))?, Some(format!("value{}", i)));
+            }
+        }
+
+        // Tables not in the manifest are cleaned up in the level directories too
+        let stray = temp_dir.path().join("L1").join("sstable_999999.db");
+        fs::write(&stray, b"")?;
+        LSMTree::<String, String>::with_config(config(true))?;
+        assert!(!stray.exists());
+
+        Ok(())
+    }
+
+    fn setup_leveled(temp_dir: &TempDir) -> Result<LSMTree<String, String>> {
+        let config = Config {
+            memtable_size_threshold: 2048,
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: Some(2),
+            compaction_strategy: CompactionStrategy::Leveled { fanout: 2 },
+            l0_compaction_trigger: 2,
+            ..Config::default()
+        };
+        LSMTree::with_config(config)
+    }
+
+    /// Checks that levels are contiguous and deepest first, and that every level ≥ 1 is
+    /// sorted by key with no overlapping tables
+    fn assert_leveled(lsm: &LSMTree<String, String>) {
+        let levels: Vec<_> = lsm.manifest.sstables.iter().map(|entry| entry.level).collect();
+        assert!(levels.windows(2).all(|pair| pair[0] >= pair[1]), "levels out of order: {:?}", levels);
+
+        for level in 1..=lsm.max_level() {
+            let ranges: Vec<_> = lsm.sstables[lsm.level_range(level)]
+                .iter()
+                .map(|sstable| sstable.key_range().unwrap().unwrap())
+                .collect();
+            for pair in ranges.windows(2) {
+                assert!(pair[0].1 < pair[1].0, "overlapping tables in level {}", level);
+            }
+        }
+    }
+
+    #[test]
+    fn test_leveled_compaction() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let mut lsm = setup_leveled(&temp_dir)?;
+
+        for round in 0..5 {
+            for i in 0..200 {
+                if i % (round + 1) == 0 {
+                    lsm.insert(format!("key{:04}", i), format!("value{}_{}", i, round))?;
+                }
+            }
+        }
+        for i in (0..200).step_by(7) {
+            lsm.delete(format!("key{:04}", i))?;
+        }
+        lsm.flush()?;
+        lsm.compact()?;
+
+        assert_eq!(lsm.level_range(0).len(), 0);
+        assert!(lsm.max_level() >= 2);
+        assert_leveled(&lsm);
+
+        for i in 0..200 {
+            let key = format!("key{:04}", i);
+            let expected = if i % 7 == 0 {
+                None
+            } else {
+                let round = (0..5).rev().find(|round| i % (round + 1) == 0).unwrap();
+                Some(format!("value{}_{}", i, round))
+            };
+            assert_eq!(lsm.get(&key)?, expected);
+
+            // At most one table per level ≥ 1 can hold the key
+            for level in 1..=lsm.max_level() {
+                let candidates = lsm.sstables[lsm.level_range(level)]
+                    .iter()
+                    .filter(|sstable| sstable.may_contain_key(&key).unwrap())
+                    .count();
+                assert!(candidates <= 1);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_leveled_survives_reopen() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        {
+            let mut lsm = setup_leveled(&temp_dir)?;
+            for i in 0..500 {
+                lsm.insert(format!("key{:04}", i), format!("value{}", i))?;
+            }
+            lsm.insert("key0000".to_string(), "newest".to_string())?;
+        }
+
+        let lsm = setup_leveled(&temp_dir)?;
+        assert!(lsm.max_level() >= 1);
+        assert_leveled(&lsm);
+        assert_eq!(lsm.get(&"key0000".to_string())?, Some("newest".to_string()));
+        assert_eq!(lsm.get(&"key0499".to_string())?, Some("value499".to_string()));
+        assert_eq!(lsm.iter()?.count(), 500);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_leveled_fanout_validated() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_strategy: CompactionStrategy::Leveled { fanout: 1 },
+            ..Config::default()
+        };
+        let invalid = [
+            config.clone(),
+            Config { max_levels: 1, ..Config::default() },
+            Config { l0_compaction_trigger: 0, ..Config::default() },
+            Config { level_size_multiplier: Some(1), ..Config::default() },
+        ];
+        for config in invalid {
+            let config = Config { data_dir: temp_dir.path().to_path_buf(), ..config };
+            assert!(matches!(
+                LSMTree::<String, String>::with_config(config),
+                Err(crate::LSMError::InvalidConfig(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_leveled_shape() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            memtable_size_threshold: 2048,
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: Some(1),
+            compaction_strategy: CompactionStrategy::Leveled { fanout: 2 },
+            max_levels: 2,
+            l0_compaction_trigger: 3,
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config.clone())?;
+
+        // Level 0 fills up to the trigger before being merged into level 1
+        for round in 0..3 {
+            lsm.insert(format!("key{}", round), "value".to_string())?;
+            lsm.flush()?;
+            assert_eq!(lsm.max_level(), if round < 2 { 0 } else { 1 });
+        }
+        assert_eq!(lsm.level_range(0).len(), 0);
+
+        // However large level 1 grows, there is no level 2
+        for i in 0..500 {
+            lsm.insert(format!("key{:04}", i), format!("value{}", i))?;
+        }
+        lsm.flush()?;
+        lsm.compact()?;
+        assert_eq!(lsm.max_level(), 1);
+        assert_leveled(&lsm);
+        assert_eq!(lsm.get(&"key0499".to_string())?, Some("value499".to_string()));
+        drop(lsm);
+
+        // The multiplier replaces the fanout in the level budgets
+        let lsm = LSMTree::<String, String>::with_config(Config { level_size_multiplier: Some(10), ..config })?;
+        assert_eq!(lsm.level_budget(2, 2), 2048 * 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_automatic_compaction() -> Result<()> {
+        let (mut lsm, temp_dir) = setup(Some(3));
+
+        for round in 0..3 {
+            lsm.insert(format!("key{}", round), "value".to_string())?;
+            lsm.flush()?;
+        }
+        assert_eq!(sstable_files(&temp_dir), 1);
+        for round in 0..3 {
+            assert_eq!(lsm.get(&format!("key{}", round))?, Some("value".to_string()));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_maybe_compact() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            memtable_size_threshold: 2048,
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: Some(3),
+            automatic_compaction: false,
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config.clone())?;
+        for round in 0..3 {
+            lsm.insert(format!("key{}", round), "value".to_string())?;
+            lsm.flush()?;
+            // Not due before the third flush, and never run on its own
+            assert!(round == 2 || !lsm.maybe_compact()?);
+        }
+        assert_eq!(sstable_files(&temp_dir), 3);
+        assert!(lsm.maybe_compact()?);
+        assert_eq!(sstable_files(&temp_dir), 1);
+        assert!(!lsm.maybe_compact()?);
+        drop(lsm);
+
+        // Leveled compaction steps through the levels one table at a time
+        let leveled = Config {
+            compaction_strategy: CompactionStrategy::Leveled { fanout: 2 },
+            l0_compaction_trigger: 1,
+            ..config
+        };
+        let mut lsm = LSMTree::with_config(leveled)?;
+        for i in 0..500 {
+            lsm.insert(format!("key{:04}", i), format!("value{}", i))?;
+        }
+        lsm.flush()?;
+        assert_eq!(lsm.max_level(), 0);
+        let mut steps = 0;
+        while lsm.maybe_compact()? {
+            steps += 1;
+        }
+        assert!(steps > 1 && lsm.max_level() >= 2);
+        assert_eq!(lsm.level_range(0).len(), 0);
+        assert_leveled(&lsm);
+        assert_eq!(lsm.iter()?.count(), 3 + 500);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_discarded_if_inputs_replaced() -> Result<()> {
+        let (mut lsm, temp_dir) = setup(None);
+        for round in 0..2 {
+            lsm.insert(format!("key{}", round), "value".to_string())?;
+            lsm.flush()?;
+        }
+
+        // Planned and written while the tree was left alone, a merge replaces its inputs
+        let merges = lsm.plan_merges(true)?;
+        assert_eq!(merges.len(), 1);
+        let options = lsm.sstable_options();
+        for merge in merges {
+            let manifest = &mut lsm.manifest;
+            let outputs = merge.write(&lsm.config, &options, || manifest.allocate_id())?;
+            assert!(lsm.install_merge(merge, outputs)?);
+        }
+        assert_eq!(sstable_files(&temp_dir), 1);
+
+        // Once an input is gone, the output is thrown away and the tree stays as it is
+        lsm.insert("key2".to_string(), "value".to_string())?;
+        lsm.flush()?;
+        let merge = lsm.plan_merges(true)?.pop().unwrap();
+        let manifest = &mut lsm.manifest;
+        let outputs = merge.write(&lsm.config, &options, || manifest.allocate_id())?;
+        lsm.clear()?;
+        assert!(!lsm.install_merge(merge, outputs)?);
+        assert!(lsm.sstables.is_empty());
+        assert_eq!(sstable_files(&temp_dir), 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+//! Key ordering.
+//!
+//! Keys are ordered by their `Ord` impl unless a different `Comparator` is set in `Config`.
+//! The comparator orders the memtable, the SSTable indexes and every merge, and its name is
+//! recorded in the manifest: reopening a data directory with a differently named comparator
+//! fails instead of returning wrong results.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+pub trait Comparator<K>: Debug + Send + Sync {
+    /// Must be a total order, like an `Ord` impl: lookups and scans stop at the first key
+    /// greater than the one they look for, and miss keys a partial order leaves out of place.
+    /// Wrap floats in `ordered::OrderedF64` rather than comparing them with `partial_cmp`.
+    fn compare(&self, a: &K, b: &K) -> Ordering;
+
+    /// Identifies the ordering; must change whenever the order it defines changes
+    fn name(&self) -> &str;
+
+    /// Whether keys that compare equal always serialize to the same bytes. Bloom filters hash
+    /// serialized keys, so they are only built and consulted when this holds.
+    fn is_consistent_with_serialization(&self) -> bool {
+        false
+    }
+
+    /// An encoding of keys whose bytes sort in this order, if the comparator has one (see
+    /// `ordered::OrderedBytes`). SSTables written with such a comparator store their record
+    /// keys in it, so lookups compare the stored keys as bytes instead of deserializing them.
+    fn key_encoding(&self) -> Option<&dyn KeyEncoding<K>> {
+        None
+    }
+}
+
+/// A byte encoding of keys that sorts like the `Comparator` offering it
+pub trait KeyEncoding<K> {
+    fn encode(&self, key: &K) -> Vec<u8>;
+
+    /// Inverse of `encode`; `None` if `bytes` aren't a valid encoding
+    fn decode(&self, bytes: &[u8]) -> Option<K>;
+}
+
+/// Orders keys by their `Ord` impl
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NaturalOrder;
+
+impl<K: Ord> Comparator<K> for NaturalOrder {
+    fn compare(&self, a: &K, b: &K) -> Ordering {
+        a.cmp(b)
+    }
+
+    fn name(&self) -> &str {
+        "natural"
+    }
+
+    fn is_consistent_with_serialization(&self) -> bool {
+        true
+    }
+}
+
+/// A key paired with the comparator that orders it, so it can be stored in a `BTreeMap`
+pub(crate) struct OrderedKey<K> {
+    pub(crate) key: K,
+    comparator: Arc<dyn Comparator<K>>,
+}
+
+impl<K> OrderedKey<K> {
+    pub(crate) fn new(key: K, comparator: Arc<dyn Comparator<K>>) -> Self {
+        Self { key, comparator }
+    }
+}
+
+impl<K: Clone> Clone for OrderedKey<K> {
+    fn clone(&self) -> Self {
+        Self::new(self.key.clone(), Arc::clone(&self.comparator))
+    }
+}
+
+/// A borrowed key and its comparator. `OrderedKey` borrows as `dyn KeyRef`, which lets maps
+/// of `OrderedKey`s be searched with a `Probe` without cloning the key.
+pub(crate) trait KeyRef<K> {
+    fn key(&self) -> &K;
+    fn comparator(&self) -> &dyn Comparator<K>;
+}
+
+impl<K> KeyRef<K> for OrderedKey<K> {
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn comparator(&self) -> &dyn Comparator<K> {
+        &*self.comparator
+    }
+}
+
+pub(crate) struct Probe<'a, K> {
+    key: &'a K,
+    comparator: &'a dyn Comparator<K>,
+}
+
+impl<'a, K> Probe<'a, K> {
+    pub(crate) fn new(key: &'a K, comparator: &'a dyn Comparator<K>) -> Self {
+        Self { key, comparator }
+    }
+}
+
+impl<K> KeyRef<K> for Probe<'_, K> {
+    fn key(&self) -> &K {
+        self.key
+    }
+
+    fn comparator(&self) -> &dyn Comparator<K> {
+        self.comparator
+    }
+}
+
+impl<K> PartialEq for dyn KeyRef<K> + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K> Eq for dyn KeyRef<K> + '_ {}
+
+impl<K> PartialOrd for dyn KeyRef<K> + '_ {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K> Ord for dyn KeyRef<K> + '_ {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.comparator().compare(self.key(), other.key())
+    }
+}
+
+impl<'a, K: 'a> Borrow<dyn KeyRef<K> + 'a> for OrderedKey<K> {
+    fn borrow(&self) -> &(dyn KeyRef<K> + 'a) {
+        self
+    }
+}
+
+impl<K> PartialEq for OrderedKey<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K> Eq for OrderedKey<K> {}
+
+impl<K> PartialOrd for OrderedKey<K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K> Ord for OrderedKey<K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.comparator.compare(&self.key, &other.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompactionStrategy, Config, LSMError, LSMTree, Result};
+    use std::ops::Bound;
+    use tempfile::TempDir;
+
+    #[derive(Debug)]
+    struct CaseInsensitive;
+
+    impl Comparator<String> for CaseInsensitive {
+        fn compare(&self, a: &String, b: &String) -> Ordering {
+            a.to_lowercase().cmp(&b.to_lowercase())
+        }
+
+        fn name(&self) -> &str {
+            "case-insensitive"
+        }
+    }
+
+    fn config(temp_dir: &TempDir, compaction_strategy: CompactionStrategy) -> Config<String> {
+        Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            compaction_strategy,
+            comparator: Arc::new(CaseInsensitive),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_custom_comparator() -> Result<()> {
+        for strategy in [CompactionStrategy::SizeTiered, CompactionStrategy::Leveled { fanout: 2 }] {
+            let temp_dir = TempDir::new().unwrap();
+            let mut lsm = LSMTree::with_config(config(&temp_dir, strategy))?;
+
+            lsm.insert("Banana".to_string(), 1)?;
+            lsm.insert("apple".to_string(), 1)?;
+            lsm.flush()?;
+            lsm.insert("BANANA".to_string(), 2)?;
+            lsm.insert("cherry".to_string(), 2)?;
+            lsm.flush()?;
+            lsm.insert("APPLE".to_string(), 3)?;
+            lsm.delete("Cherry".to_string())?;
+
+            for round in 0..2 {
+                assert_eq!(lsm.get(&"banana".to_string())?, Some(2), "round {}", round);
+                assert_eq!(lsm.get(&"Apple".to_string())?, Some(3), "round {}", round);
+                assert_eq!(lsm.get(&"CHERRY".to_string())?, None, "round {}", round);
+                assert_eq!(
+                    lsm.get_many(&["BaNaNa".to_string(), "cherry".to_string()])?,
+                    vec![Some(2), None]
+                );
+                let keys: Vec<_> = lsm
+                    .range(Bound::Included("AAA".to_string()), Bound::Excluded("C".to_string()))?
+                    .map(|item| item.map(|(key, value)| (key.to_lowercase(), value)))
+                    .collect::<Result<_>>()?;
+                assert_eq!(keys, vec![("apple".to_string(), 3), ("banana".to_string(), 2)]);
+
+                lsm.flush()?;
+                lsm.compact()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_comparator_mismatch_rejected() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = config(&temp_dir, CompactionStrategy::SizeTiered);
+        LSMTree::with_config(config.clone())?.insert("key".to_string(), 1)?;
+
+        let natural = Config {
+            comparator: Arc::new(NaturalOrder),
+            ..config.clone()
+        };
+        assert!(matches!(
+            LSMTree::<String, i32>::with_config(natural),
+            Err(LSMError::InvalidConfig(_))
+        ));
+
+        let lsm = LSMTree::<String, i32>::with_config(config)?;
+        assert_eq!(lsm.get(&"KEY".to_string())?, Some(1));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+//! Block compression codecs for SSTables.
+//!
+//! `Lz4` and `Zstd` are only available when the crate is built with the `lz4` and `zstd`
+//! features respectively. The codec is recorded in each SSTable's header, so tables written
+//! with different settings can be read side by side.
+
+use crate::{LSMError, Result};
+use std::borrow::Cow;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Lz4),
+            2 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Whether this build includes the codec
+    pub fn is_supported(self) -> bool {
+        match self {
+            Compression::None => true,
+            Compression::Lz4 => cfg!(feature = "lz4"),
+            Compression::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    pub(crate) fn compress(self, data: &[u8]) -> Result<Cow<'_, [u8]>> {
+        match self {
+            Compression::None => Ok(Cow::Borrowed(data)),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(Cow::Owned(lz4_flex::compress_prepend_size(data))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Cow::Owned(zstd::bulk::compress(data, zstd::DEFAULT_COMPRESSION_LEVEL)?)),
+            #[allow(unreachable_patterns)]
+            unsupported => Err(LSMError::UnsupportedCompression(unsupported)),
+        }
+    }
+
+    /// Decompresses a block. Returns `Ok(None)` if the data isn't valid for this codec.
+    pub(crate) fn decompress(self, data: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        match self {
+            Compression::None => Ok(Some(data)),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(lz4_flex::decompress_size_prepended(&data).ok()),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(zstd::stream::decode_all(&data[..]).ok()),
+            #[allow(unreachable_patterns)]
+            unsupported => Err(LSMError::UnsupportedCompression(unsupported)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(compression: Compression) -> Result<()> {
+        let data = b"hello hello hello hello hello hello".repeat(20);
+        let compressed = compression.compress(&data)?.into_owned();
+        assert_eq!(compression.decompress(compressed)?, Some(data));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ids_round_trip() {
+        for compression in [Compression::None, Compression::Lz4, Compression::Zstd] {
+            assert_eq!(Compression::from_id(compression.id()), Some(compression));
+        }
+        assert_eq!(Compression::from_id(42), None);
+    }
+
+    #[test]
+    fn test_no_compression() -> Result<()> {
+        round_trip(Compression::None)
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_lz4() -> Result<()> {
+        round_trip(Compression::Lz4)?;
+        assert_eq!(Compression::Lz4.decompress(vec![0xff; 16])?, None);
+        Ok(())
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() -> Result<()> {
+        round_trip(Compression::Zstd)?;
+        assert_eq!(Compression::Zstd.decompress(vec![0xff; 16])?, None);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "lz4"))]
+    #[test]
+    fn test_unsupported_codec() {
+        assert!(!Compression::Lz4.is_supported());
+        assert!(matches!(
+            Compression::Lz4.compress(b"data"),
+            Err(LSMError::UnsupportedCompression(Compression::Lz4))
+        ));
+    }
+}
@@ -0,0 +1,645 @@
+//! Thread-safe handle to an LSM tree.
+//!
+//! `ConcurrentLSMTree` wraps an `LSMTree` in a `RwLock` and can be cloned cheaply to share it
+//! between threads. A `get` holds the read lock only while it checks the memtable and clones
+//! the list of SSTables (a vector of `Arc`s); the SSTables themselves are searched after the
+//! lock is released, so lookups that go to disk don't hold up writers.
+//!
+//! Consistency guarantees:
+//! - Every operation is linearizable: a `get` that starts after an `insert` or `delete`
+//!   returned observes it (or a later write to the same key).
+//! - A `get` reads the memtable and the SSTable list as they were when it took the lock.
+//!   Writes, flushes and compactions that happen while it searches the SSTables are not
+//!   visible to it.
+//! - Writers never wait for readers that are searching SSTables: files replaced by compaction
+//!   are only deleted once no reader holds them.
+//!
+//! Writes are serialized. A write that fills the memtable only seals it: it becomes the
+//! immutable memtable, still searched by reads, and writes continue into a new one while a
+//! background thread writes it to SSTables without holding the lock. The lock is only taken
+//! again to allocate table ids and to install the tables, followed by any compaction, which
+//! blocks other readers and writers until it's done. If the new memtable fills up before the
+//! previous one is written, it keeps growing until then.
+//!
+//! With `Config::flush_interval` set, the same thread also flushes the memtable on that
+//! schedule. It is stopped when the last handle is dropped, after a final flush; without an
+//! interval, only the immutable memtable is written then, and the rest stays in the
+//! write-ahead log.
+//!
+//! With `Config::background_compaction` set, the compactions triggered by
+//! `compaction_threshold` run on a second thread instead of in the flush that reaches it. The
+//! tables being merged are shared with the tree, so reads, writes and flushes carry on while
+//! the merged tables are written; the lock is taken to plan each merge and to swap its output
+//! in, and new level 0 tables are picked up by the next compaction. Dropping the last handle
+//! waits for the compaction in progress.
+
+use crate::batch::WriteBatch;
+use crate::snapshot::Snapshot;
+use crate::stats::{CompactionStats, Stats};
+use crate::verify::VerifyReport;
+use crate::{write_memtable, Config, LSMTree, Result};
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError, TrySendError};
+use std::sync::{Arc, Mutex, PoisonError, RwLock, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+pub struct ConcurrentLSMTree<K, V> {
+    // Declared first so they are dropped first: the last handle stops the background threads
+    // while the tree is still alive for the final flush
+    workers: Arc<Workers>,
+    /// Held by every compaction, so a background compaction never runs alongside `compact`
+    compacting: Arc<Mutex<()>>,
+    tree: Arc<RwLock<LSMTree<K, V>>>,
+}
+
+impl<K, V> Clone for ConcurrentLSMTree<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            workers: Arc::clone(&self.workers),
+            compacting: Arc::clone(&self.compacting),
+            tree: Arc::clone(&self.tree),
+        }
+    }
+}
+
+/// The background threads of a tree, stopped in declaration order: the flusher first, whose
+/// final flush may still wake the compactor, then the compactor, after the compaction it is
+/// running
+struct Workers {
+    flusher: Worker,
+    compactor: Option<Worker>,
+}
+
+/// Background thread woken up through a channel. Dropping it stops the thread once it is done
+/// with what it is doing.
+struct Worker {
+    name: &'static str,
+    /// Wakes the thread up; once it and every clone are dropped, the thread stops
+    wake: Option<mpsc::SyncSender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    /// Spawns a thread calling `run` with the outcome of every wait for a wake-up, which times
+    /// out after `interval` if set, until `run` returns false
+    fn spawn(
+        name: &'static str,
+        interval: Option<Duration>,
+        mut run: impl FnMut(std::result::Result<(), RecvTimeoutError>) -> bool + Send + 'static,
+    ) -> Result<Self> {
+        // Wake-ups coalesce: one pending is enough for the thread to look at the tree again
+        let (wake, woken) = mpsc::sync_channel::<()>(1);
+        let thread = thread::Builder::new().name(name.to_string()).spawn(move || loop {
+            let received = match interval {
+                Some(interval) => woken.recv_timeout(interval),
+                None => woken.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            if !run(received) {
+                return;
+            }
+        })?;
+        Ok(Self { name, wake: Some(wake), thread: Some(thread) })
+    }
+
+    fn wake(&self) {
+        if let Some(wake) = &self.wake {
+            wake_up(wake, self.name);
+        }
+    }
+}
+
+fn wake_up(wake: &mpsc::SyncSender<()>, name: &str) {
+    if let Err(TrySendError::Disconnected(_)) = wake.try_send(()) {
+        log::error!("Background thread {} is gone", name);
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        drop(self.wake.take());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                log::error!("Background thread {} panicked", self.name);
+            }
+        }
+    }
+}
+
+/// Spawns the thread writing sealed memtables to SSTables, and flushing the memtable
+/// periodically if `interval` is set. Flushes that reach the compaction threshold wake up
+/// `compactor`.
+fn spawn_flusher<K, V>(
+    tree: Weak<RwLock<LSMTree<K, V>>>,
+    interval: Option<Duration>,
+    compactor: Option<mpsc::SyncSender<()>>,
+) -> Result<Worker>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone + Send + Sync + 'static,
+    V: serde::Serialize + serde::de::DeserializeOwned + Send + Sync + 'static,
+{
+    Worker::spawn(FLUSHER, interval, move |received| {
+        let Some(tree) = tree.upgrade() else {
+            return false;
+        };
+        let result = match received {
+            Ok(()) => flush_sealed(&tree),
+            Err(RecvTimeoutError::Timeout) => seal(&tree).and_then(|()| flush_sealed(&tree)),
+            Err(RecvTimeoutError::Disconnected) => {
+                let mut tree = tree.write().unwrap_or_else(PoisonError::into_inner);
+                let result = match interval {
+                    Some(_) => tree.flush(),
+                    None => tree.flush_immutable(),
+                };
+                if let Err(e) = result {
+                    log::error!("Final flush failed: {}", e);
+                }
+                return false;
+            }
+        };
+        if let Err(e) = result {
+            log::error!("Background flush failed: {}", e);
+        }
+        if let Some(compactor) = &compactor {
+            if tree.read().unwrap_or_else(PoisonError::into_inner).needs_compaction() {
+                wake_up(compactor, COMPACTOR);
+            }
+        }
+        true
+    })
+}
+
+/// Spawns the thread running the compactions triggered by `compaction_threshold`
+fn spawn_compactor<K, V>(tree: Weak<RwLock<LSMTree<K, V>>>, compacting: Arc<Mutex<()>>) -> Result<Worker>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone + Send + Sync + 'static,
+    V: serde::Serialize + serde::de::DeserializeOwned + Send + Sync + 'static,
+{
+    Worker::spawn(COMPACTOR, None, move |received| {
+        let Some(tree) = tree.upgrade().filter(|_| received.is_ok()) else {
+            return false;
+        };
+        if let Err(e) = compact_in_background(&tree, &compacting) {
+            log::error!("Background compaction failed: {}", e);
+        }
+        true
+    })
+}
+
+const FLUSHER: &str = "lsm-flush";
+const COMPACTOR: &str = "lsm-compaction";
+
+/// Seals the memtable unless a sealed one is still waiting to be written
+fn seal<K, V>(tree: &RwLock<LSMTree<K, V>>) -> Result<()>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+{
+    let mut tree = tree.write().unwrap_or_else(PoisonError::into_inner);
+    match tree.immutable {
+        Some(_) => Ok(()),
+        None => tree.seal_memtable(),
+    }
+}
+
+/// Writes the immutable memtable to SSTables, taking the write lock only to allocate table ids
+/// and to install the tables. Repeats while the writes that arrived meanwhile fill the memtable.
+fn flush_sealed<K, V>(tree: &RwLock<LSMTree<K, V>>) -> Result<()>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+{
+    loop {
+        let (memtable, config, options) = {
+            let tree = tree.read().unwrap_or_else(PoisonError::into_inner);
+            let Some(memtable) = tree.immutable.clone() else {
+                return Ok(());
+            };
+            (memtable, tree.config.clone(), tree.sstable_options())
+        };
+
+        let allocate_id = || tree.write().unwrap_or_else(PoisonError::into_inner).allocate_sstable_id();
+        let tables = write_memtable(&config, &options, &*memtable, allocate_id)?;
+
+        let mut tree = tree.write().unwrap_or_else(PoisonError::into_inner);
+        tree.install_flushed(&memtable, tables)?;
+        tree.flush_if_needed()?;
+    }
+}
+
+/// Compacts for as long as enough flushes have accumulated. Each merge reads tables the tree
+/// shares with it and is written without the lock, which is only taken to plan the merge, to
+/// allocate table ids and to install the result. Flushes carry on in the meantime, adding level
+/// 0 tables that the next compaction picks up.
+fn compact_in_background<K, V>(tree: &RwLock<LSMTree<K, V>>, compacting: &Mutex<()>) -> Result<()>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+{
+    let _compacting = compacting.lock().unwrap_or_else(PoisonError::into_inner);
+    loop {
+        let (config, options) = {
+            let mut tree = tree.write().unwrap_or_else(PoisonError::into_inner);
+            if !tree.needs_compaction() {
+                return Ok(());
+            }
+            tree.flushes_since_compaction = 0;
+            (tree.config.clone(), tree.sstable_options())
+        };
+
+        let mut first = true;
+        loop {
+            let merges = tree.read().unwrap_or_else(PoisonError::into_inner).plan_merges(first)?;
+            if merges.is_empty() {
+                break;
+            }
+            for merge in merges {
+                let allocate_id = || tree.write().unwrap_or_else(PoisonError::into_inner).allocate_sstable_id();
+                let outputs = merge.write(&config, &options, allocate_id)?;
+                // Inputs replaced meanwhile, e.g. by `clear`, leave the merge to the next round
+                tree.write().unwrap_or_else(PoisonError::into_inner).install_merge(merge, outputs)?;
+            }
+            first = false;
+        }
+        tree.read().unwrap_or_else(PoisonError::into_inner).counters.compactions.add(1);
+    }
+}
+
+impl<K, V> ConcurrentLSMTree<K, V>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone + Send + Sync + 'static,
+    V: serde::Serialize + serde::de::DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    pub fn new() -> Result<Self> {
+        Self::with_config(Config::default())
+    }
+
+    /// Opens the tree and starts its background threads
+    pub fn with_config(config: Config<K>) -> Result<Self> {
+        Self::from_tree(LSMTree::with_config(config)?)
+    }
+
+    fn from_tree(mut tree: LSMTree<K, V>) -> Result<Self> {
+        tree.background_flush = true;
+        tree.background_compaction = tree.config.background_compaction;
+        // A memtable whose flush was interrupted is written right away
+        let recovered = tree.immutable.is_some();
+        let flush_interval = tree.config.flush_interval;
+        let background_compaction = tree.background_compaction;
+        let tree = Arc::new(RwLock::new(tree));
+        let compacting = Arc::new(Mutex::new(()));
+
+        let compactor = match background_compaction {
+            true => Some(spawn_compactor(Arc::downgrade(&tree), Arc::clone(&compacting))?),
+            false => None,
+        };
+        let compactor_wake = compactor.as_ref().and_then(|compactor| compactor.wake.clone());
+        let flusher = spawn_flusher(Arc::downgrade(&tree), flush_interval, compactor_wake)?;
+        let workers = Arc::new(Workers { flusher, compactor });
+        if recovered {
+            workers.flusher.wake();
+        }
+        Ok(Self { workers, compacting, tree })
+    }
+
+    /// Runs a write under the write lock, then wakes the flusher if it sealed the memtable,
+    /// and the compactor if it flushed enough times
+    fn write<T>(&self, f: impl FnOnce(&mut LSMTree<K, V>) -> Result<T>) -> Result<T> {
+        let mut tree = self.tree.write().unwrap_or_else(PoisonError::into_inner);
+        let result = f(&mut tree);
+        let sealed = tree.immutable.is_some();
+        let compact = tree.needs_compaction();
+        drop(tree);
+        if sealed {
+            self.workers.flusher.wake();
+        }
+        if let Some(compactor) = self.workers.compactor.as_ref().filter(|_| compact) {
+            compactor.wake();
+        }
+        result
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Result<()> {
+        self.write(|tree| tree.insert(key, value))
+    }
+
+    pub fn delete(&self, key: K) -> Result<()> {
+        self.write(|tree| tree.delete(key))
+    }
+
+    pub fn delete_range(&self, start: K, end: K) -> Result<()> {
+        self.write(|tree| tree.delete_range(start, end))
+    }
+
+    /// Applies all writes of `batch` or none of them, see `LSMTree::commit`
+    pub fn commit(&self, batch: WriteBatch<K, V>) -> Result<()> {
+        self.write(|tree| tree.commit(batch))
+    }
+
+    /// Applies `f` to the current value of `key`, see `LSMTree::update_with`. The write lock is
+    /// held throughout, so concurrent updates of the same key are not lost.
+    pub fn update_with<F: FnOnce(Option<V>) -> Option<V>>(&self, key: K, f: F) -> Result<()> {
+        self.write(|tree| tree.update_with(key, f))
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        let tree = self.tree.read().unwrap_or_else(PoisonError::into_inner);
+        tree.counters.gets.add(1);
+        let now = tree.config.clock.now_millis();
+        match tree.memtable_entry(key) {
+            // Operands in the memtable are applied onto the older versions under the lock
+            Some(entry) if entry.is_merge() => {
+                let entry = tree.lookup(key)?;
+                return Ok(entry.and_then(|entry| entry.into_live_value(now)).map(Arc::unwrap_or_clone));
+            }
+            Some(entry) => return Ok(entry.live_value(now).map(|value| V::clone(value))),
+            None => {}
+        }
+        let sstables = tree.sstables.clone();
+        let operator = tree.merge_operator.clone();
+        drop(tree);
+
+        let entry = crate::lookup_entry(&[], &sstables, key, operator.as_deref())?;
+        Ok(entry.and_then(|entry| entry.into_live_value(now)).map(Arc::unwrap_or_clone))
+    }
+
+    /// Writes a backup of the tree to `out`, see `LSMTree::backup`. The write lock is only held
+    /// to flush the memtable; the archive is written while other threads keep using the tree.
+    pub fn backup(&self, out: &Path) -> Result<()> {
+        let checkpoint = self.write(|tree| tree.checkpoint())?;
+        checkpoint.write_archive(out)
+    }
+
+    /// Opens a tree restored from a backup, see `LSMTree::restore`
+    pub fn restore(archive: &Path, config: Config<K>) -> Result<Self> {
+        Self::from_tree(LSMTree::restore(archive, config)?)
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.tree.read().unwrap_or_else(PoisonError::into_inner).stats()
+    }
+
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.tree.read().unwrap_or_else(PoisonError::into_inner).compaction_stats()
+    }
+
+    /// Checks every SSTable for corruption, see `LSMTree::verify`. The tables are read
+    /// without holding the lock.
+    pub fn verify(&self) -> Result<VerifyReport> {
+        let sstables = self.tree.read().unwrap_or_else(PoisonError::into_inner).sstables.clone();
+        Ok(VerifyReport {
+            tables: sstables.iter().map(|sstable| sstable.verify()).collect(),
+        })
+    }
+
+    /// Captures a point-in-time view that can be read without holding any lock
+    pub fn snapshot(&self) -> Snapshot<K, V> {
+        self.tree.read().unwrap_or_else(PoisonError::into_inner).snapshot()
+    }
+
+    /// Runs a compaction, after the one in progress in the background if any
+    pub fn compact(&self) -> Result<()> {
+        let _compacting = self.compacting.lock().unwrap_or_else(PoisonError::into_inner);
+        self.tree.write().unwrap_or_else(PoisonError::into_inner).compact()
+    }
+
+    /// Runs one step of compaction if there is one to run, see `LSMTree::maybe_compact`
+    pub fn maybe_compact(&self) -> Result<bool> {
+        let _compacting = self.compacting.lock().unwrap_or_else(PoisonError::into_inner);
+        self.tree.write().unwrap_or_else(PoisonError::into_inner).maybe_compact()
+    }
+
+    /// Rewrites all SSTables without the entries `keep` rejects, see `LSMTree::compact_with_filter`
+    pub fn compact_with_filter<F: Fn(&K, &V) -> bool>(&self, keep: F) -> Result<()> {
+        let _compacting = self.compacting.lock().unwrap_or_else(PoisonError::into_inner);
+        self.tree.write().unwrap_or_else(PoisonError::into_inner).compact_with_filter(keep)
+    }
+
+    /// Rewrites all SSTables with the values `f` returns, see `LSMTree::rewrite_with`
+    pub fn rewrite_with<F: Fn(&K, V) -> Option<V>>(&self, f: F) -> Result<()> {
+        let _compacting = self.compacting.lock().unwrap_or_else(PoisonError::into_inner);
+        self.tree.write().unwrap_or_else(PoisonError::into_inner).rewrite_with(f)
+    }
+
+    /// Removes every entry, see `LSMTree::clear`
+    pub fn clear(&self) -> Result<()> {
+        self.tree.write().unwrap_or_else(PoisonError::into_inner).clear()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use tempfile::TempDir;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    /// The handle, and what it shares between threads, can be used from any thread
+    #[test]
+    fn test_send_sync() {
+        assert_send_sync::<ConcurrentLSMTree<String, String>>();
+        assert_send_sync::<LSMTree<String, String>>();
+        assert_send_sync::<Snapshot<String, String>>();
+    }
+
+    fn setup() -> (ConcurrentLSMTree<u32, u64>, TempDir) {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            memtable_size_threshold: 512,
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: Some(3),
+            ..Config::default()
+        };
+        (ConcurrentLSMTree::with_config(config).unwrap(), temp_dir)
+    }
+
+    #[test]
+    fn test_concurrent_basic() -> Result<()> {
+        let (lsm, _temp_dir) = setup();
+
+        lsm.insert(1, 10)?;
+        let handle = lsm.clone();
+        thread::spawn(move || handle.insert(2, 20)).join().unwrap()?;
+        lsm.delete(1)?;
+
+        assert_eq!(lsm.get(&1)?, None);
+        assert_eq!(lsm.get(&2)?, Some(20));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_update_with() -> Result<()> {
+        let (lsm, _temp_dir) = setup();
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let lsm = lsm.clone();
+                thread::spawn(move || -> Result<()> {
+                    for _ in 0..100 {
+                        lsm.update_with(0, |count| Some(count.unwrap_or(0) + 1))?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap()?;
+        }
+        assert_eq!(lsm.get(&0)?, Some(400));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_background_flush() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            wal_enabled: false,
+            flush_interval: Some(Duration::from_millis(10)),
+            ..Config::default()
+        };
+        let lsm = ConcurrentLSMTree::<u32, u64>::with_config(config.clone())?;
+
+        lsm.insert(1, 10)?;
+        for _ in 0..500 {
+            if lsm.stats().num_sstables > 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(lsm.stats().num_sstables, 1);
+
+        // Dropping the last handle flushes what the timer hasn't yet
+        lsm.insert(2, 20)?;
+        let handle = lsm.clone();
+        drop(lsm);
+        assert_eq!(handle.get(&2)?, Some(20));
+        drop(handle);
+
+        let reopened = ConcurrentLSMTree::<u32, u64>::with_config(Config { flush_interval: None, ..config })?;
+        assert_eq!(reopened.get(&1)?, Some(10));
+        assert_eq!(reopened.get(&2)?, Some(20));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_in_background() -> Result<()> {
+        let (lsm, temp_dir) = setup();
+
+        // Filling the memtable seals it; the writer returns without writing it out
+        for i in 0..200 {
+            lsm.insert(i, u64::from(i))?;
+        }
+        for _ in 0..500 {
+            let tree = lsm.tree.read().unwrap();
+            if tree.immutable.is_none() && !tree.sstables.is_empty() {
+                break;
+            }
+            drop(tree);
+            thread::sleep(Duration::from_millis(10));
+        }
+        let stats = lsm.stats();
+        assert!(stats.num_sstables > 0);
+        assert!(stats.total_flushes > 0);
+        assert!(!temp_dir.path().join("wal.sealed.log").exists());
+        for i in 0..200 {
+            assert_eq!(lsm.get(&i)?, Some(u64::from(i)));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_in_background() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            memtable_size_threshold: 512,
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: Some(2),
+            background_compaction: true,
+            ..Config::default()
+        };
+        let lsm = ConcurrentLSMTree::<u32, u64>::with_config(config.clone())?;
+
+        for round in 0..5 {
+            for i in 0..100 {
+                lsm.insert(i, round)?;
+            }
+        }
+        for _ in 0..500 {
+            if lsm.stats().total_compactions > 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(lsm.stats().total_compactions > 0);
+        for i in 0..100 {
+            assert_eq!(lsm.get(&i)?, Some(4));
+        }
+
+        // Dropping the last handle waits for a compaction in progress, leaving a consistent tree
+        lsm.compact()?;
+        drop(lsm);
+        let reopened = ConcurrentLSMTree::<u32, u64>::with_config(config)?;
+        for i in 0..100 {
+            assert_eq!(reopened.get(&i)?, Some(4));
+        }
+        assert!(reopened.verify()?.is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_readers_and_writers() -> Result<()> {
+        const KEYS: u32 = 50;
+        const ROUNDS: u64 = 40;
+        let (lsm, _temp_dir) = setup();
+
+        // Each writer owns a disjoint set of keys and writes increasing values to them
+        let writers: Vec<_> = (0..2u32)
+            .map(|writer| {
+                let lsm = lsm.clone();
+                thread::spawn(move || -> Result<()> {
+                    for round in 1..=ROUNDS {
+                        for key in (writer..KEYS).step_by(2) {
+                            lsm.insert(key, round)?;
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+
+        // Readers check that every key's value only ever moves forward
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let lsm = lsm.clone();
+                thread::spawn(move || -> Result<()> {
+                    let mut last_seen = vec![0; KEYS as usize];
+                    for _ in 0..ROUNDS {
+                        for key in 0..KEYS {
+                            let value = lsm.get(&key)?.unwrap_or(0);
+                            assert!(value >= last_seen[key as usize], "key {} went backwards", key);
+                            last_seen[key as usize] = value;
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+
+        for handle in writers.into_iter().chain(readers) {
+            handle.join().unwrap()?;
+        }
+
+        for key in 0..KEYS {
+            assert_eq!(lsm.get(&key)?, Some(ROUNDS));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,257 @@
+//! Serialization of keys and values.
+//!
+//! Keys and values are encoded with bincode by default. Integers are then fixed-width and
+//! little-endian whatever the platform, which is what `bincode::serialize` produces. An
+//! `Encoding` can switch to variable-length integers, which shrink small numbers, or to
+//! big-endian integers. It can also select MessagePack, a self-describing format that
+//! generic tools can decode, for tables that need to be inspected. The encoding is recorded in
+//! each SSTable's header, so tables written with different settings can be read side by side.
+//! The manifest and the write-ahead log are private to the tree and always use the default
+//! encoding.
+
+use crate::Result;
+use bincode::Options;
+
+/// How integers are encoded
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IntEncoding {
+    /// Integers take their full width
+    #[default]
+    Fixint,
+    /// Small integers take fewer bytes; lengths of strings and collections are integers too
+    Varint,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+/// Serialization format of keys and values
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Bincode,
+    /// MessagePack through `rmp-serde`; structs are encoded as arrays. `int_encoding` and
+    /// `endianness` don't apply.
+    MessagePack,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Encoding {
+    pub format: Format,
+    pub int_encoding: IntEncoding,
+    pub endianness: Endianness,
+}
+
+/// Counts the bytes written to it
+struct ByteCounter(u64);
+
+impl std::io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Evaluates `$body` with `$options` bound to the bincode options for `$encoding`. Each
+/// combination of settings is a different type, hence the macro.
+macro_rules! with_options {
+    ($encoding:expr, |$options:ident| $body:expr) => {{
+        let options = bincode::DefaultOptions::new().allow_trailing_bytes();
+        match ($encoding.int_encoding, $encoding.endianness) {
+            (IntEncoding::Fixint, Endianness::Little) => {
+                let $options = options.with_fixint_encoding().with_little_endian();
+                $body
+            }
+            (IntEncoding::Fixint, Endianness::Big) => {
+                let $options = options.with_fixint_encoding().with_big_endian();
+                $body
+            }
+            (IntEncoding::Varint, Endianness::Little) => {
+                let $options = options.with_varint_encoding().with_little_endian();
+                $body
+            }
+            (IntEncoding::Varint, Endianness::Big) => {
+                let $options = options.with_varint_encoding().with_big_endian();
+                $body
+            }
+        }
+    }};
+}
+
+impl Encoding {
+    pub(crate) fn id(self) -> u8 {
+        let format = match self.format {
+            Format::Bincode => 0,
+            Format::MessagePack => 4,
+        };
+        let int_encoding = match self.int_encoding {
+            IntEncoding::Fixint => 0,
+            IntEncoding::Varint => 1,
+        };
+        let endianness = match self.endianness {
+            Endianness::Little => 0,
+            Endianness::Big => 2,
+        };
+        format | int_encoding | endianness
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        let format = match id & 4 {
+            0 => Format::Bincode,
+            _ => Format::MessagePack,
+        };
+        let int_encoding = match id & 1 {
+            0 => IntEncoding::Fixint,
+            _ => IntEncoding::Varint,
+        };
+        let endianness = match id & 2 {
+            0 => Endianness::Little,
+            _ => Endianness::Big,
+        };
+        (id < 8).then_some(Self { format, int_encoding, endianness })
+    }
+
+    pub fn serialize<T: serde::Serialize + ?Sized>(self, value: &T) -> Result<Vec<u8>> {
+        match self.format {
+            Format::Bincode => Ok(with_options!(self, |options| options.serialize(value))?),
+            Format::MessagePack => Ok(rmp_serde::to_vec(value)?),
+        }
+    }
+
+    /// Exact number of bytes `serialize` produces for `value`
+    pub fn serialized_size<T: serde::Serialize + ?Sized>(self, value: &T) -> Result<u64> {
+        match self.format {
+            Format::Bincode => Ok(with_options!(self, |options| options.serialized_size(value))?),
+            Format::MessagePack => {
+                let mut counter = ByteCounter(0);
+                rmp_serde::encode::write(&mut counter, value)?;
+                Ok(counter.0)
+            }
+        }
+    }
+
+    pub fn deserialize<'a, T: serde::Deserialize<'a>>(self, bytes: &'a [u8]) -> Result<T> {
+        match self.format {
+            Format::Bincode => Ok(with_options!(self, |options| options.deserialize(bytes))?),
+            Format::MessagePack => Ok(rmp_serde::from_slice(bytes)?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::NaturalOrder;
+    use crate::memtable::MemTable;
+    use crate::{Config, LSMTree};
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    const fn bincode(int_encoding: IntEncoding, endianness: Endianness) -> Encoding {
+        Encoding { format: Format::Bincode, int_encoding, endianness }
+    }
+
+    const MESSAGE_PACK: Encoding =
+        Encoding { format: Format::MessagePack, int_encoding: IntEncoding::Fixint, endianness: Endianness::Little };
+
+    const ALL: [Encoding; 5] = [
+        bincode(IntEncoding::Fixint, Endianness::Little),
+        bincode(IntEncoding::Fixint, Endianness::Big),
+        bincode(IntEncoding::Varint, Endianness::Little),
+        bincode(IntEncoding::Varint, Endianness::Big),
+        MESSAGE_PACK,
+    ];
+
+    #[test]
+    fn test_encoding_round_trip() -> Result<()> {
+        let value = (7u32, -3i64, "text".to_string(), vec![1u16, 300]);
+        for encoding in ALL {
+            assert_eq!(Encoding::from_id(encoding.id()), Some(encoding));
+
+            let bytes = encoding.serialize(&value)?;
+            assert_eq!(encoding.serialized_size(&value)?, bytes.len() as u64);
+            assert_eq!(encoding.deserialize::<(u32, i64, String, Vec<u16>)>(&bytes)?, value);
+        }
+        assert_eq!(Encoding::from_id(8), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_matches_bincode() -> Result<()> {
+        let value = (1u64, "key".to_string());
+        assert_eq!(Encoding::default().serialize(&value)?, bincode::serialize(&value)?);
+
+        let varint = Encoding { int_encoding: IntEncoding::Varint, ..Encoding::default() };
+        assert_eq!(varint.serialize(&value)?.len(), 5);
+        let big_endian = Encoding { endianness: Endianness::Big, ..Encoding::default() };
+        assert_eq!(big_endian.serialize(&1u32)?, vec![0, 0, 0, 1]);
+        // A fixarray of a positive fixint and a fixstr
+        assert_eq!(MESSAGE_PACK.serialize(&value)?, vec![0x92, 0x01, 0xa3, b'k', b'e', b'y']);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memtable_size_matches_encoding() -> Result<()> {
+        for encoding in ALL {
+            let mut memtable = MemTable::with_comparator_and_encoding(Arc::new(NaturalOrder), encoding);
+            memtable.put(1u64, "value".to_string())?;
+            memtable.put_with_expiry(2u64, "expiring".to_string(), 1000)?;
+            memtable.put_with_expiry(2u64, "overwritten".to_string(), 1000)?;
+
+            let expected = encoding.serialized_size(&1u64)?
+                + encoding.serialized_size("value")?
+                + encoding.serialized_size(&2u64)?
+                + encoding.serialized_size("overwritten")?
+                + encoding.serialized_size(&1000u64)?;
+            assert_eq!(memtable.size() as u64, expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tree_with_encoding() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = |encoding| Config {
+            memtable_size_threshold: 512,
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            encoding,
+            ..Config::default()
+        };
+
+        let mut lsm = LSMTree::with_config(config(MESSAGE_PACK))?;
+        for i in 0..100u32 {
+            lsm.insert(i, format!("value{}", i))?;
+        }
+        lsm.delete(5)?;
+        drop(lsm);
+
+        // Tables written in another encoding stay readable, and compaction rewrites them
+        let mut lsm = LSMTree::<u32, String>::with_config(config(Encoding::default()))?;
+        for i in 100..200u32 {
+            lsm.insert(i, format!("value{}", i))?;
+        }
+        lsm.flush()?;
+        assert!(lsm.sstables.iter().any(|sstable| sstable.encoding() == MESSAGE_PACK));
+        for round in 0..2 {
+            assert_eq!(lsm.get(&7)?, Some("value7".to_string()), "round {}", round);
+            assert_eq!(lsm.get(&5)?, None, "round {}", round);
+            assert_eq!(lsm.get(&150)?, Some("value150".to_string()), "round {}", round);
+            assert_eq!(lsm.iter()?.count(), 199, "round {}", round);
+            lsm.compact()?;
+        }
+
+        Ok(())
+    }
+}
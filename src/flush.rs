@@ -0,0 +1,128 @@
+//! Policies deciding when writes flush the memtable.
+//!
+//! After every write the tree asks its `FlushPolicy` whether to flush, passing the memtable's
+//! size and entry count and the time since it was started, as measured by the configured
+//! clock. Without a policy the memtable is flushed once it reaches `memtable_size_threshold`.
+//! Policies are only consulted on writes: an idle tree never flushes because of its age
+//! (`ConcurrentLSMTree` can flush on a timer instead, see `Config::flush_interval`).
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// What a policy knows about the memtable
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemTableUsage {
+    /// Serialized size of the entries in bytes
+    pub size: usize,
+    /// Number of entries, tombstones included
+    pub len: usize,
+}
+
+pub trait FlushPolicy: Debug + Send + Sync {
+    /// Whether the memtable, started `age` ago, should be flushed now
+    fn should_flush(&self, memtable: &MemTableUsage, age: Duration) -> bool;
+}
+
+/// Flushes once the memtable holds this many bytes
+#[derive(Clone, Copy, Debug)]
+pub struct SizeThreshold(pub usize);
+
+impl FlushPolicy for SizeThreshold {
+    fn should_flush(&self, memtable: &MemTableUsage, _age: Duration) -> bool {
+        memtable.size >= self.0
+    }
+}
+
+/// Flushes once the memtable holds this many entries
+#[derive(Clone, Copy, Debug)]
+pub struct EntryCount(pub usize);
+
+impl FlushPolicy for EntryCount {
+    fn should_flush(&self, memtable: &MemTableUsage, _age: Duration) -> bool {
+        memtable.len >= self.0
+    }
+}
+
+/// Flushes a non-empty memtable once it is this old
+#[derive(Clone, Copy, Debug)]
+pub struct MaxAge(pub Duration);
+
+impl FlushPolicy for MaxAge {
+    fn should_flush(&self, memtable: &MemTableUsage, age: Duration) -> bool {
+        memtable.len > 0 && age >= self.0
+    }
+}
+
+/// Flushes when any of the policies says so
+#[derive(Clone, Debug)]
+pub struct AnyOf(pub Vec<Arc<dyn FlushPolicy>>);
+
+impl FlushPolicy for AnyOf {
+    fn should_flush(&self, memtable: &MemTableUsage, age: Duration) -> bool {
+        self.0.iter().any(|policy| policy.should_flush(memtable, age))
+    }
+}
+
+/// Flushes when all of the policies say so
+#[derive(Clone, Debug)]
+pub struct AllOf(pub Vec<Arc<dyn FlushPolicy>>);
+
+impl FlushPolicy for AllOf {
+    fn should_flush(&self, memtable: &MemTableUsage, age: Duration) -> bool {
+        self.0.iter().all(|policy| policy.should_flush(memtable, age))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::{Config, LSMTree, Result};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_combined_policies() {
+        let usage = MemTableUsage { size: 4096, len: 10 };
+        let minute = Duration::from_secs(60);
+        let size_and_age = AllOf(vec![Arc::new(SizeThreshold(1024)), Arc::new(MaxAge(minute))]);
+        assert!(!size_and_age.should_flush(&usage, Duration::from_secs(59)));
+        assert!(size_and_age.should_flush(&usage, minute));
+
+        let count_or_age = AnyOf(vec![Arc::new(EntryCount(11)), Arc::new(MaxAge(minute))]);
+        assert!(!count_or_age.should_flush(&usage, Duration::ZERO));
+        assert!(count_or_age.should_flush(&MemTableUsage { len: 11, ..usage }, Duration::ZERO));
+        assert!(!count_or_age.should_flush(&MemTableUsage { size: 0, len: 0 }, minute));
+    }
+
+    #[test]
+    fn test_tree_flush_policy() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let clock = Arc::new(ManualClock::new(0));
+        let mut lsm = LSMTree::with_config(Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            flush_policy: Some(Arc::new(AnyOf(vec![
+                Arc::new(EntryCount(10)),
+                Arc::new(MaxAge(Duration::from_secs(60))),
+            ]))),
+            clock: clock.clone(),
+            ..Config::default()
+        })?;
+
+        for i in 0..25 {
+            lsm.insert(i, i)?;
+        }
+        assert_eq!(lsm.stats().total_flushes, 2);
+
+        // The age counts from the last flush, and is checked on the next write
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(lsm.stats().total_flushes, 2);
+        lsm.delete(0)?;
+        assert_eq!(lsm.stats().total_flushes, 3);
+        lsm.insert(0, 0)?;
+        assert_eq!(lsm.stats().total_flushes, 3);
+
+        Ok(())
+    }
+}
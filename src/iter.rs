@@ -0,0 +1,283 @@
+//! K-way merge over sorted key-value streams.
+//!
+//! Sources are ordered by priority: the first source wins, and when the same key appears in
+//! several sources only the value from the highest-priority one is emitted, unless the merge
+//! is given a function to combine the values with (see `MergeIterator::combining`). Values
+//! that carry a sequence number can be ordered by it instead, priority only breaking ties (see
+//! `MergeIterator::by_sequence`). The tree merges its memtable and SSTables (newest first) this
+//! way for scans and compaction. Keys are compared with a `Comparator`, and every source must
+//! be sorted by it.
+
+use crate::comparator::Comparator;
+use crate::Result;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+
+struct HeapItem<K, V> {
+    key: K,
+    value: V,
+    sequence: u64,
+    source: usize,
+    comparator: Arc<dyn Comparator<K>>,
+    descending: bool,
+}
+
+impl<K, V> PartialEq for HeapItem<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K, V> Eq for HeapItem<K, V> {}
+
+impl<K, V> PartialOrd for HeapItem<K, V> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K, V> Ord for HeapItem<K, V> {
+    // `BinaryHeap` is a max-heap, so invert the order to pop the smallest key first (the
+    // largest when descending), breaking ties in favour of the highest sequence number, then
+    // of the highest-priority (lowest-numbered) source
+    fn cmp(&self, other: &Self) -> Ordering {
+        let keys = self.comparator.compare(&other.key, &self.key);
+        let keys = if self.descending { keys.reverse() } else { keys };
+        keys.then_with(|| self.sequence.cmp(&other.sequence))
+            .then_with(|| other.source.cmp(&self.source))
+    }
+}
+
+/// Yields each key of its sources once, in order, with the value from the first source that
+/// has it. Sources yield `Result`s so reads can fail; wrap infallible ones with `.map(Ok)`.
+/// The merge stops after passing on the first error.
+pub struct MergeIterator<K, V, I, C = fn(V, V) -> Result<V>> {
+    sources: Vec<I>,
+    heap: BinaryHeap<HeapItem<K, V>>,
+    comparator: Arc<dyn Comparator<K>>,
+    descending: bool,
+    failed: bool,
+    /// Called with the value emitted so far and the next one of the same key, by priority
+    /// (or sequence number)
+    combine: C,
+    /// The sequence number of a value, 0 for all unless ordered `by_sequence`
+    sequence: fn(&V) -> u64,
+}
+
+fn keep_first<V>(first: V, _: V) -> Result<V> {
+    Ok(first)
+}
+
+fn no_sequence<V>(_: &V) -> u64 {
+    0
+}
+
+impl<K, V, I> MergeIterator<K, V, I>
+where
+    I: Iterator<Item = Result<(K, V)>>,
+{
+    /// Creates a merge over `sources`, highest priority first. Reads the first item of each
+    /// source, failing if one of them is an error.
+    pub fn new(sources: Vec<I>, comparator: Arc<dyn Comparator<K>>) -> Result<Self> {
+        Self::with_direction(sources, comparator, false)
+    }
+
+    /// Like `new`, for sources sorted in descending order; keys are yielded largest first
+    pub fn descending(sources: Vec<I>, comparator: Arc<dyn Comparator<K>>) -> Result<Self> {
+        Self::with_direction(sources, comparator, true)
+    }
+
+    fn with_direction(sources: Vec<I>, comparator: Arc<dyn Comparator<K>>, descending: bool) -> Result<Self> {
+        let mut merge = Self {
+            sources,
+            heap: BinaryHeap::new(),
+            comparator,
+            descending,
+            failed: false,
+            combine: keep_first,
+            sequence: no_sequence,
+        };
+        for source in 0..merge.sources.len() {
+            merge.advance(source)?;
+        }
+        Ok(merge)
+    }
+
+    /// Makes the merge combine the values of a key instead of keeping the first one:
+    /// `combine(value, next)` is called for each further source that has the key, by
+    /// priority, with the value combined so far. An error ends the merge.
+    pub fn combining<C: FnMut(V, V) -> Result<V>>(self, combine: C) -> MergeIterator<K, V, I, C> {
+        MergeIterator {
+            sources: self.sources,
+            heap: self.heap,
+            comparator: self.comparator,
+            descending: self.descending,
+            failed: self.failed,
+            combine,
+            sequence: self.sequence,
+        }
+    }
+}
+
+impl<K, V, I, C> MergeIterator<K, V, I, C>
+where
+    I: Iterator<Item = Result<(K, V)>>,
+{
+    /// Makes the versions of a key come out by their `sequence` number, highest first, so
+    /// that the newest version wins whichever source it is in. Source priority only breaks
+    /// ties between equal numbers.
+    pub fn by_sequence(mut self, sequence: fn(&V) -> u64) -> Self {
+        self.sequence = sequence;
+        let items = std::mem::take(&mut self.heap).into_iter();
+        self.heap = items.map(|item| HeapItem { sequence: sequence(&item.value), ..item }).collect();
+        self
+    }
+
+    fn advance(&mut self, source: usize) -> Result<()> {
+        if let Some(item) = self.sources[source].next() {
+            let (key, value) = item?;
+            let sequence = (self.sequence)(&value);
+            let comparator = Arc::clone(&self.comparator);
+            self.heap.push(HeapItem { key, value, sequence, source, comparator, descending: self.descending });
+        }
+        Ok(())
+    }
+}
+
+impl<K, V, I, C> Iterator for MergeIterator<K, V, I, C>
+where
+    I: Iterator<Item = Result<(K, V)>>,
+    C: FnMut(V, V) -> Result<V>,
+{
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let HeapItem { key, mut value, source, .. } = self.heap.pop()?;
+        let mut result = self.advance(source);
+
+        // Drop or combine the shadowed versions of the same key from lower-priority sources
+        while result.is_ok()
+            && self
+                .heap
+                .peek()
+                .is_some_and(|item| self.comparator.compare(&item.key, &key).is_eq())
+        {
+            let shadowed = self.heap.pop().unwrap();
+            result = self.advance(shadowed.source);
+            if result.is_ok() {
+                match (self.combine)(value, shadowed.value) {
+                    Ok(combined) => value = combined,
+                    Err(e) => {
+                        self.failed = true;
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+
+        if let Err(e) = result {
+            self.failed = true;
+            return Some(Err(e));
+        }
+        Some(Ok((key, value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::NaturalOrder;
+    use crate::LSMError;
+
+    fn merge(sources: Vec<Vec<(u32, &'static str)>>) -> Result<Vec<(u32, &'static str)>> {
+        let sources = sources.into_iter().map(|source| source.into_iter().map(Ok)).collect();
+        MergeIterator::new(sources, Arc::new(NaturalOrder))?.collect()
+    }
+
+    #[test]
+    fn test_merge_prefers_first_source() -> Result<()> {
+        let merged = merge(vec![
+            vec![(2, "a2"), (5, "a5")],
+            vec![(1, "b1"), (2, "b2"), (3, "b3")],
+            vec![(2, "c2"), (3, "c3"), (5, "c5"), (8, "c8")],
+        ])?;
+        assert_eq!(merged, vec![(1, "b1"), (2, "a2"), (3, "b3"), (5, "a5"), (8, "c8")]);
+
+        assert_eq!(merge(vec![])?, vec![]);
+        assert_eq!(merge(vec![vec![], vec![(1, "b1")], vec![]])?, vec![(1, "b1")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_combining() -> Result<()> {
+        let sources = vec![vec![(1, 1), (2, 20)], vec![(2, 300), (3, 3)], vec![(2, 4000)]];
+        let sources = sources.into_iter().map(|source| source.into_iter().map(Ok)).collect();
+        let mut calls = Vec::new();
+        let merged: Vec<_> = MergeIterator::new(sources, Arc::new(NaturalOrder))?
+            .combining(|value: u32, next| {
+                calls.push((value, next));
+                Ok(value + next)
+            })
+            .collect::<Result<_>>()?;
+        assert_eq!(merged, vec![(1, 1), (2, 4320), (3, 3)]);
+        assert_eq!(calls, vec![(20, 300), (320, 4000)]);
+
+        Ok(())
+    }
+
+    #[derive(Debug)]
+    struct Reverse;
+
+    impl Comparator<u32> for Reverse {
+        fn compare(&self, a: &u32, b: &u32) -> Ordering {
+            b.cmp(a)
+        }
+
+        fn name(&self) -> &str {
+            "reverse"
+        }
+    }
+
+    #[test]
+    fn test_merge_by_sequence() -> Result<()> {
+        let sources = vec![vec![(1, (1, 'a')), (2, (5, 'a'))], vec![(1, (3, 'b')), (2, (5, 'b')), (3, (0, 'b'))]];
+        let sources = sources.into_iter().map(|source| source.into_iter().map(Ok)).collect();
+        let merged: Vec<_> = MergeIterator::new(sources, Arc::new(NaturalOrder))?
+            .by_sequence(|&(sequence, _)| sequence)
+            .collect::<Result<_>>()?;
+        // The highest number wins, and priority breaks the tie
+        assert_eq!(merged, vec![(1, (3, 'b')), (2, (5, 'a')), (3, (0, 'b'))]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_with_comparator() -> Result<()> {
+        let reverse: Arc<dyn Comparator<u32>> = Arc::new(Reverse);
+        let sources = vec![vec![(9, 'a'), (4, 'a')], vec![(9, 'b'), (7, 'b'), (1, 'b')]];
+        let sources = sources.into_iter().map(|source| source.into_iter().map(Ok)).collect();
+        let merged: Vec<_> = MergeIterator::new(sources, reverse)?.collect::<Result<_>>()?;
+        assert_eq!(merged, vec![(9, 'a'), (7, 'b'), (4, 'a'), (1, 'b')]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_stops_at_error() -> Result<()> {
+        let failing = vec![Ok((1, "a1")), Err(LSMError::KeyNotFound), Ok((3, "a3"))];
+        let sources = vec![failing.into_iter(), vec![Ok((2, "b2")), Ok((4, "b4"))].into_iter()];
+        let mut merged = MergeIterator::new(sources, Arc::new(NaturalOrder))?;
+
+        // The error surfaces when the failing source is advanced past its first key
+        assert!(matches!(merged.next(), Some(Err(LSMError::KeyNotFound))));
+        assert!(merged.next().is_none());
+
+        Ok(())
+    }
+}
@@ -2,12 +2,62 @@
 //! Provides a persistent key-value store with efficient write operations
 //! by batching writes in memory before flushing to disk.
 
+#[cfg(feature = "tokio")]
+pub mod asynchronous;
+mod backup;
+pub mod batch;
+pub mod bloom;
+mod cache;
+pub mod clock;
+mod compaction;
+pub mod comparator;
+pub mod compression;
+pub mod concurrent;
+pub mod encoding;
+pub mod flush;
+pub mod iter;
+mod manifest;
 pub mod memtable;
+pub mod merge;
+pub mod ordered;
+pub mod range_tombstone;
+mod scan;
+pub mod sequence;
+mod sharded;
+#[cfg(feature = "crossbeam-skiplist")]
+pub mod skiplist;
+pub mod snapshot;
 pub mod sstable;
+pub mod stats;
+pub mod verify;
+pub mod wal;
 
+use std::borrow::Borrow;
+use std::num::NonZeroUsize;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
-use crate::memtable::MemTable;
-use crate::sstable::SSTable;
+pub use crate::compaction::{CompactionPolicy, CompactionStrategy};
+pub use crate::scan::{CollectOk, PrefixSuccessor};
+use crate::batch::WriteBatch;
+use crate::bloom::BloomHasher;
+use crate::cache::{BlockCache, FilePool};
+use crate::clock::{Clock, SystemClock};
+use crate::comparator::{Comparator, NaturalOrder};
+use crate::compression::Compression;
+use crate::encoding::Encoding;
+use crate::flush::{FlushPolicy, MemTableUsage, SizeThreshold};
+use crate::manifest::{Manifest, ManifestEntry, MANIFEST_FILE};
+use crate::memtable::{DynMemTable, MemTable, MemTableKind};
+use crate::memtable::Entry;
+use crate::merge::MergeOperator;
+use crate::range_tombstone::RangeTombstone;
+use crate::sequence::{Sequenced, Versions};
+use crate::sstable::{SSTable, SSTableOptions, SSTableWriter, ValueReader};
+use crate::stats::{Counters, Stats};
+use crate::wal::Wal;
 
 #[derive(Error, Debug)]
 pub enum LSMError {
@@ -15,206 +65,2992 @@ pub enum LSMError {
     IO(#[from] std::io::Error),
     #[error("Serialization error: {0}")]
     Serialization(#[from] bincode::Error),
+    #[error("MessagePack serialization error: {0}")]
+    MessagePackEncode(#[from] rmp_serde::encode::Error),
+    #[error("MessagePack deserialization error: {0}")]
+    MessagePackDecode(#[from] rmp_serde::decode::Error),
     #[error("Key not found")]
     KeyNotFound,
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+    #[error("Corrupted record in {path} at offset {offset}")]
+    Corruption { path: String, offset: u64 },
+    #[error("{path} is not a valid SSTable: {reason}")]
+    InvalidFormat { path: String, reason: String },
+    #[error("{path} is not a valid backup: {reason}")]
+    InvalidBackup { path: String, reason: String },
+    #[error("Keys must be added to an SSTable in strictly increasing order")]
+    UnsortedKeys,
+    #[error("Compression {0:?} is not supported by this build")]
+    UnsupportedCompression(Compression),
+    #[error("Value of {size} bytes exceeds the limit of {max} bytes")]
+    ValueTooLarge { size: u64, max: usize },
+    #[error("SSTable {path} already exists")]
+    SSTableExists { path: String },
+    #[error("The tree was opened read-only")]
+    ReadOnly,
+    #[error("Data was written with schema {found:?}, but {expected:?} is configured")]
+    SchemaMismatch { expected: String, found: String },
+    #[error("Merge operands can't be applied without a merge operator")]
+    NoMergeOperator,
+    #[error("Directory {path} is not writable: {source}")]
+    NotWritable { path: String, source: std::io::Error },
 }
 
 pub type Result<T> = std::result::Result<T, LSMError>;
 
 /// Configuration options for LSMTree
 #[derive(Clone, Debug)]
-pub struct Config {
-    /// Maximum size of memtable in bytes before flushing to disk
+pub struct Config<K> {
+    /// Maximum size of memtable in bytes before flushing to disk, unless `flush_policy` is set
     pub memtable_size_threshold: usize,
-    /// Directory where SSTable files will be stored
-    pub data_dir: String,
+    /// Decides after each write whether to flush the memtable; `None` flushes once it
+    /// reaches `memtable_size_threshold`. See the `flush` module.
+    pub flush_policy: Option<Arc<dyn FlushPolicy>>,
+    /// Which memtable recent writes are kept in: a `BTreeMap` by default, or a concurrent skip
+    /// list with the `crossbeam-skiplist` feature. See the `memtable` and `skiplist` modules.
+    pub memtable: MemTableKind,
+    /// Number of shards the memtable is split into by key hash, each behind a lock of its own;
+    /// 1, the default, keeps it whole. Lookups consult every shard, and each flush writes every
+    /// shard to level 0 tables of its own, plus one for the range tombstones. Only the BTree
+    /// memtable can be sharded. Keys are hashed in `encoding`, so keys that `comparator`
+    /// considers equal must serialize alike for merges to see each other's operands.
+    pub memtable_shards: usize,
+    /// Directory where SSTable files will be stored; anything convertible into a `PathBuf`,
+    /// e.g. `"data".into()`
+    pub data_dir: PathBuf,
+    /// Prefix for the names of the tree's files, so several trees can share `data_dir`: with
+    /// namespace `users`, tables are stored as `users_sstable_000001.db`. Empty by default,
+    /// which leaves the names unprefixed. May only contain ASCII letters, digits, `-` and `_`.
+    pub namespace: String,
+    /// Whether SSTables are stored in a subdirectory per level, `data_dir/L0/`, `data_dir/L1/`
+    /// and so on, instead of directly in `data_dir`. Tables are moved to match the setting
+    /// when the tree is opened, so it can be changed for an existing directory.
+    pub level_directories: bool,
+    /// Directory flushes and compactions write SSTables in before moving them into `data_dir`,
+    /// e.g. fast local scratch space when `data_dir` is on a slow volume. It is created if
+    /// missing, and must not be shared with another tree of the same namespace, since leftover
+    /// temporary files are deleted when the tree is opened. A directory on another filesystem
+    /// works, but each table is then copied into `data_dir` instead of renamed. `None` writes
+    /// tables in `data_dir` directly.
+    pub temp_dir: Option<PathBuf>,
+    /// Whether writes are logged to a write-ahead log so the memtable survives a crash
+    pub wal_enabled: bool,
+    /// Number of flushes after which a compaction is due; `None` disables it. Due compactions
+    /// run automatically unless `automatic_compaction` is off.
+    /// Leveled compaction only checks whether it is `None`, and otherwise starts once level 0
+    /// holds `l0_compaction_trigger` tables.
+    pub compaction_threshold: Option<usize>,
+    pub compaction_strategy: CompactionStrategy,
+    /// Which tables size-tiered compaction merges. Defaults to runs of similarly sized tables,
+    /// see `CompactionPolicy::SimilarSize`.
+    pub compaction_policy: CompactionPolicy,
+    /// Number of levels of leveled compaction, level 0 included: tables reaching the last
+    /// level stay there however large it grows. Must be at least 2; defaults to 7.
+    pub max_levels: u32,
+    /// Number of level 0 tables at which leveled compaction merges them into level 1. Must be
+    /// at least 1; defaults to 4.
+    pub l0_compaction_trigger: usize,
+    /// Factor by which the size budget of each level of leveled compaction grows over the one
+    /// before, in place of the strategy's `fanout`. Must be at least 2; common engines use 10.
+    /// `None` uses `fanout`.
+    pub level_size_multiplier: Option<usize>,
+    /// Whether due compactions run on their own after flushes. When off, nothing compacts
+    /// unless asked to, with `LSMTree::compact` or step by step with `LSMTree::maybe_compact`,
+    /// e.g. from a scheduler of the application's own. Defaults to on.
+    pub automatic_compaction: bool,
+    /// Whether `ConcurrentLSMTree` runs the compactions triggered by `compaction_threshold` on
+    /// a background thread, which merges the tables without holding the lock, so reads,
+    /// writes and flushes carry on meanwhile. A plain `LSMTree` always compacts in the flush
+    /// that reaches the threshold.
+    pub background_compaction: bool,
+    /// How often `ConcurrentLSMTree` flushes the memtable from a background thread, so that
+    /// writes become durable on a schedule and not only once the memtable is full. `None`
+    /// disables the thread; a plain `LSMTree` only flushes when asked or when full.
+    pub flush_interval: Option<Duration>,
+    /// Largest serialized value, in bytes, that inserts accept; larger values fail with
+    /// `ValueTooLarge`. Setting it below `memtable_size_threshold` keeps a single value from
+    /// filling the memtable and being flushed on its own. `None` accepts any size.
+    pub max_value_size: Option<usize>,
+    /// Largest size in bytes of an SSTable written by a flush: the memtable is split into
+    /// several tables with disjoint key ranges once one reaches it, which gives compaction
+    /// smaller units to work with. `None` writes each flush to a single table. Must be non-zero.
+    pub max_sstable_bytes: Option<usize>,
+    /// Number of SSTables above which a flush merges the oldest level 0 tables into one,
+    /// bounding how many tables a lookup may consult between compactions. `None` disables
+    /// these merges. Must be at least 1.
+    pub max_sstables_before_flush_merge: Option<usize>,
+    /// Bloom filter bits per key in each SSTable; more bits lower the false-positive
+    /// rate of lookups for missing keys. 0 disables bloom filters.
+    pub bloom_bits_per_key: usize,
+    /// Hash function bloom filters are built with, over the serialized keys. Tables keep
+    /// using the one they were written with, so changing it only affects new tables.
+    pub bloom_hasher: BloomHasher,
+    /// Maximum number of records per SSTable block; the first key of every block is kept in
+    /// the in-memory sparse index. Smaller intervals speed up lookups at the cost of a larger
+    /// index. Must be non-zero.
+    pub index_interval: u64,
+    /// Target uncompressed size of an SSTable block in bytes; a block is closed once it
+    /// reaches this size even if it holds fewer than `index_interval` records. Must be non-zero.
+    pub block_size: usize,
+    /// Capacity in bytes of the buffer SSTables are written through by flushes and compactions.
+    /// Larger buffers mean fewer, larger writes. Must be non-zero.
+    pub write_buffer_bytes: usize,
+    /// Capacity in bytes of the buffer scans and compactions read SSTables through; lookups
+    /// read whole blocks and don't use it. Larger buffers help long sequential scans. Must be
+    /// non-zero.
+    pub read_buffer_bytes: usize,
+    /// Capacity in bytes of the cache of decompressed SSTable blocks that lookups read
+    /// through, so hot keys don't go to disk. 0 disables the cache.
+    pub block_cache_bytes: usize,
+    /// Maximum number of SSTable files point lookups keep open. By default every table keeps
+    /// its own handle; on stores with many tables a limit avoids running out of file
+    /// descriptors, at the cost of reopening the least recently used tables. Scans and
+    /// compactions open files of their own and aren't limited. Must be non-zero.
+    pub max_open_files: Option<usize>,
+    /// Whether opening the tree reads every SSTable in full to compare it against the checksum
+    /// written with it, failing with `LSMError::Corruption` if one changed since. Slower to
+    /// open, but a damaged table is found at startup rather than by the read that hits it.
+    pub verify_on_open: bool,
+    /// Codec used to compress SSTable blocks. Lz4 and Zstd need the matching crate feature.
+    pub compression: Compression,
+    /// Time source for entries inserted with a TTL
+    pub clock: Arc<dyn Clock>,
+    /// Order of keys. A data directory must always be opened with the same comparator; its
+    /// name is recorded in the manifest and checked on startup.
+    pub comparator: Arc<dyn Comparator<K>>,
+    /// How keys and values are serialized in SSTables; the memtable is measured in the same
+    /// encoding. Changing it only affects tables written afterwards.
+    pub encoding: Encoding,
+    /// Identifies the key and value types, e.g. `type_schema::<K, V>()` or a version string of
+    /// your own. It is recorded in the manifest, and opening the data with a different schema
+    /// fails with `LSMError::SchemaMismatch` instead of misreading it. `None` skips the check.
+    pub schema: Option<String>,
 }
 
-impl Default for Config {
+impl<K: Ord> Default for Config<K> {
     fn default() -> Self {
         Config {
             memtable_size_threshold: 1024 * 1024, // 1MB default
-            data_dir: "data".to_string(),
+            flush_policy: None,
+            memtable: MemTableKind::default(),
+            memtable_shards: 1,
+            data_dir: PathBuf::from("data"),
+            namespace: String::new(),
+            level_directories: false,
+            temp_dir: None,
+            wal_enabled: true,
+            compaction_threshold: Some(4),
+            compaction_strategy: CompactionStrategy::SizeTiered,
+            compaction_policy: CompactionPolicy::default(),
+            max_levels: 7,
+            l0_compaction_trigger: 4,
+            level_size_multiplier: None,
+            automatic_compaction: true,
+            background_compaction: false,
+            flush_interval: None,
+            max_value_size: None,
+            max_sstable_bytes: None,
+            max_sstables_before_flush_merge: None,
+            bloom_bits_per_key: 10,
+            bloom_hasher: BloomHasher::default(),
+            index_interval: 10,
+            block_size: 4096,
+            write_buffer_bytes: 8 * 1024,
+            read_buffer_bytes: 8 * 1024,
+            block_cache_bytes: 0,
+            max_open_files: None,
+            verify_on_open: false,
+            compression: Compression::None,
+            clock: Arc::new(SystemClock),
+            comparator: Arc::new(NaturalOrder),
+            encoding: Encoding::default(),
+            schema: None,
         }
     }
 }
 
-/// LSMTree is the main structure that coordinates MemTable and SSTables
+/// A schema for `Config::schema` made of the type names of `K` and `V`. Type names aren't
+/// guaranteed to be stable across compiler versions, so a rename of the types or a toolchain
+/// upgrade may call for a schema of your own.
+pub fn type_schema<K: ?Sized, V: ?Sized>() -> String {
+    format!("{}/{}", std::any::type_name::<K>(), std::any::type_name::<V>())
+}
+
+/// LSMTree is the main structure that coordinates MemTable and SSTables.
+/// Shut it down with `close` so the memtable is flushed.
 pub struct LSMTree<K, V> {
-    memtable: MemTable<K, V>,
-    sstables: Vec<SSTable<K, V>>,
-    sstable_id: u64,
-    config: Config,
+    memtable: Box<DynMemTable<K, V>>,
+    /// When the memtable was started, in milliseconds on the configured clock
+    memtable_started_at: u64,
+    /// A full memtable being written to SSTables, still searched by reads until it is; its
+    /// writes are in the sealed write-ahead log
+    immutable: Option<Arc<DynMemTable<K, V>>>,
+    /// Whether a full memtable is only sealed, for a background thread to write it out,
+    /// instead of being flushed by the write that filled it
+    background_flush: bool,
+    /// Whether compactions are left to a background thread instead of running in the flush
+    /// that reaches `compaction_threshold`
+    background_compaction: bool,
+    wal: Option<Wal>,
+    /// Ordered oldest to newest. Tables are shared so readers can search a snapshot of the
+    /// list without holding on to the tree.
+    sstables: Vec<Arc<SSTable<K, V>>>,
+    /// Ids of `sstables` in the same order, and the next id to allocate
+    manifest: Manifest,
+    flushes_since_compaction: usize,
+    counters: Arc<Counters>,
+    /// Shared by all SSTables of the tree; `None` if disabled
+    block_cache: Option<Arc<BlockCache>>,
+    /// Handles of the SSTables' files, shared by all of them; `None` if each table keeps its own
+    file_pool: Option<Arc<FilePool>>,
+    /// Whether the tree was opened with `open_read_only`
+    read_only: bool,
+    /// Applies the operands recorded by `merge`; `None` until set with `with_merge_operator`
+    merge_operator: Option<Arc<dyn MergeOperator<V>>>,
+    config: Config<K>,
 }
 
-impl<K, V> LSMTree<K, V> 
-where 
-    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
-    V: serde::Serialize + serde::de::DeserializeOwned + Clone,
+/// Opening a tree needs `Send + Sync` keys and values, since its memtable may be a skip list
+/// that several threads write to
+impl<K, V> LSMTree<K, V>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone + Send + Sync + 'static,
+    V: serde::Serialize + serde::de::DeserializeOwned + Send + Sync + 'static,
 {
     /// Creates a new LSM Tree instance with default configuration
     pub fn new() -> Result<Self> {
         Self::with_config(Config::default())
     }
 
-    /// Creates a new LSM Tree instance with custom configuration
-    pub fn with_config(config: Config) -> Result<Self> {
-        // Ensure data directory exists
-        std::fs::create_dir_all(&config.data_dir)?;
-        
+    /// Creates a new LSM Tree instance with custom configuration.
+    /// The SSTables listed in the manifest of `data_dir` are loaded in order, and the
+    /// write-ahead log (if enabled) is replayed into the memtable. A directory written before
+    /// manifests existed is scanned for SSTable files instead, and a manifest is created for it.
+    pub fn with_config(config: Config<K>) -> Result<Self> {
+        Self::open(config, false)
+    }
+
+    /// Opens an existing tree for reads only, e.g. for a reporting process alongside the
+    /// writer. Nothing in `data_dir` is created, moved or modified: the write-ahead logs are
+    /// replayed into the memtable without being repaired, and writes, flushes and compactions
+    /// fail with `LSMError::ReadOnly`. Fails if `data_dir` doesn't exist.
+    pub fn open_read_only(config: Config<K>) -> Result<Self> {
+        Self::open(config, true)
+    }
+
+    fn open(config: Config<K>, read_only: bool) -> Result<Self> {
+        if config.index_interval == 0 {
+            return Err(LSMError::InvalidConfig("index_interval must be non-zero".to_string()));
+        }
+        if config.block_size == 0 {
+            return Err(LSMError::InvalidConfig("block_size must be non-zero".to_string()));
+        }
+        if config.write_buffer_bytes == 0 || config.read_buffer_bytes == 0 {
+            let reason = "write_buffer_bytes and read_buffer_bytes must be non-zero";
+            return Err(LSMError::InvalidConfig(reason.to_string()));
+        }
+        if let CompactionStrategy::Leveled { fanout } = config.compaction_strategy {
+            if fanout < 2 {
+                return Err(LSMError::InvalidConfig("leveled compaction fanout must be at least 2".to_string()));
+            }
+        }
+        config.compaction_policy.validate().map_err(LSMError::InvalidConfig)?;
+        if config.max_levels < 2 {
+            return Err(LSMError::InvalidConfig("max_levels must be at least 2".to_string()));
+        }
+        if config.l0_compaction_trigger == 0 {
+            return Err(LSMError::InvalidConfig("l0_compaction_trigger must be at least 1".to_string()));
+        }
+        if config.level_size_multiplier.is_some_and(|multiplier| multiplier < 2) {
+            return Err(LSMError::InvalidConfig("level_size_multiplier must be at least 2".to_string()));
+        }
+        if config.flush_interval == Some(Duration::ZERO) {
+            return Err(LSMError::InvalidConfig("flush_interval must be non-zero".to_string()));
+        }
+        if config.max_sstable_bytes == Some(0) {
+            return Err(LSMError::InvalidConfig("max_sstable_bytes must be non-zero".to_string()));
+        }
+        if config.max_open_files == Some(0) {
+            return Err(LSMError::InvalidConfig("max_open_files must be non-zero".to_string()));
+        }
+        if config.memtable_shards == 0 {
+            return Err(LSMError::InvalidConfig("memtable_shards must be at least 1".to_string()));
+        }
+        #[cfg(feature = "crossbeam-skiplist")]
+        if config.memtable_shards > 1 && config.memtable == MemTableKind::SkipList {
+            return Err(LSMError::InvalidConfig("memtable_shards needs the BTree memtable".to_string()));
+        }
+        if config.max_sstables_before_flush_merge == Some(0) {
+            return Err(LSMError::InvalidConfig("max_sstables_before_flush_merge must be at least 1".to_string()));
+        }
+        if !config.namespace.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(LSMError::InvalidConfig(format!(
+                "namespace {:?} may only contain ASCII letters, digits, '-' and '_'",
+                config.namespace
+            )));
+        }
+        if !config.compression.is_supported() {
+            return Err(LSMError::UnsupportedCompression(config.compression));
+        }
+
+        // Ensure data directory exists and fail early if it can't be written to
+        if !read_only {
+            ensure_writable(&config.data_dir, &config.file_prefix())?;
+        }
+
+        let mut manifest = match Manifest::load(&manifest_path(&config))? {
+            Some(manifest) => manifest,
+            None => {
+                let manifest = scan_sstable_ids(&config)?;
+                if !read_only {
+                    manifest.store(&manifest_path(&config))?;
+                }
+                manifest
+            }
+        };
+        if manifest.comparator != config.comparator.name() {
+            return Err(LSMError::InvalidConfig(format!(
+                "data was written with comparator {:?}, but {:?} is configured",
+                manifest.comparator,
+                config.comparator.name()
+            )));
+        }
+        match (&config.schema, &manifest.schema) {
+            (Some(expected), Some(found)) if expected != found => {
+                return Err(LSMError::SchemaMismatch { expected: expected.clone(), found: found.clone() });
+            }
+            (Some(schema), None) if !read_only => {
+                manifest.schema = Some(schema.clone());
+                manifest.store(&manifest_path(&config))?;
+            }
+            _ => {}
+        }
+
+        if !read_only {
+            remove_unlisted_sstables(&config, &manifest)?;
+            if config.level_directories {
+                std::fs::create_dir_all(level_dir(&config, 0))?;
+            }
+            if let Some(temp_dir) = &config.temp_dir {
+                ensure_writable(temp_dir, &config.file_prefix())?;
+                remove_unfinished_sstables(temp_dir, &config.file_prefix())?;
+            }
+        }
+
+        let counters = Arc::new(Counters::default());
+        let block_cache = (config.block_cache_bytes > 0).then(|| Arc::new(BlockCache::new(config.block_cache_bytes)));
+        let file_pool = config.max_open_files.and_then(NonZeroUsize::new).map(|n| Arc::new(FilePool::new(n)));
+        let mut sstables = Vec::with_capacity(manifest.sstables.len());
+        for entry in &manifest.sstables {
+            let mut path = sstable_path(&config, entry.level, entry.id);
+            if !read_only {
+                relocate_sstable(&config, entry, &path)?;
+            } else if !path.exists() {
+                // Not moved to the configured layout yet
+                path = sstable_path_in_layout(&config, !config.level_directories, entry.level, entry.id);
+            }
+            let sstable = SSTable::open_with_comparator(path, Arc::clone(&config.comparator))?
+                .with_counters(Arc::clone(&counters))
+                .with_block_cache(block_cache.clone(), entry.id)
+                .with_file_pool(file_pool.clone(), entry.id)
+                .with_read_buffer_bytes(config.read_buffer_bytes);
+            if config.verify_on_open {
+                sstable.verify_file()?;
+            }
+            sstables.push(Arc::new(sstable));
+        }
+
+        let empty = || MemTable::with_comparator_and_encoding(Arc::clone(&config.comparator), config.encoding);
+        let (memtable, immutable, wal) = if config.wal_enabled {
+            let replay = if read_only { Wal::read_into } else { Wal::replay_into };
+            // A memtable whose flush was interrupted comes back as the immutable one
+            let sealed = replay(&sealed_wal_path(&config), empty().with_last_sequence(manifest.last_sequence))?;
+            let wal_path = wal_path(&config);
+            let memtable = replay(&wal_path, empty().with_last_sequence(sealed.last_sequence()))?;
+            let immutable = match sealed.is_empty() {
+                true => None,
+                false => Some(Arc::from(config.memtable.convert(sealed, config.memtable_shards)?)),
+            };
+            let wal = if read_only { None } else { Some(Wal::open(&wal_path)?) };
+            (memtable, immutable, wal)
+        } else {
+            (empty().with_last_sequence(manifest.last_sequence), None, None)
+        };
+        // Replayed writes were recorded when they were first made
+        let memtable = config.memtable.convert(memtable.with_counters(Arc::clone(&counters)), config.memtable_shards)?;
+
         Ok(LSMTree {
-            memtable: MemTable::new(),
-            sstables: Vec::new(),
-            sstable_id: 0,
+            memtable,
+            memtable_started_at: config.clock.now_millis(),
+            immutable,
+            background_flush: false,
+            background_compaction: false,
+            wal,
+            sstables,
+            manifest,
+            flushes_since_compaction: 0,
+            counters,
+            block_cache,
+            file_pool,
+            read_only,
+            merge_operator: None,
             config,
         })
     }
+}
+
+impl<K, V> LSMTree<K, V>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Sets the operator that the operands recorded by `merge` are applied with. It isn't
+    /// persisted, so a tree holding operands must be given the same operator whenever it is
+    /// opened: without one, reads and compactions that need to apply operands fail with
+    /// `LSMError::NoMergeOperator`.
+    pub fn with_merge_operator(mut self, operator: Arc<dyn MergeOperator<V>>) -> Self {
+        self.merge_operator = Some(operator);
+        self
+    }
 
     pub fn insert(&mut self, key: K, value: V) -> Result<()> {
+        self.check_writable()?;
+        self.check_value_size(&value)?;
+        if let Some(wal) = &mut self.wal {
+            wal.append(&key, &Entry::Value(&value))?;
+        }
         self.memtable.put(key, value)?;
+        self.counters.writes.add(1);
         
-        if self.memtable.size() >= self.config.memtable_size_threshold {
-            self.flush_memtable()?;
-        }
+        self.flush_if_needed()?;
         
         Ok(())
     }
 
-    pub fn get(&self, key: &K) -> Result<Option<V>> {
-        // First check memtable
-        if let Some(value) = self.memtable.get(key) {
-            return Ok(Some(value.clone()));
+    /// Inserts a value that reads as deleted once `ttl` has passed on the configured clock.
+    /// Compaction removes expired entries from disk.
+    pub fn insert_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> Result<()> {
+        self.check_writable()?;
+        self.check_value_size(&value)?;
+        let expires_at = self.config.clock.now_millis().saturating_add(ttl.as_millis() as u64);
+        if let Some(wal) = &mut self.wal {
+            wal.append(&key, &Entry::Expiring { value: &value, expires_at })?;
         }
+        self.memtable.put_with_expiry(key, value, expires_at)?;
+        self.counters.writes.add(1);
 
-        // Then check SSTables from newest to oldest
-        for sstable in self.sstables.iter().rev() {
-            if let Some(value) = sstable.get(key)? {
-                return Ok(Some(value));
+        self.flush_if_needed()?;
+
+        Ok(())
+    }
+
+    /// Inserts several key-value pairs, checking the flush threshold once at the end rather than
+    /// after every entry. The pairs are applied atomically, like a `WriteBatch` passed to
+    /// `commit`. A large batch can make the flushed SSTable exceed `memtable_size_threshold`.
+    pub fn insert_batch(&mut self, entries: Vec<(K, V)>) -> Result<()> {
+        self.commit(entries.into_iter().collect())
+    }
+
+    /// Applies all writes of `batch`, or none of them. The batch is logged to the write-ahead
+    /// log as a single record, so replaying the log after a crash recovers either the whole
+    /// batch or nothing of it; and if an entry is too large or fails to serialize, nothing is
+    /// applied.
+    pub fn commit(&mut self, batch: WriteBatch<K, V>) -> Result<()> {
+        self.check_writable()?;
+        for (_, entry) in &batch.entries {
+            if let Some(value) = entry.value() {
+                self.check_value_size(value)?;
+            }
+        }
+        if let Some(wal) = &mut self.wal {
+            wal.append_batch(&batch.entries)?;
+        }
+        let count = batch.len() as u64;
+        self.memtable.apply_batch(batch.entries)?;
+        self.counters.writes.add(count);
+
+        self.flush_if_needed()
+    }
+
+    /// Records `operand` for `key` without reading its current value; reads see the value
+    /// the merge operator makes of the key's previous value and its operands. See the `merge`
+    /// module. Fails with `LSMError::NoMergeOperator` if none is set.
+    pub fn merge(&mut self, key: K, operand: V) -> Result<()> {
+        self.check_writable()?;
+        self.check_value_size(&operand)?;
+        let operator = self.merge_operator.as_deref().ok_or(LSMError::NoMergeOperator)?;
+        match self.memtable.get_entry(&key) {
+            // The operand is applied onto the value right away, and the merged value logged like
+            // an insert, so replaying the log doesn't need the operator
+            Some(older) if !older.is_merge() => {
+                let entry = merge::combine(Some(operator), Entry::Merge(vec![Arc::new(operand)]), older)?;
+                if let Some(wal) = &mut self.wal {
+                    wal.append(&key, &entry)?;
+                }
+                self.memtable.put_entry(key, entry)?;
+            }
+            _ => {
+                if let Some(wal) = &mut self.wal {
+                    wal.append(&key, &Entry::Merge(vec![&operand]))?;
+                }
+                self.memtable.append_operands(key, vec![operand])?;
             }
         }
+        self.counters.writes.add(1);
 
-        Ok(None)
+        self.flush_if_needed()
     }
 
-    fn flush_memtable(&mut self) -> Result<()> {
-        let old_memtable = std::mem::replace(&mut self.memtable, MemTable::new());
-        let sstable_path = format!("{}/sstable_{:06}.db", self.config.data_dir, self.sstable_id);
-        let new_sstable = SSTable::from_memtable(&old_memtable, sstable_path)?;
+    /// Deletes a key by writing a tombstone, which shadows any older value in SSTables
+    pub fn delete(&mut self, key: K) -> Result<()> {
+        self.check_writable()?;
+        if let Some(wal) = &mut self.wal {
+            wal.append(&key, &Entry::<V>::Tombstone)?;
+        }
+        self.memtable.delete(key)?;
+        self.counters.writes.add(1);
+
+        self.flush_if_needed()?;
 
-        self.sstables.push(new_sstable);
-        self.sstable_id += 1;
-        
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
-    use tempfile::TempDir;  // Add tempfile to your Cargo.toml
+    /// Deletes the keys from `start` included to `end` excluded with a single range tombstone,
+    /// however many keys the range holds (see the `range_tombstone` module). An empty or
+    /// inverted range deletes nothing.
+    pub fn delete_range(&mut self, start: K, end: K) -> Result<()> {
+        self.check_writable()?;
+        if self.config.comparator.compare(&start, &end).is_ge() {
+            return Ok(());
+        }
+        if let Some(wal) = &mut self.wal {
+            wal.append_delete_range(&start, &end)?;
+        }
+        self.memtable.delete_range(start, end)?;
+        self.counters.writes.add(1);
 
-    fn setup() -> (LSMTree<String, String>, TempDir) {
-        let temp_dir = TempDir::new().unwrap();
-        let config = Config {
-            memtable_size_threshold: 1024, // Small size for testing
-            data_dir: temp_dir.path().to_str().unwrap().to_string(),
-        };
-        let lsm = LSMTree::with_config(config).unwrap();
-        (lsm, temp_dir)
+        self.flush_if_needed()
     }
 
-    #[test]
-    fn test_basic_operations() -> Result<()> {
-        let (mut lsm, _temp_dir) = setup();
+    /// Like `get`, but returns the value behind an `Arc` instead of cloning it. A value still
+    /// in the memtable is shared with it, so repeated reads of a hot key don't copy it.
+    pub fn get_arc(&self, key: &K) -> Result<Option<Arc<V>>> {
+        self.counters.gets.add(1);
+        let now = self.config.clock.now_millis();
 
-        // Test insert and get
-        lsm.insert("key1".to_string(), "value1".to_string())?;
-        assert_eq!(
-            lsm.get(&"key1".to_string())?,
-            Some("value1".to_string())
-        );
+        Ok(self.lookup(key)?.and_then(|entry| entry.into_live_value(now)))
+    }
 
-        // Test non-existent key
-        assert_eq!(lsm.get(&"nonexistent".to_string())?, None);
+    /// Whether `key` has a live value. Unlike `get`, values in SSTables aren't deserialized.
+    pub fn contains_key(&self, key: &K) -> Result<bool> {
+        self.counters.gets.add(1);
+        let now = self.config.clock.now_millis();
 
-        // Test overwrite
-        lsm.insert("key1".to_string(), "value2".to_string())?;
-        assert_eq!(
-            lsm.get(&"key1".to_string())?,
-            Some("value2".to_string())
-        );
+        if let Some(entry) = self.memtable_entry(key) {
+            if entry.is_merge() {
+                return Ok(self.lookup(key)?.is_some_and(|entry| entry.live_value(now).is_some()));
+            }
+            return Ok(entry.live_value(now).is_some());
+        }
+        let versions = sstable_versions(Versions::new(), &self.sstables, key, SSTable::get_kind_sequenced)?;
+        match versions.newest() {
+            Some(entry) if entry.is_merge() => {
+                Ok(self.lookup(key)?.is_some_and(|entry| entry.live_value(now).is_some()))
+            }
+            entry => Ok(entry.is_some_and(|entry| entry.live_value(now).is_some())),
+        }
+    }
 
-        Ok(())
+    /// The live value for `key` as serialized bytes, without deserializing it, e.g. to forward
+    /// it as is. Values from SSTables are copied out in the encoding of their table (see
+    /// `SSTable::get_entry_raw`); values from the memtable, or made by the merge operator,
+    /// are serialized with `Config::encoding`.
+    pub fn get_raw(&self, key: &K) -> Result<Option<Vec<u8>>> {
+        self.counters.gets.add(1);
+        let now = self.config.clock.now_millis();
+        if let Some(entry) = self.memtable_entry(key) {
+            if entry.is_merge() {
+                return self.merged_raw(key, now);
+            }
+            return entry.live_value(now).map(|value| self.config.encoding.serialize(&**value)).transpose();
+        }
+        let versions = sstable_versions(Versions::new(), &self.sstables, key, SSTable::get_raw_sequenced)?;
+        match versions.newest() {
+            Some(entry) if entry.is_merge() => self.merged_raw(key, now),
+            entry => Ok(entry.and_then(|entry| entry.into_live_value(now))),
+        }
     }
 
-    #[test]
-    fn test_memtable_flush() -> Result<()> {
-        let (mut lsm, temp_dir) = setup();
+    /// A reader over the serialized bytes of the live value for `key`, as returned by
+    /// `get_raw`, for streaming a large value out without holding it in memory. A value in an
+    /// uncompressed SSTable is read from the file as the reader is consumed, and a corrupted
+    /// record fails the read with `InvalidData` once the value has been read; one in a
+    /// compressed table is read out of its decompressed block without being copied. Values
+    /// from the memtable, or made by the merge operator, are serialized into memory first.
+    pub fn get_reader(&self, key: &K) -> Result<Option<impl std::io::Read>> {
+        if self.memtable_entry(key).is_some() {
+            return Ok(self.get_raw(key)?.map(ValueReader::from_bytes));
+        }
+        self.counters.gets.add(1);
+        let now = self.config.clock.now_millis();
+        let versions = sstable_versions(Versions::new(), &self.sstables, key, SSTable::get_value_sequenced)?;
+        match versions.newest() {
+            Some(entry) if entry.is_merge() => Ok(self.merged_raw(key, now)?.map(ValueReader::from_bytes)),
+            entry => Ok(entry.and_then(|entry| entry.into_live_value(now))),
+        }
+    }
 
-        // Insert enough data to trigger a flush
-        for i in 0..100 {
-            let key = format!("key{}", i);
-            let value = format!("value{}", i);
-            lsm.insert(key, value)?;
+    /// The live value for `key` after applying the merge operator, serialized
+    fn merged_raw(&self, key: &K, now: u64) -> Result<Option<Vec<u8>>> {
+        let entry = self.lookup(key)?;
+        let value = entry.as_ref().and_then(|entry| entry.live_value(now));
+        value.map(|value| self.config.encoding.serialize(&**value)).transpose()
+    }
+
+    /// The newest entry for `key` in the memtables, checking the active one first
+    pub(crate) fn memtable_entry(&self, key: &K) -> Option<Entry<Arc<V>>> {
+        self.memtables().find_map(|memtable| memtable.get_entry(key))
+    }
+
+    /// The entry for `key` with merge operands applied; see `lookup_entry`
+    pub(crate) fn lookup(&self, key: &K) -> Result<Option<Entry<Arc<V>>>> {
+        let memtables: Vec<_> = self.memtables().collect();
+        lookup_entry(&memtables, &self.sstables, key, self.merge_operator.as_deref())
+    }
+
+    /// The active memtable, then the immutable one if a flush is in progress
+    pub(crate) fn memtables(&self) -> impl Iterator<Item = &DynMemTable<K, V>> {
+        std::iter::once(&*self.memtable).chain(self.immutable.as_deref())
+    }
+
+    /// The sequence number of the last write, see the `sequence` module
+    pub fn last_sequence(&self) -> u64 {
+        self.memtable.last_sequence()
+    }
+
+    /// Cheap estimate of the number of keys: the entry counts of the memtables and all SSTables
+    /// summed, so keys present in several places and tombstones are counted more than once
+    pub fn approx_len(&self) -> usize {
+        let in_memory: usize = self.memtables().map(|memtable| memtable.len()).sum();
+        let on_disk: u64 = self.sstables.iter().map(|sstable| sstable.entry_count()).sum();
+        in_memory + on_disk as usize
+    }
+
+    /// Cheap estimate of the number of keys in `[start, end)`, counted the same way as
+    /// `approx_len`: memtable entries in the range plus, for each SSTable, an estimate from its
+    /// sparse index (see `SSTable::estimate_range_count`). No data block is read, but indexes
+    /// that aren't loaded yet are.
+    pub fn estimate_range_count(&self, start: &K, end: &K) -> Result<usize> {
+        let in_memory: usize = self
+            .memtables()
+            .map(|memtable| memtable.sequenced_range(Bound::Included(start), Bound::Excluded(end)).count())
+            .sum();
+        let mut on_disk = 0;
+        for sstable in &self.sstables {
+            on_disk += sstable.estimate_range_count(start, end)?;
         }
+        Ok(in_memory + on_disk as usize)
+    }
 
-        // Verify that at least one SSTable was created
-        let sstable_count = fs::read_dir(temp_dir.path())
-            .unwrap()
-            .filter(|entry| {
-                entry.as_ref()
-                    .unwrap()
-                    .file_name()
-                    .to_str()
-                    .unwrap()
-                    .starts_with("sstable_")
-            })
-            .count();
-        assert!(sstable_count > 0, "No SSTable files were created");
+    /// Counters of the work done since the tree was opened, along with its current shape
+    pub fn stats(&self) -> Stats {
+        Stats {
+            num_sstables: self.sstables.len(),
+            memtable_bytes: self.memtables().map(|memtable| memtable.size()).sum(),
+            memtable_entries: self.memtables().map(|memtable| memtable.len()).sum(),
+            total_writes: self.counters.writes.get(),
+            total_gets: self.counters.gets.get(),
+            total_flushes: self.counters.flushes.get(),
+            total_compactions: self.counters.compactions.get(),
+            sstable_bytes_written: self.counters.sstable_bytes_written.get(),
+            sstable_files_opened: self.counters.sstable_files_opened.get(),
+            sstable_indexes_loaded: self.counters.sstable_indexes_loaded.get(),
+            block_cache_hits: self.counters.block_cache_hits.get(),
+            block_cache_misses: self.counters.block_cache_misses.get(),
+            key_size_histogram: self.counters.key_sizes.get(),
+            value_size_histogram: self.counters.value_sizes.get(),
+        }
+    }
 
-        // Verify we can still read all values
-        for i in 0..100 {
-            let key = format!("key{}", i);
-            let expected_value = format!("value{}", i);
-            assert_eq!(
-                lsm.get(&key)?,
-                Some(expected_value),
-                "Failed to read key {}",
-                key
-            );
+    /// Rejects values larger than `max_value_size` before anything is written
+    fn check_value_size(&self, value: &V) -> Result<()> {
+        check_value_size(value, self.config.max_value_size, self.config.encoding)
+    }
+
+    pub(crate) fn allocate_sstable_id(&mut self) -> u64 {
+        self.manifest.allocate_id()
+    }
+
+    /// An empty memtable that continues the sequence numbers of the active one
+    fn empty_memtable(&self) -> Box<DynMemTable<K, V>> {
+        self.memtable.next_memtable()
+    }
+
+    pub(crate) fn sstable_options(&self) -> SSTableOptions {
+        SSTableOptions {
+            bloom_bits_per_key: self.config.bloom_bits_per_key,
+            bloom_hasher: self.config.bloom_hasher,
+            index_interval: self.config.index_interval,
+            block_size: self.config.block_size,
+            compression: self.config.compression,
+            encoding: self.config.encoding,
+            write_buffer_bytes: self.config.write_buffer_bytes,
+            temp_dir: self.config.temp_dir.clone(),
         }
+    }
 
+    /// Flushes the memtable if the flush policy asks for it
+    pub(crate) fn flush_if_needed(&mut self) -> Result<()> {
+        let usage = MemTableUsage {
+            size: self.memtable.size(),
+            len: self.memtable.len(),
+        };
+        let should_flush = match &self.config.flush_policy {
+            Some(policy) => {
+                let age = self.config.clock.now_millis().saturating_sub(self.memtable_started_at);
+                policy.should_flush(&usage, Duration::from_millis(age))
+            }
+            None => SizeThreshold(self.config.memtable_size_threshold).should_flush(&usage, Duration::ZERO),
+        };
+        if !should_flush {
+            return Ok(());
+        }
+        if !self.background_flush {
+            return self.flush();
+        }
+        // While the previous memtable is still being written, this one keeps growing
+        if self.immutable.is_none() {
+            self.seal_memtable()?;
+        }
         Ok(())
     }
 
-/*     #[test]
-    fn test_persistence() -> Result<()> {
-        let temp_dir = TempDir::new().unwrap();
-        let config = Config {
-            memtable_size_threshold: 1024,
-            data_dir: temp_dir.path().to_str().unwrap().to_string(),
+    /// Writes the memtable to a new SSTable even if it hasn't reached
+    /// `memtable_size_threshold`, making its entries durable without relying on the WAL.
+    /// A memtable sealed for a background flush that hasn't completed yet is written first.
+    /// Does nothing if the memtables are empty.
+    pub fn flush(&mut self) -> Result<()> {
+        self.check_writable()?;
+        self.flush_immutable()?;
+        self.seal_memtable()?;
+        self.flush_immutable()
+    }
+
+    /// Makes the memtable immutable and starts an empty one, sealing the write-ahead log with
+    /// it. The immutable memtable is still read until `flush_immutable` writes it out. Does
+    /// nothing if the memtable is empty; there must be no immutable memtable yet.
+    pub(crate) fn seal_memtable(&mut self) -> Result<()> {
+        debug_assert!(self.immutable.is_none());
+        if self.memtable.is_empty() {
+            return Ok(());
+        }
+        if let Some(wal) = &mut self.wal {
+            wal.seal(&wal_path(&self.config), &sealed_wal_path(&self.config))?;
+        }
+        let empty = self.empty_memtable();
+        self.immutable = Some(Arc::from(std::mem::replace(&mut self.memtable, empty)));
+        self.memtable_started_at = self.config.clock.now_millis();
+        Ok(())
+    }
+
+    /// Writes the immutable memtable, if any, to SSTables and installs them
+    pub(crate) fn flush_immutable(&mut self) -> Result<()> {
+        let Some(memtable) = self.immutable.clone() else {
+            return Ok(());
         };
+        let options = self.sstable_options();
+        let manifest = &mut self.manifest;
+        let tables = write_memtable(&self.config, &options, &*memtable, || manifest.allocate_id())?;
+        self.install_flushed(&memtable, tables)
+    }
 
-        // Insert data and flush
-        {
-            let mut lsm = LSMTree::with_config(config.clone())?;
-            lsm.insert("key1".to_string(), "value1".to_string())?;
-            lsm.flush_memtable()?;
+    /// Adds the SSTables written from `memtable` to the tree and drops the memtable, unless it
+    /// was flushed or cleared in the meantime, in which case the tables are discarded. Then
+    /// compacts if enough flushes accumulated, unless that is left to a background thread.
+    pub(crate) fn install_flushed(
+        &mut self,
+        memtable: &Arc<DynMemTable<K, V>>,
+        tables: Vec<(u64, SSTable<K, V>)>,
+    ) -> Result<()> {
+        if !self.immutable.as_ref().is_some_and(|immutable| Arc::ptr_eq(immutable, memtable)) {
+            for (_, sstable) in tables {
+                sstable.mark_obsolete();
+            }
+            return Ok(());
+        }
+        self.counters.flushes.add(1);
+        for (_, sstable) in &tables {
+            self.counters.flush_bytes_written.add(sstable.file_size());
         }
+        let position = self.sstables.len();
+        self.insert_tables(position, 0, tables);
+        self.manifest.last_sequence = self.manifest.last_sequence.max(memtable.last_sequence());
+        self.manifest.store(&manifest_path(&self.config))?;
 
-        // Create new LSM tree instance and verify data
-        {
-            let lsm = LSMTree::with_config(config)?;
-            assert_eq!(
-                lsm.get(&"key1".to_string())?,
-                Some("value1".to_string()),
-                "Failed to recover data from SSTable"
-            );
+        // The flushed entries are durable in the SSTables now
+        self.immutable = None;
+        remove_sealed_wal(&self.config)?;
+
+        self.flushes_since_compaction += 1;
+        if self.needs_compaction() && !self.background_compaction {
+            self.compact()?;
+        }
+        if let Some(limit) = self.config.max_sstables_before_flush_merge {
+            self.merge_oldest(limit)?;
+        }
+
+        Ok(())
+    }
+
+    /// Bulk-loads pairs, which must be sorted by key without duplicates, straight into new
+    /// SSTables of about `memtable_size_threshold` bytes, bypassing the memtable and the
+    /// write-ahead log. The memtable is flushed first, so ingested values replace earlier
+    /// writes to the same keys. `count` is the expected number of pairs, used to size buffers.
+    /// Returns `LSMError::UnsortedKeys`, leaving the tree unchanged, if the keys aren't sorted.
+    pub fn ingest_sorted<I: Iterator<Item = (K, V)>>(&mut self, iter: I, count: usize) -> Result<()> {
+        self.flush()?;
+
+        let (max_value_size, encoding) = (self.config.max_value_size, self.config.encoding);
+        let sequence = self.memtable.next_sequence();
+        let entries = iter.map(|(key, value)| {
+            check_value_size(&value, max_value_size, encoding)?;
+            Ok((key, Sequenced::new(Entry::Value(value), sequence)))
+        });
+        let max_table_size = self.config.memtable_size_threshold.max(1) as u64;
+        let tables = self.write_tables(entries, &[], 0, Some(max_table_size), count)?;
+
+        let position = self.sstables.len();
+        self.insert_tables(position, 0, tables);
+        self.manifest.last_sequence = sequence;
+        self.manifest.store(&manifest_path(&self.config))?;
+        Ok(())
+    }
+
+    /// Writes entries to new SSTables for `level`, allocating their ids from the manifest;
+    /// see `write_sstables`
+    pub(crate) fn write_tables<Q: Borrow<K>, E: serde::Serialize>(
+        &mut self,
+        entries: impl Iterator<Item = Result<(Q, Sequenced<Entry<E>>)>>,
+        range_tombstones: &[RangeTombstone<K>],
+        level: u32,
+        max_table_size: Option<u64>,
+        expected: usize,
+    ) -> Result<Vec<(u64, SSTable<K, V>)>> {
+        let options = self.sstable_options();
+        let manifest = &mut self.manifest;
+        let allocate_id = || manifest.allocate_id();
+        write_sstables(&self.config, &options, entries, range_tombstones, level, max_table_size, expected, allocate_id)
+    }
+
+    /// Removes every entry: the memtable and the write-ahead log are emptied and all SSTables
+    /// are dropped from the manifest. If nothing else holds the tables (a snapshot, or a merge
+    /// in progress) and no immutable memtable is being flushed, their files are deleted right
+    /// away and SSTable ids start over from 0. Otherwise the files are deleted once the last
+    /// reader lets go of them, and ids keep increasing so a new table never reuses the file
+    /// name of one still being read. Only files of this tree (and its namespace) are touched.
+    pub fn clear(&mut self) -> Result<()> {
+        self.check_writable()?;
+        let unshared = self.immutable.is_none() && self.sstables.iter().all(|sstable| Arc::strong_count(sstable) == 1);
+        let next_id = if unshared { 0 } else { self.manifest.next_id };
+        let manifest = Manifest { sstables: Vec::new(), next_id, ..self.manifest.clone() };
+        manifest.store(&manifest_path(&self.config))?;
+        self.manifest = manifest;
+        let removed = std::mem::take(&mut self.sstables);
+
+        self.memtable = self.empty_memtable();
+        self.memtable_started_at = self.config.clock.now_millis();
+        self.immutable = None;
+        if let Some(wal) = &mut self.wal {
+            wal.reset()?;
+        }
+        remove_sealed_wal(&self.config)?;
+        self.flushes_since_compaction = 0;
+
+        if !unshared {
+            for sstable in removed {
+                sstable.mark_obsolete();
+            }
+            return Ok(());
+        }
+        // Deleted before their ids can be handed out again, and their blocks forgotten
+        for sstable in removed {
+            let path = sstable.path().to_path_buf();
+            drop(sstable);
+            std::fs::remove_file(path)?;
+        }
+        if let Some(cache) = &self.block_cache {
+            cache.clear();
+        }
+        Ok(())
+    }
+
+    /// Flushes the memtable and closes the tree; the recommended way to shut down. Dropping
+    /// the tree instead leaves unflushed writes in the write-ahead log, or loses them if it
+    /// is disabled. A read-only tree has nothing to flush.
+    pub fn close(mut self) -> Result<()> {
+        if self.read_only {
+            return Ok(());
         }
+        self.flush()
+    }
+
+    /// Whether the tree was opened with `open_read_only`
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
 
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(LSMError::ReadOnly);
+        }
         Ok(())
-    } */
+    }
+}
+
+/// Lookups that return owned values, which need `V: Clone` to copy them out of the memtable
+impl<K, V> LSMTree<K, V>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned + Clone,
+{
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        self.counters.gets.add(1);
+        let now = self.config.clock.now_millis();
+
+        let entry = self.lookup(key)?;
+        Ok(entry.and_then(|entry| entry.into_live_value(now)).map(Arc::unwrap_or_clone))
+    }
+
+    /// Like `get`, but a key without a live value is an error, `LSMError::KeyNotFound`
+    pub fn get_expect(&self, key: &K) -> Result<V> {
+        self.get(key)?.ok_or(LSMError::KeyNotFound)
+    }
+
+    /// Looks up several keys at once, returning their values in the order of `keys`. Each
+    /// SSTable is read in a single forward pass over the requested keys it may hold a version
+    /// of that matters, decompressing each block at most once, which is much cheaper than
+    /// calling `get` for each key.
+    pub fn get_many(&self, keys: &[K]) -> Result<Vec<Option<V>>> {
+        self.counters.gets.add(keys.len() as u64);
+        let now = self.config.clock.now_millis();
+        let mut values = vec![None; keys.len()];
+
+        // Indices of the keys not resolved yet, sorted by key
+        let mut pending = Vec::new();
+        for (i, key) in keys.iter().enumerate() {
+            match self.memtable_entry(key) {
+                Some(entry) if entry.is_merge() => values[i] = self.get_live(key, now)?,
+                Some(entry) => values[i] = entry.live_value(now).map(|value| V::clone(value)),
+                None => pending.push(i),
+            }
+        }
+        pending.sort_by(|&a, &b| self.config.comparator.compare(&keys[a], &keys[b]));
+
+        // The versions found of each pending key
+        let mut versions: Vec<Versions<V>> = pending.iter().map(|_| Versions::new()).collect();
+        for sstable in self.sstables.iter().rev() {
+            let max_sequence = sstable.max_sequence();
+            let wanted: Vec<_> = (0..pending.len()).filter(|&j| versions[j].wants(max_sequence)).collect();
+            if wanted.is_empty() {
+                continue;
+            }
+            let lookups: Vec<_> = wanted.iter().map(|&j| &keys[pending[j]]).collect();
+            for (j, entry) in wanted.into_iter().zip(sstable.get_entries_sequenced(&lookups)?) {
+                if let Some(entry) = entry {
+                    versions[j].push(entry);
+                }
+            }
+        }
+
+        let operator = self.merge_operator.as_deref();
+        for (i, versions) in pending.into_iter().zip(versions) {
+            let entry = merge::collapse(operator, versions.newest_first().map(Ok))?;
+            values[i] = entry.and_then(|entry| entry.into_live_value(now));
+        }
+        Ok(values)
+    }
+
+    /// The live value of a key as of `now`, with its merge operands applied
+    fn get_live(&self, key: &K, now: u64) -> Result<Option<V>> {
+        let entry = self.lookup(key)?;
+        Ok(entry.and_then(|entry| entry.into_live_value(now)).map(Arc::unwrap_or_clone))
+    }
+
+
+    /// Inserts a value and returns the previous live value for the key, like `HashMap::insert`.
+    /// Unlike `insert`, this costs a lookup that may go to disk.
+    pub fn insert_and_get_previous(&mut self, key: K, value: V) -> Result<Option<V>> {
+        let previous = self.get(&key)?;
+        self.insert(key, value)?;
+        Ok(previous)
+    }
+
+    /// Replaces the live value for `key` with `f(current)`, deleting the key if `f` returns
+    /// `None`. Reading the current value is a `get`, which has to search every SSTable down to
+    /// the one holding the key if it isn't in the memtable, so updating a key only present in
+    /// old tables costs a read from disk. The new value is stored without a TTL.
+    pub fn update_with<F: FnOnce(Option<V>) -> Option<V>>(&mut self, key: K, f: F) -> Result<()> {
+        let current = self.get(&key)?;
+        let existed = current.is_some();
+        match f(current) {
+            Some(value) => self.insert(key, value),
+            // A missing key needs no tombstone
+            None if existed => self.delete(key),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<V> LSMTree<Vec<u8>, V>
+where
+    V: serde::Serialize + serde::de::DeserializeOwned + Clone,
+{
+    /// Inserts a value under an already encoded key, for key types without `Ord` or serde
+    /// impls, e.g. from other crates: open the tree as `LSMTree<Vec<u8>, V>` and encode keys
+    /// with a function of your own, or with `ordered::OrderedEncode`. Keys are ordered by
+    /// comparing their bytes, so the encoding must preserve the order scans should see.
+    pub fn insert_with_key_bytes(&mut self, key_bytes: Vec<u8>, value: V) -> Result<()> {
+        self.insert(key_bytes, value)
+    }
+
+    /// Looks up the value stored under a key encoded as for `insert_with_key_bytes`
+    pub fn get_by_key_bytes(&self, key_bytes: &[u8]) -> Result<Option<V>> {
+        self.get(&key_bytes.to_vec())
+    }
+}
+
+/// Checks `memtables` (newest first), then SSTables from newest to oldest, until no memtable or
+/// table left can hold a version newer than the newest one found that isn't a merge: a
+/// tombstone or an expired value is as authoritative as a live value, so older versions can't
+/// resurrect the key. The merge operands newer than that version are applied onto it with
+/// `operator`. Tables whose key range doesn't cover the key are skipped without touching the
+/// disk.
+pub(crate) fn lookup_entry<K, V>(
+    memtables: &[&DynMemTable<K, V>],
+    sstables: &[Arc<SSTable<K, V>>],
+    key: &K,
+    operator: Option<&dyn MergeOperator<V>>,
+) -> Result<Option<Entry<Arc<V>>>>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+{
+    let mut versions = Versions::new();
+    for memtable in memtables {
+        match memtable.get_sequenced(key) {
+            Some(entry) if versions.wants(memtable.last_sequence()) => versions.push(entry),
+            _ => {}
+        }
+    }
+    let get = |sstable: &SSTable<K, V>, key: &K| {
+        Ok(sstable.get_sequenced(key)?.map(|entry| entry.map(|entry| entry.map(Arc::new))))
+    };
+    let versions = sstable_versions(versions, sstables, key, get)?;
+    merge::collapse(operator, versions.newest_first().map(Ok))
+}
+
+/// Adds the versions of `key` in `sstables`, read with `get`, to `versions`. Tables are visited
+/// newest to oldest, skipping those that hold nothing newer than a version already found that
+/// isn't a merge, and those whose key range doesn't cover the key.
+fn sstable_versions<K, V, T>(
+    mut versions: Versions<T>,
+    sstables: &[Arc<SSTable<K, V>>],
+    key: &K,
+    get: impl Fn(&SSTable<K, V>, &K) -> Result<Option<Sequenced<Entry<T>>>>,
+) -> Result<Versions<T>>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+{
+    for sstable in sstables.iter().rev() {
+        if !versions.wants(sstable.max_sequence()) || !sstable.may_contain_key(key)? {
+            continue;
+        }
+        if let Some(entry) = get(sstable, key)? {
+            versions.push(entry);
+        }
+    }
+    Ok(versions)
+}
+
+impl<K> Config<K> {
+    /// Prefix of the names of the files this tree owns in `data_dir`
+    fn file_prefix(&self) -> String {
+        if self.namespace.is_empty() {
+            String::new()
+        } else {
+            format!("{}_", self.namespace)
+        }
+    }
+
+    fn file_path(&self, name: &str) -> PathBuf {
+        self.data_dir.join(format!("{}{}", self.file_prefix(), name))
+    }
+}
+
+/// Path `<path>.tmp` that a file is written under before being renamed to `path`
+pub(crate) fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    PathBuf::from(tmp)
+}
+
+/// Syncs the directory containing `path`, which makes a rename to `path` durable
+pub(crate) fn sync_parent_dir(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+fn check_value_size<V: serde::Serialize>(value: &V, max_value_size: Option<usize>, encoding: Encoding) -> Result<()> {
+    let Some(max) = max_value_size else {
+        return Ok(());
+    };
+    let size = encoding.serialized_size(value)?;
+    if size > max as u64 {
+        return Err(LSMError::ValueTooLarge { size, max });
+    }
+    Ok(())
+}
+
+/// Writes entries, sorted by key without duplicates, to new SSTables for `level`, starting a
+/// new table once the current one reaches `max_table_size` bytes. Each table gets the part of
+/// `range_tombstones` from its first key to the next table's, and a single table is written for
+/// range tombstones without entries. `expected` is the expected number of entries, used to size
+/// buffers. Returns the tables with their ids in key order; if writing fails, or the keys turn
+/// out not to be sorted, the tables already written are deleted.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn write_sstables<K, V, Q, E>(
+    config: &Config<K>,
+    options: &SSTableOptions,
+    entries: impl Iterator<Item = Result<(Q, Sequenced<Entry<E>>)>>,
+    range_tombstones: &[RangeTombstone<K>],
+    level: u32,
+    max_table_size: Option<u64>,
+    expected: usize,
+    mut allocate_id: impl FnMut() -> u64,
+) -> Result<Vec<(u64, SSTable<K, V>)>>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+    Q: Borrow<K>,
+    E: serde::Serialize,
+{
+    let mut tables = Vec::new();
+    if config.level_directories {
+        std::fs::create_dir_all(level_dir(config, level))?;
+    }
+    let create = || {
+        let id = allocate_id();
+        let path = sstable_path(config, level, id);
+        Ok((id, SSTableWriter::create_with_comparator(path, options, Arc::clone(&config.comparator))?))
+    };
+    let comparator = &*config.comparator;
+    let written =
+        write_sstables_into(comparator, entries, range_tombstones, max_table_size, expected, create, &mut tables);
+    if let Err(err) = written {
+        for (_, sstable) in tables {
+            sstable.mark_obsolete();
+        }
+        return Err(err);
+    }
+    Ok(tables)
+}
+
+/// Writes a memtable to level 0 SSTables, each of its `flush_parts` to tables of its own.
+/// Returns the tables in the order to install them; if writing fails, the tables already
+/// written are deleted.
+pub(crate) fn write_memtable<K, V>(
+    config: &Config<K>,
+    options: &SSTableOptions,
+    memtable: &DynMemTable<K, V>,
+    mut allocate_id: impl FnMut() -> u64,
+) -> Result<Vec<(u64, SSTable<K, V>)>>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+{
+    let max_table_size = config.max_sstable_bytes.map(|max| max as u64);
+    let mut tables = Vec::new();
+    for part in memtable.flush_parts() {
+        let entries = part.entries.map(Ok);
+        let (tombstones, len) = (&part.range_tombstones, part.len);
+        match write_sstables(config, options, entries, tombstones, 0, max_table_size, len, &mut allocate_id) {
+            Ok(written) => tables.extend(written),
+            Err(err) => {
+                for (_, sstable) in tables {
+                    sstable.mark_obsolete();
+                }
+                return Err(err);
+            }
+        }
+    }
+    Ok(tables)
+}
+
+/// Writes entries to tables created by `create` as needed, pushing each one once finished
+fn write_sstables_into<K, V, Q, E>(
+    comparator: &dyn Comparator<K>,
+    entries: impl Iterator<Item = Result<(Q, Sequenced<Entry<E>>)>>,
+    range_tombstones: &[RangeTombstone<K>],
+    max_table_size: Option<u64>,
+    expected: usize,
+    mut create: impl FnMut() -> Result<(u64, SSTableWriter<K, V>)>,
+    tables: &mut Vec<(u64, SSTable<K, V>)>,
+) -> Result<()>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+    Q: Borrow<K>,
+    E: serde::Serialize,
+{
+    let add_range_tombstones = |writer: &mut SSTableWriter<K, V>, low: Option<&K>, high: Option<&K>| {
+        for tombstone in range_tombstones.iter().filter_map(|tombstone| tombstone.clip(low, high, comparator)) {
+            writer.add_range_tombstone(tombstone);
+        }
+    };
+    // A full table is only finished once the next table's first key is known
+    let mut writer: Option<(u64, SSTableWriter<K, V>, bool)> = None;
+    let mut low: Option<K> = None;
+
+    for (written, item) in entries.enumerate() {
+        let (key, entry) = item?;
+        let key = key.borrow();
+        let (id, mut current) = match writer.take() {
+            Some((id, current, false)) => (id, current),
+            full => {
+                if let Some((id, mut current, _)) = full {
+                    add_range_tombstones(&mut current, low.as_ref(), Some(key));
+                    tables.push((id, current.finish()?));
+                    low = Some(key.clone());
+                }
+                // Writers check the order of their own keys; this checks it across tables
+                let previous = match tables.last() {
+                    Some((_, sstable)) => sstable.key_range()?,
+                    None => None,
+                };
+                if previous.is_some_and(|(_, last)| comparator.compare(key, last).is_le()) {
+                    return Err(LSMError::UnsortedKeys);
+                }
+                let (id, mut current) = create()?;
+                current.reserve(expected.saturating_sub(written));
+                (id, current)
+            }
+        };
+        current.add_entry_with_sequence(key, &entry.entry, entry.sequence)?;
+        let full = max_table_size.is_some_and(|max| current.size() >= max);
+        writer = Some((id, current, full));
+    }
+    let (id, mut current) = match writer {
+        Some((id, current, _)) => (id, current),
+        None if range_tombstones.is_empty() => return Ok(()),
+        None => create()?,
+    };
+    add_range_tombstones(&mut current, low.as_ref(), None);
+    tables.push((id, current.finish()?));
+
+    Ok(())
+}
+
+/// Path of an SSTable, in the directory of its level if `level_directories` is set
+fn sstable_path<K>(config: &Config<K>, level: u32, id: u64) -> PathBuf {
+    sstable_path_in_layout(config, config.level_directories, level, id)
+}
+
+fn sstable_path_in_layout<K>(config: &Config<K>, level_directories: bool, level: u32, id: u64) -> PathBuf {
+    let name = format!("{}sstable_{:06}.db", config.file_prefix(), id);
+    if level_directories {
+        level_dir(config, level).join(name)
+    } else {
+        config.data_dir.join(name)
+    }
+}
+
+fn level_dir<K>(config: &Config<K>, level: u32) -> PathBuf {
+    config.data_dir.join(format!("L{}", level))
+}
+
+/// Moves an SSTable listed in the manifest to `path` if it is still where the other layout
+/// puts it, which happens when `level_directories` changed since it was written
+fn relocate_sstable<K>(config: &Config<K>, entry: &ManifestEntry, path: &Path) -> Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+    let old_path = sstable_path_in_layout(config, !config.level_directories, entry.level, entry.id);
+    if old_path.exists() {
+        log::info!("Moving SSTable {} from {} to {}", entry.id, old_path.display(), path.display());
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::rename(&old_path, path)?;
+        sync_parent_dir(path)?;
+    }
+    Ok(())
+}
+
+/// Directories that may hold SSTables: `data_dir` and its level subdirectories
+fn sstable_dirs<K>(config: &Config<K>) -> Result<Vec<PathBuf>> {
+    let mut dirs = vec![config.data_dir.clone()];
+    for dir_entry in std::fs::read_dir(&config.data_dir)? {
+        let dir_entry = dir_entry?;
+        let is_level_dir = dir_entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix('L'))
+            .is_some_and(|level| level.parse::<u32>().is_ok());
+        if is_level_dir && dir_entry.file_type()?.is_dir() {
+            dirs.push(dir_entry.path());
+        }
+    }
+    Ok(dirs)
+}
+
+fn wal_path<K>(config: &Config<K>) -> PathBuf {
+    config.file_path("wal.log")
+}
+
+/// Log of the immutable memtable, see `Wal::seal`
+fn sealed_wal_path<K>(config: &Config<K>) -> PathBuf {
+    config.file_path("wal.sealed.log")
+}
+
+fn remove_sealed_wal<K>(config: &Config<K>) -> Result<()> {
+    match std::fs::remove_file(sealed_wal_path(config)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn manifest_path<K>(config: &Config<K>) -> PathBuf {
+    config.file_path(MANIFEST_FILE)
+}
+
+/// Builds a manifest for a data directory without one from the SSTable files it contains,
+/// which were always numbered in flush order
+fn scan_sstable_ids<K>(config: &Config<K>) -> Result<Manifest> {
+    let prefix = config.file_prefix();
+    let mut ids = Vec::new();
+    for dir_entry in std::fs::read_dir(&config.data_dir)? {
+        let file_name = dir_entry?.file_name();
+        if let Some(id) = file_name.to_str().and_then(|name| parse_sstable_id(name, &prefix)) {
+            ids.push(id);
+        }
+    }
+    ids.sort_unstable();
+
+    Ok(Manifest {
+        next_id: ids.last().map_or(0, |id| id + 1),
+        sstables: ids.into_iter().map(|id| ManifestEntry { id, level: 0 }).collect(),
+        comparator: config.comparator.name().to_string(),
+        schema: config.schema.clone(),
+        last_sequence: 0,
+    })
+}
+
+/// Deletes SSTable files the manifest doesn't list: output of a compaction interrupted by a
+/// crash, or replaced tables whose deletion was still pending. Temporary files of tables that
+/// were still being written when the process stopped are deleted too.
+fn remove_unlisted_sstables<K>(config: &Config<K>, manifest: &Manifest) -> Result<()> {
+    let prefix = config.file_prefix();
+    for dir in sstable_dirs(config)? {
+        for dir_entry in std::fs::read_dir(dir)? {
+            let dir_entry = dir_entry?;
+            let file_name = dir_entry.file_name();
+            let Some(file_name) = file_name.to_str() else {
+                continue;
+            };
+            if is_unfinished_sstable(file_name, &prefix) {
+                log::info!("Removing unfinished SSTable {}", file_name);
+                std::fs::remove_file(dir_entry.path())?;
+                continue;
+            }
+            let Some(id) = parse_sstable_id(file_name, &prefix) else {
+                continue;
+            };
+            if !manifest.sstables.iter().any(|entry| entry.id == id) {
+                log::info!("Removing SSTable {} which is not in the manifest", id);
+                std::fs::remove_file(dir_entry.path())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Creates `dir` if missing and checks that files can be written in it, so a read-only
+/// directory is reported when the tree is opened rather than by its first flush
+fn ensure_writable(dir: &Path, prefix: &str) -> Result<()> {
+    let not_writable = |source| LSMError::NotWritable { path: dir.display().to_string(), source };
+    std::fs::create_dir_all(dir).map_err(not_writable)?;
+    let probe = dir.join(format!(".{}write_probe_{}", prefix, std::process::id()));
+    std::fs::write(&probe, b"probe").map_err(not_writable)?;
+    std::fs::remove_file(&probe).map_err(not_writable)
+}
+
+/// Deletes the temporary files of tables that were still being written in `dir` when the
+/// process stopped
+fn remove_unfinished_sstables(dir: &Path, prefix: &str) -> Result<()> {
+    for dir_entry in std::fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        if dir_entry.file_name().to_str().is_some_and(|file_name| is_unfinished_sstable(file_name, prefix)) {
+            log::info!("Removing unfinished SSTable {}", dir_entry.path().display());
+            std::fs::remove_file(dir_entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+fn is_unfinished_sstable(file_name: &str, prefix: &str) -> bool {
+    file_name.strip_suffix(".tmp").and_then(|name| parse_sstable_id(name, prefix)).is_some()
+}
+
+/// Extracts the id from a file name of the form `<prefix>sstable_<id>.db`
+fn parse_sstable_id(file_name: &str, prefix: &str) -> Option<u64> {
+    file_name
+        .strip_prefix(prefix)?
+        .strip_prefix("sstable_")?
+        .strip_suffix(".db")?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::stats::SizeHistogram;
+    use std::fs;
+    use std::ops::Bound;
+    use tempfile::TempDir;  // Add tempfile to your Cargo.toml
+
+    fn setup() -> (LSMTree<String, String>, TempDir) {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            memtable_size_threshold: 1024, // Small size for testing
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        let lsm = LSMTree::with_config(config).unwrap();
+        (lsm, temp_dir)
+    }
+
+    #[test]
+    fn test_basic_operations() -> Result<()> {
+        let (mut lsm, _temp_dir) = setup();
+
+        // Test insert and get
+        lsm.insert("key1".to_string(), "value1".to_string())?;
+        assert_eq!(
+            lsm.get(&"key1".to_string())?,
+            Some("value1".to_string())
+        );
+
+        // Test non-existent key
+        assert_eq!(lsm.get(&"nonexistent".to_string())?, None);
+
+        // Test overwrite
+        lsm.insert("key1".to_string(), "value2".to_string())?;
+        assert_eq!(
+            lsm.get(&"key1".to_string())?,
+            Some("value2".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memtable_flush() -> Result<()> {
+        let (mut lsm, temp_dir) = setup();
+
+        // Insert enough data to trigger a flush
+        for i in 0..100 {
+            let key = format!("key{}", i);
+            let value = format!("value{}", i);
+            lsm.insert(key, value)?;
+        }
+
+        // Verify that at least one SSTable was created
+        let sstable_count = fs::read_dir(temp_dir.path())
+            .unwrap()
+            .filter(|entry| {
+                entry.as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_str()
+                    .unwrap()
+                    .starts_with("sstable_")
+            })
+            .count();
+        assert!(sstable_count > 0, "No SSTable files were created");
+
+        // Verify we can still read all values
+        for i in 0..100 {
+            let key = format!("key{}", i);
+            let expected_value = format!("value{}", i);
+            assert_eq!(
+                lsm.get(&key)?,
+                Some(expected_value),
+                "Failed to read key {}",
+                key
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_and_get_previous() -> Result<()> {
+        let (mut lsm, _temp_dir) = setup();
+
+        assert_eq!(lsm.insert_and_get_previous("key".to_string(), "v1".to_string())?, None);
+        lsm.flush()?;
+        assert_eq!(lsm.insert_and_get_previous("key".to_string(), "v2".to_string())?, Some("v1".to_string()));
+        assert_eq!(lsm.insert_and_get_previous("key".to_string(), "v3".to_string())?, Some("v2".to_string()));
+
+        lsm.delete("key".to_string())?;
+        assert_eq!(lsm.insert_and_get_previous("key".to_string(), "v4".to_string())?, None);
+        assert_eq!(lsm.get(&"key".to_string())?, Some("v4".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_with() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let mut lsm = LSMTree::<String, u64>::with_config(Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        })?;
+        let increment = |count: Option<u64>| Some(count.unwrap_or(0) + 1);
+
+        lsm.update_with("counter".to_string(), increment)?;
+        lsm.flush()?;
+        // The current value is read from the SSTable
+        lsm.update_with("counter".to_string(), increment)?;
+        lsm.update_with("counter".to_string(), increment)?;
+        assert_eq!(lsm.get(&"counter".to_string())?, Some(3));
+
+        lsm.update_with("counter".to_string(), |_| None)?;
+        assert_eq!(lsm.get(&"counter".to_string())?, None);
+        let writes = lsm.stats().total_writes;
+        lsm.update_with("missing".to_string(), |_| None)?;
+        assert_eq!(lsm.stats().total_writes, writes);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_batch() -> Result<()> {
+        let (mut lsm, _temp_dir) = setup();
+
+        let entries: Vec<_> = (0..100).map(|i| (format!("key{:03}", i), format!("value{}", i))).collect();
+        lsm.insert_batch(entries)?;
+
+        // The batch overflows the threshold, so it is flushed once as a whole
+        assert_eq!(lsm.sstables.len(), 1);
+        assert!(lsm.memtable.is_empty());
+        for i in 0..100 {
+            assert_eq!(lsm.get(&format!("key{:03}", i))?, Some(format!("value{}", i)));
+        }
+
+        lsm.insert_batch(vec![("key000".to_string(), "updated".to_string())])?;
+        assert_eq!(lsm.sstables.len(), 1);
+        assert_eq!(lsm.get(&"key000".to_string())?, Some("updated".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_many() -> Result<()> {
+        let (mut lsm, _temp_dir) = setup();
+
+        for i in 0..50 {
+            lsm.insert(format!("key{:02}", i), format!("old{}", i))?;
+        }
+        lsm.flush()?;
+        for i in (0..50).step_by(10) {
+            lsm.insert(format!("key{:02}", i), format!("new{}", i))?;
+        }
+        lsm.delete("key05".to_string())?;
+        lsm.flush()?;
+        lsm.insert("key07".to_string(), "mem".to_string())?;
+
+        let keys: Vec<_> = ["key30", "key07", "missing", "key05", "key01", "key30"]
+            .iter()
+            .map(|key| key.to_string())
+            .collect();
+        let values = lsm.get_many(&keys)?;
+        assert_eq!(
+            values,
+            vec![
+                Some("new30".to_string()),
+                Some("mem".to_string()),
+                None,
+                None,
+                Some("old1".to_string()),
+                Some("new30".to_string()),
+            ]
+        );
+        for (key, value) in keys.iter().zip(values) {
+            assert_eq!(lsm.get(key)?, value);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_arc() -> Result<()> {
+        // Values don't need to be `Clone` to be read through `get_arc`
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Blob(Vec<u8>);
+
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config)?;
+
+        lsm.insert(1, Blob(vec![1; 100]))?;
+        lsm.flush()?;
+        lsm.insert(2, Blob(vec![2; 100]))?;
+        lsm.delete(3)?;
+
+        // Reads of a value in the memtable share it instead of copying it
+        let hot = lsm.get_arc(&2)?.unwrap();
+        assert!(Arc::ptr_eq(&hot, &lsm.get_arc(&2)?.unwrap()));
+        assert_eq!(*hot, Blob(vec![2; 100]));
+
+        assert_eq!(lsm.get_arc(&1)?.as_deref(), Some(&Blob(vec![1; 100])));
+        assert_eq!(lsm.get_arc(&3)?, None);
+        assert_eq!(lsm.get_arc(&4)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_contains_key() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let clock = Arc::new(ManualClock::new(0));
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            clock: clock.clone(),
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config)?;
+
+        lsm.insert("flushed".to_string(), "value".to_string())?;
+        lsm.insert("deleted".to_string(), "value".to_string())?;
+        lsm.insert_with_ttl("expiring".to_string(), "value".to_string(), Duration::from_secs(1))?;
+        lsm.flush()?;
+        lsm.delete("deleted".to_string())?;
+        lsm.flush()?;
+        lsm.insert("mem".to_string(), "value".to_string())?;
+        lsm.delete("flushed".to_string())?;
+        lsm.insert("flushed".to_string(), "again".to_string())?;
+
+        assert!(lsm.contains_key(&"flushed".to_string())?);
+        assert!(lsm.contains_key(&"mem".to_string())?);
+        assert!(lsm.contains_key(&"expiring".to_string())?);
+        assert!(!lsm.contains_key(&"deleted".to_string())?);
+        assert!(!lsm.contains_key(&"missing".to_string())?);
+
+        clock.advance(Duration::from_secs(1));
+        assert!(!lsm.contains_key(&"expiring".to_string())?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_expect() -> Result<()> {
+        let (mut lsm, _temp_dir) = setup();
+        lsm.insert("flushed".to_string(), "value1".to_string())?;
+        lsm.insert("deleted".to_string(), "value2".to_string())?;
+        lsm.flush()?;
+        lsm.insert("mem".to_string(), "value3".to_string())?;
+        lsm.delete("deleted".to_string())?;
+
+        assert_eq!(lsm.get_expect(&"flushed".to_string())?, "value1");
+        assert_eq!(lsm.get_expect(&"mem".to_string())?, "value3");
+        assert!(matches!(lsm.get_expect(&"deleted".to_string()), Err(LSMError::KeyNotFound)));
+        assert!(matches!(lsm.get_expect(&"missing".to_string()), Err(LSMError::KeyNotFound)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_raw() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let clock = Arc::new(ManualClock::new(0));
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            clock: clock.clone(),
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config)?;
+
+        lsm.insert("flushed".to_string(), "value1".to_string())?;
+        lsm.insert("deleted".to_string(), "value2".to_string())?;
+        lsm.insert_with_ttl("expiring".to_string(), "value3".to_string(), Duration::from_secs(1))?;
+        lsm.flush()?;
+        lsm.delete("deleted".to_string())?;
+        lsm.insert("mem".to_string(), "value4".to_string())?;
+
+        let raw = |value: &str| Some(bincode::serialize(value).unwrap());
+        assert_eq!(lsm.get_raw(&"flushed".to_string())?, raw("value1"));
+        assert_eq!(lsm.get_raw(&"expiring".to_string())?, raw("value3"));
+        assert_eq!(lsm.get_raw(&"mem".to_string())?, raw("value4"));
+        assert_eq!(lsm.get_raw(&"deleted".to_string())?, None);
+        assert_eq!(lsm.get_raw(&"missing".to_string())?, None);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(lsm.get_raw(&"expiring".to_string())?, None);
+
+        let mut streamed = Vec::new();
+        let mut reader = lsm.get_reader(&"flushed".to_string())?.unwrap();
+        std::io::copy(&mut reader, &mut streamed)?;
+        assert_eq!(Some(streamed), raw("value1"));
+        assert!(lsm.get_reader(&"deleted".to_string())?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_reader_streams_large_values() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            block_cache_bytes: 64 * 1024,
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config)?;
+        let big: String = (0..200_000).map(|i| char::from(b'a' + (i % 26) as u8)).collect();
+        let stream = |lsm: &LSMTree<String, String>, key: &str| -> std::io::Result<Option<Vec<u8>>> {
+            let Some(mut reader) = lsm.get_reader(&key.to_string()).map_err(std::io::Error::other)? else {
+                return Ok(None);
+            };
+            let mut streamed = Vec::new();
+            std::io::copy(&mut reader, &mut streamed)?;
+            Ok(Some(streamed))
+        };
+
+        lsm.insert("a".to_string(), "small".to_string())?;
+        lsm.flush()?;
+        lsm.insert("big".to_string(), big.clone())?;
+        lsm.insert_with_ttl("big-expiring".to_string(), big.clone(), Duration::from_secs(60))?;
+        lsm.insert("c".to_string(), "small".to_string())?;
+        lsm.flush()?;
+        lsm.insert("deleted".to_string(), big.clone())?;
+        lsm.flush()?;
+        lsm.delete("deleted".to_string())?;
+        lsm.flush()?;
+
+        // Larger than both the read buffer and the block cache, so streamed from the file
+        let raw = bincode::serialize(&big).unwrap();
+        assert_eq!(stream(&lsm, "big")?, Some(raw.clone()));
+        assert_eq!(stream(&lsm, "big-expiring")?, Some(raw.clone()));
+        assert_eq!(stream(&lsm, "deleted")?, None);
+        assert_eq!(stream(&lsm, "c")?, Some(bincode::serialize("small").unwrap()));
+
+        // A cached block is read from memory
+        lsm.get(&"a".to_string())?;
+        let hits = lsm.stats().block_cache_hits;
+        assert_eq!(stream(&lsm, "a")?, Some(bincode::serialize("small").unwrap()));
+        assert_eq!(lsm.stats().block_cache_hits, hits + 1);
+
+        // A corrupted value is only noticed once it has been read
+        let path = lsm.sstables[1].path().to_path_buf();
+        let mut bytes = std::fs::read(&path)?;
+        let at = bytes.windows(raw.len()).position(|window| window == raw).unwrap() + raw.len() / 2;
+        bytes[at] ^= 1;
+        std::fs::write(&path, bytes)?;
+        let error = stream(&lsm, "big").unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete() -> Result<()> {
+        let (mut lsm, _temp_dir) = setup();
+
+        lsm.insert("key1".to_string(), "value1".to_string())?;
+        lsm.delete("key1".to_string())?;
+        assert_eq!(lsm.get(&"key1".to_string())?, None);
+
+        // Deleting a missing key is not an error
+        lsm.delete("missing".to_string())?;
+        assert_eq!(lsm.get(&"missing".to_string())?, None);
+
+        // Overwriting after a delete re-exposes the key
+        lsm.insert("key1".to_string(), "value2".to_string())?;
+        assert_eq!(lsm.get(&"key1".to_string())?, Some("value2".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_shadows_flushed_value() -> Result<()> {
+        let (mut lsm, _temp_dir) = setup();
+
+        lsm.insert("key1".to_string(), "value1".to_string())?;
+        lsm.flush()?;
+        lsm.delete("key1".to_string())?;
+        assert_eq!(lsm.get(&"key1".to_string())?, None);
+
+        // The tombstone must keep shadowing the old value once it is flushed too
+        lsm.flush()?;
+        assert_eq!(lsm.get(&"key1".to_string())?, None);
+
+        lsm.insert("key1".to_string(), "value2".to_string())?;
+        lsm.flush()?;
+        assert_eq!(lsm.get(&"key1".to_string())?, Some("value2".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_stops_at_newer_tombstone() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let mut lsm = LSMTree::with_config(Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            block_cache_bytes: 1024 * 1024,
+            ..Config::default()
+        })?;
+        let key = "key".to_string();
+        lsm.insert(key.clone(), "value".to_string())?;
+        lsm.flush()?;
+        lsm.delete(key.clone())?;
+        lsm.flush()?;
+
+        // Each lookup reads a single block: the tombstone's, never the older value's
+        let blocks_read = |lsm: &LSMTree<String, String>| lsm.stats().block_cache_hits + lsm.stats().block_cache_misses;
+        let before = blocks_read(&lsm);
+        assert_eq!(lsm.get(&key)?, None);
+        assert_eq!(blocks_read(&lsm) - before, 1);
+        assert!(!lsm.contains_key(&key)?);
+        assert_eq!(blocks_read(&lsm) - before, 2);
+        assert_eq!(lsm.get_many(&[key])?, vec![None]);
+        assert_eq!(blocks_read(&lsm) - before, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_persistence() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            memtable_size_threshold: 1024,
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+
+        // Insert data and flush
+        {
+            let mut lsm = LSMTree::with_config(config.clone())?;
+            lsm.insert("key1".to_string(), "value1".to_string())?;
+            lsm.flush()?;
+        }
+
+        // Create new LSM tree instance and verify data
+        {
+            let lsm = LSMTree::with_config(config)?;
+            assert_eq!(
+                lsm.get(&"key1".to_string())?,
+                Some("value1".to_string()),
+                "Failed to recover data from SSTable"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_keys_and_values() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+
+        {
+            let mut lsm = LSMTree::with_config(config.clone())?;
+            lsm.insert(String::new(), "empty key".to_string())?;
+            lsm.insert("empty value".to_string(), String::new())?;
+            lsm.flush()?;
+            // Left in the write-ahead log
+            lsm.insert(String::new(), String::new())?;
+        }
+
+        let mut lsm = LSMTree::<String, String>::with_config(config)?;
+        assert_eq!(lsm.get(&String::new())?, Some(String::new()));
+        assert_eq!(lsm.get(&"empty value".to_string())?, Some(String::new()));
+
+        lsm.flush()?;
+        lsm.compact()?;
+        assert_eq!(lsm.get(&String::new())?, Some(String::new()));
+        let keys: Vec<_> = lsm
+            .range(Bound::Unbounded, Bound::Unbounded)?
+            .map(|item| item.map(|(key, _)| key))
+            .collect::<Result<_>>()?;
+        assert_eq!(keys, vec![String::new(), "empty value".to_string()]);
+
+        lsm.delete(String::new())?;
+        assert_eq!(lsm.get(&String::new())?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen_resumes_sstable_id() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            memtable_size_threshold: 1024,
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+
+        {
+            let mut lsm = LSMTree::with_config(config.clone())?;
+            lsm.insert("key1".to_string(), "old".to_string())?;
+            lsm.flush()?;
+            lsm.insert("key2".to_string(), "value2".to_string())?;
+            lsm.flush()?;
+        }
+
+        {
+            let mut lsm = LSMTree::<String, String>::with_config(config.clone())?;
+            assert_eq!(lsm.manifest.next_id, 2);
+            lsm.insert("key1".to_string(), "new".to_string())?;
+            lsm.flush()?;
+        }
+
+        let lsm = LSMTree::<String, String>::with_config(config)?;
+        assert_eq!(lsm.sstables.len(), 3);
+        assert_eq!(lsm.get(&"key1".to_string())?, Some("new".to_string()));
+        assert_eq!(lsm.get(&"key2".to_string())?, Some("value2".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest_is_source_of_truth() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+
+        {
+            let mut lsm = LSMTree::with_config(config.clone())?;
+            lsm.insert("key1".to_string(), "value1".to_string())?;
+            lsm.flush()?;
+            lsm.insert("key2".to_string(), "value2".to_string())?;
+            lsm.flush()?;
+        }
+
+        // A table that never made it into the manifest is ignored and cleaned up, and so is a
+        // flush that was interrupted before its table was complete
+        fs::write(temp_dir.path().join("sstable_000002.db"), b"garbage")?;
+        fs::write(temp_dir.path().join("sstable_000003.db.tmp"), b"garbage")?;
+        let lsm = LSMTree::<String, String>::with_config(config.clone())?;
+        assert_eq!(lsm.sstables.len(), 2);
+        assert_eq!(lsm.get(&"key2".to_string())?, Some("value2".to_string()));
+        assert!(!temp_dir.path().join("sstable_000002.db").exists());
+        assert!(!temp_dir.path().join("sstable_000003.db.tmp").exists());
+        drop(lsm);
+
+        // Without a manifest the directory is scanned, as before manifests existed
+        fs::remove_file(temp_dir.path().join("MANIFEST"))?;
+        let lsm = LSMTree::<String, String>::with_config(config)?;
+        let ids: Vec<_> = lsm.manifest.sstables.iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, vec![0, 1]);
+        assert_eq!(lsm.manifest.next_id, 2);
+        assert_eq!(lsm.get(&"key1".to_string())?, Some("value1".to_string()));
+        assert!(temp_dir.path().join("MANIFEST").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_ids_survive_reopen() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = || Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            ..Config::default()
+        };
+
+        let mut lsm = LSMTree::with_config(config())?;
+        for i in 0..50 {
+            lsm.insert(i, format!("first{}", i))?;
+        }
+        lsm.flush()?;
+        let first_path = lsm.sstables[0].path().to_path_buf();
+        let first_table = fs::read(&first_path)?;
+        drop(lsm);
+
+        let mut lsm = LSMTree::with_config(config())?;
+        assert_eq!(lsm.manifest.next_id, 1);
+        for i in 50..100 {
+            lsm.insert(i, format!("second{}", i))?;
+        }
+        lsm.flush()?;
+        assert_eq!(lsm.sstables.len(), 2);
+        assert_ne!(lsm.sstables[1].path(), first_path);
+        assert_eq!(fs::read(&first_path)?, first_table);
+        drop(lsm);
+
+        let lsm = LSMTree::<i32, String>::with_config(config())?;
+        for i in 0..50 {
+            assert_eq!(lsm.get(&i)?, Some(format!("first{}", i)));
+        }
+        for i in 50..100 {
+            assert_eq!(lsm.get(&i)?, Some(format!("second{}", i)));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_data_dir_path() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let data_dir = temp_dir.path().join("nested dir").join("db");
+        let config = || Config {
+            data_dir: data_dir.clone(),
+            ..Config::default()
+        };
+
+        let mut lsm = LSMTree::with_config(config())?;
+        lsm.insert("key".to_string(), "value".to_string())?;
+        lsm.flush()?;
+        assert_eq!(lsm.sstables[0].path(), data_dir.join("sstable_000000.db"));
+        drop(lsm);
+
+        let lsm = LSMTree::<String, String>::with_config(config())?;
+        assert_eq!(lsm.get(&"key".to_string())?, Some("value".to_string()));
+        assert!(data_dir.join("MANIFEST").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_namespaces_share_data_dir() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = |namespace: &str| Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            namespace: namespace.to_string(),
+            ..Config::default()
+        };
+
+        for namespace in ["", "users", "users_archive"] {
+            let mut lsm = LSMTree::with_config(config(namespace))?;
+            lsm.insert("key".to_string(), format!("from {:?}", namespace))?;
+            lsm.flush()?;
+            lsm.insert("unflushed".to_string(), namespace.to_string())?;
+        }
+        assert!(temp_dir.path().join("sstable_000000.db").exists());
+        assert!(temp_dir.path().join("users_sstable_000000.db").exists());
+        assert!(temp_dir.path().join("users_archive_MANIFEST").exists());
+        assert!(temp_dir.path().join("users_wal.log").exists());
+
+        // Each tree only loads, and only cleans up, the files carrying its own prefix
+        for namespace in ["", "users", "users_archive"] {
+            let lsm = LSMTree::<String, String>::with_config(config(namespace))?;
+            assert_eq!(lsm.sstables.len(), 1);
+            assert_eq!(lsm.get(&"key".to_string())?, Some(format!("from {:?}", namespace)));
+            assert_eq!(lsm.get(&"unflushed".to_string())?, Some(namespace.to_string()));
+        }
+
+        // The same holds when the manifests are missing and the directory is scanned instead
+        for namespace in ["", "users", "users_archive"] {
+            fs::remove_file(config(namespace).file_path(MANIFEST_FILE))?;
+        }
+        let lsm = LSMTree::<String, String>::with_config(config("users"))?;
+        assert_eq!(lsm.sstables.len(), 1);
+        assert_eq!(lsm.get(&"key".to_string())?, Some("from \"users\"".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_clear() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = |namespace: &str| Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            namespace: namespace.to_string(),
+            compaction_threshold: None,
+            ..Config::default()
+        };
+        let mut other = LSMTree::with_config(config("other"))?;
+        other.insert("key".to_string(), "kept".to_string())?;
+        other.flush()?;
+
+        let mut lsm = LSMTree::with_config(config(""))?;
+        for i in 0..3 {
+            lsm.insert(format!("key{}", i), "value".to_string())?;
+            lsm.flush()?;
+        }
+        lsm.insert("unflushed".to_string(), "value".to_string())?;
+        let snapshot = lsm.snapshot();
+
+        lsm.clear()?;
+        assert!(lsm.is_empty()?);
+        assert_eq!(lsm.get(&"key0".to_string())?, None);
+        // The snapshot keeps reading the tables it captured until it's dropped
+        assert_eq!(snapshot.get(&"key1".to_string())?, Some("value".to_string()));
+        assert!(temp_dir.path().join("sstable_000001.db").exists());
+        drop(snapshot);
+        assert!(!temp_dir.path().join("sstable_000001.db").exists());
+
+        // Ids kept increasing past the tables the snapshot held
+        lsm.insert("after".to_string(), "clear".to_string())?;
+        lsm.flush()?;
+        assert!(temp_dir.path().join("sstable_000003.db").exists());
+        drop(lsm);
+        let mut lsm = LSMTree::<String, String>::with_config(config(""))?;
+        assert_eq!(lsm.iter()?.collect::<Result<Vec<_>>>()?, vec![("after".to_string(), "clear".to_string())]);
+
+        // With nothing holding the tables, they are gone right away and ids start over
+        lsm.clear()?;
+        assert!(!temp_dir.path().join("sstable_000003.db").exists());
+        lsm.insert("again".to_string(), "value".to_string())?;
+        lsm.flush()?;
+        assert!(temp_dir.path().join("sstable_000000.db").exists());
+        assert_eq!(lsm.get(&"again".to_string())?, Some("value".to_string()));
+
+        let other = LSMTree::<String, String>::with_config(config("other"))?;
+        assert_eq!(other.get(&"key".to_string())?, Some("kept".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_splits_at_max_sstable_bytes() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = || Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            max_sstable_bytes: Some(2048),
+            compaction_threshold: None,
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config())?;
+        for i in 0..500 {
+            lsm.insert(i, format!("value{}", i))?;
+        }
+        lsm.delete(7)?;
+        lsm.flush()?;
+
+        let tables = lsm.stats().num_sstables;
+        assert!(tables > 2);
+        assert_eq!(lsm.stats().total_flushes, 1);
+        // Each table after the first starts past the end of the one before
+        for pair in lsm.sstables.windows(2) {
+            let (_, last) = pair[0].key_range()?.unwrap();
+            let (first, _) = pair[1].key_range()?.unwrap();
+            assert!(last < first);
+        }
+
+        drop(lsm);
+        let lsm = LSMTree::<i32, String>::with_config(config())?;
+        assert_eq!(lsm.stats().num_sstables, tables);
+        assert_eq!(lsm.iter()?.count(), 499);
+        assert_eq!(lsm.get(&7)?, None);
+        assert_eq!(lsm.get(&499)?, Some("value499".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ingest_sorted() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = || Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            memtable_size_threshold: 4096,
+            compaction_threshold: None,
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config())?;
+        lsm.insert(5, "old".to_string())?;
+        lsm.insert(5000, "kept".to_string())?;
+
+        lsm.ingest_sorted((0..1000).map(|i| (i, format!("value{}", i))), 1000)?;
+        assert!(lsm.stats().num_sstables > 2);
+        assert_eq!(lsm.get(&5)?, Some("value5".to_string()));
+        assert_eq!(lsm.get(&5000)?, Some("kept".to_string()));
+
+        // Unsorted input fails without touching the tree, even across table boundaries
+        let tables = lsm.stats().num_sstables;
+        let unsorted = (2000..3000).chain(1500..1600).map(|i| (i, "unsorted".to_string()));
+        assert!(matches!(lsm.ingest_sorted(unsorted, 1100), Err(LSMError::UnsortedKeys)));
+        assert_eq!(lsm.stats().num_sstables, tables);
+        assert_eq!(lsm.get(&2000)?, None);
+        assert_eq!(fs::read_dir(temp_dir.path())?.count(), tables + 2);
+
+        drop(lsm);
+        let lsm = LSMTree::<i32, String>::with_config(config())?;
+        assert_eq!(lsm.iter()?.count(), 1001);
+        assert_eq!(lsm.get(&999)?, Some("value999".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_commit_write_batch() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = || Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            max_value_size: Some(64),
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config())?;
+        lsm.insert("stale".to_string(), "value".to_string())?;
+
+        let mut batch = WriteBatch::new();
+        batch.put("from".to_string(), "90".to_string());
+        batch.put("to".to_string(), "10".to_string());
+        batch.put("to".to_string(), "110".to_string());
+        batch.delete("stale".to_string());
+        lsm.commit(batch)?;
+
+        // A batch with an oversized value is rejected as a whole
+        let mut batch = WriteBatch::new();
+        batch.put("from".to_string(), "0".to_string());
+        batch.put("to".to_string(), "x".repeat(100));
+        assert!(matches!(lsm.commit(batch), Err(LSMError::ValueTooLarge { .. })));
+
+        // Recovered from the write-ahead log
+        drop(lsm);
+        let lsm = LSMTree::<String, String>::with_config(config())?;
+        assert_eq!(lsm.get(&"from".to_string())?, Some("90".to_string()));
+        assert_eq!(lsm.get(&"to".to_string())?, Some("110".to_string()));
+        assert_eq!(lsm.get(&"stale".to_string())?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wal_recovery() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            memtable_size_threshold: 1024 * 1024,
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+
+        // Nothing reaches the flush threshold, so only the WAL holds these writes
+        {
+            let mut lsm = LSMTree::with_config(config.clone())?;
+            lsm.insert("key1".to_string(), "value1".to_string())?;
+            lsm.insert("key2".to_string(), "value2".to_string())?;
+            lsm.delete("key1".to_string())?;
+            lsm.insert_batch(vec![
+                ("key3".to_string(), "value3".to_string()),
+                ("key4".to_string(), "value4".to_string()),
+            ])?;
+        }
+
+        let lsm = LSMTree::<String, String>::with_config(config)?;
+        assert_eq!(lsm.get(&"key1".to_string())?, None);
+        assert_eq!(lsm.get(&"key2".to_string())?, Some("value2".to_string()));
+        assert_eq!(lsm.get(&"key3".to_string())?, Some("value3".to_string()));
+        assert_eq!(lsm.get(&"key4".to_string())?, Some("value4".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_immutable_memtable() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        let sealed_log = temp_dir.path().join("wal.sealed.log");
+
+        // A sealed memtable is still read, behind the new one, until it is written out
+        {
+            let mut lsm = LSMTree::with_config(config.clone())?;
+            lsm.insert("a".to_string(), "old".to_string())?;
+            lsm.insert("b".to_string(), "old".to_string())?;
+            lsm.seal_memtable()?;
+            lsm.insert("a".to_string(), "new".to_string())?;
+            lsm.delete("b".to_string())?;
+            lsm.insert("c".to_string(), "new".to_string())?;
+            assert!(sealed_log.exists());
+
+            let snapshot = lsm.snapshot();
+            let expected = vec![("a".to_string(), "new".to_string()), ("c".to_string(), "new".to_string())];
+            assert_eq!(lsm.iter()?.collect::<Result<Vec<_>>>()?, expected);
+            assert_eq!(snapshot.range(Bound::Unbounded, Bound::Unbounded)?.collect::<Result<Vec<_>>>()?, expected);
+            assert_eq!(lsm.approx_len(), 5);
+            assert_eq!(lsm.get(&"b".to_string())?, None);
+        }
+
+        // The sealed log brings the memtable back as the immutable one after a crash
+        let mut lsm = LSMTree::<String, String>::with_config(config)?;
+        assert_eq!(lsm.immutable.as_ref().map(|immutable| immutable.len()), Some(2));
+        assert_eq!(lsm.memtable.len(), 3);
+        assert_eq!(lsm.get(&"a".to_string())?, Some("new".to_string()));
+        assert_eq!(lsm.get(&"b".to_string())?, None);
+
+        // A flush writes both memtables, oldest first
+        lsm.flush()?;
+        assert!(lsm.immutable.is_none());
+        assert!(!sealed_log.exists());
+        assert_eq!(lsm.sstables.len(), 2);
+        assert_eq!(lsm.get(&"a".to_string())?, Some("new".to_string()));
+        assert_eq!(lsm.get(&"b".to_string())?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wal_reset_after_flush() -> Result<()> {
+        let (mut lsm, temp_dir) = setup();
+
+        lsm.insert("key1".to_string(), "value1".to_string())?;
+        lsm.flush()?;
+        // Only the log's 8-byte magic number is left
+        assert_eq!(fs::metadata(temp_dir.path().join("wal.log"))?.len(), 8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_empty_memtable() -> Result<()> {
+        let (mut lsm, _temp_dir) = setup();
+
+        lsm.flush()?;
+        assert!(lsm.sstables.is_empty());
+
+        lsm.insert("key1".to_string(), "value1".to_string())?;
+        lsm.flush()?;
+        lsm.flush()?;
+        assert_eq!(lsm.sstables.len(), 1);
+        assert_eq!(lsm.manifest.next_id, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats() -> Result<()> {
+        let (mut lsm, _temp_dir) = setup();
+        assert_eq!(lsm.stats(), Stats::default());
+
+        lsm.insert("key1".to_string(), "value1".to_string())?;
+        lsm.insert_batch(vec![("key2".to_string(), "value2".to_string()); 2])?;
+        lsm.delete("key3".to_string())?;
+        let stats = lsm.stats();
+        assert_eq!(stats.total_writes, 4);
+        assert_eq!(stats.memtable_bytes, lsm.memtable.size());
+        // The batch writes key2 twice, which takes up a single entry
+        assert_eq!(stats.memtable_entries, 3);
+
+        lsm.flush()?;
+        assert_eq!(lsm.get(&"key1".to_string())?, Some("value1".to_string()));
+        lsm.get_many(&["key2".to_string(), "key4".to_string()])?;
+        lsm.insert("key4".to_string(), "value4".to_string())?;
+        lsm.flush()?;
+        lsm.compact()?;
+
+        let stats = lsm.stats();
+        assert_eq!(stats.num_sstables, 1);
+        assert_eq!(stats.memtable_bytes, 0);
+        assert_eq!(stats.memtable_entries, 0);
+        assert_eq!(stats.total_writes, 5);
+        assert_eq!(stats.total_gets, 3);
+        assert_eq!(stats.total_flushes, 2);
+        assert_eq!(stats.total_compactions, 1);
+        let on_disk: u64 = lsm.sstables.iter().map(|sstable| sstable.file_size()).sum();
+        assert!(stats.sstable_bytes_written > on_disk);
+        // Lookups reuse each table's handle; only the compaction's two inputs were opened
+        assert_eq!(stats.sstable_files_opened, 2);
+
+        // Keys like "key1" take 12 bytes, values like "value1" 14; tombstones have no value
+        assert_eq!(stats.key_size_histogram.count(), 5);
+        assert_eq!(stats.key_size_histogram.buckets[SizeHistogram::bucket(12)], 5);
+        assert_eq!(stats.value_size_histogram.count(), 4);
+        assert_eq!(stats.value_size_histogram.buckets[SizeHistogram::bucket(14)], 4);
+        lsm.insert("key5".to_string(), "x".repeat(5000))?;
+        assert_eq!(lsm.stats().value_size_histogram.buckets[SizeHistogram::bucket(5008)], 1);
+
+        let buckets: Vec<_> = [0, 1, 2, 3, 4, 1023, 1024, u64::MAX].into_iter().map(SizeHistogram::bucket).collect();
+        assert_eq!(buckets, vec![0, 1, 2, 2, 3, 10, 11, 31]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_indexes_load_lazily() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = || Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config())?;
+        for table in 0..3 {
+            for i in 0..10 {
+                lsm.insert(table * 10 + i, format!("value{}", i))?;
+            }
+            lsm.flush()?;
+        }
+        // Written tables keep the index they built
+        assert_eq!(lsm.get(&5)?, Some("value5".to_string()));
+        assert_eq!(lsm.stats().sstable_indexes_loaded, 0);
+        drop(lsm);
+
+        let lsm = LSMTree::<i32, String>::with_config(config())?;
+        assert_eq!(lsm.stats().sstable_indexes_loaded, 0);
+        // The bloom filters rule out a missing key without any index
+        assert_eq!(lsm.get(&100)?, None);
+        assert_eq!(lsm.stats().sstable_indexes_loaded, 0);
+        // The lookup checks the tables from newest to oldest, but the newest one's bloom filter
+        // rules the key out
+        assert_eq!(lsm.get(&15)?, Some("value5".to_string()));
+        assert_eq!(lsm.stats().sstable_indexes_loaded, 1);
+        assert_eq!(lsm.get(&16)?, Some("value6".to_string()));
+        assert_eq!(lsm.stats().sstable_indexes_loaded, 1);
+        assert_eq!(lsm.range(Bound::Unbounded, Bound::Unbounded)?.count(), 30);
+        assert_eq!(lsm.stats().sstable_indexes_loaded, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_cache() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = |block_cache_bytes| Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            block_cache_bytes,
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config(64 * 1024))?;
+        for i in 0..100 {
+            lsm.insert(format!("key{:03}", i), format!("value{}", i))?;
+        }
+        lsm.flush()?;
+
+        let cache_stats = |lsm: &LSMTree<String, String>| (lsm.stats().block_cache_hits, lsm.stats().block_cache_misses);
+        assert_eq!(lsm.get(&"key005".to_string())?, Some("value5".to_string()));
+        assert_eq!(cache_stats(&lsm), (0, 1));
+        // Same block
+        assert_eq!(lsm.get(&"key006".to_string())?, Some("value6".to_string()));
+        assert_eq!(cache_stats(&lsm), (1, 1));
+        lsm.get_many(&["key007".to_string(), "key050".to_string()])?;
+        assert_eq!(cache_stats(&lsm), (2, 2));
+        drop(lsm);
+
+        let lsm = LSMTree::<String, String>::with_config(config(0))?;
+        assert_eq!(lsm.get(&"key005".to_string())?, Some("value5".to_string()));
+        assert_eq!(cache_stats(&lsm), (0, 0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_bytes() -> Result<()> {
+        // Stands in for a key type from another crate, with neither `Ord` nor serde impls
+        struct Point {
+            x: u16,
+            y: u16,
+        }
+        let encode = |point: &Point| [point.x.to_be_bytes(), point.y.to_be_bytes()].concat();
+
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::<Vec<u8>, String>::with_config(config.clone())?;
+        lsm.insert_with_key_bytes(encode(&Point { x: 300, y: 1 }), "c".to_string())?;
+        lsm.insert_with_key_bytes(encode(&Point { x: 2, y: 7 }), "b".to_string())?;
+        lsm.flush()?;
+        lsm.insert_with_key_bytes(encode(&Point { x: 2, y: 5 }), "a".to_string())?;
+        lsm.close()?;
+
+        let lsm = LSMTree::<Vec<u8>, String>::with_config(config)?;
+        assert_eq!(lsm.get_by_key_bytes(&encode(&Point { x: 2, y: 7 }))?, Some("b".to_string()));
+        assert_eq!(lsm.get_by_key_bytes(&encode(&Point { x: 2, y: 6 }))?, None);
+        let values: Vec<String> = lsm.iter()?.map(|entry| entry.map(|(_, value)| value)).collect::<Result<_>>()?;
+        assert_eq!(values, vec!["a", "b", "c"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_on_open() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = |verify_on_open| Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            verify_on_open,
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config(true))?;
+        lsm.insert("key".to_string(), "value".to_string())?;
+        lsm.flush()?;
+        let path = lsm.sstables[0].path().to_path_buf();
+        drop(lsm);
+        LSMTree::<String, String>::with_config(config(true))?;
+
+        let mut bytes = std::fs::read(&path)?;
+        bytes[0] ^= 1;
+        std::fs::write(&path, bytes)?;
+        assert!(LSMTree::<String, String>::with_config(config(false)).is_ok());
+        assert!(matches!(
+            LSMTree::<String, String>::with_config(config(true)),
+            Err(LSMError::Corruption { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_open_files() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            max_open_files: Some(2),
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config.clone())?;
+        for i in 0..5 {
+            lsm.insert(format!("key{}", i), format!("value{}", i))?;
+            lsm.flush()?;
+        }
+        drop(lsm);
+
+        let lsm = LSMTree::<String, String>::with_config(config)?;
+        let opened = |lsm: &LSMTree<String, String>| lsm.stats().sstable_files_opened;
+        // Reading the tables in turn evicts every handle before it is used again
+        for _ in 0..2 {
+            for i in 0..5 {
+                assert_eq!(lsm.get(&format!("key{}", i))?, Some(format!("value{}", i)));
+            }
+        }
+        assert_eq!(opened(&lsm), 10);
+        // The two most recently used tables keep their handles
+        lsm.get(&"key3".to_string())?;
+        lsm.get(&"key4".to_string())?;
+        assert_eq!(opened(&lsm), 10);
+
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            max_open_files: Some(0),
+            ..Config::default()
+        };
+        assert!(matches!(
+            LSMTree::<String, String>::with_config(config),
+            Err(LSMError::InvalidConfig(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wal_disabled() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            wal_enabled: false,
+            ..Config::default()
+        };
+
+        {
+            let mut lsm = LSMTree::with_config(config.clone())?;
+            lsm.insert("key1".to_string(), "value1".to_string())?;
+        }
+        assert!(!temp_dir.path().join("wal.log").exists());
+
+        let mut lsm = LSMTree::<String, String>::with_config(config.clone())?;
+        assert_eq!(lsm.get(&"key1".to_string())?, None);
+
+        // Closing the tree flushes the memtable
+        lsm.insert("key2".to_string(), "value2".to_string())?;
+        lsm.close()?;
+        let lsm = LSMTree::<String, String>::with_config(config)?;
+        assert_eq!(lsm.get(&"key2".to_string())?, Some("value2".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ttl_expiry() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            clock: clock.clone(),
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config.clone())?;
+
+        lsm.insert("old".to_string(), "value".to_string())?;
+        lsm.flush()?;
+        lsm.insert_with_ttl("old".to_string(), "short".to_string(), Duration::from_secs(10))?;
+        lsm.insert_with_ttl("flushed".to_string(), "short".to_string(), Duration::from_secs(10))?;
+        lsm.flush()?;
+        lsm.insert_with_ttl("mem".to_string(), "short".to_string(), Duration::from_secs(10))?;
+        lsm.insert_with_ttl("long".to_string(), "long".to_string(), Duration::from_secs(60))?;
+
+        assert_eq!(lsm.get(&"old".to_string())?, Some("short".to_string()));
+        assert_eq!(lsm.get(&"flushed".to_string())?, Some("short".to_string()));
+        assert_eq!(lsm.get(&"mem".to_string())?, Some("short".to_string()));
+
+        clock.advance(Duration::from_secs(10));
+        // An expired value hides older versions of the key instead of exposing them
+        assert_eq!(lsm.get(&"old".to_string())?, None);
+        assert_eq!(lsm.get(&"flushed".to_string())?, None);
+        assert_eq!(lsm.get(&"mem".to_string())?, None);
+        assert_eq!(lsm.iter()?.collect::<Result<Vec<_>>>()?, vec![("long".to_string(), "long".to_string())]);
+
+        // Expiry times survive WAL replay
+        drop(lsm);
+        let lsm = LSMTree::<String, String>::with_config(config)?;
+        assert_eq!(lsm.get(&"mem".to_string())?, None);
+        assert_eq!(lsm.get(&"long".to_string())?, Some("long".to_string()));
+        clock.advance(Duration::from_secs(50));
+        assert_eq!(lsm.get(&"long".to_string())?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zero_index_interval_rejected() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            index_interval: 0,
+            ..Config::default()
+        };
+        assert!(matches!(
+            LSMTree::<String, String>::with_config(config),
+            Err(LSMError::InvalidConfig(_))
+        ));
+
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            block_size: 0,
+            ..Config::default()
+        };
+        assert!(matches!(
+            LSMTree::<String, String>::with_config(config),
+            Err(LSMError::InvalidConfig(_))
+        ));
+
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            max_sstables_before_flush_merge: Some(0),
+            ..Config::default()
+        };
+        assert!(matches!(
+            LSMTree::<String, String>::with_config(config),
+            Err(LSMError::InvalidConfig(_))
+        ));
+
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            max_sstable_bytes: Some(0),
+            ..Config::default()
+        };
+        assert!(matches!(
+            LSMTree::<String, String>::with_config(config),
+            Err(LSMError::InvalidConfig(_))
+        ));
+
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            memtable_shards: 0,
+            ..Config::default()
+        };
+        assert!(matches!(
+            LSMTree::<String, String>::with_config(config),
+            Err(LSMError::InvalidConfig(_))
+        ));
+
+        #[cfg(feature = "crossbeam-skiplist")]
+        {
+            let config = Config {
+                data_dir: temp_dir.path().to_path_buf(),
+                memtable: MemTableKind::SkipList,
+                memtable_shards: 2,
+                ..Config::default()
+            };
+            assert!(matches!(
+                LSMTree::<String, String>::with_config(config),
+                Err(LSMError::InvalidConfig(_))
+            ));
+        }
+
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            namespace: "../escape".to_string(),
+            ..Config::default()
+        };
+        assert!(matches!(
+            LSMTree::<String, String>::with_config(config),
+            Err(LSMError::InvalidConfig(_))
+        ));
+
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            flush_interval: Some(Duration::ZERO),
+            ..Config::default()
+        };
+        assert!(matches!(
+            LSMTree::<String, String>::with_config(config),
+            Err(LSMError::InvalidConfig(_))
+        ));
+
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            write_buffer_bytes: 0,
+            ..Config::default()
+        };
+        assert!(matches!(
+            LSMTree::<String, String>::with_config(config),
+            Err(LSMError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_buffer_sizes() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        for (write_buffer_bytes, read_buffer_bytes) in [(1, 1), (1 << 20, 1 << 20)] {
+            let config = Config {
+                data_dir: temp_dir.path().to_path_buf(),
+                write_buffer_bytes,
+                read_buffer_bytes,
+                ..Config::default()
+            };
+            let mut lsm = LSMTree::with_config(config.clone())?;
+            for i in 0..100 {
+                lsm.insert(i, format!("value{}", i))?;
+            }
+            lsm.flush()?;
+            drop(lsm);
+
+            let lsm = LSMTree::<i32, String>::with_config(config)?;
+            assert_eq!(lsm.get(&42)?, Some("value42".to_string()));
+            assert_eq!(lsm.range(Bound::Included(10), Bound::Excluded(20))?.count(), 10);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_temp_dir() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let scratch = TempDir::new().unwrap();
+        let stale = scratch.path().join("sstable_000042.db.tmp");
+        fs::write(&stale, b"partial")?;
+        fs::write(scratch.path().join("notes.txt"), b"unrelated")?;
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            temp_dir: Some(scratch.path().to_path_buf()),
+            compaction_threshold: None,
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config.clone())?;
+        assert!(!stale.exists());
+
+        for table in 0..2 {
+            for i in 0..100 {
+                lsm.insert(i, format!("value{}_{}", table, i))?;
+            }
+            lsm.flush()?;
+        }
+        lsm.compact()?;
+        assert_eq!(lsm.get(&42)?, Some("value1_42".to_string()));
+        let files: Vec<_> = fs::read_dir(scratch.path())?.map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(files, vec!["notes.txt"]);
+        assert!(sstable_path(&config, 0, lsm.manifest.sstables[0].id).exists());
+
+        // Tables are written in the temporary directory
+        fs::remove_dir_all(scratch.path())?;
+        lsm.insert(1000, "value".to_string())?;
+        assert!(lsm.flush().is_err());
+        fs::create_dir(scratch.path())?;
+        lsm.flush()?;
+        assert_eq!(lsm.get(&1000)?, Some("value".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_read_only() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            level_directories: true,
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config.clone())?;
+        for i in 0..50 {
+            lsm.insert(i, format!("value{}", i))?;
+        }
+        lsm.flush()?;
+        lsm.insert(50, "unflushed".to_string())?;
+        lsm.delete(0)?;
+        // Leave the last writes in the write-ahead log
+        drop(lsm);
+
+        fn read_files(dir: &Path, files: &mut Vec<(PathBuf, Vec<u8>)>) -> Result<()> {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    read_files(&path, files)?;
+                } else {
+                    files.push((path.clone(), fs::read(&path)?));
+                }
+            }
+            Ok(())
+        }
+        let files = |dir: &Path| -> Result<Vec<(PathBuf, Vec<u8>)>> {
+            let mut files = Vec::new();
+            read_files(dir, &mut files)?;
+            files.sort();
+            Ok(files)
+        };
+        let before = files(temp_dir.path())?;
+
+        // Tables stay where they are even though the layout setting changed
+        let mut lsm = LSMTree::<i32, String>::open_read_only(Config { level_directories: false, ..config })?;
+        assert!(lsm.is_read_only());
+        assert_eq!(lsm.get(&10)?, Some("value10".to_string()));
+        assert_eq!(lsm.get(&50)?, Some("unflushed".to_string()));
+        assert_eq!(lsm.get(&0)?, None);
+        assert_eq!(lsm.iter()?.count(), 50);
+        assert_eq!(lsm.range(Bound::Included(10), Bound::Excluded(20))?.count(), 10);
+
+        assert!(matches!(lsm.insert(1, "x".to_string()), Err(LSMError::ReadOnly)));
+        assert!(matches!(lsm.delete(1), Err(LSMError::ReadOnly)));
+        assert!(matches!(lsm.insert_batch(vec![(1, "x".to_string())]), Err(LSMError::ReadOnly)));
+        assert!(matches!(lsm.flush(), Err(LSMError::ReadOnly)));
+        assert!(matches!(lsm.compact(), Err(LSMError::ReadOnly)));
+        assert!(matches!(lsm.clear(), Err(LSMError::ReadOnly)));
+        assert_eq!(lsm.get(&1)?, Some("value1".to_string()));
+        lsm.close()?;
+        assert_eq!(files(temp_dir.path())?, before);
+
+        let missing = temp_dir.path().join("missing");
+        let config = Config { data_dir: missing.clone(), ..Config::default() };
+        assert!(LSMTree::<i32, String>::open_read_only(config).is_err());
+        assert!(!missing.exists());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_data_dir_not_writable() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let data_dir = temp_dir.path().join("data");
+        let config = Config { data_dir: data_dir.clone(), ..Config::default() };
+        LSMTree::<i32, String>::with_config(config.clone())?.close()?;
+        // Under a file, the directory can't even be created
+        std::fs::write(temp_dir.path().join("file"), b"")?;
+        let under_file = Config { data_dir: temp_dir.path().join("file").join("data"), ..config.clone() };
+        assert!(matches!(LSMTree::<i32, String>::with_config(under_file), Err(LSMError::NotWritable { .. })));
+
+        std::fs::set_permissions(&data_dir, std::fs::Permissions::from_mode(0o555))?;
+        // Permissions don't stop root
+        if std::fs::write(data_dir.join("probe"), b"").is_ok() {
+            return Ok(());
+        }
+
+        let result = LSMTree::<i32, String>::with_config(config.clone());
+        assert!(matches!(result, Err(LSMError::NotWritable { path, .. }) if path == data_dir.display().to_string()));
+        let missing = Config { data_dir: data_dir.join("missing"), ..config.clone() };
+        assert!(matches!(LSMTree::<i32, String>::with_config(missing), Err(LSMError::NotWritable { .. })));
+        // Reading needs no write access
+        LSMTree::<i32, String>::open_read_only(config)?;
+        std::fs::set_permissions(&data_dir, std::fs::Permissions::from_mode(0o755))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_mismatch() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = |schema: Option<String>| Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            schema,
+            ..Config::default()
+        };
+        // Written without a schema, which the first open with one records
+        let mut lsm = LSMTree::with_config(config(None))?;
+        lsm.insert("key".to_string(), "value".to_string())?;
+        lsm.close()?;
+        let lsm = LSMTree::<String, String>::with_config(config(Some(type_schema::<String, String>())))?;
+        assert_eq!(lsm.get(&"key".to_string())?, Some("value".to_string()));
+        drop(lsm);
+
+        match LSMTree::<String, u64>::with_config(config(Some(type_schema::<String, u64>()))) {
+            Err(LSMError::SchemaMismatch { expected, found }) => {
+                assert_eq!(expected, type_schema::<String, u64>());
+                assert_eq!(found, type_schema::<String, String>());
+            }
+            _ => panic!("expected a schema mismatch"),
+        }
+        let config = config(Some("users v2".to_string()));
+        let result = LSMTree::<String, String>::open_read_only(config.clone());
+        assert!(matches!(result, Err(LSMError::SchemaMismatch { .. })));
+
+        // The check is opt-in
+        let lsm = LSMTree::<String, String>::with_config(Config { schema: None, ..config })?;
+        assert_eq!(lsm.get(&"key".to_string())?, Some("value".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_value_size() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            memtable_size_threshold: 1024,
+            max_value_size: Some(256),
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config.clone())?;
+
+        let huge = "x".repeat(2048);
+        assert!(matches!(
+            lsm.insert("key".to_string(), huge.clone()),
+            Err(LSMError::ValueTooLarge { size: 2056, max: 256 })
+        ));
+        assert!(matches!(
+            lsm.insert_with_ttl("key".to_string(), huge.clone(), Duration::from_secs(1)),
+            Err(LSMError::ValueTooLarge { .. })
+        ));
+        // One oversized value rejects the whole batch
+        let batch = vec![("a".to_string(), "small".to_string()), ("b".to_string(), huge)];
+        assert!(matches!(lsm.insert_batch(batch), Err(LSMError::ValueTooLarge { .. })));
+
+        // Nothing was written, neither to the memtable nor to the log or an SSTable
+        assert!(lsm.is_empty()?);
+        assert_eq!(lsm.stats().total_flushes, 0);
+        lsm.insert("key".to_string(), "x".repeat(248))?;
+        drop(lsm);
+        let lsm = LSMTree::<String, String>::with_config(config)?;
+        assert_eq!(lsm.iter()?.count(), 1);
+
+        Ok(())
+    }
 
     #[test]
     fn test_large_dataset() -> Result<()> {
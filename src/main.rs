@@ -6,7 +6,8 @@ use lsm_tree::{Config, LSMTree};
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let config = Config {
         memtable_size_threshold: 4096,  // 4KB threshold
-        data_dir: "demo_db".to_string(),
+        data_dir: "demo_db".into(),
+        ..Config::default()
     };
     
     let mut lsm_tree = LSMTree::with_config(config)?;
@@ -43,5 +44,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("Can read new data - bulk:key:500 -> {}", value);
     }
 
+    lsm_tree.close()?;
     Ok(())
 }
@@ -0,0 +1,107 @@
+//! The manifest records which SSTables make up the tree.
+//!
+//! It lists the live SSTables (by id, with their compaction level) oldest first, together with
+//! the next id to allocate, the name of the comparator that orders the keys and, if configured,
+//! the schema the keys and values were written with. It is rewritten after every flush and
+//! compaction by writing a temporary file, syncing it and renaming it over the old one. On
+//! startup it is the source of truth: SSTable files it doesn't list (such as the output of a
+//! compaction interrupted by a crash) are ignored.
+
+use crate::{sync_parent_dir, tmp_path, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::Path;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    /// The live SSTables, oldest first
+    pub(crate) sstables: Vec<ManifestEntry>,
+    /// Id for the next SSTable to be written
+    pub(crate) next_id: u64,
+    /// Name of the comparator the keys are ordered by
+    pub(crate) comparator: String,
+    /// `Config::schema` of the data, if it was ever opened with one
+    pub(crate) schema: Option<String>,
+    /// The last sequence number written to SSTables, which writes replayed from the write-ahead
+    /// log are numbered after
+    pub(crate) last_sequence: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ManifestEntry {
+    pub(crate) id: u64,
+    /// Compaction level; flushed tables start at level 0
+    pub(crate) level: u32,
+}
+
+impl Manifest {
+    /// Hands out the next SSTable id
+    pub(crate) fn allocate_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Reads the manifest at `path`, or returns `None` if there isn't one
+    pub(crate) fn load(path: &Path) -> Result<Option<Self>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(bincode::deserialize_from(BufReader::new(file))?))
+    }
+
+    /// Atomically replaces the manifest at `path`
+    pub(crate) fn store(&self, path: &Path) -> Result<()> {
+        let tmp_path = tmp_path(path);
+
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&bincode::serialize(self)?)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+        sync_parent_dir(path)?;
+
+        Ok(())
+    }
+}
+
+/// Name of the manifest file within the data directory, after the tree's namespace prefix
+pub(crate) const MANIFEST_FILE: &str = "MANIFEST";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_manifest_round_trip() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let path = &temp_dir.path().join(MANIFEST_FILE);
+        assert_eq!(Manifest::load(path)?, None);
+
+        let manifest = Manifest {
+            sstables: vec![ManifestEntry { id: 3, level: 2 }, ManifestEntry { id: 7, level: 0 }],
+            next_id: 8,
+            comparator: "natural".to_string(),
+            schema: None,
+            last_sequence: 0,
+        };
+        manifest.store(path)?;
+        assert_eq!(Manifest::load(path)?, Some(manifest));
+
+        let manifest = Manifest {
+            sstables: vec![ManifestEntry { id: 8, level: 1 }],
+            next_id: 9,
+            comparator: "natural".to_string(),
+            schema: Some("alloc::string::String/u64".to_string()),
+            last_sequence: 42,
+        };
+        manifest.store(path)?;
+        assert_eq!(Manifest::load(path)?, Some(manifest));
+        assert!(!temp_dir.path().join("MANIFEST.tmp").exists());
+
+        Ok(())
+    }
+}
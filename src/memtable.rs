@@ -2,53 +2,633 @@
 //!
 //! A MemTable is the in-memory component of the LSM tree that stores key-value pairs in sorted order
 //! using a BTreeMap. It accumulates writes until it reaches a size threshold, at which point it is
-//! flushed to disk as an SSTable. The size tracking is done by estimating the serialized size of
-//! entries using bincode.
+//! flushed to disk as an SSTable. The size tracking adds up the serialized sizes of keys and
+//! values in the memtable's encoding, the one its SSTables are written with.
+//!
+//! The map is reference counted, so cloning a MemTable (as snapshots do) is cheap; the first
+//! write after a clone copies the map. Values are stored behind an `Arc` too, so that copy
+//! and reads of hot keys through `LSMTree::get_arc` don't clone the values themselves.
+//!
+//! Keys are ordered by the memtable's comparator, `NaturalOrder` unless given another one.
+//!
+//! Sizes are added up with saturating arithmetic, and serialized sizes, which are `u64`, are
+//! clamped to `usize::MAX` rather than truncated, so on 32-bit targets a memtable that outgrows
+//! the address space in serialized bytes reads as full instead of wrapping around to empty.
+//!
+//! Each write takes the next sequence number from the memtable, which keeps it with the entry
+//! (see the `sequence` module).
+//!
+//! Writes need `&mut self`; `skiplist::SkipListMemTable`, with the `crossbeam-skiplist`
+//! feature, takes them through `&self` from several threads at once instead. The tree reaches
+//! its memtables through the `MemTableStore` trait, and `Config::memtable` picks which of the
+//! two it writes to. `Config::memtable_shards` splits a `MemTable` into several, each behind a
+//! lock of its own, by key hash (see the `sharded` module).
 
 use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+use crate::comparator::{Comparator, KeyRef, NaturalOrder, OrderedKey, Probe};
+use crate::stats::Counters;
+use crate::encoding::Encoding;
+use crate::merge;
+use crate::range_tombstone::{self, RangeTombstone};
+use crate::sequence::Sequenced;
+use crate::sharded::ShardedMemTable;
 use crate::Result;
 
+/// A stored slot for a key: either a live value, a value with an expiry time, a tombstone
+/// marking the key as deleted, or merge operands. Tombstones are flushed to SSTables like
+/// regular values so that they shadow older tables. The order of the variants is part of the
+/// SSTable format.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Entry<V> {
+    Value(V),
+    Tombstone,
+    /// A value that reads as deleted once the clock passes `expires_at`
+    /// (milliseconds since the UNIX epoch)
+    Expiring { value: V, expires_at: u64 },
+    /// Operands recorded by `LSMTree::merge`, oldest first, still to be applied onto the older
+    /// versions of the key; see the `merge` module
+    Merge(Vec<V>),
+}
+
+impl<V> Entry<V> {
+    /// Returns the stored value, or `None` for a tombstone or merge operands. Expiry is not
+    /// checked.
+    pub fn value(&self) -> Option<&V> {
+        match self {
+            Entry::Value(value) | Entry::Expiring { value, .. } => Some(value),
+            Entry::Tombstone | Entry::Merge(_) => None,
+        }
+    }
+
+    pub fn into_value(self) -> Option<V> {
+        match self {
+            Entry::Value(value) | Entry::Expiring { value, .. } => Some(value),
+            Entry::Tombstone | Entry::Merge(_) => None,
+        }
+    }
+
+    pub fn is_merge(&self) -> bool {
+        matches!(self, Entry::Merge(_))
+    }
+
+    pub fn is_expired(&self, now_millis: u64) -> bool {
+        matches!(self, Entry::Expiring { expires_at, .. } if *expires_at <= now_millis)
+    }
+
+    /// Returns the value if it is neither deleted nor expired at `now_millis`
+    pub fn live_value(&self, now_millis: u64) -> Option<&V> {
+        if self.is_expired(now_millis) {
+            return None;
+        }
+        self.value()
+    }
+
+    pub fn into_live_value(self, now_millis: u64) -> Option<V> {
+        if self.is_expired(now_millis) {
+            return None;
+        }
+        self.into_value()
+    }
+
+    pub fn as_ref(&self) -> Entry<&V> {
+        match self {
+            Entry::Value(value) => Entry::Value(value),
+            Entry::Tombstone => Entry::Tombstone,
+            Entry::Expiring { value, expires_at } => Entry::Expiring { value, expires_at: *expires_at },
+            Entry::Merge(operands) => Entry::Merge(operands.iter().collect()),
+        }
+    }
+
+    /// Converts the value, or each merge operand, keeping the kind of entry and its expiry time
+    pub fn map<U>(self, mut f: impl FnMut(V) -> U) -> Entry<U> {
+        match self {
+            Entry::Value(value) => Entry::Value(f(value)),
+            Entry::Tombstone => Entry::Tombstone,
+            Entry::Expiring { value, expires_at } => Entry::Expiring { value: f(value), expires_at },
+            Entry::Merge(operands) => Entry::Merge(operands.into_iter().map(f).collect()),
+        }
+    }
+}
+
+/// An entry with the sequence number of the write that made it
+pub(crate) type Version<V> = Sequenced<Entry<Arc<V>>>;
+
 pub struct MemTable<K, V> {
-    pub(crate) data: BTreeMap<K, V>,
+    data: Arc<BTreeMap<OrderedKey<K>, Version<V>>>,
+    /// Recorded by `delete_range`, oldest first. Entries in their ranges are newer than them.
+    range_tombstones: Arc<Vec<RangeTombstone<K>>>,
+    comparator: Arc<dyn Comparator<K>>,
+    encoding: Encoding,
     size_bytes: usize,
+    /// Sequence number of the last write
+    last_sequence: u64,
+    /// Records the sizes of the keys and values written
+    counters: Arc<Counters>,
+}
+
+// Not derived, since that would require `K: Clone` and `V: Clone`
+impl<K, V> Clone for MemTable<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            data: Arc::clone(&self.data),
+            range_tombstones: Arc::clone(&self.range_tombstones),
+            comparator: Arc::clone(&self.comparator),
+            encoding: self.encoding,
+            size_bytes: self.size_bytes,
+            last_sequence: self.last_sequence,
+            counters: Arc::clone(&self.counters),
+        }
+    }
 }
 
+impl<K, V> Default for MemTable<K, V>
+where
+    K: Ord + serde::Serialize + Clone,
+    V: serde::Serialize,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes need `K: Clone`: the first write after the memtable was cloned copies the map
 impl<K, V> MemTable<K, V>
 where
     K: Ord + serde::Serialize + Clone,
-    V: serde::Serialize + Clone,
+    V: serde::Serialize,
 {
     pub fn new() -> Self {
+        Self::with_comparator(Arc::new(NaturalOrder))
+    }
+
+    pub fn with_comparator(comparator: Arc<dyn Comparator<K>>) -> Self {
+        Self::with_comparator_and_encoding(comparator, Encoding::default())
+    }
+
+    /// Creates a memtable that measures entries by their size in `encoding`
+    pub fn with_comparator_and_encoding(comparator: Arc<dyn Comparator<K>>, encoding: Encoding) -> Self {
         Self {
-            data: BTreeMap::new(),
+            data: Arc::new(BTreeMap::new()),
+            range_tombstones: Arc::new(Vec::new()),
+            comparator,
+            encoding,
             size_bytes: 0,
+            last_sequence: 0,
+            counters: Arc::new(Counters::default()),
         }
     }
 
+    /// Makes writes record their sizes in `counters`
+    pub(crate) fn with_counters(mut self, counters: Arc<Counters>) -> Self {
+        self.counters = counters;
+        self
+    }
+
+    /// Makes the sequence numbers of writes continue after `last_sequence`
+    pub(crate) fn with_last_sequence(mut self, last_sequence: u64) -> Self {
+        self.set_last_sequence(last_sequence);
+        self
+    }
+
+    /// Makes the next write take the number after `last_sequence`, for memtables that share
+    /// a sequence with others
+    pub(crate) fn set_last_sequence(&mut self, last_sequence: u64) {
+        self.last_sequence = last_sequence;
+    }
+
+    /// Sequence number of the last write, or of the one the memtable continues from if it
+    /// has none
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    pub(crate) fn counters(&self) -> &Arc<Counters> {
+        &self.counters
+    }
+
+    /// Takes a sequence number for a write made elsewhere, such as a bulk load
+    pub(crate) fn next_sequence(&mut self) -> u64 {
+        self.last_sequence += 1;
+        self.last_sequence
+    }
+
+    pub fn comparator(&self) -> &Arc<dyn Comparator<K>> {
+        &self.comparator
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Inserts a value, returning the size of the new entry
     pub fn put(&mut self, key: K, value: V) -> Result<usize> {
-        let key_size = bincode::serialized_size(&key)? as usize;
-        let value_size = bincode::serialized_size(&value)? as usize;
-        
-        self.data.insert(key, value);
-        self.size_bytes += key_size + value_size;
-        
-        Ok(key_size + value_size)
+        let key_size = self.serialized_size(&key)?;
+        let value_size = self.serialized_size(&value)?;
+
+        self.insert_sized(key, Entry::Value(Arc::new(value)), key_size, value_size);
+
+        Ok(key_size.saturating_add(value_size))
+    }
+
+    /// Inserts a value that expires at `expires_at` (milliseconds since the UNIX epoch)
+    pub fn put_with_expiry(&mut self, key: K, value: V, expires_at: u64) -> Result<usize> {
+        let key_size = self.serialized_size(&key)?;
+        let value_size = self.serialized_size(&value)?.saturating_add(self.serialized_size(&expires_at)?);
+
+        let entry = Entry::Expiring { value: Arc::new(value), expires_at };
+        self.insert_sized(key, entry, key_size, value_size);
+
+        Ok(key_size.saturating_add(value_size))
+    }
+
+    /// Inserts all `entries`, returning their total size. Sizes are computed up front, so if
+    /// any entry fails to serialize nothing is inserted.
+    pub fn put_batch(&mut self, entries: Vec<(K, V)>) -> Result<usize> {
+        self.apply_batch(entries.into_iter().map(|(key, value)| (key, Entry::Value(value))).collect())
+    }
+
+    /// Stores each entry in order, so later entries for a key replace earlier ones, and returns
+    /// their total size. Like `put_batch`, nothing is stored if any entry fails to serialize.
+    pub fn apply_batch(&mut self, entries: Vec<(K, Entry<V>)>) -> Result<usize> {
+        let sizes = entries
+            .iter()
+            .map(|(key, entry)| Ok((self.serialized_size(key)?, self.payload_size(entry)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let total = sizes
+            .iter()
+            .fold(0usize, |total, (key_size, entry_size)| total.saturating_add(*key_size).saturating_add(*entry_size));
+
+        for ((key, entry), (key_size, entry_size)) in entries.into_iter().zip(sizes) {
+            self.insert_sized(key, entry.map(Arc::new), key_size, entry_size);
+        }
+
+        Ok(total)
+    }
+
+    /// Stores `entry` for `key`, replacing any entry the key has, and returns its size
+    pub(crate) fn put_entry(&mut self, key: K, entry: Entry<Arc<V>>) -> Result<usize> {
+        let key_size = self.serialized_size(&key)?;
+        let entry_size = self.payload_size(&entry)?;
+
+        self.insert_sized(key, entry, key_size, entry_size);
+
+        Ok(key_size.saturating_add(entry_size))
+    }
+
+    /// Appends merge operands to those stored for `key`, and returns the size of its new entry.
+    /// Fails with `NoMergeOperator` if the key has an entry that isn't a merge, since the
+    /// operands would have to be applied onto it.
+    pub(crate) fn append_operands(&mut self, key: K, operands: Vec<V>) -> Result<usize> {
+        let entry = Entry::Merge(operands.into_iter().map(Arc::new).collect());
+        let entry = match self.get_entry(&key) {
+            Some(older) => merge::combine::<V, _>(None, entry, older.clone())?,
+            None => entry,
+        };
+        self.put_entry(key, entry)
+    }
+
+    /// Records a tombstone for `key`, shadowing any value stored for it here or in older SSTables
+    pub fn delete(&mut self, key: K) -> Result<usize> {
+        let key_size = self.serialized_size(&key)?;
+
+        self.insert_sized(key, Entry::Tombstone, key_size, 0);
+
+        Ok(key_size)
     }
 
+    /// Records a range tombstone deleting the keys in `[start, end)`, here and in older
+    /// SSTables, and removes the entries in that range. Returns the size of the tombstone;
+    /// an empty or inverted range records nothing.
+    pub fn delete_range(&mut self, start: K, end: K) -> Result<usize> {
+        if self.comparator.compare(&start, &end).is_ge() {
+            return Ok(0);
+        }
+        let size = self.serialized_size(&start)?.saturating_add(self.serialized_size(&end)?);
+
+        self.remove_range(&start, &end);
+        let sequence = self.next_sequence();
+        Arc::make_mut(&mut self.range_tombstones).push(RangeTombstone { start, end, sequence });
+        self.size_bytes = self.size_bytes.saturating_add(size);
+        Ok(size)
+    }
+
+    /// Removes the entries in `[start, end)` along with their sizes, without recording a
+    /// range tombstone; `start` must be before `end`
+    pub(crate) fn remove_range(&mut self, start: &K, end: &K) {
+        let comparator = &*self.comparator;
+        let (start_probe, end_probe) = (Probe::new(start, comparator), Probe::new(end, comparator));
+        let data = Arc::make_mut(&mut self.data);
+        let mut removed = data.split_off(&start_probe as &dyn KeyRef<K>);
+        let mut after = removed.split_off(&end_probe as &dyn KeyRef<K>);
+        data.append(&mut after);
+        for (key, entry) in &removed {
+            // Both were sized successfully when the entry was inserted
+            let key_size = self.serialized_size(&key.key).unwrap_or(0);
+            self.size_bytes = self.size_bytes.saturating_sub(key_size.saturating_add(self.entry_size(&entry.entry)));
+        }
+    }
+
+    /// Inserts an entry whose key and payload sizes were already computed. If the key is
+    /// present, only the difference between the old and new payload is accounted for.
+    fn insert_sized(&mut self, key: K, entry: Entry<Arc<V>>, key_size: usize, entry_size: usize) {
+        self.counters.key_sizes.record(key_size);
+        if entry.value().is_some() {
+            self.counters.value_sizes.record(entry_size);
+        }
+        let key = OrderedKey::new(key, Arc::clone(&self.comparator));
+        let entry = Sequenced::new(entry, self.next_sequence());
+        match Arc::make_mut(&mut self.data).insert(key, entry) {
+            Some(old) => self.size_bytes = self.size_bytes.saturating_sub(self.entry_size(&old.entry)),
+            None => self.size_bytes = self.size_bytes.saturating_add(key_size),
+        }
+        self.size_bytes = self.size_bytes.saturating_add(entry_size);
+    }
+
+    /// Size of `value` in the memtable's encoding, clamped to `usize::MAX`
+    fn serialized_size<T: serde::Serialize + ?Sized>(&self, value: &T) -> Result<usize> {
+        let size = self.encoding.serialized_size(value)?;
+        Ok(usize::try_from(size).unwrap_or(usize::MAX))
+    }
+
+    /// Payload size of a stored entry as accounted in `size_bytes`
+    fn entry_size(&self, entry: &Entry<Arc<V>>) -> usize {
+        // The entry was sized successfully when it was inserted, so this can't fail in practice
+        self.payload_size(entry).unwrap_or(0)
+    }
+
+    /// Size of an entry's value and expiry time, if any, in the memtable's encoding
+    fn payload_size<T: serde::Serialize>(&self, entry: &Entry<T>) -> Result<usize> {
+        Ok(match entry {
+            Entry::Value(value) => self.serialized_size(value)?,
+            Entry::Tombstone => 0,
+            Entry::Expiring { value, expires_at } => {
+                self.serialized_size(value)?.saturating_add(self.serialized_size(expires_at)?)
+            }
+            Entry::Merge(operands) => self.serialized_size(operands)?,
+        })
+    }
+
+    /// Returns the value for `key`; deleted keys are reported as `None`. Expiry is not checked.
     pub fn get(&self, key: &K) -> Option<&V> {
-        self.data.get(key)
+        self.get_entry(key).and_then(Entry::value).map(Arc::as_ref)
+    }
+
+    /// Returns the raw entry for `key`, including tombstones. A key without an entry that a
+    /// range tombstone covers has a tombstone.
+    pub fn get_entry(&self, key: &K) -> Option<&Entry<Arc<V>>> {
+        self.get_sequenced(key).map(|entry| entry.entry)
+    }
+
+    /// Like `get_entry`, with the sequence number of the write that made the entry
+    pub fn get_sequenced(&self, key: &K) -> Option<Sequenced<&Entry<Arc<V>>>> {
+        match self.data.get(&Probe::new(key, &*self.comparator) as &dyn KeyRef<K>) {
+            Some(entry) => Some(entry.as_ref()),
+            None => range_tombstone::covering_sequence(&self.range_tombstones, key, &*self.comparator)
+                .map(|sequence| Sequenced::new(const { &Entry::Tombstone }, sequence)),
+        }
+    }
+
+    /// Whether one of the memtable's range tombstones covers `key`
+    pub fn is_range_deleted(&self, key: &K) -> bool {
+        range_tombstone::is_covered(&self.range_tombstones, key, &*self.comparator)
+    }
+
+    /// The range tombstones recorded by `delete_range`, oldest first
+    pub fn range_tombstones(&self) -> &[RangeTombstone<K>] {
+        &self.range_tombstones
     }
 
     pub fn size(&self) -> usize {
         self.size_bytes
     }
 
+    /// Number of entries, including tombstones but not range tombstones
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Whether the memtable has neither entries nor range tombstones
     pub fn is_empty(&self) -> bool {
-        self.data.is_empty()
+        self.data.is_empty() && self.range_tombstones.is_empty()
     }
 
+    /// Iterates key-value pairs in key order, skipping tombstones. Expiry is not checked.
     pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
-        self.data.iter()
+        self.entries().filter_map(|(key, entry)| entry.value().map(|value| (key, value.as_ref())))
+    }
+
+    /// Iterates all entries in key order, including tombstones
+    pub fn entries(&self) -> impl Iterator<Item = (&K, &Entry<Arc<V>>)> {
+        self.sequenced_entries().map(|(key, entry)| (key, &entry.entry))
+    }
+
+    /// Like `entries`, with the sequence number of each entry
+    pub fn sequenced_entries(&self) -> impl Iterator<Item = (&K, &Version<V>)> {
+        self.data.iter().map(|(key, entry)| (&key.key, entry))
+    }
+
+    /// Iterates the entries within the given bounds in key order, including tombstones.
+    /// An empty or inverted range yields nothing.
+    pub fn range<'a>(
+        &'a self,
+        start: Bound<&K>,
+        end: Bound<&K>,
+    ) -> impl DoubleEndedIterator<Item = (&'a K, &'a Entry<Arc<V>>)> + 'a {
+        self.sequenced_range(start, end).map(|(key, entry)| (key, &entry.entry))
+    }
+
+    /// Like `range`, with the sequence number of each entry
+    pub fn sequenced_range<'a>(
+        &'a self,
+        start: Bound<&K>,
+        end: Bound<&K>,
+    ) -> impl DoubleEndedIterator<Item = (&'a K, &'a Version<V>)> + 'a {
+        let comparator = &*self.comparator;
+        let is_empty = match (start, end) {
+            (Bound::Included(s), Bound::Included(e)) => comparator.compare(s, e).is_gt(),
+            (Bound::Included(s) | Bound::Excluded(s), Bound::Included(e) | Bound::Excluded(e)) => {
+                comparator.compare(s, e).is_ge()
+            }
+            _ => false,
+        };
+
+        let start = start.map(|key| Probe::new(key, comparator));
+        let end = end.map(|key| Probe::new(key, comparator));
+        let bounds = (
+            start.as_ref().map(|probe| probe as &dyn KeyRef<K>),
+            end.as_ref().map(|probe| probe as &dyn KeyRef<K>),
+        );
+        // `BTreeMap::range` panics on inverted ranges
+        let range = if is_empty { None } else { Some(self.data.range::<dyn KeyRef<K>, _>(bounds)) };
+        range.into_iter().flatten().map(|(key, entry)| (&key.key, entry))
+    }
+}
+
+/// Which memtable the tree keeps its latest writes in, see `Config::memtable`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemTableKind {
+    /// `MemTable`, whose map snapshots share until the next write
+    #[default]
+    BTree,
+    /// `skiplist::SkipListMemTable`, which snapshots copy
+    #[cfg(feature = "crossbeam-skiplist")]
+    SkipList,
+}
+
+impl MemTableKind {
+    /// Moves the entries of `memtable`, e.g. replayed from the write-ahead log, into a
+    /// memtable of this kind, split into `shards` shards if there are more than one
+    pub(crate) fn convert<K, V>(self, memtable: MemTable<K, V>, shards: usize) -> Result<Box<DynMemTable<K, V>>>
+    where
+        K: Ord + serde::Serialize + Clone + Send + Sync + 'static,
+        V: serde::Serialize + Send + Sync + 'static,
+    {
+        Ok(match self {
+            MemTableKind::BTree if shards > 1 => Box::new(ShardedMemTable::from_memtable(memtable, shards)?),
+            MemTableKind::BTree => Box::new(memtable),
+            #[cfg(feature = "crossbeam-skiplist")]
+            MemTableKind::SkipList => Box::new(crate::skiplist::SkipListMemTable::from(memtable)),
+        })
+    }
+}
+
+/// Entries that a flush writes to level 0 SSTables of their own, see `flush_parts`
+pub(crate) struct FlushPart<'a, K, V> {
+    /// In key order
+    pub(crate) entries: Box<dyn Iterator<Item = (K, Version<V>)> + 'a>,
+    /// Number of entries, used to size buffers
+    pub(crate) len: usize,
+    pub(crate) range_tombstones: Arc<Vec<RangeTombstone<K>>>,
+}
+
+/// A memtable as the tree uses it, whatever its kind. Lookups and iteration return owned
+/// entries, since the skip list only lends its entries out through guards.
+pub(crate) trait MemTableStore<K, V>: Send + Sync {
+    fn last_sequence(&self) -> u64;
+    /// Takes a sequence number for a write made elsewhere, such as a bulk load
+    fn next_sequence(&mut self) -> u64;
+    fn comparator(&self) -> &Arc<dyn Comparator<K>>;
+    fn put(&mut self, key: K, value: V) -> Result<usize>;
+    fn put_with_expiry(&mut self, key: K, value: V, expires_at: u64) -> Result<usize>;
+    fn apply_batch(&mut self, entries: Vec<(K, Entry<V>)>) -> Result<usize>;
+    fn put_entry(&mut self, key: K, entry: Entry<Arc<V>>) -> Result<usize>;
+    fn append_operands(&mut self, key: K, operands: Vec<V>) -> Result<usize>;
+    fn delete(&mut self, key: K) -> Result<usize>;
+    fn delete_range(&mut self, start: K, end: K) -> Result<usize>;
+    /// The entry for `key` with its sequence number, a tombstone if a range tombstone covers it
+    fn get_sequenced(&self, key: &K) -> Option<Version<V>>;
+    fn range_tombstones(&self) -> Arc<Vec<RangeTombstone<K>>>;
+    fn size(&self) -> usize;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool;
+    /// The entries within the bounds in key order, with their sequence numbers
+    fn sequenced_range<'a>(
+        &'a self,
+        start: Bound<&K>,
+        end: Bound<&K>,
+    ) -> Box<dyn DoubleEndedIterator<Item = (K, Version<V>)> + 'a>;
+    /// A copy that later writes to this memtable don't change
+    fn snapshot(&self) -> Box<DynMemTable<K, V>>;
+    /// An empty memtable of the same kind, comparator and encoding, recording into the same
+    /// counters, whose sequence numbers continue this one's
+    fn next_memtable(&self) -> Box<DynMemTable<K, V>>;
+
+    /// The parts a flush writes, each to tables of its own, installed in this order: entries
+    /// of later parts hide those of earlier ones, and so do their range tombstones
+    fn flush_parts(&self) -> Vec<FlushPart<'_, K, V>> {
+        let entries = self.sequenced_range(Bound::Unbounded, Bound::Unbounded);
+        vec![FlushPart { entries, len: self.len(), range_tombstones: self.range_tombstones() }]
+    }
+
+    fn get_entry(&self, key: &K) -> Option<Entry<Arc<V>>> {
+        self.get_sequenced(key).map(|entry| entry.entry)
+    }
+}
+
+pub(crate) type DynMemTable<K, V> = dyn MemTableStore<K, V>;
+
+impl<K, V> MemTableStore<K, V> for MemTable<K, V>
+where
+    K: Ord + serde::Serialize + Clone + Send + Sync + 'static,
+    V: serde::Serialize + Send + Sync + 'static,
+{
+    fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    fn next_sequence(&mut self) -> u64 {
+        MemTable::next_sequence(self)
+    }
+
+    fn comparator(&self) -> &Arc<dyn Comparator<K>> {
+        &self.comparator
+    }
+
+    fn put(&mut self, key: K, value: V) -> Result<usize> {
+        MemTable::put(self, key, value)
+    }
+
+    fn put_with_expiry(&mut self, key: K, value: V, expires_at: u64) -> Result<usize> {
+        MemTable::put_with_expiry(self, key, value, expires_at)
+    }
+
+    fn apply_batch(&mut self, entries: Vec<(K, Entry<V>)>) -> Result<usize> {
+        MemTable::apply_batch(self, entries)
+    }
+
+    fn put_entry(&mut self, key: K, entry: Entry<Arc<V>>) -> Result<usize> {
+        MemTable::put_entry(self, key, entry)
+    }
+
+    fn append_operands(&mut self, key: K, operands: Vec<V>) -> Result<usize> {
+        MemTable::append_operands(self, key, operands)
+    }
+
+    fn delete(&mut self, key: K) -> Result<usize> {
+        MemTable::delete(self, key)
+    }
+
+    fn delete_range(&mut self, start: K, end: K) -> Result<usize> {
+        MemTable::delete_range(self, start, end)
+    }
+
+    fn get_sequenced(&self, key: &K) -> Option<Version<V>> {
+        MemTable::get_sequenced(self, key).map(|entry| entry.map(Entry::clone))
+    }
+
+    fn range_tombstones(&self) -> Arc<Vec<RangeTombstone<K>>> {
+        Arc::clone(&self.range_tombstones)
+    }
+
+    fn size(&self) -> usize {
+        self.size_bytes
+    }
+
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        MemTable::is_empty(self)
+    }
+
+    fn sequenced_range<'a>(
+        &'a self,
+        start: Bound<&K>,
+        end: Bound<&K>,
+    ) -> Box<dyn DoubleEndedIterator<Item = (K, Version<V>)> + 'a> {
+        Box::new(MemTable::sequenced_range(self, start, end).map(|(key, entry)| (key.clone(), entry.clone())))
+    }
+
+    fn snapshot(&self) -> Box<DynMemTable<K, V>> {
+        Box::new(self.clone())
+    }
+
+    fn next_memtable(&self) -> Box<DynMemTable<K, V>> {
+        let memtable = MemTable::with_comparator_and_encoding(Arc::clone(&self.comparator), self.encoding);
+        Box::new(memtable.with_counters(Arc::clone(&self.counters)).with_last_sequence(self.last_sequence))
     }
 }
 
@@ -70,10 +650,81 @@ mod tests {
         assert_eq!(table.get(&3), Some(&"three".to_string()));
         assert_eq!(table.get(&4), None);
         
-        total_size += table.put(2, "TWO".to_string())?;
+        assert_eq!(table.size(), total_size);
+
+        // Overwriting with a value of the same length doesn't change the size
+        table.put(2, "TWO".to_string())?;
         assert_eq!(table.get(&2), Some(&"TWO".to_string()));
-        
         assert_eq!(table.size(), total_size);
+        assert_eq!(table.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_memtable_size_on_overwrite() -> Result<()> {
+        let mut table = MemTable::new();
+        table.put("other".to_string(), "x".repeat(10))?;
+        let base = table.size();
+
+        table.put("key".to_string(), "x".repeat(100))?;
+        let expected = table.size();
+        for i in 0..1000 {
+            table.put("key".to_string(), format!("{:0100}", i))?;
+        }
+        assert_eq!(table.size(), expected);
+
+        // Shrinking and deleting the value keep the count exact
+        let key_size = bincode::serialized_size(&"key".to_string())? as usize;
+        let short_size = bincode::serialized_size(&"short".to_string())? as usize;
+        table.put("key".to_string(), "short".to_string())?;
+        assert_eq!(table.size(), base + key_size + short_size);
+        table.delete("key".to_string())?;
+        assert_eq!(table.size(), base + key_size);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memtable_size_saturates() {
+        let mut table = MemTable::<i32, String>::new();
+
+        // Sizes that would overflow `usize` stop at its maximum, and overwrites still shrink it
+        table.insert_sized(1, Entry::Value(Arc::new("a".to_string())), usize::MAX - 1, usize::MAX - 1);
+        table.insert_sized(2, Entry::Value(Arc::new("b".to_string())), usize::MAX - 1, 0);
+        assert_eq!(table.size(), usize::MAX);
+        table.insert_sized(1, Entry::Tombstone, 0, 0);
+        assert!(table.size() < usize::MAX);
+    }
+
+    /// A value that fails to serialize when `fail` is set
+    #[derive(Clone, Debug, PartialEq)]
+    struct Fallible {
+        fail: bool,
+    }
+
+    impl serde::Serialize for Fallible {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+            if self.fail {
+                return Err(serde::ser::Error::custom("unserializable value"));
+            }
+            serializer.serialize_bool(false)
+        }
+    }
+
+    #[test]
+    fn test_memtable_put_batch() -> Result<()> {
+        let mut table = MemTable::new();
+
+        let size = table.put_batch(vec![(1, Fallible { fail: false }), (2, Fallible { fail: false })])?;
+        assert_eq!(table.size(), size);
+        assert_eq!(table.iter().count(), 2);
+
+        // A failing entry leaves the memtable untouched
+        let result = table.put_batch(vec![(3, Fallible { fail: false }), (4, Fallible { fail: true })]);
+        assert!(result.is_err());
+        assert_eq!(table.get(&3), None);
+        assert_eq!(table.size(), size);
+
         Ok(())
     }
 
@@ -195,4 +846,43 @@ mod tests {
         
         Ok(())
     }
+
+    #[test]
+    fn test_memtable_range() -> Result<()> {
+        let mut table = MemTable::new();
+        for i in 0..10 {
+            table.put(i, i)?;
+        }
+        table.delete(5)?;
+
+        let keys: Vec<_> = table.range(Bound::Included(&3), Bound::Excluded(&6)).map(|(k, _)| *k).collect();
+        assert_eq!(keys, vec![3, 4, 5]);
+        assert_eq!(table.range(Bound::Included(&5), Bound::Included(&5)).next(), Some((&5, &Entry::Tombstone)));
+        assert_eq!(table.range(Bound::Excluded(&5), Bound::Excluded(&5)).count(), 0);
+        assert_eq!(table.range(Bound::Included(&8), Bound::Included(&2)).count(), 0);
+        assert_eq!(table.range(Bound::Unbounded, Bound::Unbounded).count(), 10);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memtable_delete() -> Result<()> {
+        let mut table = MemTable::new();
+
+        table.put(1, "one".to_string())?;
+        table.put(2, "two".to_string())?;
+        table.delete(1)?;
+
+        assert_eq!(table.get(&1), None);
+        assert_eq!(table.get_entry(&1), Some(&Entry::Tombstone));
+        assert_eq!(table.get(&2), Some(&"two".to_string()));
+
+        assert_eq!(table.iter().collect::<Vec<_>>(), vec![(&2, &"two".to_string())]);
+        assert_eq!(table.entries().count(), 2);
+
+        table.put(1, "uno".to_string())?;
+        assert_eq!(table.get(&1), Some(&"uno".to_string()));
+
+        Ok(())
+    }
 } 
\ No newline at end of file
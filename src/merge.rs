@@ -0,0 +1,222 @@
+//! Merge operators, for values that accumulate updates such as counters or lists.
+//!
+//! `LSMTree::merge` records an operand for a key without reading its current value. If the
+//! memtable holds a value for the key, the operand is applied onto it right away; otherwise it
+//! is stored as an `Entry::Merge`, which flushes write to SSTables like any other entry. Reads
+//! gather the operands above the newest entry of the key that isn't a merge and apply them
+//! onto it with the tree's `MergeOperator`, and compactions do the same when they merge the
+//! versions of a key, so operands are collapsed lazily.
+//!
+//! A tombstone ends the history of a key: operands recorded after a delete are applied onto
+//! no value, and never see the values before it. Operands applied onto a value inserted with a
+//! TTL keep its expiry time, so the merged value expires with it.
+
+use crate::memtable::Entry;
+use crate::{LSMError, Result};
+use std::borrow::Borrow;
+
+/// Combines a value with the merge operands recorded for its key
+pub trait MergeOperator<V>: Send + Sync {
+    /// The new value of a key, given its value before the operands, `None` if it had none or
+    /// was deleted, and the operands in the order they were recorded
+    fn merge(&self, existing: Option<&V>, operands: &[&V]) -> V;
+}
+
+/// Combines two versions of a key. `newer` shadows `older` unless it is a merge: its operands
+/// are then applied onto `older`, or appended to those of `older` if it is a merge too.
+pub(crate) fn combine<V, T>(operator: Option<&dyn MergeOperator<V>>, newer: Entry<T>, older: Entry<T>) -> Result<Entry<T>>
+where
+    T: Borrow<V> + From<V>,
+{
+    let Entry::Merge(operands) = newer else {
+        return Ok(newer);
+    };
+    if let Entry::Merge(mut older_operands) = older {
+        older_operands.extend(operands);
+        return Ok(Entry::Merge(older_operands));
+    }
+    let operator = operator.ok_or(LSMError::NoMergeOperator)?;
+    let operands: Vec<&V> = operands.iter().map(Borrow::borrow).collect();
+    Ok(match older {
+        Entry::Value(value) => Entry::Value(operator.merge(Some(value.borrow()), &operands).into()),
+        Entry::Expiring { value, expires_at } => Entry::Expiring {
+            value: operator.merge(Some(value.borrow()), &operands).into(),
+            expires_at,
+        },
+        Entry::Tombstone | Entry::Merge(_) => Entry::Value(operator.merge(None, &operands).into()),
+    })
+}
+
+/// Like `combine`, for the kinds of the versions only: operands make a value of the older
+/// version, with its expiry time, or of no value, and are appended to older operands
+pub(crate) fn combine_kinds(newer: Entry<()>, older: Entry<()>) -> Entry<()> {
+    match (newer, older) {
+        (Entry::Merge(operands), Entry::Merge(mut older_operands)) => {
+            older_operands.extend(operands);
+            Entry::Merge(older_operands)
+        }
+        (Entry::Merge(_), Entry::Expiring { expires_at, .. }) => Entry::Expiring { value: (), expires_at },
+        (Entry::Merge(_), _) => Entry::Value(()),
+        (newer, _) => newer,
+    }
+}
+
+/// Applies the operands of a merge that no older version of its key lies under onto no value.
+/// Other entries are returned as they are.
+pub(crate) fn resolve<V, T>(operator: Option<&dyn MergeOperator<V>>, entry: Entry<T>) -> Result<Entry<T>>
+where
+    T: Borrow<V> + From<V>,
+{
+    combine(operator, entry, Entry::Tombstone)
+}
+
+/// Collapses the versions of a key, newest first, into its entry. Versions are only read down
+/// to the first one that isn't a merge; `None` if there are none.
+pub(crate) fn collapse<V, T>(
+    operator: Option<&dyn MergeOperator<V>>,
+    versions: impl IntoIterator<Item = Result<Entry<T>>>,
+) -> Result<Option<Entry<T>>>
+where
+    T: Borrow<V> + From<V>,
+{
+    let mut collapsed: Option<Entry<T>> = None;
+    for version in versions {
+        let entry = match collapsed {
+            Some(newer) => combine(operator, newer, version?)?,
+            None => version?,
+        };
+        let done = !entry.is_merge();
+        collapsed = Some(entry);
+        if done {
+            break;
+        }
+    }
+    collapsed.map(|entry| resolve(operator, entry)).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, LSMTree};
+    use std::ops::Bound;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    struct Sum;
+
+    impl MergeOperator<u64> for Sum {
+        fn merge(&self, existing: Option<&u64>, operands: &[&u64]) -> u64 {
+            existing.copied().unwrap_or(0) + operands.iter().copied().sum::<u64>()
+        }
+    }
+
+    #[test]
+    fn test_collapse() -> Result<()> {
+        let collapse = |versions: Vec<Entry<u64>>| collapse(Some(&Sum), versions.into_iter().map(Ok));
+        assert_eq!(collapse(vec![])?, None);
+        assert_eq!(collapse(vec![Entry::Merge(vec![1, 2])])?, Some(Entry::Value(3)));
+        assert_eq!(collapse(vec![Entry::Merge(vec![1]), Entry::Merge(vec![2]), Entry::Value(10)])?, Some(Entry::Value(13)));
+        assert_eq!(collapse(vec![Entry::Merge(vec![1]), Entry::Tombstone, Entry::Value(10)])?, Some(Entry::Value(1)));
+        assert_eq!(collapse(vec![Entry::Value(5), Entry::Merge(vec![1])])?, Some(Entry::Value(5)));
+        assert_eq!(
+            collapse(vec![Entry::Merge(vec![1]), Entry::Expiring { value: 10, expires_at: 7 }])?,
+            Some(Entry::Expiring { value: 11, expires_at: 7 })
+        );
+
+        // Versions below the first entry that isn't a merge aren't read
+        let versions = vec![Ok(Entry::Merge(vec![1])), Ok(Entry::Value(10)), Err(LSMError::KeyNotFound)];
+        assert_eq!(super::collapse(Some(&Sum), versions)?, Some(Entry::Value(11)));
+
+        // Operands can be appended to one another without an operator, but not applied
+        let appended = combine::<u64, u64>(None, Entry::Merge(vec![2]), Entry::Merge(vec![1]))?;
+        assert_eq!(appended, Entry::Merge(vec![1, 2]));
+        assert!(matches!(resolve::<u64, u64>(None, appended), Err(LSMError::NoMergeOperator)));
+
+        Ok(())
+    }
+
+    /// Appends the operands to the value, separated by commas
+    struct Append;
+
+    impl MergeOperator<String> for Append {
+        fn merge(&self, existing: Option<&String>, operands: &[&String]) -> String {
+            let mut parts: Vec<&str> = existing.map(String::as_str).into_iter().collect();
+            parts.extend(operands.iter().map(|operand| operand.as_str()));
+            parts.join(",")
+        }
+    }
+
+    #[test]
+    fn test_merge() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            ..Config::default()
+        };
+        let key = |name: &str| name.to_string();
+        let mut lsm = LSMTree::with_config(config.clone())?;
+        assert!(matches!(lsm.merge(key("a"), "x".to_string()), Err(LSMError::NoMergeOperator)));
+        let mut lsm = lsm.with_merge_operator(Arc::new(Append));
+
+        // Operands on a key without a value, and onto a value in the memtable
+        lsm.merge(key("a"), "1".to_string())?;
+        lsm.merge(key("a"), "2".to_string())?;
+        lsm.insert(key("b"), "base".to_string())?;
+        lsm.merge(key("b"), "1".to_string())?;
+        assert_eq!(lsm.get(&key("a"))?, Some("1,2".to_string()));
+        assert_eq!(lsm.get(&key("b"))?, Some("base,1".to_string()));
+        lsm.flush()?;
+
+        // Operands above values in SSTables, across several tables
+        lsm.merge(key("a"), "3".to_string())?;
+        lsm.flush()?;
+        lsm.merge(key("a"), "4".to_string())?;
+        lsm.merge(key("b"), "2".to_string())?;
+        lsm.insert(key("c"), "c".to_string())?;
+        // A delete ends the history of the key
+        lsm.delete(key("c"))?;
+        lsm.merge(key("c"), "fresh".to_string())?;
+        let expected = vec![
+            (key("a"), "1,2,3,4".to_string()),
+            (key("b"), "base,1,2".to_string()),
+            (key("c"), "fresh".to_string()),
+        ];
+        let check = |lsm: &LSMTree<String, String>| -> Result<()> {
+            for (key, value) in &expected {
+                assert_eq!(lsm.get(key)?.as_ref(), Some(value));
+                assert!(lsm.contains_key(key)?);
+                assert_eq!(lsm.get_raw(key)?, Some(lsm.config.encoding.serialize(value)?));
+            }
+            assert_eq!(lsm.iter()?.collect::<Result<Vec<_>>>()?, expected);
+            let reversed: Vec<_> = expected.iter().rev().cloned().collect();
+            assert_eq!(lsm.iter_rev()?.collect::<Result<Vec<_>>>()?, reversed);
+            let keys: Vec<_> = expected.iter().map(|(key, _)| key.clone()).collect();
+            let values: Vec<_> = expected.iter().map(|(_, value)| Some(value.clone())).collect();
+            assert_eq!(lsm.get_many(&keys)?, values);
+            let snapshot = lsm.snapshot();
+            assert_eq!(snapshot.get(&key("a"))?, Some("1,2,3,4".to_string()));
+            assert_eq!(snapshot.range(Bound::Unbounded, Bound::Unbounded)?.count(), 3);
+            Ok(())
+        };
+        check(&lsm)?;
+
+        // The operands in the write-ahead log are replayed without the operator
+        drop(lsm);
+        let lsm = LSMTree::<String, String>::with_config(config.clone())?;
+        assert!(matches!(lsm.get(&key("a")), Err(LSMError::NoMergeOperator)));
+        let mut lsm = lsm.with_merge_operator(Arc::new(Append));
+        check(&lsm)?;
+
+        // Compaction applies the operands, after which reads don't need the operator
+        lsm.flush()?;
+        lsm.compact()?;
+        assert_eq!(lsm.sstables.len(), 1);
+        check(&lsm)?;
+        lsm.close()?;
+        let lsm = LSMTree::<String, String>::with_config(config)?;
+        assert_eq!(lsm.get(&key("a"))?, Some("1,2,3,4".to_string()));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,341 @@
+//! Order-preserving key encoding.
+//!
+//! The serialized keys stored in SSTables don't sort like the keys themselves: bincode writes
+//! integers little-endian and prefixes strings with their length. `OrderedEncode` is an
+//! encoding whose bytes, compared lexicographically, order values exactly like their `Ord`
+//! impl, so keys can be compared as bytes without deserializing them.
+//!
+//! Unsigned integers are written big-endian, and signed integers the same after flipping the
+//! sign bit, so negative numbers come first. Strings end with `00 00`, and every `00` byte in
+//! them is written as `00 FF`: a string then sorts before the longer strings it is a prefix
+//! of, and the encoding of a tuple, which is its fields' encodings one after another, sorts
+//! field by field.
+//!
+//! `OrderedBytes` is a comparator that has SSTables store keys in this encoding, so point
+//! lookups compare the keys of a block as bytes instead of deserializing them.
+//!
+//! Floats aren't `Ord`, as NaN compares to nothing, and keys must be totally ordered: lookups
+//! and scans stop at the first key greater than the one they look for. `OrderedF32` and
+//! `OrderedF64` wrap floats to use them as keys, ordered by `total_cmp`.
+
+use crate::comparator::{Comparator, KeyEncoding};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// A byte encoding that preserves order: for any `a` and `b`, `a.cmp(&b)` equals the
+/// lexicographic comparison of their encodings. Encodings must also be self-delimiting, so
+/// that values can be concatenated into composite keys.
+pub trait OrderedEncode: Ord {
+    /// Appends the encoding of `self` to `out`
+    fn encode_ordered(&self, out: &mut Vec<u8>);
+
+    /// Decodes a value from the start of `bytes` and advances past it. Returns `None` if
+    /// `bytes` doesn't start with a valid encoding.
+    fn decode_ordered(bytes: &mut &[u8]) -> Option<Self>
+    where
+        Self: Sized;
+
+    fn to_ordered_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_ordered(&mut out);
+        out
+    }
+}
+
+/// Decodes a value that takes up all of `bytes`
+pub fn from_ordered_bytes<T: OrderedEncode>(mut bytes: &[u8]) -> Option<T> {
+    let value = T::decode_ordered(&mut bytes)?;
+    bytes.is_empty().then_some(value)
+}
+
+/// Orders keys by their `Ord` impl, like `NaturalOrder`, and has SSTables store record keys
+/// with `OrderedEncode` instead of `Config::encoding`. Its name differs from `NaturalOrder`'s,
+/// as tables written with it can't be read without it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OrderedBytes;
+
+impl<K: OrderedEncode> Comparator<K> for OrderedBytes {
+    fn compare(&self, a: &K, b: &K) -> Ordering {
+        a.cmp(b)
+    }
+
+    fn name(&self) -> &str {
+        "ordered-bytes"
+    }
+
+    fn is_consistent_with_serialization(&self) -> bool {
+        true
+    }
+
+    fn key_encoding(&self) -> Option<&dyn KeyEncoding<K>> {
+        Some(self)
+    }
+}
+
+impl<K: OrderedEncode> KeyEncoding<K> for OrderedBytes {
+    fn encode(&self, key: &K) -> Vec<u8> {
+        key.to_ordered_bytes()
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<K> {
+        from_ordered_bytes(bytes)
+    }
+}
+
+fn take<const N: usize>(bytes: &mut &[u8]) -> Option<[u8; N]> {
+    let (head, rest) = bytes.split_first_chunk::<N>()?;
+    *bytes = rest;
+    Some(*head)
+}
+
+macro_rules! unsigned {
+    ($($t:ty),*) => {$(
+        impl OrderedEncode for $t {
+            fn encode_ordered(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_be_bytes());
+            }
+
+            fn decode_ordered(bytes: &mut &[u8]) -> Option<Self> {
+                take(bytes).map(<$t>::from_be_bytes)
+            }
+        }
+    )*};
+}
+
+macro_rules! signed {
+    ($($t:ty => $unsigned:ty),*) => {$(
+        impl OrderedEncode for $t {
+            fn encode_ordered(&self, out: &mut Vec<u8>) {
+                let flipped = (*self as $unsigned) ^ (1 << (<$unsigned>::BITS - 1));
+                out.extend_from_slice(&flipped.to_be_bytes());
+            }
+
+            fn decode_ordered(bytes: &mut &[u8]) -> Option<Self> {
+                let flipped = take(bytes).map(<$unsigned>::from_be_bytes)?;
+                Some((flipped ^ (1 << (<$unsigned>::BITS - 1))) as $t)
+            }
+        }
+    )*};
+}
+
+unsigned!(u8, u16, u32, u64, u128);
+signed!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+impl OrderedEncode for String {
+    fn encode_ordered(&self, out: &mut Vec<u8>) {
+        for &byte in self.as_bytes() {
+            match byte {
+                0 => out.extend_from_slice(&[0, 0xff]),
+                _ => out.push(byte),
+            }
+        }
+        out.extend_from_slice(&[0, 0]);
+    }
+
+    fn decode_ordered(bytes: &mut &[u8]) -> Option<Self> {
+        let mut decoded = Vec::new();
+        loop {
+            match take::<1>(bytes)? {
+                [0] => match take::<1>(bytes)? {
+                    [0] => break,
+                    [0xff] => decoded.push(0),
+                    _ => return None,
+                },
+                [byte] => decoded.push(byte),
+            }
+        }
+        String::from_utf8(decoded).ok()
+    }
+}
+
+macro_rules! tuple {
+    ($($name:ident),*) => {
+        impl<$($name: OrderedEncode),*> OrderedEncode for ($($name,)*) {
+            #[allow(non_snake_case)]
+            fn encode_ordered(&self, out: &mut Vec<u8>) {
+                let ($($name,)*) = self;
+                $($name.encode_ordered(out);)*
+            }
+
+            fn decode_ordered(bytes: &mut &[u8]) -> Option<Self> {
+                Some(($($name::decode_ordered(bytes)?,)*))
+            }
+        }
+    };
+}
+
+tuple!(A, B);
+tuple!(A, B, C);
+tuple!(A, B, C, D);
+
+macro_rules! ordered_float {
+    ($($name:ident($t:ty => $bits:ty)),*) => {$(
+        #[doc = concat!("An `", stringify!($t), "` ordered by `total_cmp`: negative NaNs come first")]
+        /// and positive NaNs last, -0.0 sorts before 0.0, and only values with the same bits are
+        /// equal
+        #[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+        #[serde(transparent)]
+        pub struct $name(pub $t);
+
+        impl PartialEq for $name {
+            fn eq(&self, other: &Self) -> bool {
+                self.cmp(other).is_eq()
+            }
+        }
+
+        impl Eq for $name {}
+
+        impl PartialOrd for $name {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for $name {
+            fn cmp(&self, other: &Self) -> Ordering {
+                self.0.total_cmp(&other.0)
+            }
+        }
+
+        impl From<$t> for $name {
+            fn from(value: $t) -> Self {
+                Self(value)
+            }
+        }
+
+        /// Written big-endian with the sign bit flipped, and all the other bits too for negative
+        /// values, so that larger magnitudes sort first among them
+        impl OrderedEncode for $name {
+            fn encode_ordered(&self, out: &mut Vec<u8>) {
+                let bits = self.0.to_bits();
+                let sign = 1 << (<$bits>::BITS - 1);
+                let flipped = if bits & sign != 0 { !bits } else { bits ^ sign };
+                out.extend_from_slice(&flipped.to_be_bytes());
+            }
+
+            fn decode_ordered(bytes: &mut &[u8]) -> Option<Self> {
+                let flipped = take(bytes).map(<$bits>::from_be_bytes)?;
+                let sign = 1 << (<$bits>::BITS - 1);
+                let bits = if flipped & sign != 0 { flipped ^ sign } else { !flipped };
+                Some(Self(<$t>::from_bits(bits)))
+            }
+        }
+    )*};
+}
+
+ordered_float!(OrderedF32(f32 => u32), OrderedF64(f64 => u64));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Debug;
+
+    /// Checks that encodings sort like the values and decode back to them
+    fn check_order<T: OrderedEncode + Clone + Debug>(mut values: Vec<T>) {
+        values.sort();
+        let encoded: Vec<_> = values.iter().map(|value| value.to_ordered_bytes()).collect();
+        for (pair, bytes) in values.windows(2).zip(encoded.windows(2)) {
+            assert_eq!(pair[0].cmp(&pair[1]), bytes[0].cmp(&bytes[1]), "{:?}", pair);
+        }
+        for (value, bytes) in values.iter().zip(&encoded) {
+            assert_eq!(from_ordered_bytes::<T>(bytes).as_ref(), Some(value));
+        }
+    }
+
+    #[test]
+    fn test_integers() {
+        check_order(vec![0u8, 1, 127, 128, 255]);
+        check_order(vec![0u32, 1, 255, 256, 65_536, u32::MAX]);
+        check_order(vec![0u128, 1, u64::MAX as u128 + 1, u128::MAX]);
+        check_order(vec![i8::MIN, -1, 0, 1, i8::MAX]);
+        check_order(vec![i64::MIN, -256, -255, -1, 0, 1, 255, 256, i64::MAX]);
+        assert_eq!(300u16.to_ordered_bytes(), vec![1, 44]);
+        assert_eq!((-1i16).to_ordered_bytes(), vec![0x7f, 0xff]);
+
+        // Unlike the default encoding, which writes integers little-endian
+        let default = crate::encoding::Encoding::default();
+        assert!(default.serialize(&256u32).unwrap() < default.serialize(&1u32).unwrap());
+        assert!(256u32.to_ordered_bytes() > 1u32.to_ordered_bytes());
+    }
+
+    #[test]
+    fn test_strings() {
+        let strings = ["", "\0", "\0\0", "\0a", "a", "a\0", "a\0b", "ab", "b", "é"];
+        check_order(strings.iter().map(|s| s.to_string()).collect());
+
+        assert_eq!(from_ordered_bytes::<String>(b"ab"), None);
+        assert_eq!(from_ordered_bytes::<String>(&[b'a', 0, 1]), None);
+        assert_eq!(from_ordered_bytes::<String>(&[0xc3, 0, 0]), None);
+        assert_eq!(from_ordered_bytes::<String>(&[b'a', 0, 0, b'b']), None);
+    }
+
+    #[test]
+    fn test_floats() {
+        let values = [f64::NEG_INFINITY, -1.5, -f64::MIN_POSITIVE, -0.0, 0.0, 1e-300, 2.0, f64::INFINITY];
+        let mut floats: Vec<OrderedF64> = values.into_iter().map(OrderedF64).collect();
+        floats.push(OrderedF64(f64::NAN));
+        floats.push(OrderedF64(-f64::NAN));
+        check_order(floats.clone());
+        floats.sort();
+        assert!(floats[0].0.is_nan() && floats[0].0.is_sign_negative());
+        assert!(floats[floats.len() - 1].0.is_nan());
+        assert_eq!(OrderedF64(f64::NAN), OrderedF64(f64::NAN));
+        assert_ne!(OrderedF64(0.0), OrderedF64(-0.0));
+        check_order(vec![OrderedF32(-3.0), OrderedF32(-0.5), OrderedF32(0.25), OrderedF32(f32::NAN)]);
+
+        // Serialized like the float itself
+        let encoding = crate::encoding::Encoding::default();
+        assert_eq!(encoding.serialize(&OrderedF64(2.5)).unwrap(), encoding.serialize(&2.5f64).unwrap());
+    }
+
+    #[test]
+    fn test_composite_keys() {
+        let mut keys = Vec::new();
+        for user in ["", "a", "a\0", "ab", "b"] {
+            for id in [-1i32, 0, 7] {
+                keys.push((user.to_string(), id));
+            }
+        }
+        check_order(keys);
+        check_order(vec![(1u8, "x".to_string(), 2u64), (1, "x".to_string(), 3), (1, "xy".to_string(), 0)]);
+    }
+
+    #[test]
+    fn test_ordered_bytes_tree() -> crate::Result<()> {
+        use crate::{Config, LSMTree};
+        use std::ops::Bound;
+        use std::sync::Arc;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            comparator: Arc::new(OrderedBytes),
+            ..Config::default()
+        };
+        let key = |user: &str, id: i64| (user.to_string(), id);
+        let mut lsm = LSMTree::with_config(config.clone())?;
+        for id in [-5, 0, 300] {
+            lsm.insert(key("a", id), id)?;
+            lsm.insert(key("a\0", id), id)?;
+        }
+        lsm.flush()?;
+        lsm.insert(key("a", 300), 301)?;
+        lsm.delete(key("a\0", -5))?;
+        lsm.flush()?;
+        lsm.compact()?;
+        drop(lsm);
+
+        let lsm = LSMTree::<(String, i64), i64>::with_config(config)?;
+        assert_eq!(lsm.get(&key("a", -5))?, Some(-5));
+        assert_eq!(lsm.get(&key("a", 300))?, Some(301));
+        assert_eq!(lsm.get(&key("a\0", -5))?, None);
+        assert_eq!(lsm.get(&key("a", 1))?, None);
+        let keys: Vec<_> = lsm
+            .range(Bound::Included(key("a", 0)), Bound::Unbounded)?
+            .map(|item| item.map(|(key, _)| key))
+            .collect::<crate::Result<_>>()?;
+        assert_eq!(keys, vec![key("a", 0), key("a", 300), key("a\0", 0), key("a\0", 300)]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+//! Range deletes.
+//!
+//! `LSMTree::delete_range` records a single `RangeTombstone` covering `[start, end)` instead of
+//! a tombstone per key. Range tombstones are kept in the memtable, logged to the write-ahead log
+//! and written to SSTables next to the point entries, and precedence between the two follows
+//! the order of writes the same way it does for point entries:
+//!
+//! - A range delete removes the memtable's entries in its range, so the point entries of a
+//!   memtable or SSTable are always newer than its range tombstones, and win over them.
+//! - The range tombstones of a memtable or SSTable hide the entries for their keys in every
+//!   older one. Lookups treat a range tombstone covering the key as a tombstone with the
+//!   tombstone's sequence number (see `sequence`); scans and compactions drop the entries of
+//!   each source that a newer source's range tombstones cover.
+//!
+//! Merge operands applied after a range delete see no value under them: `LSMTree::merge` applies
+//! them right away, and compactions do before writing them next to a range tombstone covering
+//! them. Compactions keep range tombstones as long as they keep point tombstones, and a table
+//! split across several outputs gives each one the part of its range tombstones between its own
+//! first key and the next table's, so tables of a level still don't overlap.
+
+use crate::comparator::Comparator;
+use crate::Result;
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// Deletes the keys from `start` included to `end` excluded
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RangeTombstone<K> {
+    pub start: K,
+    pub end: K,
+    /// Sequence number of the range delete, 0 for those made without one
+    pub sequence: u64,
+}
+
+impl<K> RangeTombstone<K> {
+    pub fn new(start: K, end: K) -> Self {
+        Self { start, end, sequence: 0 }
+    }
+
+    pub fn covers(&self, key: &K, comparator: &dyn Comparator<K>) -> bool {
+        comparator.compare(&self.start, key).is_le() && comparator.compare(key, &self.end).is_lt()
+    }
+
+    /// Whether the tombstone may cover a key within the bounds
+    pub fn overlaps(&self, start: Bound<&K>, end: Bound<&K>, comparator: &dyn Comparator<K>) -> bool {
+        let starts_before_end = match start {
+            Bound::Included(key) | Bound::Excluded(key) => comparator.compare(key, &self.end).is_lt(),
+            Bound::Unbounded => true,
+        };
+        let ends_after_start = match end {
+            Bound::Included(key) => comparator.compare(&self.start, key).is_le(),
+            Bound::Excluded(key) => comparator.compare(&self.start, key).is_lt(),
+            Bound::Unbounded => true,
+        };
+        starts_before_end && ends_after_start
+    }
+
+    /// The part of the tombstone from `low` included to `high` excluded, if any
+    pub(crate) fn clip(&self, low: Option<&K>, high: Option<&K>, comparator: &dyn Comparator<K>) -> Option<Self>
+    where
+        K: Clone,
+    {
+        let start = match low {
+            Some(low) if comparator.compare(low, &self.start).is_gt() => low,
+            _ => &self.start,
+        };
+        let end = match high {
+            Some(high) if comparator.compare(high, &self.end).is_lt() => high,
+            _ => &self.end,
+        };
+        let sequence = self.sequence;
+        comparator.compare(start, end).is_lt().then(|| Self { start: start.clone(), end: end.clone(), sequence })
+    }
+}
+
+/// Whether any of `tombstones` covers `key`
+pub(crate) fn is_covered<K>(tombstones: &[RangeTombstone<K>], key: &K, comparator: &dyn Comparator<K>) -> bool {
+    tombstones.iter().any(|tombstone| tombstone.covers(key, comparator))
+}
+
+/// The sequence number of the newest of `tombstones` that covers `key`, if any
+pub(crate) fn covering_sequence<K>(
+    tombstones: &[RangeTombstone<K>],
+    key: &K,
+    comparator: &dyn Comparator<K>,
+) -> Option<u64> {
+    let covering = tombstones.iter().filter(|tombstone| tombstone.covers(key, comparator));
+    covering.map(|tombstone| tombstone.sequence).max()
+}
+
+/// Drops the entries of `source` whose keys the range tombstones of newer sources cover
+pub(crate) fn uncovered<'a, K: 'a, T: 'a>(
+    source: impl Iterator<Item = Result<(K, T)>> + 'a,
+    newer: Arc<[RangeTombstone<K>]>,
+    comparator: Arc<dyn Comparator<K>>,
+) -> impl Iterator<Item = Result<(K, T)>> + 'a {
+    source.filter(move |item| !item.as_ref().is_ok_and(|(key, _)| is_covered(&newer, key, &*comparator)))
+}
+
+/// The range tombstones of the sources newer than each source, given the tombstones of each
+/// source, newest first
+pub(crate) fn newer_tombstones<K: Clone>(sources: &[&[RangeTombstone<K>]]) -> Vec<Arc<[RangeTombstone<K>]>> {
+    let mut newer = Vec::new();
+    sources
+        .iter()
+        .map(|tombstones| {
+            let above: Arc<[RangeTombstone<K>]> = newer.clone().into();
+            newer.extend_from_slice(tombstones);
+            above
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::NaturalOrder;
+    use crate::merge::MergeOperator;
+    use crate::{CompactionStrategy, Config, LSMTree};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_clip() {
+        let tombstone = RangeTombstone::new(10, 20);
+        let clip = |low: Option<i32>, high: Option<i32>| tombstone.clip(low.as_ref(), high.as_ref(), &NaturalOrder);
+        assert_eq!(clip(None, None), Some(RangeTombstone::new(10, 20)));
+        assert_eq!(clip(Some(15), None), Some(RangeTombstone::new(15, 20)));
+        assert_eq!(clip(Some(5), Some(12)), Some(RangeTombstone::new(10, 12)));
+        assert_eq!(clip(Some(20), None), None);
+        assert_eq!(clip(None, Some(10)), None);
+        assert!(tombstone.covers(&10, &NaturalOrder) && !tombstone.covers(&20, &NaturalOrder));
+        assert!(!tombstone.overlaps(Bound::Included(&20), Bound::Unbounded, &NaturalOrder));
+        assert!(!tombstone.overlaps(Bound::Unbounded, Bound::Excluded(&10), &NaturalOrder));
+        assert!(tombstone.overlaps(Bound::Unbounded, Bound::Included(&10), &NaturalOrder));
+    }
+
+    #[test]
+    fn test_delete_range() -> Result<()> {
+        for compaction_strategy in [CompactionStrategy::SizeTiered, CompactionStrategy::Leveled { fanout: 2 }] {
+            let temp_dir = TempDir::new().unwrap();
+            let config = Config {
+                data_dir: temp_dir.path().to_path_buf(),
+                compaction_threshold: None,
+                compaction_strategy,
+                memtable_size_threshold: 256,
+                ..Config::default()
+            };
+            let mut lsm = LSMTree::with_config(config.clone())?;
+            for i in 0..100 {
+                lsm.insert(i, format!("old{}", i))?;
+            }
+            lsm.flush()?;
+
+            // In the memtable, over values in an SSTable, and overwritten after the delete
+            lsm.insert(25, "memtable".to_string())?;
+            let snapshot = lsm.snapshot();
+            lsm.delete_range(20, 40)?;
+            lsm.insert(30, "new".to_string())?;
+            assert_eq!(snapshot.get(&25)?, Some("memtable".to_string()));
+            drop(snapshot);
+            let expected = |i: i32| match i {
+                30 => Some("new".to_string()),
+                20..40 | 60..70 => None,
+                _ => Some(format!("old{}", i)),
+            };
+            let check = |lsm: &LSMTree<i32, String>, through: i32| -> Result<()> {
+                for i in 0..100 {
+                    let want = if i < through { expected(i) } else { Some(format!("old{}", i)) };
+                    assert_eq!(lsm.get(&i)?, want, "key {}", i);
+                    assert_eq!(lsm.contains_key(&i)?, want.is_some());
+                }
+                let keys: Vec<i32> = (0..100).collect();
+                let wanted: Vec<_> = (0..100).map(|i| if i < through { expected(i) } else { Some(format!("old{}", i)) }).collect();
+                assert_eq!(lsm.get_many(&keys)?, wanted);
+                let live: Vec<_> = keys.iter().zip(&wanted).filter_map(|(&i, value)| Some((i, value.clone()?))).collect();
+                assert_eq!(lsm.iter()?.collect::<Result<Vec<_>>>()?, live);
+                let reversed: Vec<_> = live.iter().rev().cloned().collect();
+                assert_eq!(lsm.iter_rev()?.collect::<Result<Vec<_>>>()?, reversed);
+                Ok(())
+            };
+            check(&lsm, 60)?;
+            lsm.flush()?;
+            check(&lsm, 60)?;
+
+            // A range delete alone in a flush, then surviving reopening and compaction
+            lsm.delete_range(60, 70)?;
+            check(&lsm, 100)?;
+            drop(lsm);
+            let mut lsm = LSMTree::with_config(config.clone())?;
+            check(&lsm, 100)?;
+            lsm.flush()?;
+            lsm.insert(65, "after".to_string())?;
+            lsm.flush()?;
+            assert_eq!(lsm.get(&65)?, Some("after".to_string()));
+            lsm.delete(65)?;
+            lsm.compact()?;
+            check(&lsm, 100)?;
+            lsm.compact_with_filter(|_, _| true)?;
+            check(&lsm, 100)?;
+            assert_eq!(lsm.len()?, 71);
+        }
+
+        Ok(())
+    }
+
+    struct Sum;
+
+    impl MergeOperator<u64> for Sum {
+        fn merge(&self, existing: Option<&u64>, operands: &[&u64]) -> u64 {
+            existing.copied().unwrap_or(0) + operands.iter().copied().sum::<u64>()
+        }
+    }
+
+    #[test]
+    fn test_merge_after_delete_range() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            compaction_strategy: CompactionStrategy::Leveled { fanout: 2 },
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config)?.with_merge_operator(Arc::new(Sum));
+        lsm.insert(1, 10u64)?;
+        lsm.insert(2, 20)?;
+        lsm.flush()?;
+        lsm.compact()?;
+
+        // Operands onto a key deleted in the memtable, or in a table of its own
+        lsm.delete_range(0, 2)?;
+        lsm.merge(1, 1)?;
+        lsm.flush()?;
+        lsm.delete_range(2, 3)?;
+        lsm.flush()?;
+        lsm.merge(2, 2)?;
+        lsm.flush()?;
+        assert_eq!(lsm.get(&1)?, Some(1));
+        assert_eq!(lsm.get(&2)?, Some(2));
+
+        // Merging level 0 alone keeps the range tombstones, so the operands are applied then,
+        // and the values in level 1 stay hidden
+        lsm.merge_oldest(2)?;
+        assert_eq!(lsm.sstables.len(), 2);
+        assert_eq!(lsm.iter()?.collect::<Result<Vec<_>>>()?, vec![(1, 1), (2, 2)]);
+        lsm.compact()?;
+        assert_eq!(lsm.iter()?.collect::<Result<Vec<_>>>()?, vec![(1, 1), (2, 2)]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,630 @@
+//! Ordered scans across the memtable and all SSTables.
+//!
+//! Each source yields its entries in key order and the sources are combined with a k-way
+//! merge in which the memtable takes precedence over SSTables, and newer SSTables over older
+//! ones. Tombstones shadow older versions of a key and are then dropped from the output, and
+//! merge operands are applied onto the older versions of their key (see the `merge` module).
+//! The entries of a source under the range tombstones of newer sources are skipped before they
+//! are merged (see the `range_tombstone` module).
+//!
+//! Scans yield `Result`s: an SSTable that can't be read is reported as an error item, which
+//! ends the scan. `CollectOk::collect_ok` gathers the pairs read before such an error.
+
+use crate::memtable::{DynMemTable, Entry};
+use crate::iter::MergeIterator;
+use crate::merge::{self, MergeOperator};
+use crate::range_tombstone;
+use crate::sequence::Sequenced;
+use crate::sstable::{decode_entry, decode_kind, DecodeEntry, SSTable};
+use crate::{LSMTree, Result};
+use std::ops::Bound;
+use std::sync::Arc;
+
+type EntrySource<'a, K, V> = Box<dyn Iterator<Item = Result<(K, Sequenced<Entry<V>>)>> + 'a>;
+type MergedEntries<'a, K, V> = MergeIterator<K, Sequenced<Entry<V>>, EntrySource<'a, K, V>>;
+
+/// Key types with a meaningful prefix relation, which enables `LSMTree::scan_prefix`.
+///
+/// Prefix scans assume the keys starting with a prefix sort right after it and before its
+/// successor, which holds for the natural ordering of strings and byte vectors.
+pub trait PrefixSuccessor: Sized {
+    /// The smallest key greater than every key that starts with `self`, or `None` if there is
+    /// no such key (for the empty prefix, for instance)
+    fn prefix_successor(&self) -> Option<Self>;
+}
+
+impl PrefixSuccessor for Vec<u8> {
+    fn prefix_successor(&self) -> Option<Self> {
+        let mut successor = self.clone();
+        while let Some(last) = successor.pop() {
+            if last < u8::MAX {
+                successor.push(last + 1);
+                return Some(successor);
+            }
+        }
+        None
+    }
+}
+
+impl PrefixSuccessor for String {
+    // Strings compare by their UTF-8 bytes, which is the same as comparing code points, so
+    // incrementing the last character (rather than the last byte) keeps the result valid UTF-8
+    fn prefix_successor(&self) -> Option<Self> {
+        let mut successor = self.clone();
+        while let Some(last) = successor.pop() {
+            let next = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+            if let Some(next) = next {
+                successor.push(next);
+                return Some(successor);
+            }
+        }
+        None
+    }
+}
+
+impl<K, V> LSMTree<K, V>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned + Clone,
+{
+    /// Returns the live key-value pairs with keys within the given bounds, in key order.
+    /// SSTables are read lazily and those whose key range lies outside the bounds are skipped.
+    /// A read error is yielded as the last item.
+    pub fn range(&self, start: Bound<K>, end: Bound<K>) -> Result<impl Iterator<Item = Result<(K, V)>> + '_> {
+        let memtables: Vec<_> = self.memtables().collect();
+        let now = self.config.clock.now_millis();
+        range_over(&memtables, &self.sstables, start, end, now, self.merge_operator.clone(), false)
+    }
+
+    /// Like `range`, in descending key order. SSTables are read a block at a time from the
+    /// end bound backwards; see the `sstable` module.
+    pub fn range_rev(&self, start: Bound<K>, end: Bound<K>) -> Result<impl Iterator<Item = Result<(K, V)>> + '_> {
+        let memtables: Vec<_> = self.memtables().collect();
+        let now = self.config.clock.now_millis();
+        range_over(&memtables, &self.sstables, start, end, now, self.merge_operator.clone(), true)
+    }
+
+    /// Returns every live key-value pair in key order. Each key is emitted once with its
+    /// newest value; entries are read lazily, so the dataset is never held in memory.
+    pub fn iter(&self) -> Result<impl Iterator<Item = Result<(K, V)>> + '_> {
+        self.range(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    /// Returns every live key in key order. Like `iter`, without decoding the values of
+    /// SSTables: only the kind of each entry is read, as `SSTable::get_entry_kind` does.
+    pub fn keys(&self) -> Result<impl Iterator<Item = Result<K>> + '_> {
+        let memtables: Vec<_> = self.memtables().collect();
+        let now = self.config.clock.now_millis();
+        keys_over(&memtables, &self.sstables, Bound::Unbounded, Bound::Unbounded, now)
+    }
+
+    /// Returns every live value in key order. The keys are decoded all the same, since the
+    /// sources are merged by key.
+    pub fn values(&self) -> Result<impl Iterator<Item = Result<V>> + '_> {
+        Ok(self.iter()?.map(|item| item.map(|(_, value)| value)))
+    }
+
+    /// Returns every live key-value pair in descending key order
+    pub fn iter_rev(&self) -> Result<impl Iterator<Item = Result<(K, V)>> + '_> {
+        self.range_rev(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    /// The live entry with the smallest key, or `None` if the tree holds none. Only the first
+    /// block of each SSTable is read, and the ones after it while tombstones hide the keys.
+    pub fn first_key_value(&self) -> Result<Option<(K, V)>> {
+        self.iter()?.next().transpose()
+    }
+
+    /// The live entry with the largest key, or `None` if the tree holds none; see
+    /// `first_key_value`
+    pub fn last_key_value(&self) -> Result<Option<(K, V)>> {
+        self.iter_rev()?.next().transpose()
+    }
+
+    /// Counts the live keys by merging all sources, which reads every SSTable in full.
+    /// See `approx_len` for a cheap estimate.
+    pub fn len(&self) -> Result<usize> {
+        self.iter()?.try_fold(0, |count, item| item.map(|_| count + 1))
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.iter()?.next().transpose()?.is_none())
+    }
+
+    /// Returns the live key-value pairs whose keys start with `prefix`, in key order
+    pub fn scan_prefix(&self, prefix: &K) -> Result<impl Iterator<Item = Result<(K, V)>> + '_>
+    where
+        K: PrefixSuccessor,
+    {
+        let end = match prefix.prefix_successor() {
+            Some(successor) => Bound::Excluded(successor),
+            None => Bound::Unbounded,
+        };
+        self.range(Bound::Included(prefix.clone()), end)
+    }
+}
+
+/// Merges the entries of `memtable` and `sstables` (oldest first) within the bounds,
+/// yielding the live key-value pairs as of `now_millis` in ascending or, if `reverse`,
+/// descending key order. Merge operands are applied with `operator`.
+pub(crate) fn range_over<'a, K, V>(
+    memtables: &[&'a DynMemTable<K, V>],
+    sstables: &[Arc<SSTable<K, V>>],
+    start: Bound<K>,
+    end: Bound<K>,
+    now_millis: u64,
+    operator: Option<Arc<dyn MergeOperator<V>>>,
+    reverse: bool,
+) -> Result<impl Iterator<Item = Result<(K, V)>> + 'a>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone + 'a,
+    V: serde::Serialize + serde::de::DeserializeOwned + Clone + 'a,
+{
+    let combine_operator = operator.clone();
+    let memtable_entry = |entry: &Entry<Arc<V>>| entry.as_ref().map(|value| V::clone(value));
+    let merged = merged_over(memtables, sstables, start, end, reverse, memtable_entry, decode_entry)?;
+    let merged = merged.combining(move |newer, older| {
+        let entry = merge::combine(combine_operator.as_deref(), newer.entry, older.entry)?;
+        Ok(Sequenced::new(entry, newer.sequence))
+    });
+    let merged = merged.map(|item| item.map(|(key, entry)| (key, entry.entry)));
+    Ok(live_entries(merged, now_millis, operator))
+}
+
+/// Like `range_over` in ascending key order, yielding the live keys only. SSTable values
+/// aren't decoded; operands count as a value, since they always make one.
+fn keys_over<'a, K, V>(
+    memtables: &[&'a DynMemTable<K, V>],
+    sstables: &[Arc<SSTable<K, V>>],
+    start: Bound<K>,
+    end: Bound<K>,
+    now_millis: u64,
+) -> Result<impl Iterator<Item = Result<K>> + 'a>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone + 'a,
+    V: serde::Serialize + serde::de::DeserializeOwned + Clone + 'a,
+{
+    let memtable_entry = |entry: &Entry<Arc<V>>| entry.as_ref().map(|_| ());
+    let merged = merged_over(memtables, sstables, start, end, false, memtable_entry, decode_kind::<V>)?;
+    let merged = merged.combining(|newer, older| {
+        Ok(Sequenced::new(merge::combine_kinds(newer.entry, older.entry), newer.sequence))
+    });
+    Ok(merged.filter_map(move |item| match item {
+        Ok((key, entry)) => {
+            let live = !entry.entry.is_expired(now_millis) && !matches!(entry.entry, Entry::Tombstone);
+            live.then_some(Ok(key))
+        }
+        Err(e) => Some(Err(e)),
+    }))
+}
+
+/// Merges the entries of all sources within the bounds, tombstones and expired entries
+/// included, each key once with its newest entry by sequence number. `memtables` are ordered
+/// newest first. The entries of memtables are converted with `memtable_entry` and those of
+/// SSTables decoded with `decode`, in full or only as far as the scan needs.
+fn merged_over<'a, K, V, T>(
+    memtables: &[&'a DynMemTable<K, V>],
+    sstables: &[Arc<SSTable<K, V>>],
+    start: Bound<K>,
+    end: Bound<K>,
+    reverse: bool,
+    memtable_entry: fn(&Entry<Arc<V>>) -> Entry<T>,
+    decode: DecodeEntry<T>,
+) -> Result<MergedEntries<'a, K, T>>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone + 'a,
+    V: serde::Serialize + serde::de::DeserializeOwned + Clone + 'a,
+    T: serde::de::DeserializeOwned + 'a,
+{
+    let mut sources: Vec<EntrySource<'a, K, T>> = Vec::with_capacity(sstables.len() + memtables.len());
+    let mut tombstones = Vec::with_capacity(sstables.len() + memtables.len());
+
+    for memtable in memtables {
+        let memtable_range = memtable.sequenced_range(start.as_ref(), end.as_ref());
+        let memtable_range: Box<dyn Iterator<Item = _>> = match reverse {
+            true => Box::new(memtable_range.rev()),
+            false => Box::new(memtable_range),
+        };
+        let memtable_range = memtable_range.map(move |(key, entry)| Ok((key, entry.as_ref().map(memtable_entry))));
+        sources.push(Box::new(memtable_range));
+    }
+    let memtable_tombstones: Vec<_> = memtables.iter().map(|memtable| memtable.range_tombstones()).collect();
+    tombstones.extend(memtable_tombstones.iter().map(|tombstones| tombstones.as_slice()));
+
+    for sstable in sstables.iter().rev() {
+        if !sstable.overlaps_range(start.as_ref(), end.as_ref())? {
+            continue;
+        }
+        match reverse {
+            true => sources.push(Box::new(sstable.range_rev_with(start.clone(), end.clone(), decode)?.sequenced())),
+            false => sources.push(Box::new(sstable.range_with(start.clone(), end.clone(), decode)?.sequenced())),
+        }
+        tombstones.push(sstable.range_tombstones());
+    }
+
+    let comparator = Arc::clone(memtables[0].comparator());
+    let sources = sources
+        .into_iter()
+        .zip(range_tombstone::newer_tombstones(&tombstones))
+        .map(|(source, newer)| -> EntrySource<'a, K, T> {
+            Box::new(range_tombstone::uncovered(source, newer, Arc::clone(&comparator)))
+        })
+        .collect();
+    let merged = match reverse {
+        true => MergeIterator::descending(sources, comparator)?,
+        false => MergeIterator::new(sources, comparator)?,
+    };
+    Ok(merged.by_sequence(|entry| entry.sequence))
+}
+
+/// Drops tombstones and entries expired at `now_millis` from a merged stream, which ends
+/// after its first error. Merge operands left over are applied onto no value.
+fn live_entries<K, V>(
+    merged: impl Iterator<Item = Result<(K, Entry<V>)>>,
+    now_millis: u64,
+    operator: Option<Arc<dyn MergeOperator<V>>>,
+) -> impl Iterator<Item = Result<(K, V)>> {
+    merged.filter_map(move |item| {
+        match item.and_then(|(key, entry)| Ok((key, merge::resolve(operator.as_deref(), entry)?))) {
+            Ok((key, entry)) => entry.into_live_value(now_millis).map(|value| Ok((key, value))),
+            Err(e) => Some(Err(e)),
+        }
+    })
+}
+
+/// Collects the successful items of a scan, for callers content with what could be read
+pub trait CollectOk<T> {
+    /// Collects the `Ok` items, logging and skipping the errors
+    fn collect_ok(self) -> Vec<T>;
+}
+
+impl<T, I: Iterator<Item = Result<T>>> CollectOk<T> for I {
+    fn collect_ok(self) -> Vec<T> {
+        self.filter_map(|item| item.map_err(|e| log::error!("Skipping an item that failed to read: {}", e)).ok())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{Encoding, Format};
+    use crate::Config;
+    use tempfile::TempDir;
+
+    fn setup() -> (LSMTree<i32, String>, TempDir) {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            memtable_size_threshold: 1024 * 1024,
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            ..Config::default()
+        };
+        (LSMTree::with_config(config).unwrap(), temp_dir)
+    }
+
+    #[test]
+    fn test_range_across_sources() -> Result<()> {
+        let (mut lsm, _temp_dir) = setup();
+
+        for i in 0..50 {
+            lsm.insert(i, format!("old{}", i))?;
+        }
+        lsm.flush()?;
+        for i in (0..50).step_by(5) {
+            lsm.insert(i, format!("new{}", i))?;
+        }
+        lsm.delete(12)?;
+        lsm.flush()?;
+        lsm.insert(13, "mem13".to_string())?;
+        lsm.delete(14)?;
+
+        let result: Vec<_> = lsm.range(Bound::Included(10), Bound::Excluded(16))?.collect::<Result<_>>()?;
+        assert_eq!(
+            result,
+            vec![
+                (10, "new10".to_string()),
+                (11, "old11".to_string()),
+                (13, "mem13".to_string()),
+                (15, "new15".to_string()),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_prefers_newest_source() -> Result<()> {
+        let (mut lsm, _temp_dir) = setup();
+
+        for round in 0..3 {
+            for i in 0..30 {
+                if (i + round) % 3 == 0 {
+                    lsm.insert(i, format!("{}_{}", i, round))?;
+                }
+            }
+            lsm.flush()?;
+        }
+        lsm.insert(0, "mem".to_string())?;
+        lsm.delete(1)?;
+
+        let result: Vec<_> = lsm.iter()?.collect::<Result<_>>()?;
+        let keys: Vec<_> = result.iter().map(|(k, _)| *k).collect();
+        assert_eq!(keys, (0..30).filter(|&k| k != 1).collect::<Vec<_>>());
+        assert_eq!(result[0], (0, "mem".to_string()));
+        // Key 2 was only written in round 1 and key 4 in round 2
+        assert_eq!(result[1], (2, "2_1".to_string()));
+        assert_eq!(result[3], (4, "4_2".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_skips_disjoint_sstables() -> Result<()> {
+        let (mut lsm, _temp_dir) = setup();
+
+        for table in 0..4 {
+            for i in table * 100..table * 100 + 50 {
+                lsm.insert(i, i.to_string())?;
+            }
+            lsm.flush()?;
+        }
+
+        let opened = |lsm: &LSMTree<i32, String>| lsm.stats().sstable_files_opened;
+        let before = opened(&lsm);
+        assert_eq!(lsm.range(Bound::Included(110), Bound::Excluded(120))?.count(), 10);
+        assert_eq!(opened(&lsm) - before, 1);
+
+        // The end bound is exclusive, and the gap between tables holds no keys
+        let before = opened(&lsm);
+        assert_eq!(lsm.range(Bound::Excluded(49), Bound::Excluded(200))?.count(), 50);
+        assert_eq!(opened(&lsm) - before, 1);
+        let before = opened(&lsm);
+        assert_eq!(lsm.range(Bound::Included(60), Bound::Included(90))?.count(), 0);
+        assert_eq!(opened(&lsm) - before, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_rev() -> Result<()> {
+        let (mut lsm, _temp_dir) = setup();
+
+        for i in 0..50 {
+            lsm.insert(i, format!("old{}", i))?;
+        }
+        lsm.flush()?;
+        for i in (0..50).step_by(5) {
+            lsm.insert(i, format!("new{}", i))?;
+        }
+        lsm.delete(12)?;
+        lsm.flush()?;
+        lsm.insert(13, "mem13".to_string())?;
+        lsm.delete(14)?;
+
+        let result: Vec<_> = lsm.range_rev(Bound::Included(10), Bound::Excluded(16))?.collect::<Result<_>>()?;
+        assert_eq!(
+            result,
+            vec![
+                (15, "new15".to_string()),
+                (13, "mem13".to_string()),
+                (11, "old11".to_string()),
+                (10, "new10".to_string()),
+            ]
+        );
+
+        let mut forward: Vec<_> = lsm.iter()?.collect::<Result<_>>()?;
+        forward.reverse();
+        assert_eq!(lsm.iter_rev()?.collect::<Result<Vec<_>>>()?, forward);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_reports_read_errors() -> Result<()> {
+        let (mut lsm, _temp_dir) = setup();
+        for i in 0..100 {
+            lsm.insert(i, format!("value{}", i))?;
+        }
+        lsm.flush()?;
+
+        // Flip a byte in the middle of the table's data
+        let path = lsm.sstables[0].path().to_path_buf();
+        let mut bytes = std::fs::read(&path)?;
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xff;
+        std::fs::write(&path, bytes)?;
+
+        let items: Vec<_> = lsm.iter()?.collect();
+        let (last, read) = items.split_last().unwrap();
+        assert!(last.is_err());
+        assert!(!read.is_empty() && read.iter().all(|item| item.is_ok()));
+        assert_eq!(lsm.iter()?.collect_ok().len(), read.len());
+        assert!(lsm.len().is_err());
+
+        Ok(())
+    }
+
+    /// A value that can be written but not read back
+    #[derive(Clone, Debug, PartialEq, serde::Serialize)]
+    struct Unreadable(String);
+
+    impl<'de> serde::Deserialize<'de> for Unreadable {
+        fn deserialize<D: serde::Deserializer<'de>>(_: D) -> std::result::Result<Self, D::Error> {
+            Err(serde::de::Error::custom("values aren't read"))
+        }
+    }
+
+    #[test]
+    fn test_keys_and_values() -> Result<()> {
+        for format in [Format::Bincode, Format::MessagePack] {
+            let temp_dir = TempDir::new().unwrap();
+            let config = Config {
+                data_dir: temp_dir.path().to_path_buf(),
+                compaction_threshold: None,
+                encoding: Encoding { format, ..Encoding::default() },
+                ..Config::default()
+            };
+            let mut lsm = LSMTree::with_config(config)?;
+            for i in 0..20 {
+                lsm.insert(i, Unreadable(i.to_string()))?;
+            }
+            lsm.delete(3)?;
+            lsm.flush()?;
+            lsm.delete_range(10, 15)?;
+            lsm.flush()?;
+            lsm.insert(12, Unreadable("back".to_string()))?;
+            lsm.delete(5)?;
+            lsm.insert(30, Unreadable("30".to_string()))?;
+
+            // The values in SSTables are never decoded
+            let pairs = || -> Result<Vec<_>> { lsm.iter()?.collect() };
+            assert!(pairs().is_err());
+            let keys: Vec<_> = lsm.keys()?.collect::<Result<_>>()?;
+            assert_eq!(keys, vec![0, 1, 2, 4, 6, 7, 8, 9, 12, 15, 16, 17, 18, 19, 30]);
+        }
+
+        let (mut lsm, _temp_dir) = setup();
+        for i in 0..10 {
+            lsm.insert(i, format!("value{}", i))?;
+        }
+        lsm.flush()?;
+        lsm.delete(4)?;
+        let values: Vec<_> = lsm.values()?.collect::<Result<_>>()?;
+        assert_eq!(values, (0..10).filter(|&i| i != 4).map(|i| format!("value{}", i)).collect::<Vec<_>>());
+        let keys: Vec<_> = lsm.keys()?.collect::<Result<_>>()?;
+        assert_eq!(keys, lsm.iter()?.map(|item| item.map(|(key, _)| key)).collect::<Result<Vec<_>>>()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_empty() -> Result<()> {
+        let (lsm, _temp_dir) = setup();
+        assert_eq!(lsm.iter()?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_first_and_last_key_value() -> Result<()> {
+        let (mut lsm, _temp_dir) = setup();
+        assert_eq!(lsm.first_key_value()?, None);
+        assert_eq!(lsm.last_key_value()?, None);
+
+        for i in 10..20 {
+            lsm.insert(i, i.to_string())?;
+        }
+        lsm.flush()?;
+        lsm.insert(5, "5".to_string())?;
+        assert_eq!(lsm.first_key_value()?, Some((5, "5".to_string())));
+        assert_eq!(lsm.last_key_value()?, Some((19, "19".to_string())));
+
+        // Tombstones at either end, in the memtable or on disk, are skipped
+        lsm.delete(5)?;
+        lsm.delete(10)?;
+        lsm.flush()?;
+        lsm.delete(19)?;
+        lsm.delete(18)?;
+        assert_eq!(lsm.first_key_value()?, Some((11, "11".to_string())));
+        assert_eq!(lsm.last_key_value()?, Some((17, "17".to_string())));
+
+        for i in 11..18 {
+            lsm.delete(i)?;
+        }
+        assert_eq!(lsm.first_key_value()?, None);
+        assert_eq!(lsm.last_key_value()?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_len() -> Result<()> {
+        let (mut lsm, _temp_dir) = setup();
+        assert_eq!(lsm.len()?, 0);
+        assert!(lsm.is_empty()?);
+
+        for i in 0..20 {
+            lsm.insert(i, i.to_string())?;
+        }
+        lsm.flush()?;
+        for i in 10..30 {
+            lsm.insert(i, i.to_string())?;
+        }
+        lsm.delete(0)?;
+        lsm.delete(100)?;
+
+        assert_eq!(lsm.len()?, 29);
+        assert!(!lsm.is_empty()?);
+        // Overwrites and tombstones are counted once per source
+        assert_eq!(lsm.approx_len(), 20 + 22);
+        assert_eq!(lsm.estimate_range_count(&0, &30)?, 20 + 21);
+        assert_eq!(lsm.estimate_range_count(&30, &100)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prefix_successor() {
+        assert_eq!("user:".to_string().prefix_successor(), Some("user;".to_string()));
+        assert_eq!("a\u{10FFFF}".to_string().prefix_successor(), Some("b".to_string()));
+        assert_eq!("\u{D7FF}".to_string().prefix_successor(), Some("\u{E000}".to_string()));
+        assert_eq!("\u{10FFFF}".to_string().prefix_successor(), None);
+        assert_eq!(String::new().prefix_successor(), None);
+
+        assert_eq!(vec![1u8, 2].prefix_successor(), Some(vec![1, 3]));
+        assert_eq!(vec![1u8, 0xff, 0xff].prefix_successor(), Some(vec![2]));
+        assert_eq!(vec![0xffu8].prefix_successor(), None);
+    }
+
+    #[test]
+    fn test_scan_prefix() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config)?;
+
+        for key in ["user:1:name", "user:1:session", "user:10:name", "user", "users:1", "user:2:name"] {
+            lsm.insert(key.to_string(), 1)?;
+        }
+        lsm.flush()?;
+        lsm.insert("user:1:email".to_string(), 2)?;
+        lsm.delete("user:1:session".to_string())?;
+
+        let keys = |prefix: &str| -> Result<Vec<String>> {
+            lsm.scan_prefix(&prefix.to_string())?.map(|item| item.map(|(key, _)| key)).collect()
+        };
+        assert_eq!(keys("user:1:")?, vec!["user:1:email", "user:1:name"]);
+        assert_eq!(keys("user:1")?, vec!["user:10:name", "user:1:email", "user:1:name"]);
+        assert_eq!(keys("user:")?.len(), 4);
+        assert_eq!(keys("")?.len(), 6);
+        assert!(keys("nobody")?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_bounds() -> Result<()> {
+        let (mut lsm, _temp_dir) = setup();
+
+        for i in 0..20 {
+            lsm.insert(i, i.to_string())?;
+            if i == 9 {
+                lsm.flush()?;
+            }
+        }
+
+        let keys = |start, end| -> Result<Vec<i32>> {
+            lsm.range(start, end)?.map(|item| item.map(|(k, _)| k)).collect()
+        };
+        assert_eq!(keys(Bound::Excluded(8), Bound::Included(11))?, vec![9, 10, 11]);
+        assert_eq!(keys(Bound::Unbounded, Bound::Excluded(2))?, vec![0, 1]);
+        assert_eq!(keys(Bound::Included(18), Bound::Unbounded)?, vec![18, 19]);
+        assert_eq!(keys(Bound::Unbounded, Bound::Unbounded)?, (0..20).collect::<Vec<_>>());
+        assert_eq!(keys(Bound::Included(15), Bound::Excluded(5))?, Vec::<i32>::new());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+//! Sequence numbers.
+//!
+//! Every write gets the next number of a counter that only goes up, and the entries and range
+//! tombstones it leaves in memtables and SSTables keep it, so the versions of a key are ordered
+//! by when they were written rather than by where they are stored. Lookups still visit the
+//! memtables and then the SSTables newest first, but only skip a source once it holds nothing
+//! newer than a version of the key that isn't a merge, and then collapse the versions they
+//! found by sequence number. Scans and compactions merge the versions of a key the same way
+//! (see `MergeIterator::by_sequence`), and a compaction's output keeps the number of each
+//! version it writes.
+//!
+//! The active memtable hands out the numbers, continuing from the last one of the memtable it
+//! replaces. The last number written to SSTables is kept in the manifest, and the writes in the
+//! write-ahead log are numbered again from there when it is replayed, in the order they were
+//! made. A bulk load takes a single number for all of its entries. Entries added to an
+//! `SSTableWriter` without a number have 0: they are older than any numbered write, and file
+//! order decides between them.
+
+use crate::memtable::Entry;
+
+/// An entry, or anything else written, with the sequence number of the write that made it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sequenced<T> {
+    pub entry: T,
+    pub sequence: u64,
+}
+
+impl<T> Sequenced<T> {
+    pub fn new(entry: T, sequence: u64) -> Self {
+        Self { entry, sequence }
+    }
+
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Sequenced<U> {
+        Sequenced::new(f(self.entry), self.sequence)
+    }
+
+    pub fn as_ref(&self) -> Sequenced<&T> {
+        Sequenced::new(&self.entry, self.sequence)
+    }
+}
+
+/// The versions of a key found so far by a lookup that visits its sources newest first
+pub(crate) struct Versions<T> {
+    versions: Vec<Sequenced<Entry<T>>>,
+    /// Sequence number of the newest version found that isn't a merge: nothing older matters
+    floor: Option<u64>,
+}
+
+impl<T> Versions<T> {
+    pub(crate) fn new() -> Self {
+        Self { versions: Vec::new(), floor: None }
+    }
+
+    /// Whether a source whose newest write is `max_sequence` may still hold a version that
+    /// matters. Versions written without a sequence number all have 0, and of those only
+    /// the first one found does.
+    pub(crate) fn wants(&self, max_sequence: u64) -> bool {
+        self.floor.is_none_or(|floor| max_sequence > floor)
+    }
+
+    pub(crate) fn push(&mut self, version: Sequenced<Entry<T>>) {
+        if !version.entry.is_merge() {
+            self.floor = self.floor.max(Some(version.sequence));
+        }
+        self.versions.push(version);
+    }
+
+    /// The versions, newest first, down to the first one that isn't a merge. Versions with
+    /// the same number keep the order they were found in.
+    pub(crate) fn newest_first(mut self) -> impl Iterator<Item = Entry<T>> {
+        self.versions.sort_by_key(|version| std::cmp::Reverse(version.sequence));
+        self.versions.into_iter().map(|version| version.entry)
+    }
+
+    /// The newest version, if any
+    pub(crate) fn newest(self) -> Option<Entry<T>> {
+        self.newest_first().next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, LSMTree, Result};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_versions() {
+        let mut versions = Versions::new();
+        assert!(versions.wants(0));
+        versions.push(Sequenced::new(Entry::Merge(vec![3]), 7));
+        assert!(versions.wants(5));
+        versions.push(Sequenced::new(Entry::Value(1), 5));
+        assert!(!versions.wants(5) && versions.wants(6));
+        // Found later, but newer
+        versions.push(Sequenced::new(Entry::Value(2), 6));
+        let newest: Vec<_> = versions.newest_first().collect();
+        assert_eq!(newest, vec![Entry::Merge(vec![3]), Entry::Value(2), Entry::Value(1)]);
+    }
+
+    #[test]
+    fn test_sequence_numbers() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config.clone())?;
+        assert_eq!(lsm.last_sequence(), 0);
+        lsm.insert(1, "one".to_string())?;
+        lsm.insert(2, "two".to_string())?;
+        lsm.delete(1)?;
+        assert_eq!(lsm.last_sequence(), 3);
+        lsm.flush()?;
+        assert_eq!(lsm.sstables[0].max_sequence(), 3);
+        let entry = lsm.sstables[0].get_sequenced(&2)?;
+        assert_eq!(entry, Some(Sequenced::new(Entry::Value("two".to_string()), 2)));
+
+        // The counter survives reopening, whether the writes were flushed or are replayed
+        lsm.insert(3, "three".to_string())?;
+        lsm.delete_range(10, 20)?;
+        drop(lsm);
+        let mut lsm = LSMTree::<i32, String>::with_config(config.clone())?;
+        assert_eq!(lsm.last_sequence(), 5);
+        lsm.flush()?;
+        assert_eq!(lsm.sstables[1].range_tombstones()[0].sequence, 5);
+        lsm.close()?;
+        let mut lsm = LSMTree::<i32, String>::with_config(config.clone())?;
+        assert_eq!(lsm.last_sequence(), 5);
+
+        // A bulk load takes one number, and compaction keeps them
+        lsm.ingest_sorted((4..8).map(|i| (i, i.to_string())), 4)?;
+        assert_eq!(lsm.last_sequence(), 6);
+        lsm.compact()?;
+        let mut sequences = Vec::new();
+        for sstable in &lsm.sstables {
+            for item in sstable.sequenced_entries()? {
+                let (key, entry) = item?;
+                sequences.push((key, entry.sequence));
+            }
+        }
+        sequences.sort();
+        assert_eq!(sequences, vec![(2, 2), (3, 4), (4, 6), (5, 6), (6, 6), (7, 6)]);
+        lsm.insert(1, "again".to_string())?;
+        assert_eq!(lsm.last_sequence(), 7);
+        assert_eq!(lsm.get(&1)?, Some("again".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_newest_wins_by_sequence() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            ..Config::default()
+        };
+        let mut lsm = LSMTree::with_config(config)?;
+        lsm.insert(1, "old".to_string())?;
+        lsm.insert(2, "old".to_string())?;
+        lsm.flush()?;
+        lsm.insert(1, "new".to_string())?;
+        lsm.flush()?;
+
+        // Put the older table last in search order: the newer version still wins
+        lsm.sstables.swap(0, 1);
+        lsm.manifest.sstables.swap(0, 1);
+        assert_eq!(lsm.get(&1)?, Some("new".to_string()));
+        assert_eq!(lsm.get_many(&[1, 2])?, vec![Some("new".to_string()), Some("old".to_string())]);
+        assert!(lsm.contains_key(&2)?);
+        assert_eq!(lsm.iter()?.collect::<Result<Vec<_>>>()?, vec![(1, "new".to_string()), (2, "old".to_string())]);
+        assert_eq!(lsm.iter_rev()?.next().transpose()?, Some((2, "old".to_string())));
+        lsm.compact()?;
+        assert_eq!(lsm.get(&1)?, Some("new".to_string()));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,559 @@
+//! Memtable split into shards by key hash, see `Config::memtable_shards`.
+//!
+//! `ShardedMemTable` keeps its entries in several `MemTable`s, each behind a lock of its own,
+//! and sends every key to the shard picked by the xxh3 hash of the key in the memtable's
+//! encoding, modulo the number of shards. Writes through `&self` to keys of different shards
+//! don't wait for one another. The shards share one sequence counter, which a write takes from
+//! with its shard's lock held.
+//!
+//! Range tombstones are kept once for the whole memtable. `delete_range` locks every shard,
+//! removes the older entries in its range from each and records the tombstone, so entries left
+//! in the shards are always newer than the range tombstones.
+//!
+//! Lookups consult every shard and keep the newest entry. Iteration copies the entries in range
+//! out of each shard, one lock at a time, and merges them. A flush writes the range tombstones
+//! to a level 0 table of their own, installed before the tables of the shards, then each shard
+//! to tables of its own.
+
+use crate::comparator::Comparator;
+use crate::encoding::Encoding;
+use crate::memtable::{DynMemTable, Entry, FlushPart, MemTable, MemTableStore, Version};
+use crate::merge;
+use crate::range_tombstone::{self, RangeTombstone};
+use crate::sequence::Sequenced;
+use crate::stats::Counters;
+use crate::Result;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock};
+
+pub(crate) struct ShardedMemTable<K, V> {
+    shards: Vec<Mutex<MemTable<K, V>>>,
+    /// Recorded by `delete_range`, oldest first
+    range_tombstones: RwLock<Arc<Vec<RangeTombstone<K>>>>,
+    /// Serialized size of the bounds of the range tombstones
+    range_tombstones_size: AtomicUsize,
+    comparator: Arc<dyn Comparator<K>>,
+    encoding: Encoding,
+    /// Sequence number of the last write to any shard
+    last_sequence: AtomicU64,
+    /// Records the sizes of the keys and values written
+    counters: Arc<Counters>,
+}
+
+impl<K, V> ShardedMemTable<K, V>
+where
+    K: Ord + serde::Serialize + Clone + Send + Sync + 'static,
+    V: serde::Serialize + Send + Sync + 'static,
+{
+    /// An empty memtable of `shards` shards recording into `counters`, whose writes continue
+    /// after `last_sequence`
+    fn empty(
+        comparator: Arc<dyn Comparator<K>>,
+        encoding: Encoding,
+        counters: Arc<Counters>,
+        shards: usize,
+        last_sequence: u64,
+    ) -> Self {
+        let shard = || {
+            let memtable = MemTable::with_comparator_and_encoding(Arc::clone(&comparator), encoding);
+            Mutex::new(memtable.with_counters(Arc::clone(&counters)))
+        };
+        Self {
+            shards: (0..shards.max(1)).map(|_| shard()).collect(),
+            range_tombstones: RwLock::new(Arc::new(Vec::new())),
+            range_tombstones_size: AtomicUsize::new(0),
+            comparator,
+            encoding,
+            last_sequence: AtomicU64::new(last_sequence),
+            counters,
+        }
+    }
+
+    /// Spreads the entries of `memtable`, e.g. replayed from the write-ahead log, over
+    /// `shards` shards, keeping their sequence numbers
+    pub(crate) fn from_memtable(memtable: MemTable<K, V>, shards: usize) -> Result<Self> {
+        let comparator = Arc::clone(memtable.comparator());
+        // Replayed writes were recorded when they were first made
+        let (encoding, last_sequence) = (memtable.encoding(), memtable.last_sequence());
+        let sharded = Self::empty(comparator, encoding, Arc::default(), shards, last_sequence);
+        for (key, version) in memtable.sequenced_entries() {
+            let mut shard = sharded.lock(sharded.shard_of(key)?);
+            shard.set_last_sequence(version.sequence.saturating_sub(1));
+            shard.put_entry(key.clone(), version.entry.clone())?;
+        }
+        let mut range_tombstones_size = 0usize;
+        for tombstone in memtable.range_tombstones() {
+            let size = sharded.serialized_size(&tombstone.start)?;
+            let size = size.saturating_add(sharded.serialized_size(&tombstone.end)?);
+            range_tombstones_size = range_tombstones_size.saturating_add(size);
+        }
+        let counters = Arc::clone(memtable.counters());
+        let shards = sharded.shards.into_iter().map(|shard| {
+            let shard = shard.into_inner().unwrap_or_else(PoisonError::into_inner);
+            Mutex::new(shard.with_counters(Arc::clone(&counters)))
+        });
+        Ok(Self {
+            shards: shards.collect(),
+            range_tombstones: RwLock::new(Arc::new(memtable.range_tombstones().to_vec())),
+            range_tombstones_size: AtomicUsize::new(range_tombstones_size),
+            counters,
+            ..sharded
+        })
+    }
+
+    /// Sequence number of the last write to take one
+    pub(crate) fn last_sequence(&self) -> u64 {
+        self.last_sequence.load(Ordering::SeqCst)
+    }
+
+    fn next_sequence(&self) -> u64 {
+        self.last_sequence.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Inserts a value, returning the size of the new entry
+    pub(crate) fn put(&self, key: K, value: V) -> Result<usize> {
+        self.write(key, |shard, key| shard.put(key, value))
+    }
+
+    /// Inserts a value that expires at `expires_at` (milliseconds since the UNIX epoch)
+    pub(crate) fn put_with_expiry(&self, key: K, value: V, expires_at: u64) -> Result<usize> {
+        self.write(key, |shard, key| shard.put_with_expiry(key, value, expires_at))
+    }
+
+    /// Stores each entry in order and returns their total size, like `MemTable::apply_batch`.
+    /// Nothing is stored if any entry fails to serialize. The shards the batch writes to stay
+    /// locked until it is stored, so the entries take consecutive sequence numbers.
+    pub(crate) fn apply_batch(&self, entries: Vec<(K, Entry<V>)>) -> Result<usize> {
+        let mut batch = Vec::with_capacity(entries.len());
+        let mut written = vec![false; self.shards.len()];
+        for (key, entry) in entries {
+            self.encoding.serialized_size(&entry)?;
+            let index = self.shard_of(&key)?;
+            written[index] = true;
+            batch.push((index, key, entry));
+        }
+        // Locked in index order, like `delete_range` does
+        let mut shards: Vec<_> =
+            written.into_iter().enumerate().map(|(index, written)| written.then(|| self.lock(index))).collect();
+
+        let mut total = 0usize;
+        for (index, key, entry) in batch {
+            if let Some(shard) = &mut shards[index] {
+                shard.set_last_sequence(self.last_sequence.fetch_add(1, Ordering::SeqCst));
+                total = total.saturating_add(shard.put_entry(key, entry.map(Arc::new))?);
+            }
+        }
+        Ok(total)
+    }
+
+    /// Stores `entry` for `key`, replacing any entry the key has, and returns its size
+    pub(crate) fn put_entry(&self, key: K, entry: Entry<Arc<V>>) -> Result<usize> {
+        self.write(key, |shard, key| shard.put_entry(key, entry))
+    }
+
+    /// Appends merge operands to those stored for `key`, and returns the size of its new entry.
+    /// Fails with `NoMergeOperator` if the key has an entry that isn't a merge, like
+    /// `MemTable::append_operands`.
+    pub(crate) fn append_operands(&self, key: K, operands: Vec<V>) -> Result<usize> {
+        self.write(key, |shard, key| {
+            let entry = Entry::Merge(operands.into_iter().map(Arc::new).collect());
+            let current = shard.get_sequenced(&key).map(|entry| entry.map(Entry::clone));
+            let entry = match self.resolve(current, &key) {
+                Some(older) => merge::combine::<V, _>(None, entry, older.entry)?,
+                None => entry,
+            };
+            shard.put_entry(key, entry)
+        })
+    }
+
+    /// Records a tombstone for `key`, shadowing any value stored for it here or in older SSTables
+    pub(crate) fn delete(&self, key: K) -> Result<usize> {
+        self.write(key, |shard, key| shard.delete(key))
+    }
+
+    /// Records a range tombstone deleting the keys in `[start, end)`, here and in older
+    /// SSTables, and removes the older entries in that range from every shard, like
+    /// `MemTable::delete_range`.
+    pub(crate) fn delete_range(&self, start: K, end: K) -> Result<usize> {
+        if self.comparator.compare(&start, &end).is_ge() {
+            return Ok(0);
+        }
+        let size = self.serialized_size(&start)?.saturating_add(self.serialized_size(&end)?);
+
+        // Every shard stays locked until the tombstone is recorded, so each write either lands
+        // before it and is removed, or takes a later sequence number
+        let mut shards: Vec<_> = (0..self.shards.len()).map(|index| self.lock(index)).collect();
+        for shard in &mut shards {
+            shard.remove_range(&start, &end);
+        }
+        let mut tombstones = self.range_tombstones.write().unwrap_or_else(PoisonError::into_inner);
+        let sequence = self.next_sequence();
+        Arc::make_mut(&mut tombstones).push(RangeTombstone { start, end, sequence });
+        let _ = self.range_tombstones_size.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |total| {
+            Some(total.saturating_add(size))
+        });
+        Ok(size)
+    }
+
+    /// Runs `write` on the shard of `key` with its lock held, making the write take the next
+    /// sequence number
+    fn write<T>(&self, key: K, write: impl FnOnce(&mut MemTable<K, V>, K) -> Result<T>) -> Result<T> {
+        let mut shard = self.lock(self.shard_of(&key)?);
+        shard.set_last_sequence(self.last_sequence.fetch_add(1, Ordering::SeqCst));
+        write(&mut shard, key)
+    }
+
+    /// Index of the shard that stores `key`
+    fn shard_of(&self, key: &K) -> Result<usize> {
+        let hash = xxhash_rust::xxh3::xxh3_64(&self.encoding.serialize(key)?);
+        Ok((hash % self.shards.len() as u64) as usize)
+    }
+
+    fn lock(&self, index: usize) -> MutexGuard<'_, MemTable<K, V>> {
+        self.shards[index].lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Size of `value` in the memtable's encoding, clamped to `usize::MAX`
+    fn serialized_size<T: serde::Serialize + ?Sized>(&self, value: &T) -> Result<usize> {
+        let size = self.encoding.serialized_size(value)?;
+        Ok(usize::try_from(size).unwrap_or(usize::MAX))
+    }
+
+    /// The newer of `current`, the entry stored for `key`, and the range tombstone covering
+    /// `key`, if any
+    fn resolve(&self, current: Option<Version<V>>, key: &K) -> Option<Version<V>> {
+        let tombstones = self.range_tombstones.read().unwrap_or_else(PoisonError::into_inner);
+        let covering = range_tombstone::covering_sequence(&tombstones, key, &*self.comparator);
+        match (current, covering) {
+            (Some(version), Some(sequence)) if sequence > version.sequence => {
+                Some(Sequenced::new(Entry::Tombstone, sequence))
+            }
+            (Some(version), _) => Some(version),
+            (None, sequence) => sequence.map(|sequence| Sequenced::new(Entry::Tombstone, sequence)),
+        }
+    }
+
+    /// The newest entry for `key` in any shard, a tombstone if a newer range tombstone covers it
+    pub(crate) fn get_sequenced(&self, key: &K) -> Option<Version<V>> {
+        let newest = (0..self.shards.len())
+            .filter_map(|index| self.lock(index).get_sequenced(key).map(|entry| entry.map(Entry::clone)))
+            .max_by_key(|version| version.sequence);
+        self.resolve(newest, key)
+    }
+
+    pub(crate) fn range_tombstones(&self) -> Arc<Vec<RangeTombstone<K>>> {
+        Arc::clone(&self.range_tombstones.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        let sizes = (0..self.shards.len()).map(|index| self.lock(index).size());
+        sizes.fold(0usize, usize::saturating_add).saturating_add(self.range_tombstones_size.load(Ordering::Relaxed))
+    }
+
+    /// Number of entries, including tombstones but not range tombstones
+    pub(crate) fn len(&self) -> usize {
+        (0..self.shards.len()).map(|index| self.lock(index).len()).sum()
+    }
+
+    /// Whether the memtable has neither entries nor range tombstones
+    pub(crate) fn is_empty(&self) -> bool {
+        self.range_tombstones().is_empty() && (0..self.shards.len()).all(|index| self.lock(index).is_empty())
+    }
+
+    /// The entries within the bounds in key order, copied out of the shards. Each shard's run
+    /// is already sorted, so merging them is a single pass of the stable sort over the runs;
+    /// should two shards have keys the comparator considers equal, the newer entry is kept.
+    pub(crate) fn sequenced_range(&self, start: Bound<&K>, end: Bound<&K>) -> Vec<(K, Version<V>)> {
+        let mut entries = Vec::new();
+        for index in 0..self.shards.len() {
+            // Cloning shares the shard's map, so the lock isn't held while copying entries
+            let shard = self.lock(index).clone();
+            entries.extend(shard.sequenced_range(start, end).map(|(key, version)| (key.clone(), version.clone())));
+        }
+        if self.shards.len() > 1 {
+            let comparator = &*self.comparator;
+            entries.sort_by(|(a, older), (b, newer)| {
+                comparator.compare(a, b).then(newer.sequence.cmp(&older.sequence))
+            });
+            entries.dedup_by(|(later, _), (earlier, _)| comparator.compare(later, earlier).is_eq());
+        }
+        entries
+    }
+
+    /// A copy sharing the maps of the shards until their next write, like `MemTable::clone`
+    fn copy(&self) -> Self {
+        Self {
+            shards: (0..self.shards.len()).map(|index| Mutex::new(self.lock(index).clone())).collect(),
+            range_tombstones: RwLock::new(self.range_tombstones()),
+            range_tombstones_size: AtomicUsize::new(self.range_tombstones_size.load(Ordering::Relaxed)),
+            comparator: Arc::clone(&self.comparator),
+            encoding: self.encoding,
+            last_sequence: AtomicU64::new(self.last_sequence()),
+            counters: Arc::clone(&self.counters),
+        }
+    }
+}
+
+impl<K, V> MemTableStore<K, V> for ShardedMemTable<K, V>
+where
+    K: Ord + serde::Serialize + Clone + Send + Sync + 'static,
+    V: serde::Serialize + Send + Sync + 'static,
+{
+    fn last_sequence(&self) -> u64 {
+        ShardedMemTable::last_sequence(self)
+    }
+
+    fn next_sequence(&mut self) -> u64 {
+        ShardedMemTable::next_sequence(self)
+    }
+
+    fn comparator(&self) -> &Arc<dyn Comparator<K>> {
+        &self.comparator
+    }
+
+    fn put(&mut self, key: K, value: V) -> Result<usize> {
+        ShardedMemTable::put(self, key, value)
+    }
+
+    fn put_with_expiry(&mut self, key: K, value: V, expires_at: u64) -> Result<usize> {
+        ShardedMemTable::put_with_expiry(self, key, value, expires_at)
+    }
+
+    fn apply_batch(&mut self, entries: Vec<(K, Entry<V>)>) -> Result<usize> {
+        ShardedMemTable::apply_batch(self, entries)
+    }
+
+    fn put_entry(&mut self, key: K, entry: Entry<Arc<V>>) -> Result<usize> {
+        ShardedMemTable::put_entry(self, key, entry)
+    }
+
+    fn append_operands(&mut self, key: K, operands: Vec<V>) -> Result<usize> {
+        ShardedMemTable::append_operands(self, key, operands)
+    }
+
+    fn delete(&mut self, key: K) -> Result<usize> {
+        ShardedMemTable::delete(self, key)
+    }
+
+    fn delete_range(&mut self, start: K, end: K) -> Result<usize> {
+        ShardedMemTable::delete_range(self, start, end)
+    }
+
+    fn get_sequenced(&self, key: &K) -> Option<Version<V>> {
+        ShardedMemTable::get_sequenced(self, key)
+    }
+
+    fn range_tombstones(&self) -> Arc<Vec<RangeTombstone<K>>> {
+        ShardedMemTable::range_tombstones(self)
+    }
+
+    fn size(&self) -> usize {
+        ShardedMemTable::size(self)
+    }
+
+    fn len(&self) -> usize {
+        ShardedMemTable::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        ShardedMemTable::is_empty(self)
+    }
+
+    fn sequenced_range<'a>(
+        &'a self,
+        start: Bound<&K>,
+        end: Bound<&K>,
+    ) -> Box<dyn DoubleEndedIterator<Item = (K, Version<V>)> + 'a> {
+        Box::new(ShardedMemTable::sequenced_range(self, start, end).into_iter())
+    }
+
+    fn snapshot(&self) -> Box<DynMemTable<K, V>> {
+        Box::new(self.copy())
+    }
+
+    fn next_memtable(&self) -> Box<DynMemTable<K, V>> {
+        let (comparator, counters) = (Arc::clone(&self.comparator), Arc::clone(&self.counters));
+        Box::new(Self::empty(comparator, self.encoding, counters, self.shards.len(), self.last_sequence()))
+    }
+
+    /// The range tombstones first, then each shard on its own
+    fn flush_parts(&self) -> Vec<FlushPart<'_, K, V>> {
+        let range_tombstones = self.range_tombstones();
+        let mut parts = Vec::new();
+        if !range_tombstones.is_empty() {
+            parts.push(FlushPart { entries: Box::new(std::iter::empty()), len: 0, range_tombstones });
+        }
+        for index in 0..self.shards.len() {
+            let shard = self.lock(index).clone();
+            let entries: Vec<_> =
+                shard.sequenced_entries().map(|(key, version)| (key.clone(), version.clone())).collect();
+            let len = entries.len();
+            parts.push(FlushPart { entries: Box::new(entries.into_iter()), len, range_tombstones: Arc::default() });
+        }
+        parts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::MergeOperator;
+    use crate::{Config, LSMTree};
+    use tempfile::tempdir;
+
+    fn sharded<K, V>(shards: usize) -> ShardedMemTable<K, V>
+    where
+        K: Ord + serde::Serialize + Clone + Send + Sync + 'static,
+        V: serde::Serialize + Send + Sync + 'static,
+    {
+        let comparator = Arc::new(crate::comparator::NaturalOrder);
+        ShardedMemTable::empty(comparator, Encoding::default(), Arc::default(), shards, 0)
+    }
+
+    #[test]
+    fn test_sharded_memtable_matches_memtable() -> Result<()> {
+        let key = |name: &str| name.to_string();
+        let mut expected: MemTable<String, String> = MemTable::new();
+        let mut memtable = sharded(4);
+        for memtable in [&mut expected as &mut DynMemTable<_, _>, &mut memtable] {
+            memtable.put(key("a"), "short".to_string())?;
+            memtable.put(key("a"), "a much longer value".to_string())?;
+            memtable.put_with_expiry(key("b"), "expiring".to_string(), 1_000)?;
+            memtable.delete(key("c"))?;
+            memtable.append_operands(key("d"), vec!["1".to_string()])?;
+            memtable.append_operands(key("d"), vec!["2".to_string(), "3".to_string()])?;
+            memtable.put(key("e"), "e".to_string())?;
+            memtable.delete_range(key("b"), key("d"))?;
+            memtable.put_entry(key("c"), Entry::Merge(vec![Arc::new("fresh".to_string())]))?;
+            let batch = (0..20).map(|i| (format!("k{:02}", i), Entry::Value(i.to_string())));
+            memtable.apply_batch(batch.chain([(key("e"), Entry::Tombstone)]).collect())?;
+        }
+
+        assert_eq!(memtable.size(), expected.size());
+        assert_eq!(memtable.len(), expected.len());
+        assert_eq!(memtable.last_sequence(), 30);
+        assert_eq!(MemTableStore::get_entry(&memtable, &key("b")), Some(Entry::Tombstone));
+        for (key, version) in expected.sequenced_entries() {
+            assert_eq!(memtable.get_sequenced(key).as_ref(), Some(version));
+        }
+        let entries = memtable.sequenced_range(Bound::Unbounded, Bound::Unbounded);
+        let expected_entries: Vec<_> = expected.sequenced_entries().map(|(key, v)| (key.clone(), v.clone())).collect();
+        assert_eq!(entries, expected_entries);
+        let range = memtable.sequenced_range(Bound::Included(&key("c")), Bound::Excluded(&key("k05")));
+        assert_eq!(range.into_iter().map(|(key, _)| key).collect::<Vec<_>>(), [
+            "c", "d", "e", "k00", "k01", "k02", "k03", "k04"
+        ]);
+        assert_eq!(*memtable.range_tombstones(), expected.range_tombstones());
+
+        // The range tombstones come first, then every shard, which all got some of the keys
+        let parts = memtable.flush_parts();
+        assert_eq!(parts.len(), 5);
+        assert_eq!(parts[0].range_tombstones.len(), 1);
+        assert!(parts[1..].iter().all(|part| part.len > 0 && part.range_tombstones.is_empty()));
+        let mut flushed: Vec<_> = parts.into_iter().flat_map(|part| part.entries).collect();
+        flushed.sort_by(|(a, _), (b, _)| a.cmp(b));
+        assert_eq!(flushed, expected_entries);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sharded_memtable_concurrent_writes() -> Result<()> {
+        let memtable = sharded(4);
+        std::thread::scope(|scope| {
+            for thread in 0..4u32 {
+                let memtable = &memtable;
+                scope.spawn(move || {
+                    for i in 0..250u32 {
+                        memtable.put(i * 4 + thread, thread).unwrap();
+                        memtable.append_operands(u32::MAX, vec![thread]).unwrap();
+                    }
+                });
+            }
+        });
+
+        // Every write took a sequence number of its own
+        assert_eq!(memtable.last_sequence(), 2000);
+        assert_eq!(memtable.len(), 1001);
+        let entries = memtable.sequenced_range(Bound::Unbounded, Bound::Unbounded);
+        let mut sequences: Vec<_> = entries.into_iter().map(|(_, version)| version.sequence).collect();
+        sequences.sort_unstable();
+        sequences.dedup();
+        assert_eq!(sequences.len(), 1001);
+        match memtable.get_sequenced(&u32::MAX).map(|version| version.entry) {
+            Some(Entry::Merge(operands)) => assert_eq!(operands.len(), 1000),
+            other => panic!("expected merge operands, got {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    /// Appends the operands to the value, separated by commas
+    struct Append;
+
+    impl MergeOperator<String> for Append {
+        fn merge(&self, existing: Option<&String>, operands: &[&String]) -> String {
+            let mut parts: Vec<&str> = existing.map(String::as_str).into_iter().collect();
+            parts.extend(operands.iter().map(|operand| operand.as_str()));
+            parts.join(",")
+        }
+    }
+
+    #[test]
+    fn test_tree_with_sharded_memtable() -> Result<()> {
+        let dir = tempdir()?;
+        let config = Config {
+            data_dir: dir.path().to_path_buf(),
+            memtable_shards: 4,
+            compaction_threshold: None,
+            ..Config::default()
+        };
+        let key = |i: u32| format!("key{:02}", i);
+        let mut lsm = LSMTree::with_config(config.clone())?.with_merge_operator(Arc::new(Append));
+        for i in 0..20 {
+            lsm.insert(key(i), i.to_string())?;
+        }
+        lsm.flush()?;
+        assert_eq!(lsm.stats().num_sstables, 4);
+
+        lsm.merge(key(1), "x".to_string())?;
+        lsm.merge(key(30), "y".to_string())?;
+        lsm.delete_range(key(10), key(15))?;
+        let snapshot = lsm.snapshot();
+        lsm.insert(key(12), "again".to_string())?;
+        lsm.delete(key(2))?;
+
+        assert_eq!(snapshot.get(&key(12))?, None);
+        assert_eq!(snapshot.get(&key(2))?, Some("2".to_string()));
+        assert_eq!(snapshot.iter()?.count(), 16);
+
+        let check = |lsm: &LSMTree<String, String>| -> Result<()> {
+            assert_eq!(lsm.get(&key(1))?, Some("1,x".to_string()));
+            assert_eq!(lsm.get(&key(30))?, Some("y".to_string()));
+            assert_eq!(lsm.get(&key(2))?, None);
+            assert_eq!(lsm.get(&key(11))?, None);
+            assert_eq!(lsm.get(&key(12))?, Some("again".to_string()));
+            let keys: Vec<_> = lsm.keys()?.collect::<Result<_>>()?;
+            let live = (0..20).filter(|&i| i != 2 && !(10..15).contains(&i) || i == 12).chain([30]);
+            assert_eq!(keys, live.map(key).collect::<Vec<_>>());
+            Ok(())
+        };
+        check(&lsm)?;
+
+        // The write-ahead log is replayed into the shards
+        drop(lsm);
+        let mut lsm = LSMTree::with_config(config.clone())?.with_merge_operator(Arc::new(Append));
+        check(&lsm)?;
+        assert_eq!(lsm.stats().memtable_entries, 4);
+
+        // The range tombstone gets a table of its own, before those of the shards, so it hides
+        // the older tables but not the key written after it
+        lsm.flush()?;
+        assert_eq!(lsm.stats().memtable_bytes, 0);
+        assert!(lsm.stats().num_sstables > 5);
+        check(&lsm)?;
+
+        Ok(())
+    }
+}
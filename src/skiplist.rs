@@ -0,0 +1,746 @@
+//! Memtable backed by a concurrent skip list, available with the `crossbeam-skiplist` feature.
+//!
+//! `MemTable` keeps its entries in a `BTreeMap`, so every write needs `&mut self` and writers
+//! take turns. `SkipListMemTable` takes writes through `&self` from any number of threads at
+//! once, and reads don't block them. The skip list only hands out its entries through guards,
+//! so `get` and `iter` return values as `Arc<V>` and keys cloned, instead of references. A tree
+//! keeps its writes in one when `Config::memtable` is `MemTableKind::SkipList`; snapshots of
+//! the tree copy it, where they share the map of a `MemTable`.
+//!
+//! Each key's entry sits behind a lock of its own, which writes to that key take in turn, so
+//! only writes to the same key wait for one another. A write takes its sequence number under
+//! the lock, replaces the entry and swaps its size for the new one, so `size` is the size of
+//! the entries in the map, as for `MemTable`, and merge operands are appended onto the entry
+//! they replace without racing another write to the key.
+//!
+//! `delete_range` records a range tombstone, then removes the older entries in its range one
+//! key at a time; reads made meanwhile skip those it hasn't reached yet.
+//!
+//! `iter` yields entries in key order, like `MemTable::iter`, and `write_sstable` flushes them to
+//! an SSTable in that order.
+
+use crate::comparator::{Comparator, KeyRef, NaturalOrder, OrderedKey, Probe};
+use crate::encoding::Encoding;
+use crate::memtable::{DynMemTable, Entry, MemTable, MemTableStore, Version};
+use crate::merge;
+use crate::range_tombstone::{self, RangeTombstone};
+use crate::sequence::Sequenced;
+use crate::sstable::{SSTable, SSTableOptions, SSTableWriter};
+use crate::stats::Counters;
+use crate::Result;
+use crossbeam_skiplist::map::Entry as MapEntry;
+use crossbeam_skiplist::SkipMap;
+use std::ops::Bound;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard};
+
+/// The entry of a key, with the size it adds to the memtable
+struct Slot<V> {
+    /// `None` until the first write to the key lands, and once the slot was removed
+    version: Option<Version<V>>,
+    /// Serialized size of the key and entry
+    size: usize,
+    /// Set when the slot is taken out of the map; a write that finds it set starts over
+    removed: bool,
+}
+
+impl<V> Slot<V> {
+    fn new(version: Option<Version<V>>, size: usize) -> RwLock<Self> {
+        RwLock::new(Self { version, size, removed: false })
+    }
+}
+
+pub struct SkipListMemTable<K, V> {
+    data: SkipMap<OrderedKey<K>, RwLock<Slot<V>>>,
+    /// Recorded by `delete_range`, oldest first
+    range_tombstones: RwLock<Arc<Vec<RangeTombstone<K>>>>,
+    comparator: Arc<dyn Comparator<K>>,
+    encoding: Encoding,
+    size_bytes: AtomicUsize,
+    /// Sequence number of the last write
+    last_sequence: AtomicU64,
+    /// Records the sizes of the keys and values written
+    counters: Arc<Counters>,
+}
+
+impl<K, V> Default for SkipListMemTable<K, V>
+where
+    K: Ord + serde::Serialize + Clone + Send + Sync + 'static,
+    V: serde::Serialize + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> SkipListMemTable<K, V>
+where
+    K: Ord + serde::Serialize + Clone + Send + Sync + 'static,
+    V: serde::Serialize + Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Self::with_comparator(Arc::new(NaturalOrder))
+    }
+
+    pub fn with_comparator(comparator: Arc<dyn Comparator<K>>) -> Self {
+        Self::with_comparator_and_encoding(comparator, Encoding::default())
+    }
+
+    /// Creates a memtable that measures entries by their size in `encoding`
+    pub fn with_comparator_and_encoding(comparator: Arc<dyn Comparator<K>>, encoding: Encoding) -> Self {
+        Self::empty(comparator, encoding, Arc::new(Counters::default()), 0)
+    }
+
+    /// An empty memtable recording into `counters`, whose writes continue after `last_sequence`
+    fn empty(
+        comparator: Arc<dyn Comparator<K>>,
+        encoding: Encoding,
+        counters: Arc<Counters>,
+        last_sequence: u64,
+    ) -> Self {
+        Self {
+            data: SkipMap::new(),
+            range_tombstones: RwLock::new(Arc::new(Vec::new())),
+            comparator,
+            encoding,
+            size_bytes: AtomicUsize::new(0),
+            last_sequence: AtomicU64::new(last_sequence),
+            counters,
+        }
+    }
+
+    /// Sequence number of the last write to take one
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence.load(Ordering::SeqCst)
+    }
+
+    fn next_sequence(&self) -> u64 {
+        self.last_sequence.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    pub fn comparator(&self) -> &Arc<dyn Comparator<K>> {
+        &self.comparator
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Inserts a value, returning the size of the new entry
+    pub fn put(&self, key: K, value: V) -> Result<usize> {
+        let key_size = self.serialized_size(&key)?;
+        let value_size = self.serialized_size(&value)?;
+        Ok(self.store(key, Entry::Value(Arc::new(value)), key_size, value_size))
+    }
+
+    /// Inserts a value that expires at `expires_at` (milliseconds since the UNIX epoch)
+    pub fn put_with_expiry(&self, key: K, value: V, expires_at: u64) -> Result<usize> {
+        let key_size = self.serialized_size(&key)?;
+        let value_size = self.serialized_size(&value)?.saturating_add(self.serialized_size(&expires_at)?);
+        let entry = Entry::Expiring { value: Arc::new(value), expires_at };
+        Ok(self.store(key, entry, key_size, value_size))
+    }
+
+    /// Stores each entry in order and returns their total size, like `MemTable::apply_batch`.
+    /// Nothing is stored if any entry fails to serialize, but concurrent writes may land
+    /// between the entries.
+    pub fn apply_batch(&self, entries: Vec<(K, Entry<V>)>) -> Result<usize> {
+        let sizes = entries
+            .iter()
+            .map(|(key, entry)| Ok((self.serialized_size(key)?, self.payload_size(entry)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let total = entries
+            .into_iter()
+            .zip(sizes)
+            .map(|((key, entry), (key_size, entry_size))| self.store(key, entry.map(Arc::new), key_size, entry_size))
+            .fold(0usize, usize::saturating_add);
+        Ok(total)
+    }
+
+    /// Stores `entry` for `key`, replacing any entry the key has, and returns its size
+    pub(crate) fn put_entry(&self, key: K, entry: Entry<Arc<V>>) -> Result<usize> {
+        let key_size = self.serialized_size(&key)?;
+        let entry_size = self.payload_size(&entry)?;
+        Ok(self.store(key, entry, key_size, entry_size))
+    }
+
+    /// Appends merge operands to those stored for `key`, and returns the size of its new entry.
+    /// Fails with `NoMergeOperator` if the key has an entry that isn't a merge, like
+    /// `MemTable::append_operands`.
+    pub(crate) fn append_operands(&self, key: K, operands: Vec<V>) -> Result<usize> {
+        let key_size = self.serialized_size(&key)?;
+        let operands: Vec<_> = operands.into_iter().map(Arc::new).collect();
+        self.write(key, key_size, |key, current| {
+            let entry = Entry::Merge(operands.clone());
+            let entry = match self.resolve(current, key) {
+                Some(older) => merge::combine::<V, _>(None, entry, older.entry)?,
+                None => entry,
+            };
+            let entry_size = self.payload_size(&entry)?;
+            Ok((entry, entry_size))
+        })
+    }
+
+    /// Records a tombstone for `key`, shadowing any value stored for it here or in older SSTables
+    pub fn delete(&self, key: K) -> Result<usize> {
+        let key_size = self.serialized_size(&key)?;
+        Ok(self.store(key, Entry::Tombstone, key_size, 0))
+    }
+
+    /// Records a range tombstone deleting the keys in `[start, end)`, here and in older
+    /// SSTables, and removes the older entries in that range, like `MemTable::delete_range`.
+    pub fn delete_range(&self, start: K, end: K) -> Result<usize> {
+        if self.comparator.compare(&start, &end).is_ge() {
+            return Ok(0);
+        }
+        let size = self.serialized_size(&start)?.saturating_add(self.serialized_size(&end)?);
+        let bounds = (
+            Bound::Included(OrderedKey::new(start.clone(), Arc::clone(&self.comparator))),
+            Bound::Excluded(OrderedKey::new(end.clone(), Arc::clone(&self.comparator))),
+        );
+
+        // Taken under the lock, so a write with a later sequence number sees the tombstone
+        let sequence = {
+            let mut tombstones = self.range_tombstones.write().unwrap_or_else(PoisonError::into_inner);
+            let sequence = self.next_sequence();
+            Arc::make_mut(&mut tombstones).push(RangeTombstone { start, end, sequence });
+            sequence
+        };
+        self.add_size(size);
+        for entry in self.data.range::<OrderedKey<K>, _>(bounds) {
+            let mut slot = entry.value().write().unwrap_or_else(PoisonError::into_inner);
+            // Writes that take the lock after this one are newer than the tombstone
+            if slot.version.as_ref().is_some_and(|version| version.sequence < sequence) {
+                self.remove(&entry, &mut slot);
+            }
+        }
+        Ok(size)
+    }
+
+    /// Stores an entry whose key and payload sizes were already computed, and returns its size
+    fn store(&self, key: K, entry: Entry<Arc<V>>, key_size: usize, entry_size: usize) -> usize {
+        let written = self.write(key, key_size, |_, _| Ok((entry.clone(), entry_size)));
+        // `make` never fails
+        written.unwrap_or(0)
+    }
+
+    /// Stores the entry that `make` returns, with its payload size, given the key's current
+    /// entry, and returns the size of the key and entry. The sequence number is taken under
+    /// the key's lock before `make` runs, so the range tombstones it sees include every older
+    /// one. A slot without an entry is removed again if `make` fails.
+    fn write(
+        &self,
+        key: K,
+        key_size: usize,
+        make: impl Fn(&K, Option<&Version<V>>) -> Result<(Entry<Arc<V>>, usize)>,
+    ) -> Result<usize> {
+        loop {
+            let ordered = OrderedKey::new(key.clone(), Arc::clone(&self.comparator));
+            let entry = self.data.get_or_insert_with(ordered, || Slot::new(None, 0));
+            let mut slot = entry.value().write().unwrap_or_else(PoisonError::into_inner);
+            // Removed by a range delete, which is about to take it out of the map
+            if slot.removed {
+                continue;
+            }
+
+            let sequence = self.next_sequence();
+            let (new_entry, entry_size) = match make(&key, slot.version.as_ref()) {
+                Ok(made) => made,
+                Err(err) => {
+                    if slot.version.is_none() {
+                        self.remove(&entry, &mut slot);
+                    }
+                    return Err(err);
+                }
+            };
+            self.counters.key_sizes.record(key_size);
+            if new_entry.value().is_some() {
+                self.counters.value_sizes.record(entry_size);
+            }
+            let size = key_size.saturating_add(entry_size);
+            self.subtract_size(std::mem::replace(&mut slot.size, size));
+            self.add_size(size);
+            slot.version = Some(Sequenced::new(new_entry, sequence));
+            return Ok(size);
+        }
+    }
+
+    /// Takes the slot of `entry`, locked by the caller, out of the map along with its size
+    fn remove(&self, entry: &MapEntry<'_, OrderedKey<K>, RwLock<Slot<V>>>, slot: &mut Slot<V>) {
+        slot.removed = true;
+        slot.version = None;
+        self.subtract_size(std::mem::take(&mut slot.size));
+        entry.remove();
+    }
+
+    fn add_size(&self, size: usize) {
+        let _ = self.size_bytes.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |total| {
+            Some(total.saturating_add(size))
+        });
+    }
+
+    fn subtract_size(&self, size: usize) {
+        let _ = self.size_bytes.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |total| {
+            Some(total.saturating_sub(size))
+        });
+    }
+
+    /// Size of `value` in the memtable's encoding, clamped to `usize::MAX`
+    fn serialized_size<T: serde::Serialize + ?Sized>(&self, value: &T) -> Result<usize> {
+        let size = self.encoding.serialized_size(value)?;
+        Ok(usize::try_from(size).unwrap_or(usize::MAX))
+    }
+
+    /// Size of an entry's value and expiry time, if any, in the memtable's encoding
+    fn payload_size<T: serde::Serialize>(&self, entry: &Entry<T>) -> Result<usize> {
+        Ok(match entry {
+            Entry::Value(value) => self.serialized_size(value)?,
+            Entry::Tombstone => 0,
+            Entry::Expiring { value, expires_at } => {
+                self.serialized_size(value)?.saturating_add(self.serialized_size(expires_at)?)
+            }
+            Entry::Merge(operands) => self.serialized_size(operands)?,
+        })
+    }
+
+    /// The newer of `current`, the entry stored for `key`, and the range tombstone covering
+    /// `key`, if any
+    fn resolve(&self, current: Option<&Version<V>>, key: &K) -> Option<Version<V>> {
+        let tombstones = self.range_tombstones.read().unwrap_or_else(PoisonError::into_inner);
+        let covering = range_tombstone::covering_sequence(&tombstones, key, &*self.comparator);
+        match (current, covering) {
+            (Some(version), Some(sequence)) if sequence > version.sequence => {
+                Some(Sequenced::new(Entry::Tombstone, sequence))
+            }
+            (Some(version), _) => Some(version.clone()),
+            (None, sequence) => sequence.map(|sequence| Sequenced::new(Entry::Tombstone, sequence)),
+        }
+    }
+
+    /// Returns the value for `key`; deleted keys are reported as `None`. Expiry is not checked.
+    pub fn get(&self, key: &K) -> Option<Arc<V>> {
+        self.get_entry(key).and_then(Entry::into_value)
+    }
+
+    /// Returns the raw entry for `key`, including tombstones. A key without an entry that a
+    /// range tombstone covers has a tombstone.
+    pub fn get_entry(&self, key: &K) -> Option<Entry<Arc<V>>> {
+        self.get_sequenced(key).map(|entry| entry.entry)
+    }
+
+    /// Like `get_entry`, with the sequence number of the write that made the entry
+    pub fn get_sequenced(&self, key: &K) -> Option<Version<V>> {
+        let current = self.data.get(&Probe::new(key, &*self.comparator) as &dyn KeyRef<K>);
+        let version = current.and_then(|entry| Self::read(&entry).version.clone());
+        self.resolve(version.as_ref(), key)
+    }
+
+    /// The range tombstones recorded by `delete_range`, oldest first
+    pub fn range_tombstones(&self) -> Arc<Vec<RangeTombstone<K>>> {
+        Arc::clone(&self.range_tombstones.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Serialized size of the entries and range tombstones, see the module docs
+    pub fn size(&self) -> usize {
+        self.size_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Number of entries, including tombstones but not range tombstones
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Whether the memtable has neither entries nor range tombstones
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty() && self.range_tombstones().is_empty()
+    }
+
+    /// Iterates key-value pairs in key order, skipping tombstones. Expiry is not checked.
+    /// Writes made during the iteration may or may not be seen.
+    pub fn iter(&self) -> impl Iterator<Item = (K, Arc<V>)> + '_ {
+        self.entries().filter_map(|(key, entry)| entry.into_value().map(|value| (key, value)))
+    }
+
+    /// Iterates all entries in key order, including tombstones
+    pub fn entries(&self) -> impl Iterator<Item = (K, Entry<Arc<V>>)> + '_ {
+        self.sequenced_entries().map(|(key, entry)| (key, entry.entry))
+    }
+
+    /// Like `entries`, with the sequence number of each entry
+    pub fn sequenced_entries(&self) -> impl Iterator<Item = (K, Version<V>)> + '_ {
+        self.sequenced_range(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    /// Iterates the entries within the given bounds in key order, with their sequence
+    /// numbers, including tombstones. An empty or inverted range yields nothing.
+    pub fn sequenced_range<'a>(
+        &'a self,
+        start: Bound<&K>,
+        end: Bound<&K>,
+    ) -> impl DoubleEndedIterator<Item = (K, Version<V>)> + 'a {
+        let bound = |key: &K| OrderedKey::new(key.clone(), Arc::clone(&self.comparator));
+        let bounds = (start.map(bound), end.map(bound));
+        let tombstones = self.range_tombstones();
+        self.data.range::<OrderedKey<K>, _>(bounds).filter_map(move |entry| {
+            let (key, version) = (&entry.key().key, Self::read(&entry).version.clone()?);
+            // Not removed yet by the range delete of a newer tombstone
+            let covering = range_tombstone::covering_sequence(&tombstones, key, &*self.comparator);
+            let hidden = covering.is_some_and(|sequence| sequence > version.sequence);
+            (!hidden).then(|| (key.clone(), version))
+        })
+    }
+
+    fn read<'a>(entry: &'a MapEntry<'_, OrderedKey<K>, RwLock<Slot<V>>>) -> RwLockReadGuard<'a, Slot<V>> {
+        entry.value().read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// A copy of the entries, range tombstones and size, recording into the same counters
+    fn copy(&self) -> Self {
+        let copy = Self::empty(Arc::clone(&self.comparator), self.encoding, Arc::clone(&self.counters), 0);
+        for entry in self.data.iter() {
+            let slot = Self::read(&entry);
+            if slot.version.is_some() {
+                copy.data.insert(entry.key().clone(), Slot::new(slot.version.clone(), slot.size));
+            }
+        }
+        *copy.range_tombstones.write().unwrap_or_else(PoisonError::into_inner) = self.range_tombstones();
+        copy.size_bytes.store(self.size(), Ordering::Relaxed);
+        copy.last_sequence.store(self.last_sequence(), Ordering::SeqCst);
+        copy
+    }
+}
+
+/// Moves the entries and range tombstones of a `MemTable`, e.g. one replayed from the
+/// write-ahead log, into a skip list that continues its sequence numbers
+impl<K, V> From<MemTable<K, V>> for SkipListMemTable<K, V>
+where
+    K: Ord + serde::Serialize + Clone + Send + Sync + 'static,
+    V: serde::Serialize + Send + Sync + 'static,
+{
+    fn from(memtable: MemTable<K, V>) -> Self {
+        let comparator = Arc::clone(memtable.comparator());
+        let counters = Arc::clone(memtable.counters());
+        let skiplist = Self::empty(comparator, memtable.encoding(), counters, memtable.last_sequence());
+        for (key, version) in memtable.sequenced_entries() {
+            // Both were sized when the entry was written
+            let key_size = skiplist.serialized_size(key).unwrap_or(0);
+            let size = key_size.saturating_add(skiplist.payload_size(&version.entry).unwrap_or(0));
+            let key = OrderedKey::new(key.clone(), Arc::clone(&skiplist.comparator));
+            skiplist.data.insert(key, Slot::new(Some(version.clone()), size));
+        }
+        let tombstones = Arc::new(memtable.range_tombstones().to_vec());
+        *skiplist.range_tombstones.write().unwrap_or_else(PoisonError::into_inner) = tombstones;
+        skiplist.size_bytes.store(memtable.size(), Ordering::Relaxed);
+        skiplist
+    }
+}
+
+impl<K, V> MemTableStore<K, V> for SkipListMemTable<K, V>
+where
+    K: Ord + serde::Serialize + Clone + Send + Sync + 'static,
+    V: serde::Serialize + Send + Sync + 'static,
+{
+    fn last_sequence(&self) -> u64 {
+        SkipListMemTable::last_sequence(self)
+    }
+
+    fn next_sequence(&mut self) -> u64 {
+        SkipListMemTable::next_sequence(self)
+    }
+
+    fn comparator(&self) -> &Arc<dyn Comparator<K>> {
+        &self.comparator
+    }
+
+    fn put(&mut self, key: K, value: V) -> Result<usize> {
+        SkipListMemTable::put(self, key, value)
+    }
+
+    fn put_with_expiry(&mut self, key: K, value: V, expires_at: u64) -> Result<usize> {
+        SkipListMemTable::put_with_expiry(self, key, value, expires_at)
+    }
+
+    fn apply_batch(&mut self, entries: Vec<(K, Entry<V>)>) -> Result<usize> {
+        SkipListMemTable::apply_batch(self, entries)
+    }
+
+    fn put_entry(&mut self, key: K, entry: Entry<Arc<V>>) -> Result<usize> {
+        SkipListMemTable::put_entry(self, key, entry)
+    }
+
+    fn append_operands(&mut self, key: K, operands: Vec<V>) -> Result<usize> {
+        SkipListMemTable::append_operands(self, key, operands)
+    }
+
+    fn delete(&mut self, key: K) -> Result<usize> {
+        SkipListMemTable::delete(self, key)
+    }
+
+    fn delete_range(&mut self, start: K, end: K) -> Result<usize> {
+        SkipListMemTable::delete_range(self, start, end)
+    }
+
+    fn get_sequenced(&self, key: &K) -> Option<Version<V>> {
+        SkipListMemTable::get_sequenced(self, key)
+    }
+
+    fn range_tombstones(&self) -> Arc<Vec<RangeTombstone<K>>> {
+        SkipListMemTable::range_tombstones(self)
+    }
+
+    fn size(&self) -> usize {
+        SkipListMemTable::size(self)
+    }
+
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        SkipListMemTable::is_empty(self)
+    }
+
+    fn sequenced_range<'a>(
+        &'a self,
+        start: Bound<&K>,
+        end: Bound<&K>,
+    ) -> Box<dyn DoubleEndedIterator<Item = (K, Version<V>)> + 'a> {
+        Box::new(SkipListMemTable::sequenced_range(self, start, end))
+    }
+
+    fn snapshot(&self) -> Box<DynMemTable<K, V>> {
+        Box::new(self.copy())
+    }
+
+    fn next_memtable(&self) -> Box<DynMemTable<K, V>> {
+        let counters = Arc::clone(&self.counters);
+        let last_sequence = SkipListMemTable::last_sequence(self);
+        Box::new(Self::empty(Arc::clone(&self.comparator), self.encoding, counters, last_sequence))
+    }
+}
+
+impl<K, V> SkipListMemTable<K, V>
+where
+    K: Ord + serde::Serialize + for<'de> serde::Deserialize<'de> + Clone + Send + Sync + 'static,
+    V: serde::Serialize + for<'de> serde::Deserialize<'de> + Send + Sync + 'static,
+{
+    /// Writes the entries to a new SSTable in key order, with their sequence numbers and the
+    /// range tombstones, like a flush. Writes made meanwhile may or may not be included.
+    pub fn write_sstable(&self, path: impl Into<PathBuf>, options: &SSTableOptions) -> Result<SSTable<K, V>> {
+        let mut writer = SSTableWriter::create_with_comparator(path, options, Arc::clone(&self.comparator))?;
+        for (key, entry) in self.sequenced_entries() {
+            writer.add_entry_with_sequence(&key, &entry.entry, entry.sequence)?;
+        }
+        for tombstone in self.range_tombstones().iter() {
+            writer.add_range_tombstone(tombstone.clone());
+        }
+        writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::MergeOperator;
+    use crate::memtable::MemTableKind;
+    use crate::{Config, LSMTree};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_skiplist_memtable_basic_operations() -> Result<()> {
+        let memtable = SkipListMemTable::new();
+        assert!(memtable.is_empty());
+
+        memtable.put("b".to_string(), 2)?;
+        memtable.put("a".to_string(), 1)?;
+        memtable.put_with_expiry("c".to_string(), 3, 1_000)?;
+        memtable.delete("b".to_string())?;
+
+        assert_eq!(memtable.get(&"a".to_string()), Some(Arc::new(1)));
+        assert_eq!(memtable.get(&"b".to_string()), None);
+        assert_eq!(memtable.get_entry(&"b".to_string()), Some(Entry::Tombstone));
+        assert_eq!(
+            memtable.get_entry(&"c".to_string()),
+            Some(Entry::Expiring { value: Arc::new(3), expires_at: 1_000 })
+        );
+        assert_eq!(memtable.get(&"d".to_string()), None);
+        assert_eq!(memtable.len(), 3);
+        assert_eq!(memtable.last_sequence(), 4);
+        assert_eq!(memtable.get_sequenced(&"b".to_string()).map(|entry| entry.sequence), Some(4));
+
+        let items: Vec<_> = memtable.iter().map(|(key, value)| (key, *value)).collect();
+        assert_eq!(items, vec![("a".to_string(), 1), ("c".to_string(), 3)]);
+        assert!(memtable.size() > 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_skiplist_memtable_matches_memtable() -> Result<()> {
+        let key = |name: &str| name.to_string();
+        let memtables: [Box<DynMemTable<String, String>>; 2] =
+            [Box::new(MemTable::new()), Box::new(SkipListMemTable::new())];
+        for mut memtable in memtables {
+            memtable.put(key("a"), "short".to_string())?;
+            memtable.put(key("a"), "a much longer value".to_string())?;
+            memtable.put_with_expiry(key("b"), "expiring".to_string(), 1_000)?;
+            memtable.delete(key("c"))?;
+            memtable.append_operands(key("d"), vec!["1".to_string()])?;
+            memtable.append_operands(key("d"), vec!["2".to_string(), "3".to_string()])?;
+            memtable.put(key("e"), "e".to_string())?;
+            memtable.delete_range(key("b"), key("d"))?;
+            memtable.put_entry(key("c"), Entry::Merge(vec![Arc::new("fresh".to_string())]))?;
+            memtable.apply_batch(vec![(key("f"), Entry::Value("f".to_string())), (key("e"), Entry::Tombstone)])?;
+
+            let mut expected = MemTable::new();
+            expected.put(key("a"), "a much longer value".to_string())?;
+            expected.delete_range(key("b"), key("d"))?;
+            expected.put_entry(key("c"), Entry::Merge(vec![Arc::new("fresh".to_string())]))?;
+            let operands = ["1", "2", "3"].map(|operand| Arc::new(operand.to_string()));
+            expected.put_entry(key("d"), Entry::Merge(operands.to_vec()))?;
+            expected.delete(key("e"))?;
+            expected.put(key("f"), "f".to_string())?;
+
+            // Overwritten and range-deleted entries no longer count towards the size
+            assert_eq!(memtable.size(), expected.size());
+            assert_eq!(memtable.len(), 5);
+            assert_eq!(memtable.last_sequence(), 11);
+            assert_eq!(memtable.get_entry(&key("b")), Some(Entry::Tombstone));
+            let entries: Vec<_> = memtable
+                .sequenced_range(Bound::Unbounded, Bound::Unbounded)
+                .map(|(key, entry)| (key, entry.entry))
+                .collect();
+            let expected: Vec<_> = expected.entries().map(|(key, entry)| (key.clone(), entry.clone())).collect();
+            assert_eq!(entries, expected);
+            let range = memtable.sequenced_range(Bound::Included(&key("c")), Bound::Excluded(&key("e")));
+            assert_eq!(range.rev().map(|(key, _)| key).collect::<Vec<_>>(), vec![key("d"), key("c")]);
+            assert_eq!(memtable.sequenced_range(Bound::Included(&key("e")), Bound::Excluded(&key("a"))).count(), 0);
+            assert_eq!(memtable.range_tombstones().len(), 1);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_skiplist_memtable_concurrent_writes() -> Result<()> {
+        let memtable = SkipListMemTable::new();
+        std::thread::scope(|scope| {
+            for thread in 0..4u32 {
+                let memtable = &memtable;
+                scope.spawn(move || {
+                    for i in 0..250u32 {
+                        memtable.put(i * 4 + thread, thread).unwrap();
+                        // Every thread also writes to the same keys
+                        memtable.put(u32::MAX - i % 8, thread).unwrap();
+                        memtable.append_operands(u32::MAX - 8, vec![thread]).unwrap();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(memtable.len(), 1009);
+        assert_eq!(memtable.last_sequence(), 3000);
+        let keys: Vec<_> = memtable.entries().map(|(key, _)| key).collect();
+        assert_eq!(keys, (0..1000).chain(u32::MAX - 8..=u32::MAX).collect::<Vec<_>>());
+        for i in 0..1000 {
+            assert_eq!(memtable.get(&i), Some(Arc::new(i % 4)));
+        }
+        // No operand was lost
+        match memtable.get_entry(&(u32::MAX - 8)) {
+            Some(Entry::Merge(operands)) => assert_eq!(operands.len(), 1000),
+            entry => panic!("expected operands, found {:?}", entry),
+        }
+
+        // Only the entries left in the map count towards the size
+        let mut expected = MemTable::new();
+        for (key, entry) in memtable.entries() {
+            expected.put_entry(key, entry)?;
+        }
+        assert_eq!(memtable.size(), expected.size());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_skiplist_memtable_write_sstable() -> Result<()> {
+        let dir = tempdir()?;
+        let memtable = SkipListMemTable::new();
+        for i in (0..100).rev() {
+            memtable.put(i, i * 10)?;
+        }
+        memtable.delete(50)?;
+
+        let sstable = memtable.write_sstable(dir.path().join("skiplist.sst"), &SSTableOptions::default())?;
+        assert_eq!(sstable.get(&7)?, Some(70));
+        assert_eq!(sstable.get_entry(&50)?, Some(Entry::Tombstone));
+        assert_eq!(sstable.max_sequence(), 101);
+        let keys: Vec<_> = sstable.entries()?.map(|entry| entry.map(|(key, _)| key)).collect::<Result<_>>()?;
+        assert_eq!(keys, (0..100).collect::<Vec<_>>());
+
+        Ok(())
+    }
+
+    /// Appends the operands to the value, separated by commas
+    struct Append;
+
+    impl MergeOperator<String> for Append {
+        fn merge(&self, existing: Option<&String>, operands: &[&String]) -> String {
+            let mut parts: Vec<&str> = existing.map(String::as_str).into_iter().collect();
+            parts.extend(operands.iter().map(|operand| operand.as_str()));
+            parts.join(",")
+        }
+    }
+
+    #[test]
+    fn test_tree_with_skiplist_memtable() -> Result<()> {
+        let dir = tempdir()?;
+        let config = Config {
+            data_dir: dir.path().to_path_buf(),
+            memtable: MemTableKind::SkipList,
+            compaction_threshold: None,
+            ..Config::default()
+        };
+        let key = |i: u32| format!("key{:02}", i);
+        let mut lsm = LSMTree::with_config(config.clone())?.with_merge_operator(Arc::new(Append));
+        for i in 0..20 {
+            lsm.insert(key(i), i.to_string())?;
+        }
+        lsm.merge(key(1), "x".to_string())?;
+        lsm.merge(key(30), "y".to_string())?;
+        lsm.delete_range(key(10), key(15))?;
+        let snapshot = lsm.snapshot();
+        lsm.insert(key(12), "again".to_string())?;
+        lsm.delete(key(2))?;
+
+        // The snapshot copied the skip list, so later writes don't show through
+        assert_eq!(snapshot.get(&key(12))?, None);
+        assert_eq!(snapshot.get(&key(2))?, Some("2".to_string()));
+        assert_eq!(snapshot.iter()?.count(), 16);
+
+        let check = |lsm: &LSMTree<String, String>| -> Result<()> {
+            assert_eq!(lsm.get(&key(1))?, Some("1,x".to_string()));
+            assert_eq!(lsm.get(&key(30))?, Some("y".to_string()));
+            assert_eq!(lsm.get(&key(2))?, None);
+            assert_eq!(lsm.get(&key(11))?, None);
+            assert_eq!(lsm.get(&key(12))?, Some("again".to_string()));
+            let keys: Vec<_> = lsm.keys()?.collect::<Result<_>>()?;
+            let live = (0..20).filter(|&i| i != 2 && !(10..15).contains(&i) || i == 12).chain([30]);
+            assert_eq!(keys, live.map(key).collect::<Vec<_>>());
+            Ok(())
+        };
+        check(&lsm)?;
+
+        // The write-ahead log is replayed into a skip list
+        drop(lsm);
+        let mut lsm = LSMTree::with_config(config.clone())?.with_merge_operator(Arc::new(Append));
+        check(&lsm)?;
+        assert_eq!(lsm.stats().memtable_entries, 17);
+
+        lsm.flush()?;
+        assert_eq!(lsm.stats().memtable_bytes, 0);
+        check(&lsm)?;
+
+        Ok(())
+    }
+}
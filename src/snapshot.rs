@@ -0,0 +1,141 @@
+//! Point-in-time views of the tree.
+//!
+//! A snapshot shares the memtable's map (copied on the next write to the tree), or copies the
+//! memtable if it is a skip list (see `Config::memtable`), and holds references to the
+//! SSTables that were live when it was taken. Compaction may replace those tables in the tree,
+//! but their files are only deleted once the last snapshot using them is dropped.
+
+use crate::memtable::DynMemTable;
+use crate::merge::MergeOperator;
+use crate::scan::range_over;
+use crate::sstable::SSTable;
+use crate::{lookup_entry, LSMTree, Result};
+use std::ops::Bound;
+use std::sync::Arc;
+
+pub struct Snapshot<K, V> {
+    memtable: Box<DynMemTable<K, V>>,
+    /// The memtable being flushed when the snapshot was taken, if any
+    immutable: Option<Arc<DynMemTable<K, V>>>,
+    sstables: Vec<Arc<SSTable<K, V>>>,
+    /// Expiry of TTL entries is judged as of the moment the snapshot was taken
+    now_millis: u64,
+    merge_operator: Option<Arc<dyn MergeOperator<V>>>,
+}
+
+impl<K, V> LSMTree<K, V>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Captures the current contents of the tree. Later writes, flushes and compactions
+    /// don't affect what the snapshot returns.
+    pub fn snapshot(&self) -> Snapshot<K, V> {
+        Snapshot {
+            memtable: self.memtable.snapshot(),
+            immutable: self.immutable.clone(),
+            sstables: self.sstables.clone(),
+            now_millis: self.config.clock.now_millis(),
+            merge_operator: self.merge_operator.clone(),
+        }
+    }
+}
+
+impl<K, V> Snapshot<K, V>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned + Clone,
+{
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        let entry = lookup_entry(&self.memtables(), &self.sstables, key, self.merge_operator.as_deref())?;
+        Ok(entry.and_then(|entry| entry.into_live_value(self.now_millis)).map(Arc::unwrap_or_clone))
+    }
+
+    fn memtables(&self) -> Vec<&DynMemTable<K, V>> {
+        std::iter::once(&*self.memtable).chain(self.immutable.as_deref()).collect()
+    }
+
+    /// Returns the live key-value pairs with keys within the given bounds, in key order
+    pub fn range(&self, start: Bound<K>, end: Bound<K>) -> Result<impl Iterator<Item = Result<(K, V)>> + '_> {
+        let operator = self.merge_operator.clone();
+        range_over(&self.memtables(), &self.sstables, start, end, self.now_millis, operator, false)
+    }
+
+    /// Like `range`, in descending key order
+    pub fn range_rev(&self, start: Bound<K>, end: Bound<K>) -> Result<impl Iterator<Item = Result<(K, V)>> + '_> {
+        let operator = self.merge_operator.clone();
+        range_over(&self.memtables(), &self.sstables, start, end, self.now_millis, operator, true)
+    }
+
+    pub fn iter(&self) -> Result<impl Iterator<Item = Result<(K, V)>> + '_> {
+        self.range(Bound::Unbounded, Bound::Unbounded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Config, LSMTree, Result};
+    use tempfile::TempDir;
+
+    fn setup() -> (LSMTree<i32, String>, TempDir) {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            ..Config::default()
+        };
+        (LSMTree::with_config(config).unwrap(), temp_dir)
+    }
+
+    #[test]
+    fn test_snapshot_ignores_later_writes() -> Result<()> {
+        let (mut lsm, _temp_dir) = setup();
+
+        lsm.insert(1, "one".to_string())?;
+        lsm.flush()?;
+        lsm.insert(2, "two".to_string())?;
+        let snapshot = lsm.snapshot();
+
+        lsm.insert(1, "uno".to_string())?;
+        lsm.delete(2)?;
+        lsm.insert(3, "three".to_string())?;
+        lsm.flush()?;
+
+        assert_eq!(snapshot.get(&1)?, Some("one".to_string()));
+        assert_eq!(snapshot.get(&2)?, Some("two".to_string()));
+        assert_eq!(snapshot.get(&3)?, None);
+        assert_eq!(
+            snapshot.iter()?.collect::<Result<Vec<_>>>()?,
+            vec![(1, "one".to_string()), (2, "two".to_string())]
+        );
+        assert_eq!(lsm.get(&1)?, Some("uno".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_defers_file_deletion() -> Result<()> {
+        let (mut lsm, temp_dir) = setup();
+
+        for round in 0..3 {
+            for i in 0..20 {
+                lsm.insert(i, format!("{}_{}", i, round))?;
+            }
+            lsm.flush()?;
+        }
+        let snapshot = lsm.snapshot();
+        let files: Vec<_> = lsm.sstables.iter().map(|sstable| sstable.path().to_path_buf()).collect();
+
+        lsm.compact()?;
+        assert_eq!(lsm.sstables.len(), 1);
+        assert!(files.iter().all(|path| path.exists()));
+        assert_eq!(snapshot.get(&5)?, Some("5_2".to_string()));
+        assert_eq!(snapshot.iter()?.count(), 20);
+
+        drop(snapshot);
+        assert!(files.iter().all(|path| !path.exists()));
+        assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 3); // MANIFEST, wal.log and one table
+
+        Ok(())
+    }
+}
@@ -1,214 +1,3196 @@
 //! SSTable (Sorted String Table) implementation - the persistent storage component.
 //! Provides immutable on-disk storage of sorted key-value pairs with a sparse index
 //! for efficient lookups. Created when MemTable is flushed to disk.
+//!
+//! File layout: a header with the index interval, the compression codec and the encoding of
+//! keys and values (see `encoding`), the data blocks in key order, a trailer and finally a
+//! fixed-size footer. A flag in the encoding id is set if record keys are stored in the ordered
+//! encoding of the comparator instead (see `ordered::OrderedBytes`). The trailer holds an
+//! optional bloom filter over all keys, the range tombstones of the table (see
+//! `range_tombstone`) with their keys serialized, the largest sequence number in the table
+//! (see `sequence`), the id of the hash function of the bloom filter (see `bloom`), the sparse
+//! index and an XXH3 checksum of everything before it. The footer holds the entry count, the
+//! offset where the data blocks end and the trailer starts, the offset of the sparse index,
+//! the format version and a magic number. It lets `open` reject files that aren't SSTables,
+//! or were written in a format this build doesn't understand, before reading anything else.
+//! The checksum lets `SSTable::verify_file` confirm a file is intact without parsing its
+//! records.
+//!
+//! Records are grouped into blocks of at most `index_interval` records (cut short once a block
+//! reaches `block_size` bytes), and each block is compressed as a unit. The sparse index holds
+//! the first and last keys and the record count of every block, so a lookup decompresses at
+//! most one block, and none for a key that falls between two blocks. The stored index holds
+//! all of these, with the keys serialized, and the offset of every block, followed by a CRC32
+//! of them. An opened table reads it the first time it needs it, after the bloom filter has
+//! failed to rule out the key looked up.
+//!
+//! Each record is the length-prefixed serialized key and entry, then the sequence number of the
+//! entry, followed by a CRC32 of all three, so corrupted records are reported instead of being
+//! mistaken for missing keys. Lengths may be zero: empty keys and values (or keys that
+//! serialize to no bytes at all) are stored like any other.
+//!
+//! Records are variable-length and only read front to back, so reverse scans work a block at a
+//! time: they start at the block the sparse index points to for the end bound, decode it whole
+//! and yield its records backwards, then move to the previous index entry, stopping after the
+//! block whose first key is at or before the start bound.
 
-use crate::memtable::MemTable;
-use std::io::{Write, Seek};
-use crate::Result;
+use crate::bloom::{BloomFilter, BloomHasher};
+use crate::cache::{BlockCache, FilePool};
+use crate::comparator::{Comparator, KeyEncoding, NaturalOrder};
+use crate::compression::Compression;
+use crate::encoding::{Encoding, Format, IntEncoding};
+use crate::memtable::{Entry, MemTable};
+use crate::range_tombstone::{self, RangeTombstone};
+use crate::sequence::Sequenced;
+use crate::stats::Counters;
+use crate::verify::TableReport;
+use serde::de::IgnoredAny;
+use xxhash_rust::xxh3::Xxh3;
+use std::io::{Read, Write, Seek};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use crate::{sync_parent_dir, tmp_path, LSMError, Result};
 
+/// Size of the header: the index interval, the compression codec id and the encoding id
+const HEADER_LEN: u64 = 10;
+/// Size of the footer: `[entry_count: u64][data_end: u64][index_offset: u64][version: u8][magic]`
+const FOOTER_LEN: u64 = 33;
+/// Size of the file checksum right before the footer
+const CHECKSUM_LEN: u64 = 8;
+const MAGIC: [u8; 8] = *b"LSMTABLE";
+const FORMAT_VERSION: u8 = 1;
+/// Set in the encoding id of the header when record keys are stored in the comparator's
+/// `KeyEncoding` rather than serialized
+const ORDERED_KEYS: u8 = 0x80;
+/// Default capacity of the buffers files are read and written through, as for `BufReader::new`
+const DEFAULT_BUFFER_BYTES: usize = 8 * 1024;
+
+/// A record as stored in a block:
+/// `[key_len: u32][key][entry_len: u32][entry][sequence: u64][crc32: u32]`, with the checksum
+/// covering everything before it.
+struct RawRecord<'a> {
+    key: &'a [u8],
+    entry: &'a [u8],
+    sequence: u64,
+    checksum: u32,
+}
+
+impl<'a> RawRecord<'a> {
+    fn compute_checksum(key: &[u8], entry: &[u8], sequence: u64) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&(key.len() as u32).to_le_bytes());
+        hasher.update(key);
+        hasher.update(&(entry.len() as u32).to_le_bytes());
+        hasher.update(entry);
+        hasher.update(&sequence.to_le_bytes());
+        hasher.finalize()
+    }
+
+    fn write(buf: &mut Vec<u8>, key: &[u8], entry: &[u8], sequence: u64) {
+        buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        buf.extend_from_slice(key);
+        buf.extend_from_slice(&(entry.len() as u32).to_le_bytes());
+        buf.extend_from_slice(entry);
+        buf.extend_from_slice(&sequence.to_le_bytes());
+        buf.extend_from_slice(&Self::compute_checksum(key, entry, sequence).to_le_bytes());
+    }
+
+    /// Parses the record at the start of `buf`, returning it with its encoded length, or `None`
+    /// if `buf` is too short to hold it.
+    fn parse(buf: &'a [u8]) -> Option<(Self, usize)> {
+        let (key, rest) = Self::parse_field(buf)?;
+        let (entry, rest) = Self::parse_field(rest)?;
+        let sequence = u64::from_le_bytes(rest.get(..8)?.try_into().ok()?);
+        let checksum = u32::from_le_bytes(rest.get(8..12)?.try_into().ok()?);
+        let len = 4 + key.len() + 4 + entry.len() + 8 + 4;
+        Some((Self { key, entry, sequence, checksum }, len))
+    }
+
+    fn parse_field(buf: &'a [u8]) -> Option<(&'a [u8], &'a [u8])> {
+        let len = u32::from_le_bytes(buf.get(..4)?.try_into().ok()?) as usize;
+        let rest = &buf[4..];
+        (rest.len() >= len).then(|| rest.split_at(len))
+    }
+
+    fn is_valid(&self) -> bool {
+        self.checksum == Self::compute_checksum(self.key, self.entry, self.sequence)
+    }
+
+}
+
+/// Reads and decompresses the block at `position`, stored as `[len: u32][payload]`.
+/// Returns the block contents and the offset of the next block.
+fn read_block(
+    reader: &mut impl Read,
+    path: &Path,
+    compression: Compression,
+    position: u64,
+    data_end: u64,
+) -> Result<(Vec<u8>, u64)> {
+    let corruption = || LSMError::Corruption {
+        path: path.display().to_string(),
+        offset: position,
+    };
+
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as u64;
+    if position + 4 + len > data_end {
+        return Err(corruption());
+    }
+
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    let block = compression.decompress(payload)?.ok_or_else(corruption)?;
+
+    Ok((block, position + 4 + len))
+}
+
+/// Passes writes through to `inner` and hashes them, for the file checksum
+struct ChecksumWriter<W> {
+    inner: W,
+    hasher: Xxh3,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reader over a serialized value, as returned by `SSTable::get_value_sequenced`
+pub(crate) enum ValueReader {
+    /// A value serialized into memory
+    Owned(std::io::Cursor<Vec<u8>>),
+    /// A value within a decompressed block, read out of it without copying
+    Block { block: Arc<Vec<u8>>, range: std::ops::Range<usize> },
+    /// A value in the file of an uncompressed table, read as the reader is consumed
+    File(FileValue),
+}
+
+impl ValueReader {
+    pub(crate) fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self::Owned(std::io::Cursor::new(bytes))
+    }
+}
+
+impl Read for ValueReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Owned(cursor) => cursor.read(buf),
+            Self::Block { block, range } => {
+                let read = (&block[range.clone()]).read(buf)?;
+                range.start += read;
+                Ok(read)
+            }
+            Self::File(value) => value.read(buf),
+        }
+    }
+}
+
+/// A value streamed from the file of an uncompressed table. `hasher` holds the CRC32 of the
+/// record up to the value; once the value has been read, the `suffix_len` bytes after it and
+/// the record checksum are read too, and a mismatch fails the read with `InvalidData`.
+pub(crate) struct FileValue {
+    value: std::io::Take<std::io::BufReader<std::fs::File>>,
+    hasher: crc32fast::Hasher,
+    suffix_len: usize,
+    checked: bool,
+    path: PathBuf,
+    record: u64,
+}
+
+impl FileValue {
+    fn check(&mut self) -> std::io::Result<()> {
+        let mut suffix = vec![0; self.suffix_len + 4];
+        self.value.get_mut().read_exact(&mut suffix)?;
+        let (suffix, checksum) = suffix.split_at(self.suffix_len);
+        self.hasher.update(suffix);
+        if std::mem::take(&mut self.hasher).finalize().to_le_bytes() != checksum {
+            let corruption = LSMError::Corruption {
+                path: self.path.display().to_string(),
+                offset: self.record,
+            };
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, corruption));
+        }
+        self.checked = true;
+        Ok(())
+    }
+}
+
+impl Read for FileValue {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.value.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        if read == 0 && !buf.is_empty() && !self.checked {
+            if self.value.limit() > 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            self.check()?;
+        }
+        Ok(read)
+    }
+}
+
+/// Async counterpart of `read_block`
+#[cfg(feature = "tokio")]
+async fn read_block_async(
+    file: &mut tokio::fs::File,
+    path: &Path,
+    compression: Compression,
+    position: u64,
+    data_end: u64,
+) -> Result<Vec<u8>> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    let corruption = || LSMError::Corruption {
+        path: path.display().to_string(),
+        offset: position,
+    };
+
+    file.seek(std::io::SeekFrom::Start(position)).await?;
+    let len = file.read_u32_le().await? as u64;
+    if position + 4 + len > data_end {
+        return Err(corruption());
+    }
+
+    let mut payload = vec![0; len as usize];
+    file.read_exact(&mut payload).await?;
+    compression.decompress(payload)?.ok_or_else(corruption)
+}
+
+/// The sparse index of a table, one entry per block
 #[derive(Debug)]
+struct Index<K> {
+    entries: Vec<IndexEntry<K>>,
+}
+
+impl<K> Index<K> {
+    fn key_range(&self) -> Option<(&K, &K)> {
+        Some((&self.entries.first()?.key, &self.entries.last()?.last_key))
+    }
+}
+
+/// Stored with its keys serialized in the table's encoding
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 struct IndexEntry<K> {
+    /// First key of the block
     key: K,
+    /// Last key of the block, so that lookups for keys between two blocks read neither
+    last_key: K,
+    /// Offset of the block
     position: u64,
+    /// Number of records in the block
+    records: u64,
 }
 
-pub struct SSTable<K, V> {
-    path: String,
-    index: Vec<IndexEntry<K>>,
-    _phantom: std::marker::PhantomData<(K, V)>,
-}
+/// Options controlling how SSTables are written
+#[derive(Clone, Debug)]
+pub struct SSTableOptions {
+    /// Bloom filter bits per key; 0 disables the filter
+    pub bloom_bits_per_key: usize,
+    pub bloom_hasher: BloomHasher,
+    /// Maximum number of records per block, and so per sparse index entry; must be non-zero
+    pub index_interval: u64,
+    /// A block is closed early once its uncompressed size reaches this many bytes
+    pub block_size: usize,
+    pub compression: Compression,
+    pub encoding: Encoding,
+    /// Capacity of the buffer the file is written through; must be non-zero
+    pub write_buffer_bytes: usize,
+    /// Directory the file is written in before it is moved to its path; `None` writes it next
+    /// to the path. See `SSTableWriter`.
+    pub temp_dir: Option<PathBuf>,
+}
+
+impl Default for SSTableOptions {
+    fn default() -> Self {
+        SSTableOptions {
+            bloom_bits_per_key: 10,
+            bloom_hasher: BloomHasher::default(),
+            index_interval: 10,
+            block_size: 4096,
+            compression: Compression::None,
+            encoding: Encoding::default(),
+            write_buffer_bytes: DEFAULT_BUFFER_BYTES,
+            temp_dir: None,
+        }
+    }
+}
+
+/// Where point lookups get the table's file handle from
+enum FileHandle {
+    /// Opened with the table and kept open as long as it lives
+    Owned(Arc<Mutex<std::fs::File>>),
+    /// Taken from the tree's pool, where the table's handle is keyed by its id
+    Pooled(Arc<FilePool>, u64),
+}
+
+/// An immutable sorted table on disk. It is `Send + Sync` whenever `K` and `V` are: the file
+/// handle shared by lookups is behind a mutex and the lazily built index in a `OnceLock`, so
+/// an `Arc<SSTable>` can be read from several threads while another one merges it.
+pub struct SSTable<K, V> {
+    path: PathBuf,
+    /// Loaded on first use for opened tables, so tables that are never read cost no memory
+    index: OnceLock<Index<K>>,
+    bloom: Option<BloomFilter>,
+    /// The hash function the bloom filter was built with
+    bloom_hasher: BloomHasher,
+    entry_count: u64,
+    index_interval: u64,
+    compression: Compression,
+    encoding: Encoding,
+    /// Offset one past the last block
+    data_end: u64,
+    /// Offset of the stored sparse index
+    index_offset: u64,
+    /// Offset one past the trailer, where the checksum starts
+    trailer_end: u64,
+    file_size: u64,
+    /// Checksum of the file up to the checksum itself
+    checksum: u64,
+    /// Cover keys in older tables; this table's own entries are newer than them
+    range_tombstones: Vec<RangeTombstone<K>>,
+    /// Whether record keys are stored in the comparator's key encoding
+    ordered_keys: bool,
+    /// The largest sequence number of the table's entries and range tombstones
+    max_sequence: u64,
+    /// Handle shared by point lookups, which seek it to the block they read. Scans open
+    /// the file again so they can keep their own position.
+    file: FileHandle,
+    /// Capacity of the buffer of scans
+    read_buffer_bytes: usize,
+    comparator: Arc<dyn Comparator<K>>,
+    /// Counters of the tree the table belongs to
+    counters: Arc<Counters>,
+    /// Block cache of the tree and the id the table's blocks are cached under
+    block_cache: Option<(Arc<BlockCache>, u64)>,
+    /// Set once the table is no longer part of the tree; the file is deleted on drop
+    obsolete: AtomicBool,
+    _phantom: std::marker::PhantomData<(K, V)>,
+}
+
+impl<K, V> Drop for SSTable<K, V> {
+    fn drop(&mut self) {
+        if let FileHandle::Pooled(pool, id) = &self.file {
+            pool.remove(*id);
+        }
+        if self.obsolete.load(Ordering::Acquire) {
+            if let Err(e) = std::fs::remove_file(&self.path) {
+                log::warn!("Failed to delete obsolete SSTable {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+/// Writing a table needs `K: Clone`, see `SSTableWriter`
+impl<K, V> SSTable<K, V>
+where
+    K: Ord + serde::Serialize + for<'de> serde::Deserialize<'de> + Clone,
+    V: serde::Serialize + for<'de> serde::Deserialize<'de>,
+{
+    pub fn from_memtable(memtable: &MemTable<K, V>, path: impl Into<PathBuf>) -> Result<Self> {
+        Self::from_memtable_with_options(memtable, path, &SSTableOptions::default())
+    }
+
+    pub fn from_memtable_with_options(
+        memtable: &MemTable<K, V>,
+        path: impl Into<PathBuf>,
+        options: &SSTableOptions,
+    ) -> Result<Self> {
+        let comparator = Arc::clone(memtable.comparator());
+        let mut writer = SSTableWriter::create_with_comparator(path, options, Arc::clone(&comparator))?;
+        let mut previous = None;
+        for (key, entry) in memtable.sequenced_entries() {
+            // The writer rejects such keys too, but from a memtable they would be a bug in its map
+            debug_assert!(
+                previous.is_none_or(|previous| comparator.compare(previous, key).is_lt()),
+                "memtable keys are not strictly increasing"
+            );
+            previous = Some(key);
+            writer.add_entry_with_sequence(key, &entry.entry, entry.sequence)?;
+        }
+        writer.finish()
+    }
+
+    /// Writes key-value pairs, which must be sorted by key without duplicates, to a new table.
+    /// Returns `LSMError::UnsortedKeys`, without creating the table, if they aren't.
+    pub fn from_sorted(
+        entries: impl IntoIterator<Item = (K, V)>,
+        path: impl Into<PathBuf>,
+        options: &SSTableOptions,
+    ) -> Result<Self> {
+        let mut writer = SSTableWriter::create(path, options)?;
+        for (key, value) in entries {
+            writer.add(&key, &value)?;
+        }
+        writer.finish()
+    }
+}
+
+/// Reading never clones keys
+impl<K, V> SSTable<K, V>
+where
+    K: Ord + serde::Serialize + for<'de> serde::Deserialize<'de>,
+    V: serde::Serialize + for<'de> serde::Deserialize<'de>,
+{
+    /// Opens an existing SSTable file, reading its header, footer and bloom filter. The sparse
+    /// index is only built on first use, see `SSTable::index`.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        Self::open_with_comparator(path, Arc::new(NaturalOrder))
+    }
+
+    /// Opens a table whose keys are ordered by `comparator`, which must be the comparator it
+    /// was written with
+    pub fn open_with_comparator(path: impl Into<PathBuf>, comparator: Arc<dyn Comparator<K>>) -> Result<Self> {
+        let path = path.into();
+        let name = || path.display().to_string();
+        let file = std::fs::File::open(&path)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = std::io::BufReader::new(file.try_clone()?);
+
+        let too_short = || LSMError::InvalidFormat { path: name(), reason: "file is too short".to_string() };
+        if file_len < HEADER_LEN + FOOTER_LEN {
+            return Err(too_short());
+        }
+        let footer_start = file_len - FOOTER_LEN;
+        reader.seek(std::io::SeekFrom::Start(footer_start))?;
+        let mut footer = [0u8; FOOTER_LEN as usize];
+        reader.read_exact(&mut footer)?;
+        if footer[25..] != MAGIC {
+            return Err(LSMError::InvalidFormat { path: name(), reason: "bad magic number".to_string() });
+        }
+        if footer[24] != FORMAT_VERSION {
+            let reason = format!("unsupported format version {}", footer[24]);
+            return Err(LSMError::InvalidFormat { path: name(), reason });
+        }
+        let entry_count = u64::from_le_bytes(footer[..8].try_into().unwrap());
+        let data_end = u64::from_le_bytes(footer[8..16].try_into().unwrap());
+        let index_offset = u64::from_le_bytes(footer[16..24].try_into().unwrap());
+        if data_end < HEADER_LEN || data_end + CHECKSUM_LEN > footer_start {
+            return Err(LSMError::Corruption { path: name(), offset: footer_start });
+        }
+        let trailer_end = footer_start - CHECKSUM_LEN;
+        if index_offset < data_end || index_offset > trailer_end {
+            return Err(LSMError::Corruption { path: name(), offset: footer_start });
+        }
+        reader.seek(std::io::SeekFrom::Start(trailer_end))?;
+        let mut checksum = [0u8; CHECKSUM_LEN as usize];
+        reader.read_exact(&mut checksum)?;
+        let checksum = u64::from_le_bytes(checksum);
+
+        reader.seek(std::io::SeekFrom::Start(data_end))?;
+        let bloom: Option<BloomFilter> = bincode::deserialize_from(&mut reader)?;
+        let bloom = bloom.filter(|_| comparator.is_consistent_with_serialization());
+        let range_tombstones: Vec<RangeTombstone<Vec<u8>>> = bincode::deserialize_from(&mut reader)?;
+        let max_sequence: u64 = bincode::deserialize_from(&mut reader)?;
+        let bloom_hasher = BloomHasher::from_id(bincode::deserialize_from(&mut reader)?)
+            .ok_or_else(|| LSMError::Corruption { path: name(), offset: data_end })?;
+
+        reader.seek(std::io::SeekFrom::Start(0))?;
+        let index_interval: u64 = bincode::deserialize_from(&mut reader)?;
+        let compression_id: u8 = bincode::deserialize_from(&mut reader)?;
+        let encoding_id: u8 = bincode::deserialize_from(&mut reader)?;
+        let compression = Compression::from_id(compression_id);
+        let (compression, encoding) = match (compression, Encoding::from_id(encoding_id & !ORDERED_KEYS)) {
+            (Some(compression), Some(encoding)) if index_interval > 0 => (compression, encoding),
+            _ => return Err(LSMError::Corruption { path: name(), offset: 0 }),
+        };
+        let ordered_keys = encoding_id & ORDERED_KEYS != 0;
+        if ordered_keys && comparator.key_encoding().is_none() {
+            let reason = "keys are stored in an ordered encoding the comparator doesn't have".to_string();
+            return Err(LSMError::InvalidFormat { path: name(), reason });
+        }
+        let range_tombstones = range_tombstones
+            .into_iter()
+            .map(|tombstone| {
+                let (start, end) = (encoding.deserialize(&tombstone.start)?, encoding.deserialize(&tombstone.end)?);
+                Ok(RangeTombstone { start, end, sequence: tombstone.sequence })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            path,
+            index: OnceLock::new(),
+            bloom,
+            bloom_hasher,
+            entry_count,
+            index_interval,
+            compression,
+            encoding,
+            data_end,
+            index_offset,
+            trailer_end,
+            file_size: file_len,
+            checksum,
+            range_tombstones,
+            ordered_keys,
+            max_sequence,
+            file: FileHandle::Owned(Arc::new(Mutex::new(file))),
+            read_buffer_bytes: DEFAULT_BUFFER_BYTES,
+            comparator,
+            counters: Arc::default(),
+            block_cache: None,
+            obsolete: AtomicBool::new(false),
+            _phantom: std::marker::PhantomData,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Size of the table's file in bytes
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// Makes the table count the files it opens for scans in `counters`
+    pub(crate) fn with_counters(mut self, counters: Arc<Counters>) -> Self {
+        self.counters = counters;
+        self
+    }
+
+    /// Makes lookups go through `cache`, where the table's blocks are keyed by `id`
+    pub(crate) fn with_block_cache(mut self, cache: Option<Arc<BlockCache>>, id: u64) -> Self {
+        self.block_cache = cache.map(|cache| (cache, id));
+        self
+    }
+
+    /// Makes lookups take the table's file handle from `pool`, keyed by `id`, instead of keeping
+    /// one open
+    pub(crate) fn with_file_pool(mut self, pool: Option<Arc<FilePool>>, id: u64) -> Self {
+        if let Some(pool) = pool {
+            self.file = FileHandle::Pooled(pool, id);
+        }
+        self
+    }
+
+    /// Makes scans read the file through a buffer of `bytes` bytes; larger buffers mean fewer
+    /// reads for long sequential scans
+    pub(crate) fn with_read_buffer_bytes(mut self, bytes: usize) -> Self {
+        self.read_buffer_bytes = bytes.max(1);
+        self
+    }
+
+    fn open_file(&self) -> Result<std::io::BufReader<std::fs::File>> {
+        self.counters.sstable_files_opened.add(1);
+        Ok(std::io::BufReader::with_capacity(self.read_buffer_bytes, std::fs::File::open(&self.path)?))
+    }
+
+    /// The sparse index, loaded on first use from the index section
+    fn index(&self) -> Result<&Index<K>> {
+        if let Some(index) = self.index.get() {
+            return Ok(index);
+        }
+        let index = self.read_index(self.index_offset)?;
+        self.counters.sstable_indexes_loaded.add(1);
+        Ok(self.index.get_or_init(|| index))
+    }
+
+    /// Reads the index section at `offset`, checking its CRC32, that its blocks follow each
+    /// other within the data and that their records add up to the entry count in the footer
+    fn read_index(&self, offset: u64) -> Result<Index<K>> {
+        let corruption = || LSMError::Corruption {
+            path: self.path.display().to_string(),
+            offset,
+        };
+        let len = self.trailer_end - offset;
+        if len < 4 {
+            return Err(corruption());
+        }
+        let mut file = std::fs::File::open(&self.path)?;
+        file.seek(std::io::SeekFrom::Start(offset))?;
+        let mut section = vec![0; len as usize];
+        file.read_exact(&mut section)?;
+        let (stored, crc) = section.split_at(section.len() - 4);
+        if crc32fast::hash(stored).to_le_bytes() != crc {
+            return Err(corruption());
+        }
+
+        let stored: Vec<IndexEntry<Vec<u8>>> = bincode::deserialize(stored)?;
+        let positions: Vec<_> = stored.iter().map(|entry| entry.position).collect();
+        let in_data = positions.first().is_none_or(|&first| first == HEADER_LEN)
+            && positions.windows(2).all(|pair| pair[0] < pair[1])
+            && positions.last().is_none_or(|&last| last < self.data_end);
+        if !in_data || stored.iter().map(|entry| entry.records).sum::<u64>() != self.entry_count {
+            return Err(corruption());
+        }
+        let entries = stored
+            .into_iter()
+            .map(|entry| {
+                Ok(IndexEntry {
+                    key: self.encoding.deserialize(&entry.key)?,
+                    last_key: self.encoding.deserialize(&entry.last_key)?,
+                    position: entry.position,
+                    records: entry.records,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Index { entries })
+    }
+
+    /// The smallest and largest keys in the table, or `None` if it is empty
+    pub fn key_range(&self) -> Result<Option<(&K, &K)>> {
+        Ok(self.index()?.key_range())
+    }
+
+    /// Whether `key` falls within one of the table's range tombstones, or may have an entry
+    /// judging by the bloom filter and the key range. The bloom filter is checked first, so a
+    /// key it rules out doesn't load the index.
+    pub fn may_contain_key(&self, key: &K) -> Result<bool> {
+        if self.range_deleted::<()>(key).is_some() {
+            return Ok(true);
+        }
+        Ok(!self.bloom_rules_out(key)? && self.in_key_range(self.index()?, key))
+    }
+
+    /// Whether the index is in memory, so lookups don't have to read it from disk first
+    #[cfg(feature = "tokio")]
+    pub(crate) fn index_is_loaded(&self) -> bool {
+        self.index.get().is_some()
+    }
+
+    /// Loads the index ahead of the lookups that need it
+    #[cfg(feature = "tokio")]
+    pub(crate) fn load_index(&self) -> Result<()> {
+        self.index().map(|_| ())
+    }
+
+    /// Whether the bloom filter, if the table has one, says `key` has no entry
+    pub(crate) fn bloom_rules_out(&self, key: &K) -> Result<bool> {
+        match &self.bloom {
+            Some(bloom) => Ok(!bloom.may_contain_hash(self.bloom_hasher.hash_key(key, self.encoding)?)),
+            None => Ok(false),
+        }
+    }
+
+    /// The range tombstones of the table
+    pub fn range_tombstones(&self) -> &[RangeTombstone<K>] {
+        &self.range_tombstones
+    }
+
+    /// The largest sequence number of the table's entries and range tombstones, 0 for tables
+    /// written without them
+    pub fn max_sequence(&self) -> u64 {
+        self.max_sequence
+    }
+
+    /// The smallest and largest keys the table has entries or range tombstones for. A range
+    /// tombstone's end is excluded from it, but counts as its largest key here.
+    pub(crate) fn span(&self) -> Result<Option<(&K, &K)>> {
+        let comparator = &*self.comparator;
+        let starts = self.range_tombstones.iter().map(|tombstone| (&tombstone.start, &tombstone.end));
+        let span = self.key_range()?.into_iter().chain(starts).reduce(|(low, high), (first, last)| {
+            let low = if comparator.compare(first, low).is_lt() { first } else { low };
+            let high = if comparator.compare(last, high).is_gt() { last } else { high };
+            (low, high)
+        });
+        Ok(span)
+    }
+
+    /// A tombstone, with the sequence number of the newest of the table's range tombstones
+    /// covering `key`, if any does
+    fn range_deleted<T>(&self, key: &K) -> Option<Sequenced<Entry<T>>> {
+        let sequence = range_tombstone::covering_sequence(&self.range_tombstones, key, &*self.comparator)?;
+        Some(Sequenced::new(Entry::Tombstone, sequence))
+    }
+
+    fn in_key_range(&self, index: &Index<K>, key: &K) -> bool {
+        index.key_range().is_some_and(|(first, last)| {
+            self.comparator.compare(first, key).is_le() && self.comparator.compare(key, last).is_le()
+        })
+    }
+
+    /// Whether any key within the bounds may be in the table or deleted by one of its range
+    /// tombstones, judging by its key range alone, so scans can skip a table without reading
+    /// its blocks
+    pub fn overlaps_range(&self, start: Bound<&K>, end: Bound<&K>) -> Result<bool> {
+        let comparator = &*self.comparator;
+        if self.range_tombstones.iter().any(|tombstone| tombstone.overlaps(start, end, comparator)) {
+            return Ok(true);
+        }
+        let Some((first, last)) = self.key_range()? else {
+            return Ok(false);
+        };
+        let starts_before_last = match start {
+            Bound::Included(key) => self.comparator.compare(key, last).is_le(),
+            Bound::Excluded(key) => self.comparator.compare(key, last).is_lt(),
+            Bound::Unbounded => true,
+        };
+        let ends_after_first = match end {
+            Bound::Included(key) => self.comparator.compare(first, key).is_le(),
+            Bound::Excluded(key) => self.comparator.compare(first, key).is_lt(),
+            Bound::Unbounded => true,
+        };
+        Ok(starts_before_last && ends_after_first)
+    }
+
+    /// Estimates the number of records, tombstones included, with keys in `[start, end)` from
+    /// the sparse index alone: the records of the blocks whose first key falls in the range,
+    /// plus half of those of the block straddling `start`. Off by up to about a block at
+    /// either end, and reads nothing once the index is loaded.
+    pub fn estimate_range_count(&self, start: &K, end: &K) -> Result<u64> {
+        let is_empty = self.comparator.compare(start, end).is_ge();
+        if is_empty || !self.overlaps_range(Bound::Included(start), Bound::Excluded(end))? {
+            return Ok(0);
+        }
+        let index = &self.index()?.entries;
+        let from = index.partition_point(|entry| self.comparator.compare(&entry.key, start).is_lt());
+        let to = index.partition_point(|entry| self.comparator.compare(&entry.key, end).is_lt());
+        let straddling = from.checked_sub(1).map_or(0, |pos| index[pos].records / 2);
+        Ok(straddling + index[from..to].iter().map(|entry| entry.records).sum::<u64>())
+    }
+
+    /// Schedules the file for deletion once the last reference to the table is dropped, so
+    /// that readers still holding it (such as snapshots) can finish
+    pub(crate) fn mark_obsolete(&self) {
+        self.obsolete.store(true, Ordering::Release);
+    }
+
+    /// Number of records in the table, including tombstones
+    pub fn entry_count(&self) -> u64 {
+        self.entry_count
+    }
+
+    /// The maximum number of records per block this table was written with
+    pub fn index_interval(&self) -> u64 {
+        self.index_interval
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Reads every record and checks that it passes its checksum and decodes, that the keys
+    /// are in strictly increasing order within the table's key range and pass the bloom filter,
+    /// and that their number matches the footer. Anomalies are reported, not returned as errors.
+    pub fn verify(&self) -> TableReport {
+        let mut report = TableReport {
+            path: self.path.clone(),
+            ..TableReport::default()
+        };
+        if let Err(e) = self.verify_file() {
+            report.errors.push(format!("file checksum check failed: {}", e));
+        }
+        let entries = match self.entries() {
+            Ok(entries) => entries,
+            Err(e) => {
+                report.errors.push(format!("cannot read the table: {}", e));
+                return report;
+            }
+        };
+
+        let index = self
+            .index()
+            .map_err(|e| report.errors.push(format!("cannot read the index: {}", e)))
+            .ok();
+
+        let mut previous: Option<K> = None;
+        for item in entries {
+            let key = match item {
+                Ok((key, _)) => key,
+                Err(e) => {
+                    report.errors.push(format!("unreadable record after {} records: {}", report.entry_count, e));
+                    return report;
+                }
+            };
+            let record = report.entry_count;
+            report.entry_count += 1;
+
+            if previous.as_ref().is_some_and(|previous| self.comparator.compare(previous, &key).is_ge()) {
+                report.errors.push(format!("record {} is out of order", record));
+            }
+            if index.is_some_and(|index| !self.in_key_range(index, &key)) {
+                report.errors.push(format!("record {} is outside the table's key range", record));
+            }
+            if let Some(bloom) = &self.bloom {
+                let hash = self.bloom_hasher.hash_key(&key, self.encoding);
+                let in_bloom = hash.is_ok_and(|hash| bloom.may_contain_hash(hash));
+                if !in_bloom {
+                    report.errors.push(format!("record {} is missing from the bloom filter", record));
+                }
+            }
+            previous = Some(key);
+        }
+
+        if report.entry_count != self.entry_count {
+            report.errors.push(format!(
+                "found {} records, but the footer says {}",
+                report.entry_count, self.entry_count
+            ));
+        }
+        report
+    }
+
+    /// Re-reads the file and compares it against the checksum written with it, without parsing
+    /// any record. A changed file is reported as `LSMError::Corruption`.
+    pub fn verify_file(&self) -> Result<()> {
+        let checksum_offset = self.trailer_end;
+        let file = std::fs::File::open(&self.path)?;
+        let mut hashed = ChecksumWriter {
+            inner: std::io::sink(),
+            hasher: Xxh3::new(),
+        };
+        let len = file.metadata()?.len();
+        std::io::copy(&mut file.take(checksum_offset), &mut hashed)?;
+        if len != self.file_size || hashed.hasher.digest() != self.checksum {
+            return Err(LSMError::Corruption {
+                path: self.path.display().to_string(),
+                offset: checksum_offset,
+            });
+        }
+        Ok(())
+    }
+
+    /// Streams all entries in key order, including tombstones
+    pub(crate) fn entries(&self) -> Result<impl Iterator<Item = Result<(K, Entry<V>)>>> {
+        Ok(self.sequenced_entries()?.map(|item| item.map(|(key, entry)| (key, entry.entry))))
+    }
+
+    /// Like `entries`, with the sequence number of each entry
+    pub(crate) fn sequenced_entries(&self) -> Result<SSTableEntries<K, V>> {
+        self.entries_from(None)
+    }
+
+    /// Streams the key-value pairs in key order, skipping tombstones; expiry is not checked.
+    /// The file is read sequentially, one block at a time, through its own handle. A record
+    /// that can't be read is yielded as an error, which ends the iteration.
+    pub fn iter(&self) -> Result<impl Iterator<Item = Result<(K, V)>>> {
+        Ok(self
+            .entries()?
+            .filter_map(|item| item.map(|(key, entry)| entry.into_value().map(|value| (key, value))).transpose()))
+    }
+
+    /// Writes a readable listing of the table to `w` for troubleshooting: its header and
+    /// footer fields, the sparse index and every record in key order, tombstones included. A
+    /// record that can't be read ends the listing with an error.
+    pub fn dump(&self, w: &mut impl Write) -> Result<()>
+    where
+        K: std::fmt::Debug,
+        V: std::fmt::Debug,
+    {
+        writeln!(w, "SSTable {}", self.path.display())?;
+        writeln!(w, "  file size: {} bytes", self.file_size)?;
+        writeln!(w, "  entries: {}", self.entry_count)?;
+        writeln!(w, "  index interval: {}", self.index_interval)?;
+        writeln!(w, "  compression: {:?}", self.compression)?;
+        writeln!(w, "  encoding: {:?}", self.encoding)?;
+        writeln!(w, "  data: bytes {} to {}", HEADER_LEN, self.data_end)?;
+        writeln!(w, "  stored index: bytes {} to {}", self.index_offset, self.trailer_end)?;
+        writeln!(w, "  max sequence: {}", self.max_sequence)?;
+        match &self.bloom {
+            Some(bloom) => writeln!(w, "  bloom filter: {} bytes", bincode::serialized_size(bloom)?)?,
+            None => writeln!(w, "  bloom filter: none")?,
+        }
+
+        let index = &self.index()?.entries;
+        writeln!(w, "Index ({} blocks)", index.len())?;
+        for (i, entry) in index.iter().enumerate() {
+            writeln!(
+                w,
+                "  block {} at {}: {} records, {:?} to {:?}",
+                i, entry.position, entry.records, entry.key, entry.last_key
+            )?;
+        }
+
+        writeln!(w, "Records")?;
+        for item in self.sequenced_entries()? {
+            let (key, entry) = item?;
+            writeln!(w, "  {:?} => {:?} (sequence {})", key, entry.entry, entry.sequence)?;
+        }
+        Ok(())
+    }
+
+    /// Streams entries starting at the block of the given sparse index entry (or the first block)
+    fn entries_from(&self, index_pos: Option<usize>) -> Result<SSTableEntries<K, V>> {
+        self.entries_from_with(index_pos, decode_entry)
+    }
+
+    /// Like `entries_from`, decoding each entry with `decode`
+    fn entries_from_with<T>(&self, index_pos: Option<usize>, decode: DecodeEntry<T>) -> Result<SSTableEntries<K, T>> {
+        let mut reader = self.open_file()?;
+
+        let position = match index_pos {
+            Some(pos) => self.index()?.entries[pos].position,
+            None => HEADER_LEN,
+        };
+        reader.seek(std::io::SeekFrom::Start(position))?;
+
+        Ok(SSTableEntries {
+            reader,
+            path: self.path.clone(),
+            compression: self.compression,
+            encoding: self.encoding,
+            next_block: position,
+            data_end: self.data_end,
+            block: Vec::new(),
+            block_position: position,
+            offset: 0,
+            done: false,
+            decode,
+            ordered_keys: self.ordered_keys.then(|| Arc::clone(&self.comparator)),
+        })
+    }
+
+    /// Streams the entries with keys within `[start, end]` bounds in key order, including
+    /// tombstones. The scan starts at the first block whose last key is within the start
+    /// bound, and stops at the first key past the end bound.
+    pub fn range(&self, start: Bound<K>, end: Bound<K>) -> Result<SSTableRange<K, V>> {
+        self.range_with(start, end, decode_entry)
+    }
+
+    /// Like `range`, decoding each entry with `decode`, e.g. `decode_kind` to skip the values
+    pub(crate) fn range_with<T>(
+        &self,
+        start: Bound<K>,
+        end: Bound<K>,
+        decode: DecodeEntry<T>,
+    ) -> Result<SSTableRange<K, T>> {
+        let index = &self.index()?.entries;
+        let first_block = match &start {
+            Bound::Included(key) => {
+                index.partition_point(|entry| self.comparator.compare(&entry.last_key, key).is_lt())
+            }
+            Bound::Excluded(key) => {
+                index.partition_point(|entry| self.comparator.compare(&entry.last_key, key).is_le())
+            }
+            Bound::Unbounded => 0,
+        };
+
+        let mut entries = self.entries_from_with((first_block < index.len()).then_some(first_block), decode)?;
+        // Every key is before the start bound
+        entries.done = first_block == index.len();
+        Ok(SSTableRange {
+            entries,
+            start,
+            end,
+            comparator: Arc::clone(&self.comparator),
+        })
+    }
+
+    /// Streams the entries with keys within `[start, end]` bounds in descending key order,
+    /// including tombstones. One block is decoded and buffered at a time.
+    pub fn range_rev(&self, start: Bound<K>, end: Bound<K>) -> Result<SSTableRevRange<K, V>> {
+        self.range_rev_with(start, end, decode_entry)
+    }
+
+    /// Like `range_rev`, decoding each entry with `decode`
+    pub(crate) fn range_rev_with<T>(
+        &self,
+        start: Bound<K>,
+        end: Bound<K>,
+        decode: DecodeEntry<T>,
+    ) -> Result<SSTableRevRange<K, T>> {
+        // The last block that may hold a key within the end bound
+        let index = &self.index()?.entries;
+        let blocks = match &end {
+            Bound::Included(key) | Bound::Excluded(key) => {
+                match index.binary_search_by(|entry| self.comparator.compare(&entry.key, key)) {
+                    // A block starting at an excluded end holds nothing within the bounds
+                    Ok(pos) if matches!(end, Bound::Excluded(_)) => pos,
+                    Ok(pos) => pos + 1,
+                    Err(pos) => pos,
+                }
+            }
+            Bound::Unbounded => index.len(),
+        };
+
+        Ok(SSTableRevRange {
+            reader: self.open_file()?,
+            path: self.path.clone(),
+            compression: self.compression,
+            encoding: self.encoding,
+            data_end: self.data_end,
+            blocks: index[..blocks].iter().map(|entry| entry.position).collect(),
+            buffered: Vec::new(),
+            start,
+            end,
+            comparator: Arc::clone(&self.comparator),
+            ordered_keys: self.ordered_keys,
+            decode,
+        })
+    }
+
+    /// Looks up a value, treating a tombstone as a missing key. Expiry is not checked;
+    /// use `get_entry` and `Entry::live_value` to honour TTLs.
+    pub fn get(&self, search_key: &K) -> Result<Option<V>> {
+        Ok(self.get_entry(search_key)?.and_then(Entry::into_value))
+    }
+
+    /// Looks up the raw entry for a key, including tombstones, so callers can
+    /// stop searching older tables once a deletion is found. Only the key's block is searched,
+    /// and its end is known from its length prefix, so a record that can't be read or decoded
+    /// is an error rather than a missing key. A key without an entry that one of the table's
+    /// range tombstones covers has a tombstone.
+    pub fn get_entry(&self, search_key: &K) -> Result<Option<Entry<V>>> {
+        Ok(self.get_sequenced(search_key)?.map(|entry| entry.entry))
+    }
+
+    /// Like `get_entry`, with the sequence number of the write that made the entry
+    pub fn get_sequenced(&self, search_key: &K) -> Result<Option<Sequenced<Entry<V>>>> {
+        let Some(block_pos) = self.locate_block(search_key)? else {
+            return Ok(self.range_deleted(search_key));
+        };
+
+        let block = self.read_indexed_block(block_pos)?;
+        Ok(self.search_block(&block, block_pos, search_key)?.or_else(|| self.range_deleted(search_key)))
+    }
+
+    /// Like `get_entry`, but without deserializing the value: returns what kind of entry the
+    /// key has, and its expiry time if any
+    pub fn get_entry_kind(&self, search_key: &K) -> Result<Option<Entry<()>>> {
+        Ok(self.get_kind_sequenced(search_key)?.map(|entry| entry.entry))
+    }
+
+    /// Like `get_entry_kind`, with the sequence number of the entry
+    pub(crate) fn get_kind_sequenced(&self, search_key: &K) -> Result<Option<Sequenced<Entry<()>>>> {
+        let Some(block_pos) = self.locate_block(search_key)? else {
+            return Ok(self.range_deleted(search_key));
+        };
+
+        let block = self.read_indexed_block(block_pos)?;
+        let Some((record, _)) = self.find_in_block(&block, block_pos, search_key)? else {
+            return Ok(self.range_deleted(search_key));
+        };
+        let kind = decode_kind::<V>(self.encoding, record.entry)?;
+        Ok(Some(Sequenced::new(kind, record.sequence)))
+    }
+
+    /// Like `get_entry`, but returns the value as it is serialized in the table, in the table's
+    /// encoding. With bincode and fixed-width integers the bytes are copied out of the block
+    /// without being decoded; other encodings don't delimit the value, so it is decoded and
+    /// encoded again.
+    pub fn get_entry_raw(&self, search_key: &K) -> Result<Option<Entry<Vec<u8>>>> {
+        Ok(self.get_raw_sequenced(search_key)?.map(|entry| entry.entry))
+    }
+
+    /// Like `get_entry_raw`, with the sequence number of the entry
+    pub(crate) fn get_raw_sequenced(&self, search_key: &K) -> Result<Option<Sequenced<Entry<Vec<u8>>>>> {
+        let Some(block_pos) = self.locate_block(search_key)? else {
+            return Ok(self.range_deleted(search_key));
+        };
+
+        let block = self.read_indexed_block(block_pos)?;
+        let Some((record, _)) = self.find_in_block(&block, block_pos, search_key)? else {
+            return Ok(self.range_deleted(search_key));
+        };
+        Ok(Some(Sequenced::new(self.raw_entry(record.entry)?, record.sequence)))
+    }
+
+    /// Like `get_raw_sequenced`, but returns readers over the values instead of copies. In an
+    /// uncompressed table that doesn't have the key's block cached, only the record headers of
+    /// the block are read, and a value is left in the file to be streamed; in any other table
+    /// it is read out of the decompressed block. Values the encoding doesn't delimit are
+    /// serialized again into memory, as for `get_entry_raw`.
+    pub(crate) fn get_value_sequenced(&self, search_key: &K) -> Result<Option<Sequenced<Entry<ValueReader>>>> {
+        let Some(block_pos) = self.locate_block(search_key)? else {
+            return Ok(self.range_deleted(search_key));
+        };
+
+        let position = self.index()?.entries[block_pos].position;
+        let block = match self.compression {
+            Compression::None => self.cached_block(position),
+            _ => Some(self.read_indexed_block(block_pos)?),
+        };
+        let Some(block) = block else {
+            let entry = self.find_in_file(block_pos, search_key)?;
+            return Ok(entry.or_else(|| self.range_deleted(search_key)));
+        };
+
+        let Some((record, offset)) = self.find_in_block(&block, block_pos, search_key)? else {
+            return Ok(self.range_deleted(search_key));
+        };
+        let entry_start = offset + 4 + record.key.len() + 4;
+        let entry = match self.value_range(record.entry)? {
+            Some(entry) => entry.map(|range| ValueReader::Block {
+                block: Arc::clone(&block),
+                range: entry_start + range.start..entry_start + range.end,
+            }),
+            None => self.raw_entry(record.entry)?.map(ValueReader::from_bytes),
+        };
+        Ok(Some(Sequenced::new(entry, record.sequence)))
+    }
+
+    /// The entry as it is serialized in the table: with bincode and fixed-width integers the
+    /// value is copied out without being decoded; other encodings don't delimit the value, so
+    /// it is decoded and encoded again
+    fn raw_entry(&self, entry: &[u8]) -> Result<Entry<Vec<u8>>> {
+        if let Some(entry_range) = self.value_range(entry)? {
+            return Ok(entry_range.map(|range| entry[range].to_vec()));
+        }
+        Ok(match self.encoding.deserialize::<Entry<V>>(entry)? {
+            Entry::Value(value) => Entry::Value(self.encoding.serialize(&value)?),
+            Entry::Tombstone => Entry::Tombstone,
+            Entry::Expiring { value, expires_at } => Entry::Expiring {
+                value: self.encoding.serialize(&value)?,
+                expires_at,
+            },
+            Entry::Merge(operands) => Entry::Merge(
+                operands
+                    .iter()
+                    .map(|operand| self.encoding.serialize(operand))
+                    .collect::<Result<_>>()?,
+            ),
+        })
+    }
+
+    /// Where the value of a serialized entry is within it, if the encoding delimits it: with
+    /// bincode, the entry is the variant index, then the value, then for an expiring entry its
+    /// expiry time, which has a fixed width only with fixed-width integers
+    fn value_range(&self, entry: &[u8]) -> Result<Option<Entry<std::ops::Range<usize>>>> {
+        if self.encoding.format != Format::Bincode {
+            return Ok(None);
+        }
+        let tag_len = self.encoding.serialized_size(&0u32)? as usize;
+        let fixint = self.encoding.int_encoding == IntEncoding::Fixint;
+        Ok(match self.encoding.deserialize::<u32>(entry)? {
+            0 => Some(Entry::Value(tag_len..entry.len())),
+            1 => Some(Entry::Tombstone),
+            2 if fixint && entry.len() >= tag_len + 8 => Some(Entry::Expiring {
+                value: tag_len..entry.len() - 8,
+                expires_at: self.encoding.deserialize(&entry[entry.len() - 8..])?,
+            }),
+            _ => None,
+        })
+    }
+
+    /// Finds the record for the key in the block at `block_pos` of an uncompressed table
+    /// reading only the record headers from the file, and seeking past the entries of the
+    /// records before it. A value is left in the file to be streamed, and its record checked
+    /// once it has been read; any other entry is read and checked whole.
+    fn find_in_file(&self, block_pos: usize, search_key: &K) -> Result<Option<Sequenced<Entry<ValueReader>>>> {
+        let first = &self.index()?.entries[block_pos];
+        let corruption = |offset| LSMError::Corruption {
+            path: self.path.display().to_string(),
+            offset,
+        };
+        let read_u32 = |reader: &mut std::io::BufReader<std::fs::File>| -> Result<u64> {
+            let mut bytes = [0u8; 4];
+            reader.read_exact(&mut bytes)?;
+            Ok(u32::from_le_bytes(bytes) as u64)
+        };
+        let search_bytes = self.key_encoding().map(|keys| keys.encode(search_key));
+        let first_bytes = self.key_encoding().map(|keys| keys.encode(&first.key));
+
+        let mut reader = self.open_file()?;
+        reader.seek(std::io::SeekFrom::Start(first.position))?;
+        let block_end = first.position + 4 + read_u32(&mut reader)?;
+        if block_end > self.data_end {
+            return Err(corruption(first.position));
+        }
+        let mut offset = first.position + 4;
+        while offset < block_end {
+            let key_len = read_u32(&mut reader)?;
+            if offset + 4 + key_len + 4 > block_end {
+                return Err(corruption(offset));
+            }
+            let mut key_bytes = vec![0; key_len as usize];
+            reader.read_exact(&mut key_bytes)?;
+            let entry_len = read_u32(&mut reader)?;
+            let record_len = 4 + key_len + 4 + entry_len + 8 + 4;
+            if offset + record_len > block_end {
+                return Err(corruption(offset));
+            }
+
+            // As in `find_in_block`, the block must start with the key the index was built from
+            let is_first = offset == first.position + 4;
+            if is_first && self.compare_stored(&key_bytes, &first.key, first_bytes.as_deref())?.is_ne() {
+                return Err(corruption(first.position));
+            }
+            match self.compare_stored(&key_bytes, search_key, search_bytes.as_deref())? {
+                std::cmp::Ordering::Equal => {
+                    return self.read_file_entry(reader, offset, key_bytes, entry_len as usize).map(Some);
+                }
+                std::cmp::Ordering::Greater => return Ok(None),
+                std::cmp::Ordering::Less => {
+                    reader.seek_relative((entry_len + 8 + 4) as i64)?;
+                    offset += record_len;
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads the entry of the record at `offset` for `find_in_file`, with `reader` positioned
+    /// at its start
+    fn read_file_entry(
+        &self,
+        mut reader: std::io::BufReader<std::fs::File>,
+        offset: u64,
+        key: Vec<u8>,
+        entry_len: usize,
+    ) -> Result<Sequenced<Entry<ValueReader>>> {
+        let tag_len = self.encoding.serialized_size(&0u32)? as usize;
+        let fixint = self.encoding.int_encoding == IntEncoding::Fixint;
+        if self.encoding.format == Format::Bincode && entry_len >= tag_len {
+            let mut tag = vec![0; tag_len];
+            reader.read_exact(&mut tag)?;
+            let expiry_len = match self.encoding.deserialize::<u32>(&tag)? {
+                0 => Some(0),
+                2 if fixint && entry_len >= tag_len + 8 => Some(8),
+                _ => None,
+            };
+            if let Some(expiry_len) = expiry_len {
+                // The expiry time and the sequence number follow the value
+                let value_len = entry_len - tag_len - expiry_len;
+                let suffix_len = expiry_len + 8;
+                let mut suffix = [0u8; 16];
+                reader.seek_relative(value_len as i64)?;
+                reader.read_exact(&mut suffix[..suffix_len])?;
+                reader.seek_relative(-((value_len + suffix_len) as i64))?;
+                let sequence = u64::from_le_bytes(suffix[expiry_len..suffix_len].try_into().expect("8 bytes"));
+
+                let mut hasher = crc32fast::Hasher::new();
+                hasher.update(&(key.len() as u32).to_le_bytes());
+                hasher.update(&key);
+                hasher.update(&(entry_len as u32).to_le_bytes());
+                hasher.update(&tag);
+                let value = ValueReader::File(FileValue {
+                    value: reader.take(value_len as u64),
+                    hasher,
+                    suffix_len,
+                    checked: false,
+                    path: self.path.clone(),
+                    record: offset,
+                });
+                let entry = match expiry_len {
+                    0 => Entry::Value(value),
+                    _ => Entry::Expiring {
+                        value,
+                        expires_at: self.encoding.deserialize(&suffix[..8])?,
+                    },
+                };
+                return Ok(Sequenced::new(entry, sequence));
+            }
+            reader.seek_relative(-(tag_len as i64))?;
+        }
+
+        // Anything else is read whole, and checked before it is used
+        let mut record = Vec::with_capacity(4 + key.len() + 4 + entry_len + 8 + 4);
+        record.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        record.extend_from_slice(&key);
+        record.extend_from_slice(&(entry_len as u32).to_le_bytes());
+        let start = record.len();
+        record.resize(record.capacity(), 0);
+        reader.read_exact(&mut record[start..])?;
+        let corruption = || LSMError::Corruption {
+            path: self.path.display().to_string(),
+            offset,
+        };
+        let (record, _) = RawRecord::parse(&record).ok_or_else(corruption)?;
+        if !record.is_valid() {
+            return Err(corruption());
+        }
+        Ok(Sequenced::new(self.raw_entry(record.entry)?.map(ValueReader::from_bytes), record.sequence))
+    }
+
+    /// Like `get_entry`, but reads the block through `tokio::fs` so the calling task yields
+    /// instead of blocking its thread
+    #[cfg(feature = "tokio")]
+    pub async fn get_entry_async(&self, search_key: &K) -> Result<Option<Entry<V>>> {
+        Ok(self.get_sequenced_async(search_key).await?.map(|entry| entry.entry))
+    }
+
+    /// Like `get_entry_async`, with the sequence number of the entry
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn get_sequenced_async(&self, search_key: &K) -> Result<Option<Sequenced<Entry<V>>>> {
+        let Some(block_pos) = self.locate_block(search_key)? else {
+            return Ok(self.range_deleted(search_key));
+        };
+
+        let position = self.index()?.entries[block_pos].position;
+        let block = match self.cached_block(position) {
+            Some(block) => block,
+            None => {
+                self.counters.sstable_files_opened.add(1);
+                let mut file = tokio::fs::File::open(&self.path).await?;
+                let block = read_block_async(&mut file, &self.path, self.compression, position, self.data_end).await?;
+                self.cache_block(position, block)
+            }
+        };
+        Ok(self.search_block(&block, block_pos, search_key)?.or_else(|| self.range_deleted(search_key)))
+    }
+
+    /// Looks up several keys, which must be sorted, reading each block at most once in a
+    /// single forward pass. Returns the entry for each key in order.
+    pub fn get_entries(&self, search_keys: &[&K]) -> Result<Vec<Option<Entry<V>>>> {
+        let entries = self.get_entries_sequenced(search_keys)?;
+        Ok(entries.into_iter().map(|entry| entry.map(|entry| entry.entry)).collect())
+    }
+
+    /// Like `get_entries`, with the sequence number of each entry
+    pub(crate) fn get_entries_sequenced(&self, search_keys: &[&K]) -> Result<Vec<Option<Sequenced<Entry<V>>>>> {
+        let mut current_block: Option<(usize, Arc<Vec<u8>>)> = None;
+        let mut entries = Vec::with_capacity(search_keys.len());
+
+        for &search_key in search_keys {
+            let Some(block_pos) = self.locate_block(search_key)? else {
+                entries.push(self.range_deleted(search_key));
+                continue;
+            };
+
+            if current_block.as_ref().is_none_or(|(pos, _)| *pos != block_pos) {
+                current_block = Some((block_pos, self.read_indexed_block(block_pos)?));
+            }
+            if let Some((_, block)) = &current_block {
+                let entry = self.search_block(block, block_pos, search_key)?;
+                entries.push(entry.or_else(|| self.range_deleted(search_key)));
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Finds the only block that can hold the key: the one whose first key is the greatest
+    /// not above it. Returns `None` if the bloom filter or key range rule the key out; the
+    /// index isn't loaded for a key the bloom filter rules out.
+    fn locate_block(&self, search_key: &K) -> Result<Option<usize>> {
+        if self.bloom_rules_out(search_key)? {
+            return Ok(None);
+        }
+        let index = self.index()?;
+        if !self.in_key_range(index, search_key) {
+            return Ok(None);
+        }
+
+        let block_pos = match index.entries.binary_search_by(|entry| self.comparator.compare(&entry.key, search_key)) {
+            Ok(pos) => return Ok(Some(pos)),
+            Err(0) => return Ok(None),
+            Err(pos) => pos - 1,
+        };
+        // The key may fall in the gap after the block's last key
+        let in_block = self.comparator.compare(search_key, &index.entries[block_pos].last_key).is_le();
+        Ok(in_block.then_some(block_pos))
+    }
+
+    /// Reads a block through the shared file handle
+    fn read_indexed_block(&self, block_pos: usize) -> Result<Arc<Vec<u8>>> {
+        let position = self.index()?.entries[block_pos].position;
+        if let Some(block) = self.cached_block(position) {
+            return Ok(block);
+        }
+        let file = match &self.file {
+            FileHandle::Owned(file) => Arc::clone(file),
+            FileHandle::Pooled(pool, id) => pool.acquire(*id, || {
+                self.counters.sstable_files_opened.add(1);
+                std::fs::File::open(&self.path)
+            })?,
+        };
+        // Every read seeks first, so a panic while the lock was held can't leave a bad position
+        let mut file = file.lock().unwrap_or_else(PoisonError::into_inner);
+        file.seek(std::io::SeekFrom::Start(position))?;
+        let (block, _) = read_block(&mut *file, &self.path, self.compression, position, self.data_end)?;
+        Ok(self.cache_block(position, block))
+    }
+
+    /// Looks up the block at `position` in the block cache, if the table has one
+    fn cached_block(&self, position: u64) -> Option<Arc<Vec<u8>>> {
+        let (cache, id) = self.block_cache.as_ref()?;
+        let block = cache.get(*id, position);
+        match block {
+            Some(_) => self.counters.block_cache_hits.add(1),
+            None => self.counters.block_cache_misses.add(1),
+        }
+        block
+    }
+
+    fn cache_block(&self, position: u64, block: Vec<u8>) -> Arc<Vec<u8>> {
+        let block = Arc::new(block);
+        if let Some((cache, id)) = &self.block_cache {
+            cache.insert(*id, position, Arc::clone(&block));
+        }
+        block
+    }
+
+    fn search_block(&self, block: &[u8], block_pos: usize, search_key: &K) -> Result<Option<Sequenced<Entry<V>>>> {
+        self.find_in_block(block, block_pos, search_key)?
+            .map(|(record, _)| Ok(Sequenced::new(self.encoding.deserialize(record.entry)?, record.sequence)))
+            .transpose()
+    }
+
+    /// Returns the record stored for the key in the block, if any, with its offset in the block
+    fn find_in_block<'b>(
+        &self,
+        block: &'b [u8],
+        block_pos: usize,
+        search_key: &K,
+    ) -> Result<Option<(RawRecord<'b>, usize)>> {
+        let first = &self.index()?.entries[block_pos];
+        let corruption = || LSMError::Corruption {
+            path: self.path.display().to_string(),
+            offset: first.position,
+        };
+        let search_bytes = self.key_encoding().map(|keys| keys.encode(search_key));
+        let first_bytes = self.key_encoding().map(|keys| keys.encode(&first.key));
+        let mut offset = 0;
+        while offset < block.len() {
+            let (record, len) = RawRecord::parse(&block[offset..]).ok_or_else(corruption)?;
+            if !record.is_valid() {
+                return Err(corruption());
+            }
+
+            // The block must start with the key the index was built from; anything else means
+            // the file no longer matches the index, and an exact index hit would return the
+            // wrong key's value
+            if offset == 0 && self.compare_stored(record.key, &first.key, first_bytes.as_deref())?.is_ne() {
+                return Err(corruption());
+            }
+            match self.compare_stored(record.key, search_key, search_bytes.as_deref())? {
+                std::cmp::Ordering::Equal => return Ok(Some((record, offset))),
+                std::cmp::Ordering::Greater => return Ok(None),
+                std::cmp::Ordering::Less => offset += len,
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The encoding record keys are stored in, if they aren't serialized with `encoding`
+    fn key_encoding(&self) -> Option<&dyn KeyEncoding<K>> {
+        self.comparator.key_encoding().filter(|_| self.ordered_keys)
+    }
+
+    /// Compares a stored record key with `key`. With an ordered key encoding, `encoded` is
+    /// `key` in it and the bytes are compared as is; otherwise the stored key is deserialized.
+    fn compare_stored(&self, stored: &[u8], key: &K, encoded: Option<&[u8]>) -> Result<std::cmp::Ordering> {
+        match encoded {
+            Some(encoded) => Ok(stored.cmp(encoded)),
+            None => Ok(self.comparator.compare(&self.encoding.deserialize(stored)?, key)),
+        }
+    }
+}
+
+/// Fails with `SSTableExists` if there is a file at `path`: tables are never replaced, so one
+/// there means its id was handed out twice
+fn ensure_absent(path: &Path) -> Result<()> {
+    match path.try_exists()? {
+        true => Err(LSMError::SSTableExists { path: path.display().to_string() }),
+        false => Ok(()),
+    }
+}
+
+/// A file written under a temporary name, deleted on drop unless it was persisted
+struct TempFile {
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl TempFile {
+    /// Renames the file to `path` and syncs the directory so the rename is durable
+    fn persist(&mut self, path: &Path) -> Result<()> {
+        ensure_absent(path)?;
+        match std::fs::rename(&self.path, path) {
+            Ok(()) => self.persisted = true,
+            Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+                // Copied next to `path` first, so that a file at `path` is still never partial
+                let mut staged = TempFile {
+                    path: tmp_path(path),
+                    persisted: false,
+                };
+                std::fs::copy(&self.path, &staged.path)?;
+                std::fs::File::open(&staged.path)?.sync_all()?;
+                staged.persist(path)?;
+                std::fs::remove_file(&self.path)?;
+                self.persisted = true;
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        }
+        sync_parent_dir(path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            if let Err(e) = std::fs::remove_file(&self.path) {
+                log::warn!("Failed to delete unfinished SSTable {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+/// Writes sorted entries to a new SSTable file one at a time, so that tables can be
+/// produced from a stream (e.g. a compaction merge or external sorted data) without knowing
+/// the entry count up front. `finish` completes the file and opens it as an `SSTable`.
+///
+/// The table is written to `<path>.tmp`, or to a file of that name in `SSTableOptions::temp_dir`,
+/// and only renamed to `path` once it is complete and synced, so a file at `path` is never
+/// partial. A temporary directory on another filesystem than `path` can't be renamed from:
+/// the table is then copied to `<path>.tmp`, synced and renamed from there, so finishing it
+/// costs a second write. A writer dropped without `finish` deletes its temporary file.
+/// Existing files are never overwritten: creating or finishing a table whose path is taken
+/// fails with `LSMError::SSTableExists`.
+///
+/// Keys are cloned once each, to check the order of the next one, and the first and last keys
+/// of every block once more for the sparse index. Reading a table never clones keys.
+pub struct SSTableWriter<K, V> {
+    path: PathBuf,
+    tmp: TempFile,
+    writer: ChecksumWriter<std::io::BufWriter<std::fs::File>>,
+    index: Vec<IndexEntry<K>>,
+    entry_count: u64,
+    /// Offset at which the next block will be written
+    position: u64,
+    /// Uncompressed records of the block being built
+    block: Vec<u8>,
+    block_records: u64,
+    block_first_key: Option<K>,
+    index_interval: u64,
+    block_size: usize,
+    compression: Compression,
+    encoding: Encoding,
+    bloom_bits_per_key: usize,
+    bloom_hasher: BloomHasher,
+    key_hashes: Vec<u64>,
+    last_key: Option<K>,
+    range_tombstones: Vec<RangeTombstone<K>>,
+    max_sequence: u64,
+    comparator: Arc<dyn Comparator<K>>,
+    _phantom: std::marker::PhantomData<V>,
+}
+
+impl<K, V> SSTableWriter<K, V>
+where
+    K: Ord + serde::Serialize + for<'de> serde::Deserialize<'de> + Clone,
+    V: serde::Serialize + for<'de> serde::Deserialize<'de>,
+{
+    /// Creates a table at `path` for keys in their natural order
+    pub fn create(path: impl Into<PathBuf>, options: &SSTableOptions) -> Result<Self> {
+        Self::create_with_comparator(path, options, Arc::new(NaturalOrder))
+    }
+
+    /// Creates a table for keys ordered by `comparator`, which must be passed to
+    /// `SSTable::open_with_comparator` to read it back
+    pub fn create_with_comparator(
+        path: impl Into<PathBuf>,
+        options: &SSTableOptions,
+        comparator: Arc<dyn Comparator<K>>,
+    ) -> Result<Self> {
+        if options.index_interval == 0 || options.block_size == 0 || options.write_buffer_bytes == 0 {
+            let reason = "index_interval, block_size and write_buffer_bytes must be non-zero";
+            return Err(LSMError::InvalidConfig(reason.to_string()));
+        }
+        if !options.compression.is_supported() {
+            return Err(LSMError::UnsupportedCompression(options.compression));
+        }
+
+        let path = path.into();
+        ensure_absent(&path)?;
+        // Another writer for the same path fails here, before it could remove the file on drop
+        let mut tmp_file = tmp_path(&path);
+        if let Some(dir) = &options.temp_dir {
+            tmp_file = dir.join(tmp_file.file_name().unwrap_or_default());
+        }
+        let file = std::fs::OpenOptions::new().write(true).create_new(true).open(&tmp_file)?;
+        let tmp = TempFile {
+            path: tmp_file,
+            persisted: false,
+        };
+        let mut writer = ChecksumWriter {
+            inner: std::io::BufWriter::with_capacity(options.write_buffer_bytes, file),
+            hasher: Xxh3::new(),
+        };
+
+        bincode::serialize_into(&mut writer, &options.index_interval)?;
+        bincode::serialize_into(&mut writer, &options.compression.id())?;
+        let ordered_keys = if comparator.key_encoding().is_some() { ORDERED_KEYS } else { 0 };
+        bincode::serialize_into(&mut writer, &(options.encoding.id() | ordered_keys))?;
+
+        Ok(Self {
+            path,
+            tmp,
+            writer,
+            index: Vec::new(),
+            entry_count: 0,
+            position: HEADER_LEN,
+            block: Vec::new(),
+            block_records: 0,
+            block_first_key: None,
+            index_interval: options.index_interval,
+            block_size: options.block_size,
+            compression: options.compression,
+            encoding: options.encoding,
+            bloom_bits_per_key: match comparator.is_consistent_with_serialization() {
+                true => options.bloom_bits_per_key,
+                false => 0,
+            },
+            bloom_hasher: options.bloom_hasher,
+            key_hashes: Vec::new(),
+            last_key: None,
+            range_tombstones: Vec::new(),
+            max_sequence: 0,
+            comparator,
+            _phantom: std::marker::PhantomData,
+        })
+    }
+
+    /// Appends a value; keys must be added in strictly increasing order
+    pub fn add(&mut self, key: &K, value: &V) -> Result<()> {
+        self.add_entry(key, &Entry::Value(value))
+    }
+
+    /// Appends an entry, which may be a tombstone; keys must be added in strictly increasing
+    /// order. The entry's value may be anything that serializes like `V`, such as `Arc<V>`.
+    pub fn add_entry(&mut self, key: &K, entry: &Entry<impl serde::Serialize>) -> Result<()> {
+        self.add_entry_with_sequence(key, entry, 0)
+    }
+
+    /// Like `add_entry`, for an entry made by the write with sequence number `sequence`
+    pub fn add_entry_with_sequence(
+        &mut self,
+        key: &K,
+        entry: &Entry<impl serde::Serialize>,
+        sequence: u64,
+    ) -> Result<()> {
+        if self.last_key.as_ref().is_some_and(|last| self.comparator.compare(key, last).is_le()) {
+            return Err(LSMError::UnsortedKeys);
+        }
+        if self.block_records == 0 {
+            self.block_first_key = Some(key.clone());
+        }
+
+        let key_bytes = self.encoding.serialize(key)?;
+        let entry_bytes = self.encoding.serialize(entry)?;
+        if self.bloom_bits_per_key > 0 {
+            self.key_hashes.push(self.bloom_hasher.hash(&key_bytes));
+        }
+        // The bloom filter hashes keys serialized, as lookups do, whatever they are stored as
+        let key_bytes = match self.comparator.key_encoding() {
+            Some(keys) => keys.encode(key),
+            None => key_bytes,
+        };
+
+        RawRecord::write(&mut self.block, &key_bytes, &entry_bytes, sequence);
+        self.max_sequence = self.max_sequence.max(sequence);
+        self.last_key = Some(key.clone());
+        self.block_records += 1;
+        self.entry_count += 1;
+
+        if self.block_records >= self.index_interval || self.block.len() >= self.block_size {
+            self.flush_block()?;
+        }
+
+        Ok(())
+    }
+
+    fn flush_block(&mut self) -> Result<()> {
+        let (Some(key), Some(last_key)) = (self.block_first_key.take(), self.last_key.as_ref()) else {
+            return Ok(());
+        };
+        self.index.push(IndexEntry {
+            key,
+            last_key: last_key.clone(),
+            position: self.position,
+            records: self.block_records,
+        });
+
+        let payload = self.compression.compress(&self.block)?;
+        self.writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&payload)?;
+        self.position += 4 + payload.len() as u64;
+
+        self.block.clear();
+        self.block_records = 0;
+        Ok(())
+    }
+
+    /// Adds a range tombstone, covering keys of older tables. The entries of the table in its
+    /// range must be newer than it.
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone<K>) {
+        self.max_sequence = self.max_sequence.max(tombstone.sequence);
+        self.range_tombstones.push(tombstone);
+    }
+
+    /// Reserves room for the bloom filter hashes of `additional` more keys
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        if self.bloom_bits_per_key > 0 {
+            self.key_hashes.reserve(additional);
+        }
+    }
+
+    /// Bytes written so far, counting the block being built as uncompressed
+    pub fn size(&self) -> u64 {
+        self.position + self.block.len() as u64
+    }
+
+    /// Writes the bloom filter and footer, syncs the file and returns it opened as a table
+    pub fn finish(mut self) -> Result<SSTable<K, V>> {
+        self.flush_block()?;
+
+        let data_end = self.position;
+        let bloom = (self.bloom_bits_per_key > 0)
+            .then(|| BloomFilter::from_hashes(&self.key_hashes, self.bloom_bits_per_key));
+        bincode::serialize_into(&mut self.writer, &bloom)?;
+        let range_tombstones = self
+            .range_tombstones
+            .iter()
+            .map(|tombstone| {
+                let encoding = &self.encoding;
+                let (start, end) = (encoding.serialize(&tombstone.start)?, encoding.serialize(&tombstone.end)?);
+                Ok(RangeTombstone { start, end, sequence: tombstone.sequence })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        bincode::serialize_into(&mut self.writer, &range_tombstones)?;
+        bincode::serialize_into(&mut self.writer, &self.max_sequence)?;
+        bincode::serialize_into(&mut self.writer, &self.bloom_hasher.id())?;
+        let index_offset = self.writer.inner.stream_position()?;
+        let index = self
+            .index
+            .iter()
+            .map(|entry| {
+                Ok(IndexEntry {
+                    key: self.encoding.serialize(&entry.key)?,
+                    last_key: self.encoding.serialize(&entry.last_key)?,
+                    position: entry.position,
+                    records: entry.records,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let index = bincode::serialize(&index)?;
+        self.writer.write_all(&index)?;
+        self.writer.write_all(&crc32fast::hash(&index).to_le_bytes())?;
+        let checksum = self.writer.hasher.digest();
+
+        let writer = &mut self.writer.inner;
+        let trailer_end = writer.stream_position()?;
+        writer.write_all(&checksum.to_le_bytes())?;
+        writer.write_all(&self.entry_count.to_le_bytes())?;
+        writer.write_all(&data_end.to_le_bytes())?;
+        writer.write_all(&index_offset.to_le_bytes())?;
+        writer.write_all(&[FORMAT_VERSION])?;
+        writer.write_all(&MAGIC)?;
+        writer.flush()?;
+        // The table must be durable before it gets its final name and a manifest lists it
+        writer.get_ref().sync_all()?;
+        let file_size = writer.get_ref().metadata()?.len();
+        self.tmp.persist(&self.path)?;
+        let file = std::fs::File::open(&self.path)?;
+
+        Ok(SSTable {
+            path: self.path,
+            index: OnceLock::from(Index { entries: self.index }),
+            bloom,
+            bloom_hasher: self.bloom_hasher,
+            entry_count: self.entry_count,
+            index_interval: self.index_interval,
+            compression: self.compression,
+            encoding: self.encoding,
+            data_end,
+            index_offset,
+            trailer_end,
+            file_size,
+            checksum,
+            range_tombstones: self.range_tombstones,
+            ordered_keys: self.comparator.key_encoding().is_some(),
+            max_sequence: self.max_sequence,
+            file: FileHandle::Owned(Arc::new(Mutex::new(file))),
+            read_buffer_bytes: DEFAULT_BUFFER_BYTES,
+            comparator: self.comparator,
+            counters: Arc::default(),
+            block_cache: None,
+            obsolete: AtomicBool::new(false),
+            _phantom: std::marker::PhantomData,
+        })
+    }
+}
+
+/// Sequential reader over the entries of an SSTable, decompressing one block at a time
+pub(crate) struct SSTableEntries<K, V> {
+    reader: std::io::BufReader<std::fs::File>,
+    path: PathBuf,
+    compression: Compression,
+    encoding: Encoding,
+    next_block: u64,
+    data_end: u64,
+    /// Contents of the current block and the read offset within it
+    block: Vec<u8>,
+    block_position: u64,
+    offset: usize,
+    done: bool,
+    decode: DecodeEntry<V>,
+    /// The comparator whose key encoding record keys are stored in, if any
+    ordered_keys: Option<Arc<dyn Comparator<K>>>,
+}
+
+impl<K, V> Iterator for SSTableEntries<K, V>
+where
+    K: for<'de> serde::Deserialize<'de>,
+    V: for<'de> serde::Deserialize<'de>,
+{
+    type Item = Result<(K, Sequenced<Entry<V>>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let result = self.read_entry().transpose();
+        if !matches!(result, Some(Ok(_))) {
+            self.done = true;
+        }
+        result
+    }
+}
+
+impl<K, V> SSTableEntries<K, V>
+where
+    K: for<'de> serde::Deserialize<'de>,
+    V: for<'de> serde::Deserialize<'de>,
+{
+    fn read_entry(&mut self) -> Result<Option<(K, Sequenced<Entry<V>>)>> {
+        if self.offset >= self.block.len() {
+            if self.next_block >= self.data_end {
+                return Ok(None);
+            }
+            let (block, next) = read_block(
+                &mut self.reader,
+                &self.path,
+                self.compression,
+                self.next_block,
+                self.data_end,
+            )?;
+            self.block = block;
+            self.block_position = self.next_block;
+            self.next_block = next;
+            self.offset = 0;
+        }
+
+        let data = &self.block[self.offset..];
+        let keys = self.ordered_keys.as_deref().and_then(|comparator| comparator.key_encoding());
+        let (record, len) = decode_record(data, self.encoding, keys, self.decode, &self.path, self.block_position)?;
+        self.offset += len;
+
+        Ok(Some(record))
+    }
+}
+
+/// A decoded record: the key, and the entry with its sequence number
+type Record<K, V> = (K, Sequenced<Entry<V>>);
+
+/// Decodes the serialized entry of a record, or only part of it
+pub(crate) type DecodeEntry<T> = fn(Encoding, &[u8]) -> Result<Entry<T>>;
+
+/// Decodes an entry in full
+pub(crate) fn decode_entry<V: for<'de> serde::Deserialize<'de>>(encoding: Encoding, entry: &[u8]) -> Result<Entry<V>> {
+    encoding.deserialize(entry)
+}
+
+/// Decodes what kind of entry a record holds, and its expiry time if any, without decoding the
+/// value. Bincode entries start with their variant index: only an expiring entry or merge
+/// operands are decoded in full, since the expiry time comes after the value. MessagePack is
+/// self-describing, so the value can be skipped without decoding it.
+pub(crate) fn decode_kind<V: for<'de> serde::Deserialize<'de>>(encoding: Encoding, entry: &[u8]) -> Result<Entry<()>> {
+    if encoding.format == Format::MessagePack {
+        return Ok(encoding.deserialize::<Entry<IgnoredAny>>(entry)?.map(|_| ()));
+    }
+    Ok(match encoding.deserialize::<u32>(entry)? {
+        0 => Entry::Value(()),
+        1 => Entry::Tombstone,
+        _ => encoding.deserialize::<Entry<V>>(entry)?.map(|_| ()),
+    })
+}
+
+/// Decodes the record at the start of `data`, part of the block read from `block_position`,
+/// with `decode` for its entry, returning it with its length
+fn decode_record<K, V>(
+    data: &[u8],
+    encoding: Encoding,
+    keys: Option<&dyn KeyEncoding<K>>,
+    decode: DecodeEntry<V>,
+    path: &Path,
+    block_position: u64,
+) -> Result<(Record<K, V>, usize)>
+where
+    K: for<'de> serde::Deserialize<'de>,
+    V: for<'de> serde::Deserialize<'de>,
+{
+    let corruption = || LSMError::Corruption {
+        path: path.display().to_string(),
+        offset: block_position,
+    };
+    let (record, len) = RawRecord::parse(data).ok_or_else(corruption)?;
+    if !record.is_valid() {
+        return Err(corruption());
+    }
+    let key = decode_key(encoding, keys, record.key, corruption)?;
+    let entry = decode(encoding, record.entry)?;
+
+    Ok(((key, Sequenced::new(entry, record.sequence)), len))
+}
+
+/// Decodes a record key, stored in `keys` if the table has an ordered key encoding and
+/// serialized with `encoding` otherwise
+fn decode_key<K>(
+    encoding: Encoding,
+    keys: Option<&dyn KeyEncoding<K>>,
+    bytes: &[u8],
+    corruption: impl Fn() -> LSMError,
+) -> Result<K>
+where
+    K: for<'de> serde::Deserialize<'de>,
+{
+    match keys {
+        Some(keys) => keys.decode(bytes).ok_or_else(corruption),
+        None => encoding.deserialize(bytes),
+    }
+}
+
+fn is_before_start<K>(comparator: &dyn Comparator<K>, key: &K, start: &Bound<K>) -> bool {
+    match start {
+        Bound::Included(start) => comparator.compare(key, start).is_lt(),
+        Bound::Excluded(start) => comparator.compare(key, start).is_le(),
+        Bound::Unbounded => false,
+    }
+}
+
+fn is_past_end<K>(comparator: &dyn Comparator<K>, key: &K, end: &Bound<K>) -> bool {
+    match end {
+        Bound::Included(end) => comparator.compare(key, end).is_gt(),
+        Bound::Excluded(end) => comparator.compare(key, end).is_ge(),
+        Bound::Unbounded => false,
+    }
+}
+
+/// Iterator over the entries of an SSTable within a key range
+pub struct SSTableRange<K, V> {
+    entries: SSTableEntries<K, V>,
+    start: Bound<K>,
+    end: Bound<K>,
+    comparator: Arc<dyn Comparator<K>>,
+}
+
+impl<K, V> SSTableRange<K, V>
+where
+    K: Ord + for<'de> serde::Deserialize<'de>,
+    V: for<'de> serde::Deserialize<'de>,
+{
+    /// Yields the entries with their sequence numbers
+    pub(crate) fn sequenced(mut self) -> impl Iterator<Item = Result<(K, Sequenced<Entry<V>>)>> {
+        std::iter::from_fn(move || self.next_sequenced())
+    }
+
+    fn next_sequenced(&mut self) -> Option<Result<(K, Sequenced<Entry<V>>)>> {
+        loop {
+            let (key, entry) = match self.entries.next()? {
+                Ok(record) => record,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if is_before_start(&*self.comparator, &key, &self.start) {
+                continue;
+            }
+            if is_past_end(&*self.comparator, &key, &self.end) {
+                self.entries.done = true;
+                return None;
+            }
+
+            return Some(Ok((key, entry)));
+        }
+    }
+}
+
+impl<K, V> Iterator for SSTableRange<K, V>
+where
+    K: Ord + for<'de> serde::Deserialize<'de>,
+    V: for<'de> serde::Deserialize<'de>,
+{
+    type Item = Result<(K, Entry<V>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_sequenced()?.map(|(key, entry)| (key, entry.entry)))
+    }
+}
+
+/// Iterator over the entries of an SSTable within a key range, in descending key order
+pub struct SSTableRevRange<K, V> {
+    reader: std::io::BufReader<std::fs::File>,
+    path: PathBuf,
+    compression: Compression,
+    encoding: Encoding,
+    data_end: u64,
+    /// Positions of the blocks still to read, the next one last
+    blocks: Vec<u64>,
+    /// Entries of the current block within the bounds, the next one last
+    buffered: Vec<(K, Sequenced<Entry<V>>)>,
+    start: Bound<K>,
+    end: Bound<K>,
+    comparator: Arc<dyn Comparator<K>>,
+    /// Whether record keys are stored in the comparator's key encoding
+    ordered_keys: bool,
+    decode: DecodeEntry<V>,
+}
+
+impl<K, V> SSTableRevRange<K, V>
+where
+    K: for<'de> serde::Deserialize<'de>,
+    V: for<'de> serde::Deserialize<'de>,
+{
+    /// Decodes the block at `position`, buffering its entries within the bounds
+    fn read_block_at(&mut self, position: u64) -> Result<()> {
+        self.reader.seek(std::io::SeekFrom::Start(position))?;
+        let (block, _) = read_block(&mut self.reader, &self.path, self.compression, position, self.data_end)?;
+
+        let mut offset = 0;
+        while offset < block.len() {
+            let data = &block[offset..];
+            let keys = self.comparator.key_encoding().filter(|_| self.ordered_keys);
+            let ((key, entry), len) = decode_record(data, self.encoding, keys, self.decode, &self.path, position)?;
+            // Earlier blocks only hold keys smaller than this block's first one
+            if offset == 0 && matches!(&self.start, Bound::Included(start) | Bound::Excluded(start)
+                if self.comparator.compare(&key, start).is_le())
+            {
+                self.blocks.clear();
+            }
+            offset += len;
+            if is_before_start(&*self.comparator, &key, &self.start) {
+                continue;
+            }
+            if is_past_end(&*self.comparator, &key, &self.end) {
+                break;
+            }
+            self.buffered.push((key, entry));
+        }
+        Ok(())
+    }
+
+    /// Yields the entries with their sequence numbers
+    pub(crate) fn sequenced(mut self) -> impl Iterator<Item = Result<(K, Sequenced<Entry<V>>)>> {
+        std::iter::from_fn(move || self.next_sequenced())
+    }
+
+    fn next_sequenced(&mut self) -> Option<Result<(K, Sequenced<Entry<V>>)>> {
+        loop {
+            if let Some(record) = self.buffered.pop() {
+                return Some(Ok(record));
+            }
+            let position = self.blocks.pop()?;
+            if let Err(e) = self.read_block_at(position) {
+                self.blocks.clear();
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+impl<K, V> Iterator for SSTableRevRange<K, V>
+where
+    K: for<'de> serde::Deserialize<'de>,
+    V: for<'de> serde::Deserialize<'de>,
+{
+    type Item = Result<(K, Entry<V>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_sequenced()?.map(|(key, entry)| (key, entry.entry)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_sstable_send_sync() {
+        assert_send_sync::<SSTable<String, String>>();
+        assert_send_sync::<Arc<SSTable<Vec<u8>, Vec<u8>>>>();
+        assert_send_sync::<SSTableWriter<String, String>>();
+        assert_send_sync::<SSTableRange<String, String>>();
+        assert_send_sync::<SSTableRevRange<String, String>>();
+    }
+
+    #[test]
+    fn test_sstable_basic_operations() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test.sst");
+        
+        let mut memtable = MemTable::new();
+        memtable.put(5, "five".to_string())?;
+        memtable.put(3, "three".to_string())?;
+        memtable.put(7, "seven".to_string())?;
+        memtable.put(1, "one".to_string())?;
+        memtable.put(9, "nine".to_string())?;
+        
+        let sstable = SSTable::from_memtable(&memtable, path)?;
+        
+        assert_eq!(sstable.get(&1)?, Some("one".to_string()));
+        assert_eq!(sstable.get(&3)?, Some("three".to_string()));
+        assert_eq!(sstable.get(&5)?, Some("five".to_string()));
+        assert_eq!(sstable.get(&7)?, Some("seven".to_string()));
+        assert_eq!(sstable.get(&9)?, Some("nine".to_string()));
+        
+        assert_eq!(sstable.get(&0)?, None);
+        assert_eq!(sstable.get(&2)?, None);
+        assert_eq!(sstable.get(&6)?, None);
+        assert_eq!(sstable.get(&8)?, None);
+        assert_eq!(sstable.get(&10)?, None);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_large_dataset() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_large.sst");
+        
+        let mut memtable = MemTable::new();
+        for i in 0..1000 {
+            memtable.put(i, format!("value_{}", i))?;
+        }
+        
+        let sstable = SSTable::from_memtable(&memtable, path)?;
+        
+        assert_eq!(sstable.get(&42)?, Some("value_42".to_string()));
+        assert_eq!(sstable.get(&999)?, Some("value_999".to_string()));
+        assert_eq!(sstable.get(&500)?, Some("value_500".to_string()));
+        
+        assert_eq!(sstable.get(&0)?, Some("value_0".to_string()));
+        assert_eq!(sstable.get(&999)?, Some("value_999".to_string()));
+        assert_eq!(sstable.get(&1000)?, None);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_empty() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_empty.sst");
+        
+        let memtable = MemTable::<i32, i32>::new();
+        let sstable = SSTable::from_memtable(&memtable, path)?;
+        
+        assert_eq!(sstable.get(&1)?, None);
+        assert_eq!(sstable.key_range()?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_string_keys() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_strings.sst");
+        
+        let mut memtable = MemTable::<String, i32>::new();
+        memtable.put("apple".to_string(), 1)?;
+        memtable.put("banana".to_string(), 2)?;
+        memtable.put("cherry".to_string(), 3)?;
+        
+        let sstable = SSTable::from_memtable(&memtable, path)?;
+        
+        assert_eq!(sstable.get(&"aaa".to_string())?, None);
+        
+        assert_eq!(sstable.get(&"apple".to_string())?, Some(1));
+        assert_eq!(sstable.get(&"banana".to_string())?, Some(2));
+        assert_eq!(sstable.get(&"cherry".to_string())?, Some(3));
+        assert_eq!(sstable.get(&"date".to_string())?, None);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_open() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_open.sst");
+
+        let mut memtable = MemTable::new();
+        for i in 0..100 {
+            memtable.put(i, format!("value_{}", i))?;
+        }
+        memtable.delete(42)?;
+        let written = SSTable::from_memtable(&memtable, path.clone())?;
+
+        let reopened = SSTable::<i32, String>::open(path)?;
+        assert_eq!(reopened.entry_count(), 100);
+        assert_eq!(reopened.key_range()?, Some((&0, &99)));
+        assert_eq!(written.key_range()?, Some((&0, &99)));
+        assert!(reopened.may_contain_key(&50)?);
+        assert!(!reopened.may_contain_key(&100)?);
+        assert_eq!(reopened.index()?.entries.len(), written.index()?.entries.len());
+        for (a, b) in reopened.index()?.entries.iter().zip(&written.index()?.entries) {
+            assert_eq!(a.key, b.key);
+            assert_eq!(a.position, b.position);
+        }
+
+        assert_eq!(reopened.get(&0)?, Some("value_0".to_string()));
+        assert_eq!(reopened.get(&99)?, Some("value_99".to_string()));
+        assert_eq!(reopened.get_entry(&42)?, Some(Entry::Tombstone));
+        assert_eq!(reopened.get(&100)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_bloom_filter() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_bloom.sst");
+
+        let mut memtable = MemTable::new();
+        for i in 0..100 {
+            memtable.put(i * 2, format!("value_{}", i))?;
+        }
+        let sstable = SSTable::from_memtable(&memtable, path.clone())?;
+        assert!(sstable.bloom.is_some());
+
+        let reopened = SSTable::<i32, String>::open(path)?;
+        assert_eq!(reopened.bloom, sstable.bloom);
+        for i in 0..100 {
+            assert_eq!(reopened.get(&(i * 2))?, Some(format!("value_{}", i)));
+            assert_eq!(reopened.get(&(i * 2 + 1))?, None);
+        }
+
+        // Lookups hash with the function the filter was built with
+        let path = dir.path().join("test_bloom_xxh3.sst");
+        let options = SSTableOptions { bloom_hasher: BloomHasher::Xxh3, ..SSTableOptions::default() };
+        SSTable::from_memtable_with_options(&memtable, path.clone(), &options)?;
+        let reopened = SSTable::<i32, String>::open(path)?;
+        assert_eq!(reopened.bloom_hasher, BloomHasher::Xxh3);
+        assert_ne!(reopened.bloom, sstable.bloom);
+        for i in 0..100 {
+            assert_eq!(reopened.get(&(i * 2))?, Some(format!("value_{}", i)));
+        }
+        assert_eq!(reopened.verify().errors, Vec::<String>::new());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_without_bloom_filter() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_no_bloom.sst");
+
+        let mut memtable = MemTable::new();
+        for i in 0..25 {
+            memtable.put(i, i)?;
+        }
+        let options = SSTableOptions {
+            bloom_bits_per_key: 0,
+            ..SSTableOptions::default()
+        };
+        let sstable = SSTable::from_memtable_with_options(&memtable, path.clone(), &options)?;
+        assert!(sstable.bloom.is_none());
+
+        let reopened = SSTable::<i32, i32>::open(path)?;
+        assert!(reopened.bloom.is_none());
+        assert_eq!(reopened.get(&24)?, Some(24));
+        assert_eq!(reopened.get(&25)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_range() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_range.sst");
+
+        let mut memtable = MemTable::new();
+        for i in 0..100 {
+            memtable.put(i, i)?;
+        }
+        memtable.delete(15)?;
+        let sstable = SSTable::from_memtable(&memtable, path)?;
+
+        let keys = |start, end| -> Result<Vec<i32>> {
+            sstable.range(start, end)?.map(|item| item.map(|(k, _)| k)).collect()
+        };
+
+        assert_eq!(keys(Bound::Included(12), Bound::Excluded(17))?, vec![12, 13, 14, 15, 16]);
+        assert_eq!(keys(Bound::Excluded(10), Bound::Included(12))?, vec![11, 12]);
+        assert_eq!(keys(Bound::Unbounded, Bound::Excluded(3))?, vec![0, 1, 2]);
+        assert_eq!(keys(Bound::Included(97), Bound::Unbounded)?, vec![97, 98, 99]);
+        assert_eq!(keys(Bound::Included(-5), Bound::Included(0))?, vec![0]);
+        assert_eq!(keys(Bound::Included(150), Bound::Unbounded)?, Vec::<i32>::new());
+        assert_eq!(keys(Bound::Unbounded, Bound::Unbounded)?.len(), 100);
+
+        let tombstone = sstable.range(Bound::Included(15), Bound::Included(15))?.next().unwrap()?;
+        assert_eq!(tombstone, (15, Entry::Tombstone));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_range_bounds() -> Result<()> {
+        use std::ops::RangeBounds;
+
+        let dir = tempdir()?;
+        let path = dir.path().join("test_range_bounds.sst");
+        let options = SSTableOptions {
+            index_interval: 4,
+            ..SSTableOptions::default()
+        };
+        // Blocks hold 0 to 6, 8 to 14 and so on: bounds fall on the first and last keys of
+        // blocks, between blocks and between keys
+        let sstable = SSTable::from_sorted((0..50).map(|i| (i * 2, i)), path, &options)?;
+        let all: Vec<i32> = (0..50).map(|i| i * 2).collect();
+
+        let bounds = |key| [Bound::Included(key), Bound::Excluded(key), Bound::Unbounded];
+        for low in [-1, 0, 1, 6, 7, 8, 14, 15, 16, 97, 98, 99] {
+            for high in [-1, 0, 6, 7, 8, 9, 14, 16, 98, 120] {
+                for (start, end) in bounds(low).into_iter().flat_map(|start| bounds(high).map(|end| (start, end))) {
+                    let keys: Vec<_> =
+                        sstable.range(start, end)?.map(|item| item.map(|(k, _)| k)).collect::<Result<_>>()?;
+                    let expected: Vec<_> = all.iter().copied().filter(|key| (start, end).contains(key)).collect();
+                    assert_eq!(keys, expected, "{:?}..{:?}", start, end);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_range_rev() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_range_rev.sst");
+        let options = SSTableOptions {
+            index_interval: 4,
+            ..SSTableOptions::default()
+        };
+        // Even keys only, so bounds fall both on keys and between them, and on block boundaries
+        let sstable = SSTable::from_sorted((0..50).map(|i| (i * 2, i)), path, &options)?;
+
+        let bounds = |key| [Bound::Included(key), Bound::Excluded(key), Bound::Unbounded];
+        for low in [-1, 0, 7, 8, 9, 40, 98, 99] {
+            for high in [-1, 0, 8, 31, 32, 98, 120] {
+                for (start, end) in bounds(low).into_iter().flat_map(|start| bounds(high).map(|end| (start, end))) {
+                    let mut expected: Vec<_> = sstable.range(start, end)?.collect::<Result<_>>()?;
+                    expected.reverse();
+                    let reversed: Vec<_> = sstable.range_rev(start, end)?.collect::<Result<_>>()?;
+                    assert_eq!(reversed, expected, "{:?}..{:?}", start, end);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_iter() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_iter.sst");
+
+        let mut memtable = MemTable::new();
+        for i in 0..30 {
+            memtable.put(i, format!("value_{}", i))?;
+        }
+        memtable.delete(3)?;
+        SSTable::from_memtable(&memtable, path.clone())?;
+
+        let sstable = SSTable::<i32, String>::open(path.clone())?;
+        let pairs: Vec<_> = sstable.iter()?.collect::<Result<_>>()?;
+        assert_eq!(pairs.len(), 29);
+        assert_eq!(pairs[3], (4, "value_4".to_string()));
+
+        // Corruption is reported, and ends the iteration
+        corrupt_byte(&path, sstable.index()?.entries[1].position)?;
+        let items: Vec<_> = sstable.iter()?.collect();
+        assert!(matches!(items.last(), Some(Err(LSMError::Corruption { .. }))));
+        assert!(items[..items.len() - 1].iter().all(|item| item.is_ok()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_estimate_range_count() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_estimate.sst");
+
+        let mut memtable = MemTable::new();
+        for i in 0..1000 {
+            memtable.put(i, i)?;
+        }
+        let sstable = SSTable::from_memtable(&memtable, path)?;
+        let interval = sstable.index_interval();
+
+        for (start, end) in [(0, 1000), (100, 300), (555, 556), (990, 5000), (-50, 10)] {
+            let estimate = sstable.estimate_range_count(&start, &end)?;
+            let exact = (end.min(1000) - start.max(0)) as u64;
+            assert!(estimate.abs_diff(exact) <= interval, "[{}, {}): {}", start, end, estimate);
+        }
+        assert_eq!(sstable.estimate_range_count(&1000, &2000)?, 0);
+        assert_eq!(sstable.estimate_range_count(&300, &100)?, 0);
+
+        Ok(())
+    }
+
+    /// Flips one byte inside the first record of the block at `offset`
+    fn corrupt_byte(path: &Path, offset: u64) -> Result<()> {
+        let mut bytes = std::fs::read(path)?;
+        // Skip the block length and the record's key length
+        bytes[offset as usize + 8] ^= 0xff;
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_checksum_mismatch() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_corrupt.sst");
+
+        let mut memtable = MemTable::new();
+        for i in 0..30 {
+            memtable.put(i, format!("value_{}", i))?;
+        }
+        let sstable = SSTable::from_memtable(&memtable, path.clone())?;
+
+        // Corrupt the first record of the second block (key 10)
+        let offset = sstable.index()?.entries[1].position;
+        corrupt_byte(&path, offset)?;
+
+        assert_eq!(sstable.get(&9)?, Some("value_9".to_string()));
+        for key in [10, 11] {
+            match sstable.get(&key) {
+                Err(LSMError::Corruption { path: p, offset: o }) => {
+                    assert_eq!(p, path.display().to_string());
+                    assert_eq!(o, offset);
+                }
+                other => panic!("expected corruption error, got {:?}", other),
+            }
+        }
+        assert!(sstable.entries()?.any(|item| matches!(item, Err(LSMError::Corruption { .. }))));
+
+        // Neither opening nor loading the stored index reads the blocks, so only reading the
+        // corrupted one fails
+        let reopened = SSTable::<i32, String>::open(path)?;
+        assert!(reopened.index.get().is_none());
+        assert_eq!(reopened.key_range()?, Some((&0, &29)));
+        assert_eq!(reopened.get(&0)?, Some("value_0".to_string()));
+        assert!(matches!(reopened.get(&10), Err(LSMError::Corruption { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_get_propagates_decode_errors() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_decode.sst");
+
+        let mut memtable = MemTable::new();
+        for i in 0..30 {
+            memtable.put(i, i)?;
+        }
+        SSTable::from_memtable(&memtable, path.clone())?;
+
+        // The records are intact, but their 4-byte values can't be read as strings
+        let mistyped = SSTable::<i32, String>::open(path)?;
+        assert!(matches!(mistyped.get(&5), Err(LSMError::Serialization(_))));
+        assert_eq!(mistyped.get(&100)?, None);
+
+        Ok(())
+    }
+
+    thread_local! {
+        static CLONES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    /// A key that counts how often it is cloned on the current thread
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+    struct CountedKey(u32);
+
+    impl Clone for CountedKey {
+        fn clone(&self) -> Self {
+            CLONES.with(|clones| clones.set(clones.get() + 1));
+            CountedKey(self.0)
+        }
+    }
+
+    #[test]
+    fn test_sstable_key_clones() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_clones.sst");
+        let clones = || CLONES.with(|clones| clones.get());
+
+        let entries = (0..100).map(|i| (CountedKey(i), i));
+        SSTable::from_sorted(entries, path.clone(), &SSTableOptions::default())?;
+        // Once per key for the order check and twice per block of 10 for the index
+        assert_eq!(clones(), 120);
+
+        let before = clones();
+        let sstable = SSTable::<CountedKey, u32>::open(path)?;
+        assert_eq!(sstable.get(&CountedKey(42))?, Some(42));
+        assert_eq!(sstable.get(&CountedKey(1000))?, None);
+        assert_eq!(sstable.get_entries(&[&CountedKey(1), &CountedKey(2)])?.len(), 2);
+        let range = sstable.range(Bound::Included(CountedKey(10)), Bound::Excluded(CountedKey(20)))?;
+        assert_eq!(range.count(), 10);
+        assert!(sstable.verify().errors.is_empty());
+        assert_eq!(clones(), before);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_invalid_format() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_format.sst");
+        let open = |path: &Path| SSTable::<i32, i32>::open(path);
+
+        std::fs::write(&path, b"short")?;
+        assert!(matches!(open(&path), Err(LSMError::InvalidFormat { .. })));
+        std::fs::write(&path, vec![0u8; 100])?;
+        assert!(matches!(open(&path), Err(LSMError::InvalidFormat { .. })));
+
+        let mut memtable = MemTable::new();
+        memtable.put(1, 1)?;
+        std::fs::remove_file(&path)?;
+        SSTable::from_memtable(&memtable, path.clone())?;
+        assert!(open(&path).is_ok());
+
+        // A table written by a future version of the format is rejected rather than misread
+        let mut bytes = std::fs::read(&path)?;
+        let version_offset = bytes.len() - MAGIC.len() - 1;
+        bytes[version_offset] = FORMAT_VERSION + 1;
+        std::fs::write(&path, bytes)?;
+        match open(&path) {
+            Err(LSMError::InvalidFormat { reason, .. }) => assert!(reason.contains("version")),
+            other => panic!("expected invalid format error, got {:?}", other.map(|_| ())),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_stored_index() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_stored_index.sst");
+
+        let mut memtable = MemTable::new();
+        for i in 0..35 {
+            memtable.put(i, format!("value_{}", i))?;
+        }
+        let sstable = SSTable::from_memtable(&memtable, path.clone())?;
+
+        // The footer points at the index entry of every block, followed by their CRC32
+        let bytes = std::fs::read(&path)?;
+        let footer = bytes.len() - FOOTER_LEN as usize;
+        let index_offset = u64::from_le_bytes(bytes[footer + 16..footer + 24].try_into().unwrap()) as usize;
+        let section = &bytes[index_offset..footer - CHECKSUM_LEN as usize];
+        let (index, crc) = section.split_at(section.len() - 4);
+        assert_eq!(crc32fast::hash(index).to_le_bytes(), crc);
+        let stored: Vec<IndexEntry<Vec<u8>>> = bincode::deserialize(index)?;
+        let stored: Vec<IndexEntry<i32>> = stored
+            .into_iter()
+            .map(|entry| IndexEntry {
+                key: bincode::deserialize(&entry.key).unwrap(),
+                last_key: bincode::deserialize(&entry.last_key).unwrap(),
+                position: entry.position,
+                records: entry.records,
+            })
+            .collect();
+        assert_eq!(stored.len(), 4);
+        assert_eq!((&stored[3].key, &stored[3].last_key, stored[3].records), (&30, &34, 5));
+        assert_eq!(stored, sstable.index()?.entries);
+        let reopened = SSTable::<i32, String>::open(&path)?;
+        assert_eq!(reopened.index()?.entries, sstable.index()?.entries);
+
+        // A changed byte in the index is caught when it is loaded
+        let mut corrupted = bytes.clone();
+        corrupted[index_offset + 1] ^= 0xff;
+        std::fs::write(&path, corrupted)?;
+        let reopened = SSTable::<i32, String>::open(&path)?;
+        assert!(matches!(reopened.get(&1), Err(LSMError::Corruption { .. })));
+        assert!(reopened.verify().errors.iter().any(|error| error.contains("cannot read the index")));
+
+        // An index offset outside the trailer is caught on open
+        let mut corrupted = bytes.clone();
+        corrupted[footer + 16..footer + 24].copy_from_slice(&(bytes.len() as u64).to_le_bytes());
+        std::fs::write(&path, corrupted)?;
+        assert!(matches!(SSTable::<i32, String>::open(&path), Err(LSMError::Corruption { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_never_overwritten() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_overwrite.sst");
+        let mut memtable = MemTable::new();
+        memtable.put(1, "first".to_string())?;
+        SSTable::from_memtable(&memtable, path.clone())?;
+        let written = std::fs::read(&path)?;
+
+        memtable.put(1, "second".to_string())?;
+        let result = SSTable::from_memtable(&memtable, path.clone());
+        assert!(matches!(result, Err(LSMError::SSTableExists { .. })));
+
+        // A table appearing at the path while the writer is busy isn't replaced either
+        let options = SSTableOptions::default();
+        let other_path = path.with_extension("other");
+        let mut writer = SSTableWriter::<i32, String>::create(other_path.clone(), &options)?;
+        writer.add(&1, &"third".to_string())?;
+        std::fs::copy(&path, &other_path)?;
+        assert!(matches!(writer.finish(), Err(LSMError::SSTableExists { .. })));
+        std::fs::remove_file(&other_path)?;
+
+        // A second writer for the same path is refused without disturbing the first
+        let mut first = SSTableWriter::<i32, String>::create(other_path.clone(), &options)?;
+        assert!(SSTableWriter::<i32, String>::create(other_path.clone(), &options).is_err());
+        first.add(&1, &"fourth".to_string())?;
+        assert_eq!(first.finish()?.get(&1)?, Some("fourth".to_string()));
+
+        assert_eq!(std::fs::read(&path)?, written);
+        assert_eq!(SSTable::<i32, String>::open(path)?.get(&1)?, Some("first".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_verify_file() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_checksum.sst");
+
+        let mut memtable = MemTable::new();
+        for i in 0..50 {
+            memtable.put(i, format!("value_{}", i))?;
+        }
+        let written = SSTable::from_memtable(&memtable, path.clone())?;
+        written.verify_file()?;
+        SSTable::<i32, String>::open(&path)?.verify_file()?;
+
+        // Any changed byte is caught, here one in the header that no record checksum covers
+        let bytes = std::fs::read(&path)?;
+        let mut changed = bytes.clone();
+        changed[0] ^= 1;
+        std::fs::write(&path, &changed)?;
+        assert!(matches!(written.verify_file(), Err(LSMError::Corruption { .. })));
+        let mut appended = bytes.clone();
+        appended.push(0);
+        std::fs::write(&path, &appended)?;
+        assert!(matches!(written.verify_file(), Err(LSMError::Corruption { .. })));
 
-impl<K, V> SSTable<K, V>
-where
-    K: Ord + serde::Serialize + for<'de> serde::Deserialize<'de> + Clone,
-    V: serde::Serialize + for<'de> serde::Deserialize<'de> + Clone,
-{
-    pub fn from_memtable(memtable: &MemTable<K, V>, path: String) -> Result<Self> {
-        let file = std::fs::File::create(&path)?;
-        let mut writer = std::io::BufWriter::new(file);
-        let mut index = Vec::new();
-        
-        let entry_count = memtable.data.len() as u64;
-        bincode::serialize_into(&mut writer, &entry_count)?;
-        
-        const INDEX_INTERVAL: u64 = 10;
-        
-        for (i, (key, value)) in memtable.iter().enumerate() {
-            let position = writer.stream_position()?;
-            
-            if (i as u64) % INDEX_INTERVAL == 0 {
-                index.push(IndexEntry {
-                    key: key.clone(),
-                    position,
-                });
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_get_entries()-> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_get_entries.sst");
+
+        let mut memtable = MemTable::new();
+        for i in (0..100).step_by(2) {
+            memtable.put(i, i * 10)?;
+        }
+        memtable.delete(40)?;
+        let sstable = SSTable::from_memtable(&memtable, path)?;
+
+        let keys = [-1, 0, 0, 3, 40, 42, 43, 98, 150];
+        let refs: Vec<_> = keys.iter().collect();
+        let entries = sstable.get_entries(&refs)?;
+        assert_eq!(
+            entries,
+            vec![
+                None,
+                Some(Entry::Value(0)),
+                Some(Entry::Value(0)),
+                None,
+                Some(Entry::Tombstone),
+                Some(Entry::Value(420)),
+                None,
+                Some(Entry::Value(980)),
+                None,
+            ]
+        );
+        for (key, entry) in keys.iter().zip(entries) {
+            assert_eq!(sstable.get_entry(key)?, entry);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_index_mismatch() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_mismatch.sst");
+        let other_path = dir.path().join("test_mismatch_other.sst");
+
+        let mut memtable = MemTable::new();
+        let mut other = MemTable::new();
+        for i in 0..30 {
+            memtable.put(i, i)?;
+            other.put(i + 100, i)?;
+        }
+        let sstable = SSTable::from_memtable(&memtable, path.clone())?;
+        SSTable::from_memtable(&other, other_path.clone())?;
+
+        // Same layout, different keys: every block lines up but starts with an unexpected key.
+        // The file is overwritten in place, as the table reads through the handle it holds.
+        std::fs::copy(&other_path, &path)?;
+        assert!(matches!(sstable.get(&10), Err(LSMError::Corruption { .. })));
+        assert!(matches!(sstable.get(&15), Err(LSMError::Corruption { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_index_interval() -> Result<()> {
+        let dir = tempdir()?;
+
+        let mut memtable = MemTable::new();
+        for i in 0..100 {
+            memtable.put(i, i)?;
+        }
+
+        for interval in [1, 7, 100, 1000] {
+            let path = dir.path().join(format!("test_interval_{}.sst", interval));
+            let options = SSTableOptions {
+                index_interval: interval,
+                ..SSTableOptions::default()
+            };
+            let sstable = SSTable::from_memtable_with_options(&memtable, path.clone(), &options)?;
+            assert_eq!(sstable.index()?.entries.len() as u64, 100u64.div_ceil(interval));
+
+            // The interval is read back from the file rather than assumed
+            let reopened = SSTable::<i32, i32>::open(path)?;
+            assert_eq!(reopened.index_interval, interval);
+            assert_eq!(reopened.index()?.entries.len(), sstable.index()?.entries.len());
+            for i in 0..100 {
+                assert_eq!(reopened.get(&i)?, Some(i));
             }
-            
-            bincode::serialize_into(&mut writer, &key)?;
-            bincode::serialize_into(&mut writer, &value)?;
+            assert_eq!(reopened.get(&100)?, None);
+
+            let keys = reopened
+                .range(Bound::Included(50), Bound::Excluded(53))?
+                .map(|item| item.map(|(k, _)| k))
+                .collect::<Result<Vec<_>>>()?;
+            assert_eq!(keys, vec![50, 51, 52]);
         }
-        
-        writer.flush()?;
-        
-        Ok(Self {
-            path,
-            index,
-            _phantom: std::marker::PhantomData,
-        })
+
+        Ok(())
     }
 
-    pub fn get(&self, search_key: &K) -> Result<Option<V>> {
-        let file = std::fs::File::open(&self.path)?;
-        let mut reader = std::io::BufReader::new(file);
-        
-        let entry_count: u64 = bincode::deserialize_from(&mut reader)?;
-        
-        let index_pos = match self.index.binary_search_by(|entry| entry.key.cmp(search_key)) {
-            Ok(pos) => {
-                reader.seek(std::io::SeekFrom::Start(self.index[pos].position))?;
-                reader.seek(std::io::SeekFrom::Start(self.index[pos].position))?;
-                let key: K = bincode::deserialize_from(&mut reader)?;
-                let value: V = bincode::deserialize_from(&mut reader)?;
-                return Ok(Some(value));
-            }
-            Err(pos) => {
-                if pos == 0 {
-                    // Key is before first index entry, we're already at the right position
-                    // after reading entry_count
-                    pos
+    #[test]
+    fn test_sstable_entries() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_entries.sst");
+
+        let mut memtable = MemTable::new();
+        for i in (0..30).rev() {
+            memtable.put(i, i * 10)?;
+        }
+        memtable.delete(7)?;
+        let sstable = SSTable::from_memtable(&memtable, path)?;
+
+        let entries = sstable.entries()?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(entries.len(), 30);
+        assert_eq!(entries[0], (0, Entry::Value(0)));
+        assert_eq!(entries[7], (7, Entry::Tombstone));
+        assert_eq!(entries[29], (29, Entry::Value(290)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_keys_strictly_increasing() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_increasing.sst");
+
+        // Every key is written several times, out of order, before the flush
+        let mut memtable = MemTable::new();
+        for round in 0..3 {
+            for i in 0..100 {
+                let key = (i * 37 + round) % 100;
+                if key % 11 == round {
+                    memtable.delete(key)?;
                 } else {
-                    // Seek to the previous index entry
-                    reader.seek(std::io::SeekFrom::Start(self.index[pos - 1].position))?;
-                    pos - 1
+                    memtable.put(key, round)?;
                 }
             }
-        };
-        
-        loop {
-            let position = match reader.stream_position() {
-                Ok(pos) => pos,
-                Err(_) => return Ok(None), // Handle IO error gracefully
+        }
+        let sstable = SSTable::from_memtable(&memtable, path)?;
+        let keys: Vec<_> = sstable.entries()?.map(|item| item.map(|(key, _)| key)).collect::<Result<_>>()?;
+        assert_eq!(keys.len(), 100);
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // Equal adjacent keys are rejected like decreasing ones
+        let path = dir.path().join("test_duplicates.sst");
+        let duplicates = SSTable::from_sorted(vec![(1, 1), (2, 2), (2, 3)], path, &SSTableOptions::default());
+        assert!(matches!(duplicates, Err(LSMError::UnsortedKeys)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_empty_keys_and_values() -> Result<()> {
+        let dir = tempdir()?;
+
+        let mut memtable = MemTable::new();
+        memtable.put(String::new(), String::new())?;
+        memtable.put("a".to_string(), String::new())?;
+        memtable.put("b".to_string(), "value".to_string())?;
+        memtable.delete("c".to_string())?;
+
+        // One record per block, so the empty key gets a block of its own
+        for interval in [1, 10] {
+            let path = dir.path().join(format!("test_empty_{}.sst", interval));
+            let options = SSTableOptions {
+                index_interval: interval,
+                ..SSTableOptions::default()
             };
-            
-            if index_pos + 1 < self.index.len() && position >= self.index[index_pos + 1].position {
-                return Ok(None);
+            SSTable::from_memtable_with_options(&memtable, path.clone(), &options)?;
+            let sstable = SSTable::<String, String>::open(path)?;
+
+            assert_eq!(sstable.get(&String::new())?, Some(String::new()));
+            assert_eq!(sstable.get(&"a".to_string())?, Some(String::new()));
+            assert_eq!(sstable.get_entry(&"c".to_string())?, Some(Entry::Tombstone));
+            let entries = sstable.entries()?.collect::<Result<Vec<_>>>()?;
+            assert_eq!(entries.len(), 4);
+            assert_eq!(entries[0], (String::new(), Entry::Value(String::new())));
+        }
+
+        // Unit keys serialize to zero bytes
+        let path = dir.path().join("test_unit.sst");
+        let mut memtable = MemTable::new();
+        memtable.put((), ())?;
+        SSTable::from_memtable(&memtable, path.clone())?;
+        let sstable = SSTable::<(), ()>::open(path)?;
+        assert_eq!(sstable.get(&())?, Some(()));
+        assert_eq!(sstable.entries()?.count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_from_sorted() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_from_sorted.sst");
+        let options = SSTableOptions {
+            index_interval: 4,
+            ..SSTableOptions::default()
+        };
+
+        let sstable = SSTable::from_sorted((0..100).map(|i| (i * 2, format!("value_{}", i))), path.clone(), &options)?;
+        assert_eq!(sstable.entry_count(), 100);
+        let reopened = SSTable::<i32, String>::open(path)?;
+        assert_eq!(reopened.get(&10)?, Some("value_5".to_string()));
+        assert_eq!(reopened.get(&11)?, None);
+        assert_eq!(reopened.key_range()?, Some((&0, &198)));
+
+        // The writer also takes tombstones, and rejects keys out of order
+        // Nothing is left behind by a table that couldn't be written
+        let path = dir.path().join("test_unsorted.sst");
+        let unsorted = SSTable::from_sorted(vec![(2, 2), (1, 1)], path.clone(), &options);
+        assert!(matches!(unsorted, Err(LSMError::UnsortedKeys)));
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+
+        let path = dir.path().join("test_writer.sst");
+        let mut writer = SSTableWriter::create(path.clone(), &options)?;
+        assert!(!std::path::Path::new(&path).exists());
+        writer.add(&1, &"one".to_string())?;
+        writer.add_entry(&2, &Entry::<String>::Tombstone)?;
+        assert!(matches!(writer.add(&2, &"two".to_string()), Err(LSMError::UnsortedKeys)));
+        assert!(matches!(writer.add(&0, &"zero".to_string()), Err(LSMError::UnsortedKeys)));
+        writer.add(&3, &"three".to_string())?;
+        let sstable = writer.finish()?;
+        assert_eq!(sstable.get(&1)?, Some("one".to_string()));
+        assert_eq!(sstable.get_entry(&2)?, Some(Entry::Tombstone));
+        assert_eq!(sstable.entries()?.count(), 3);
+
+        let path = dir.path().join("test_invalid.sst");
+        let invalid = SSTableOptions {
+            index_interval: 0,
+            ..SSTableOptions::default()
+        };
+        assert!(matches!(SSTableWriter::<i32, i32>::create(path, &invalid), Err(LSMError::InvalidConfig(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_get_entry_kind() -> Result<()> {
+        /// Stored as a string, but can't be read back
+        #[derive(Debug)]
+        struct Unreadable;
+
+        impl serde::Serialize for Unreadable {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+                serializer.serialize_str("opaque")
             }
-            
-            let key: K = match bincode::deserialize_from(&mut reader) {
-                Ok(k) => k,
-                Err(_) => return Ok(None),
-            };
-            
-            match key.cmp(search_key) {
-                std::cmp::Ordering::Equal => {
-                    // Also handle potential EOF or corruption when reading value
-                    let value: V = match bincode::deserialize_from(&mut reader) {
-                        Ok(v) => v,
-                        Err(_) => return Ok(None),
-                    };
-                    return Ok(Some(value));
-                }
-                std::cmp::Ordering::Greater => return Ok(None),
-                std::cmp::Ordering::Less => {
-                    // Skip the value and continue searching
-                    // Handle potential EOF or corruption when skipping value
-                    match bincode::deserialize_from::<_, V>(&mut reader) {
-                        Ok(_) => (),
-                        Err(_) => return Ok(None),
-                    }
-                }
+        }
+
+        impl<'de> serde::Deserialize<'de> for Unreadable {
+            fn deserialize<D: serde::Deserializer<'de>>(_: D) -> std::result::Result<Self, D::Error> {
+                Err(serde::de::Error::custom("values must not be deserialized"))
             }
         }
+
+        let dir = tempdir()?;
+        let varint = Encoding {
+            int_encoding: crate::encoding::IntEncoding::Varint,
+            ..Encoding::default()
+        };
+        let message_pack = Encoding {
+            format: Format::MessagePack,
+            ..Encoding::default()
+        };
+        for encoding in [Encoding::default(), varint, message_pack] {
+            let path = dir.path().join(format!("test_kind_{}.sst", encoding.id()));
+            let options = SSTableOptions { encoding, ..SSTableOptions::default() };
+            let mut writer = SSTableWriter::<i32, Unreadable>::create(path, &options)?;
+            writer.add(&1, &Unreadable)?;
+            writer.add_entry(&2, &Entry::<Unreadable>::Tombstone)?;
+            let sstable = writer.finish()?;
+
+            assert_eq!(sstable.get_entry_kind(&1)?, Some(Entry::Value(())));
+            assert_eq!(sstable.get_entry_kind(&2)?, Some(Entry::Tombstone));
+            assert_eq!(sstable.get_entry_kind(&4)?, None);
+            assert!(sstable.get_entry(&1).is_err());
+        }
+
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::tempdir;
+    #[test]
+    fn test_sstable_get_entry_raw() -> Result<()> {
+        let dir = tempdir()?;
+        let encodings = [
+            Encoding::default(),
+            Encoding { endianness: crate::encoding::Endianness::Big, ..Encoding::default() },
+            Encoding { int_encoding: IntEncoding::Varint, ..Encoding::default() },
+            Encoding { format: Format::MessagePack, ..Encoding::default() },
+        ];
+        for encoding in encodings {
+            let path = dir.path().join(format!("test_raw_{}.sst", encoding.id()));
+            let options = SSTableOptions { encoding, ..SSTableOptions::default() };
+            let mut writer = SSTableWriter::<i32, String>::create(path, &options)?;
+            writer.add(&1, &"one".to_string())?;
+            writer.add_entry(&2, &Entry::<String>::Tombstone)?;
+            writer.add_entry(&3, &Entry::Expiring { value: "three".to_string(), expires_at: 1_000_000 })?;
+            let sstable = writer.finish()?;
+
+            let raw = |value: &str| encoding.serialize(value).unwrap();
+            assert_eq!(sstable.get_entry_raw(&1)?, Some(Entry::Value(raw("one"))));
+            assert_eq!(sstable.get_entry_raw(&2)?, Some(Entry::Tombstone));
+            assert_eq!(
+                sstable.get_entry_raw(&3)?,
+                Some(Entry::Expiring { value: raw("three"), expires_at: 1_000_000 })
+            );
+            assert_eq!(sstable.get_entry_raw(&4)?, None);
+        }
+
+        Ok(())
+    }
 
     #[test]
-    fn test_sstable_basic_operations() -> Result<()> {
+    fn test_sstable_block_key_ranges() -> Result<()> {
         let dir = tempdir()?;
-        let path = dir.path().join("test.sst").to_str().unwrap().to_string();
-        
-        let mut memtable = MemTable::new();
-        memtable.put(5, "five".to_string())?;
-        memtable.put(3, "three".to_string())?;
-        memtable.put(7, "seven".to_string())?;
-        memtable.put(1, "one".to_string())?;
-        memtable.put(9, "nine".to_string())?;
-        
-        let sstable = SSTable::from_memtable(&memtable, path)?;
-        
-        assert_eq!(sstable.get(&1)?, Some("one".to_string()));
-        assert_eq!(sstable.get(&3)?, Some("three".to_string()));
-        assert_eq!(sstable.get(&5)?, Some("five".to_string()));
-        assert_eq!(sstable.get(&7)?, Some("seven".to_string()));
-        assert_eq!(sstable.get(&9)?, Some("nine".to_string()));
-        
-        assert_eq!(sstable.get(&0)?, None);
-        assert_eq!(sstable.get(&2)?, None);
-        assert_eq!(sstable.get(&6)?, None);
-        assert_eq!(sstable.get(&8)?, None);
-        assert_eq!(sstable.get(&10)?, None);
-        
+        let path = dir.path().join("test_block_ranges.sst");
+
+        // Blocks of 10 keys, multiples of 10: the first block holds 0 to 90
+        let options = SSTableOptions { bloom_bits_per_key: 0, ..SSTableOptions::default() };
+        let written = SSTable::from_sorted((0..25).map(|i| (i * 10, i)), path.clone(), &options)?;
+        let reopened = SSTable::<i32, i32>::open(path)?;
+        for sstable in [&written, &reopened] {
+            let entries = &sstable.index()?.entries;
+            let blocks: Vec<_> = entries.iter().map(|entry| (entry.key, entry.last_key, entry.records)).collect();
+            assert_eq!(blocks, vec![(0, 90, 10), (100, 190, 10), (200, 240, 5)]);
+
+            // A key past the end of a block is ruled out without reading it
+            assert_eq!(sstable.locate_block(&85)?, Some(0));
+            assert_eq!(sstable.locate_block(&95)?, None);
+            assert_eq!(sstable.locate_block(&190)?, Some(1));
+            assert_eq!(sstable.locate_block(&195)?, None);
+            assert_eq!(sstable.get(&95)?, None);
+            assert_eq!(sstable.get(&100)?, Some(10));
+        }
+
         Ok(())
     }
 
     #[test]
-    fn test_sstable_large_dataset() -> Result<()> {
+    fn test_sstable_ordered_keys() -> Result<()> {
+        use crate::ordered::{OrderedBytes, OrderedEncode};
+
         let dir = tempdir()?;
-        let path = dir.path().join("test_large.sst").to_str().unwrap().to_string();
-        
+        let path = dir.path().join("test_ordered_keys.sst");
+        let comparator: Arc<dyn Comparator<(String, u32)>> = Arc::new(OrderedBytes);
+        let options = SSTableOptions { index_interval: 4, ..SSTableOptions::default() };
+        let mut writer = SSTableWriter::create_with_comparator(path.clone(), &options, Arc::clone(&comparator))?;
+        let keys: Vec<_> = ["a", "b"].iter().flat_map(|user| (0..10).map(|id| (user.to_string(), id))).collect();
+        for (i, key) in keys.iter().enumerate() {
+            writer.add(key, &(i as u32))?;
+        }
+        writer.add_entry(&("c".to_string(), 0), &Entry::<u32>::Tombstone)?;
+        writer.finish()?;
+
+        // Record keys are stored in the ordered encoding, not serialized
+        let bytes = std::fs::read(&path)?;
+        let stored = |key: &(String, u32)| {
+            let key = key.to_ordered_bytes();
+            bytes.windows(key.len()).any(|window| window == key)
+        };
+        assert!(keys.iter().all(stored));
+
+        let sstable = SSTable::<(String, u32), u32>::open_with_comparator(path.clone(), comparator)?;
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(sstable.get(key)?, Some(i as u32));
+            let Some(Entry::Value(mut reader)) = sstable.get_value_sequenced(key)?.map(|entry| entry.entry) else {
+                panic!("expected a value for {:?}", key);
+            };
+            let mut value = Vec::new();
+            reader.read_to_end(&mut value)?;
+            assert_eq!(value, bincode::serialize(&(i as u32)).unwrap());
+        }
+        assert_eq!(sstable.get(&("a".to_string(), 10))?, None);
+        assert_eq!(sstable.get_entry(&("c".to_string(), 0))?, Some(Entry::Tombstone));
+        assert_eq!(sstable.entries()?.count(), 21);
+        let start = Bound::Included(("a".to_string(), 8));
+        let end = Bound::Excluded(("b".to_string(), 2));
+        let range: Vec<_> = sstable.range(start.clone(), end.clone())?.map(|entry| entry.map(|(key, _)| key)).collect();
+        assert_eq!(range.into_iter().collect::<Result<Vec<_>>>()?, keys[8..12]);
+        let rev: Vec<_> = sstable.range_rev(start, end)?.map(|entry| entry.map(|(key, _)| key)).collect();
+        assert_eq!(rev.into_iter().rev().collect::<Result<Vec<_>>>()?, keys[8..12]);
+
+        // The keys can't be read without the comparator's encoding
+        assert!(matches!(
+            SSTable::<(String, u32), u32>::open(path),
+            Err(LSMError::InvalidFormat { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_dump() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_dump.sst");
+
         let mut memtable = MemTable::new();
-        for i in 0..1000 {
+        for i in 0..12 {
             memtable.put(i, format!("value_{}", i))?;
         }
-        
+        memtable.delete(3)?;
         let sstable = SSTable::from_memtable(&memtable, path)?;
-        
-        assert_eq!(sstable.get(&42)?, Some("value_42".to_string()));
-        assert_eq!(sstable.get(&999)?, Some("value_999".to_string()));
-        assert_eq!(sstable.get(&500)?, Some("value_500".to_string()));
-        
-        assert_eq!(sstable.get(&0)?, Some("value_0".to_string()));
-        assert_eq!(sstable.get(&999)?, Some("value_999".to_string()));
-        assert_eq!(sstable.get(&1000)?, None);
-        
+
+        let mut out = Vec::new();
+        sstable.dump(&mut out)?;
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("  entries: 12\n"), "{}", out);
+        assert!(out.contains("Index (2 blocks)\n  block 0 at 10: 10 records, 0 to 9\n"), "{}", out);
+        assert!(out.contains("  max sequence: 13\n"), "{}", out);
+        assert!(out.contains("  2 => Value(\"value_2\") (sequence 3)\n  3 => Tombstone (sequence 13)\n"), "{}", out);
+        assert_eq!(out.lines().filter(|line| line.contains(" => ")).count(), 12);
+
         Ok(())
     }
 
     #[test]
-    fn test_sstable_empty() -> Result<()> {
+    fn test_sstable_tombstones() -> Result<()> {
         let dir = tempdir()?;
-        let path = dir.path().join("test_empty.sst").to_str().unwrap().to_string();
-        
-        let memtable = MemTable::<i32, i32>::new();
+        let path = dir.path().join("test_tombstones.sst");
+
+        let mut memtable = MemTable::new();
+        for i in 0..50 {
+            memtable.put(i, format!("value_{}", i))?;
+        }
+        memtable.delete(0)?;
+        memtable.delete(25)?;
+
         let sstable = SSTable::from_memtable(&memtable, path)?;
-        
-        assert_eq!(sstable.get(&1)?, None);
+
+        assert_eq!(sstable.get(&0)?, None);
+        assert_eq!(sstable.get_entry(&0)?, Some(Entry::Tombstone));
+        assert_eq!(sstable.get_entry(&25)?, Some(Entry::Tombstone));
+        assert_eq!(sstable.get_entry(&26)?, Some(Entry::Value("value_26".to_string())));
+        assert_eq!(sstable.get_entry(&50)?, None);
+
         Ok(())
     }
 
     #[test]
-    fn test_sstable_string_keys() -> Result<()> {
+    fn test_sstable_block_size() -> Result<()> {
         let dir = tempdir()?;
-        let path = dir.path().join("test_strings.sst").to_str().unwrap().to_string();
-        
-        let mut memtable = MemTable::<String, i32>::new();
-        memtable.put("apple".to_string(), 1)?;
-        memtable.put("banana".to_string(), 2)?;
-        memtable.put("cherry".to_string(), 3)?;
-        
-        let sstable = SSTable::from_memtable(&memtable, path)?;
-        
-        assert_eq!(sstable.get(&"aaa".to_string())?, None);
-        
-        assert_eq!(sstable.get(&"apple".to_string())?, Some(1));
-        assert_eq!(sstable.get(&"banana".to_string())?, Some(2));
-        assert_eq!(sstable.get(&"cherry".to_string())?, Some(3));
-        assert_eq!(sstable.get(&"date".to_string())?, None);
-        
+        let path = dir.path().join("test_block_size.sst");
+
+        let mut memtable = MemTable::new();
+        for i in 0..100 {
+            memtable.put(i, "x".repeat(100))?;
+        }
+        // Each record is well over 100 bytes, so blocks close before reaching the interval
+        let options = SSTableOptions {
+            index_interval: 50,
+            block_size: 512,
+            ..SSTableOptions::default()
+        };
+        let sstable = SSTable::from_memtable_with_options(&memtable, path.clone(), &options)?;
+        assert!(sstable.index()?.entries.len() > 2);
+
+        let reopened = SSTable::<i32, String>::open(path)?;
+        assert_eq!(reopened.index()?.entries.len(), sstable.index()?.entries.len());
+        for i in 0..100 {
+            assert_eq!(reopened.get(&i)?, Some("x".repeat(100)));
+        }
+        assert_eq!(reopened.entries()?.count(), 100);
+
+        Ok(())
+    }
+
+    fn check_compressed_round_trip(compression: Compression) -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test_compressed.sst");
+
+        let mut memtable = MemTable::new();
+        for i in 0..500 {
+            memtable.put(i, format!("value_{}", i % 7).repeat(10))?;
+        }
+        memtable.delete(250)?;
+        let options = SSTableOptions {
+            compression,
+            ..SSTableOptions::default()
+        };
+        SSTable::from_memtable_with_options(&memtable, path.clone(), &options)?;
+
+        // The codec is read back from the header
+        let reopened = SSTable::<i32, String>::open(path)?;
+        assert_eq!(reopened.compression(), compression);
+        for i in [0, 123, 499] {
+            assert_eq!(reopened.get(&i)?, Some(format!("value_{}", i % 7).repeat(10)));
+        }
+        assert_eq!(reopened.get_entry(&250)?, Some(Entry::Tombstone));
+        assert_eq!(reopened.get(&500)?, None);
+        assert_eq!(reopened.entries()?.count(), 500);
+
+        // Values are read out of the decompressed block, or straight from an uncompressed file
+        let Some(Entry::Value(mut reader)) = reopened.get_value_sequenced(&123)?.map(|entry| entry.entry) else {
+            panic!("expected a value");
+        };
+        let mut value = Vec::new();
+        reader.read_to_end(&mut value)?;
+        match compression {
+            Compression::None => assert!(matches!(reader, ValueReader::File(_))),
+            _ => assert!(matches!(reader, ValueReader::Block { .. })),
+        }
+        assert_eq!(value, reopened.get_entry_raw(&123)?.and_then(Entry::into_value).unwrap());
+
         Ok(())
     }
-} 
\ No newline at end of file
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_sstable_lz4() -> Result<()> {
+        check_compressed_round_trip(Compression::Lz4)
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_sstable_zstd() -> Result<()> {
+        check_compressed_round_trip(Compression::Zstd)
+    }
+
+    #[test]
+    fn test_sstable_uncompressed_round_trip() -> Result<()> {
+        check_compressed_round_trip(Compression::None)
+    }
+}
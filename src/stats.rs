@@ -0,0 +1,182 @@
+//! Operation counters for observability.
+//!
+//! The tree keeps a set of atomic counters, shared with its SSTables so they can count the
+//! files they open, and with its memtables so they can record the sizes of what is written.
+//! `LSMTree::stats` reads them together with the current shape of the tree, and
+//! `LSMTree::compaction_stats` those describing the work of compactions.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A point-in-time summary of a tree's activity since it was opened
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of live SSTables
+    pub num_sstables: usize,
+    /// Estimated size of the memtable, as compared against `memtable_size_threshold`
+    pub memtable_bytes: usize,
+    /// Number of entries in the memtable, tombstones included. Overwrites don't add entries.
+    pub memtable_entries: usize,
+    /// Inserts and deletes, counting each entry of a batch
+    pub total_writes: u64,
+    /// Point lookups, counting each key of a `get_many`
+    pub total_gets: u64,
+    pub total_flushes: u64,
+    pub total_compactions: u64,
+    /// Bytes of SSTables written by flushes and compactions
+    pub sstable_bytes_written: u64,
+    /// Times an SSTable file was opened to serve a scan or a compaction. Point lookups share
+    /// one open handle per table and don't count, except when they reopen a table whose handle
+    /// `max_open_files` closed.
+    pub sstable_files_opened: u64,
+    /// SSTable indexes read from disk. Tables opened with the tree load their index on first
+    /// use, so tables that are never read, or only looked up for keys their bloom filter rules
+    /// out, don't count.
+    pub sstable_indexes_loaded: u64,
+    /// Blocks lookups found in the block cache
+    pub block_cache_hits: u64,
+    /// Blocks lookups had to read from disk while the block cache was enabled
+    pub block_cache_misses: u64,
+    /// Serialized sizes of the keys written, tombstones included
+    pub key_size_histogram: SizeHistogram,
+    /// Serialized sizes of the values written, including the expiry of values with a TTL
+    pub value_size_histogram: SizeHistogram,
+}
+
+/// The work done by compactions since the tree was opened, to compare compaction settings.
+/// Merges written in the background but discarded because their inputs changed meanwhile
+/// count too, since their I/O was done.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// Completed compactions, each made of one or more merges
+    pub compactions: u64,
+    /// Size of the SSTables merged
+    pub bytes_read: u64,
+    /// Size of the SSTables written by merges
+    pub bytes_written: u64,
+    /// Records in the SSTables merged, tombstones included
+    pub records_read: u64,
+    /// Records in the SSTables written by merges
+    pub records_written: u64,
+    /// Records dropped because a newer version of their key was kept
+    pub shadowed_dropped: u64,
+    /// Values dropped because they expired, were rejected by `compact_with_filter` or were
+    /// rewritten to `None` by `rewrite_with`
+    pub expired_dropped: u64,
+    /// Tombstones dropped because no older version of their key was left to shadow
+    pub tombstones_dropped: u64,
+    /// Size of the SSTables written by flushes
+    pub flushed_bytes: u64,
+}
+
+impl CompactionStats {
+    /// Bytes written to SSTables by flushes and compactions per byte flushed, or 1 before the
+    /// first flush. The write-ahead log is not counted.
+    pub fn write_amplification(&self) -> f64 {
+        match self.flushed_bytes {
+            0 => 1.0,
+            flushed => (flushed + self.bytes_written) as f64 / flushed as f64,
+        }
+    }
+
+    /// Average bytes read per compaction
+    pub fn bytes_read_per_compaction(&self) -> u64 {
+        self.bytes_read.checked_div(self.compactions).unwrap_or(0)
+    }
+
+    /// Average bytes written per compaction
+    pub fn bytes_written_per_compaction(&self) -> u64 {
+        self.bytes_written.checked_div(self.compactions).unwrap_or(0)
+    }
+}
+
+/// Number of buckets of a `SizeHistogram`
+pub const SIZE_BUCKETS: usize = 32;
+
+/// Counts of sizes in power-of-two buckets: bucket 0 counts sizes of 0 bytes, and bucket `i`
+/// sizes from `2^(i-1)` to `2^i - 1` bytes. The last bucket also counts anything larger.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SizeHistogram {
+    pub buckets: [u64; SIZE_BUCKETS],
+}
+
+impl SizeHistogram {
+    /// The bucket counting `size`
+    pub fn bucket(size: u64) -> usize {
+        (u64::BITS - size.leading_zeros()).min(SIZE_BUCKETS as u32 - 1) as usize
+    }
+
+    /// Number of sizes recorded
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Counter(AtomicU64);
+
+impl Counter {
+    pub(crate) fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub(crate) fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Histogram([Counter; SIZE_BUCKETS]);
+
+impl Histogram {
+    pub(crate) fn record(&self, size: usize) {
+        self.0[SizeHistogram::bucket(size as u64)].add(1);
+    }
+
+    pub(crate) fn get(&self) -> SizeHistogram {
+        SizeHistogram {
+            buckets: std::array::from_fn(|i| self.0[i].get()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    pub(crate) writes: Counter,
+    pub(crate) gets: Counter,
+    pub(crate) flushes: Counter,
+    pub(crate) compactions: Counter,
+    pub(crate) sstable_bytes_written: Counter,
+    pub(crate) flush_bytes_written: Counter,
+    pub(crate) compaction_bytes_read: Counter,
+    pub(crate) compaction_bytes_written: Counter,
+    pub(crate) compaction_records_read: Counter,
+    pub(crate) compaction_records_written: Counter,
+    /// Records coming out of merges, before expired or rewritten away values and tombstones are
+    /// dropped
+    pub(crate) compaction_records_merged: Counter,
+    pub(crate) compaction_expired_dropped: Counter,
+    pub(crate) compaction_tombstones_dropped: Counter,
+    pub(crate) sstable_files_opened: Counter,
+    pub(crate) sstable_indexes_loaded: Counter,
+    pub(crate) block_cache_hits: Counter,
+    pub(crate) block_cache_misses: Counter,
+    pub(crate) key_sizes: Histogram,
+    pub(crate) value_sizes: Histogram,
+}
+
+impl Counters {
+    pub(crate) fn compaction_stats(&self) -> CompactionStats {
+        let records_read = self.compaction_records_read.get();
+        CompactionStats {
+            compactions: self.compactions.get(),
+            bytes_read: self.compaction_bytes_read.get(),
+            bytes_written: self.compaction_bytes_written.get(),
+            records_read,
+            records_written: self.compaction_records_written.get(),
+            shadowed_dropped: records_read.saturating_sub(self.compaction_records_merged.get()),
+            expired_dropped: self.compaction_expired_dropped.get(),
+            tombstones_dropped: self.compaction_tombstones_dropped.get(),
+            flushed_bytes: self.flush_bytes_written.get(),
+        }
+    }
+}
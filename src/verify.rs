@@ -0,0 +1,87 @@
+//! Integrity checks over the SSTables of a tree.
+//!
+//! `LSMTree::verify` reads every record of every live SSTable and reports what it finds
+//! without modifying anything: records failing their checksum, keys out of order or outside
+//! the table's key range, keys the bloom filter would reject, and entry counts that don't match
+//! the footer.
+
+use crate::{LSMTree, Result};
+use std::path::PathBuf;
+
+/// Findings of `LSMTree::verify`, one report per live SSTable, oldest first
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub tables: Vec<TableReport>,
+}
+
+impl VerifyReport {
+    /// Whether no table had any error
+    pub fn is_ok(&self) -> bool {
+        self.tables.iter().all(|table| table.errors.is_empty())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TableReport {
+    pub path: PathBuf,
+    /// Number of records read, up to the first unreadable one
+    pub entry_count: u64,
+    /// Descriptions of the anomalies found; reading stops at the first unreadable record
+    pub errors: Vec<String>,
+}
+
+impl<K, V> LSMTree<K, V>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Reads every SSTable in full and checks it for corruption; see `SSTable::verify`
+    pub fn verify(&self) -> Result<VerifyReport> {
+        Ok(VerifyReport {
+            tables: self.sstables.iter().map(|sstable| sstable.verify()).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Config, LSMTree, Result};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_verify() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let mut lsm = LSMTree::with_config(Config {
+            data_dir: temp_dir.path().to_path_buf(),
+            compaction_threshold: None,
+            ..Config::default()
+        })?;
+        for table in 0..2 {
+            for i in 0..50 {
+                lsm.insert(i, format!("value{}_{}", table, i))?;
+            }
+            lsm.delete(100)?;
+            lsm.flush()?;
+        }
+
+        let report = lsm.verify()?;
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(report.tables.len(), 2);
+        assert!(report.tables.iter().all(|table| table.entry_count == 51));
+
+        // Flip a byte in the middle of the newest table's data
+        let path = report.tables[1].path.clone();
+        let mut bytes = std::fs::read(&path)?;
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xff;
+        std::fs::write(&path, bytes)?;
+
+        let report = lsm.verify()?;
+        assert!(!report.is_ok());
+        assert!(report.tables[0].errors.is_empty());
+        assert!(report.tables[1].errors[0].contains("checksum"));
+        assert!(report.tables[1].entry_count < 51);
+
+        Ok(())
+    }
+}
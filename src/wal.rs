@@ -0,0 +1,395 @@
+//! Write-ahead log (WAL) for the memtable.
+//!
+//! Every write is appended to the log before it is applied to the memtable, so the contents of
+//! the memtable can be rebuilt after a crash. When the memtable is flushed its log is sealed:
+//! moved aside while writes continue into a new log, and deleted once the memtable's entries
+//! are in SSTables. Records always use bincode's default encoding, whatever `Config::encoding`
+//! says.
+//!
+//! The log starts with a magic number, followed by frames of `[len: u32][record][crc32: u32]`.
+//! A record holds a single write or a whole `WriteBatch`. The checksum written after the record
+//! acts as its commit marker: a frame that is cut short or fails its checksum (a torn write) is
+//! discarded on replay, with everything after it, so a batch is replayed in full or not at all.
+//! Logs written before frames existed hold bare records; they are replayed and then rewritten
+//! in the current format.
+
+use crate::comparator::Comparator;
+use crate::encoding::Encoding;
+use crate::memtable::{Entry, MemTable};
+use crate::Result;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+const MAGIC: [u8; 8] = *b"LSMWAL\0\x02";
+
+#[derive(serde::Serialize, serde::Deserialize)]
+enum Record<K, V> {
+    Write(K, Entry<V>),
+    Batch(Vec<(K, Entry<V>)>),
+    /// A range tombstone from `start` included to `end` excluded
+    DeleteRange(K, K),
+}
+
+pub struct Wal {
+    file: File,
+}
+
+impl Wal {
+    /// Opens the log at `path` for appending, creating it if it doesn't exist. An existing log
+    /// must be in the current format, which `replay` ensures.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(&MAGIC)?;
+        }
+        Ok(Self { file })
+    }
+
+    /// Appends a single record. The record is handed to the OS before returning.
+    pub fn append<K, V>(&mut self, key: &K, entry: &Entry<V>) -> Result<()>
+    where
+        K: serde::Serialize,
+        V: serde::Serialize,
+    {
+        self.append_record(&Record::Write(key, entry.as_ref()))
+    }
+
+    /// Appends the entries as one record, which replay applies in full or not at all
+    pub fn append_batch<K, V>(&mut self, entries: &[(K, Entry<V>)]) -> Result<()>
+    where
+        K: serde::Serialize,
+        V: serde::Serialize,
+    {
+        let entries = entries.iter().map(|(key, entry)| (key, entry.as_ref())).collect();
+        self.append_record(&Record::Batch(entries))
+    }
+
+    /// Appends a range delete of the keys in `[start, end)`
+    pub fn append_delete_range<K: serde::Serialize>(&mut self, start: &K, end: &K) -> Result<()> {
+        self.append_record(&Record::<&K, ()>::DeleteRange(start, end))
+    }
+
+    fn append_record<K, V>(&mut self, record: &Record<K, V>) -> Result<()>
+    where
+        K: serde::Serialize,
+        V: serde::Serialize,
+    {
+        self.file.write_all(&frame(record)?)?;
+        Ok(())
+    }
+
+    /// Moves the log to `sealed_path` and continues in a new, empty log at `path`
+    pub fn seal(&mut self, path: &Path, sealed_path: &Path) -> Result<()> {
+        std::fs::rename(path, sealed_path)?;
+        *self = Wal::open(path)?;
+        crate::sync_parent_dir(path)
+    }
+
+    /// Discards all records, called once their entries are persisted in an SSTable
+    pub fn reset(&mut self) -> Result<()> {
+        self.file.set_len(0)?;
+        self.file.write_all(&MAGIC)?;
+        Ok(())
+    }
+
+    /// Replays the log at `path` into a fresh memtable ordered by `comparator` and measured in
+    /// `encoding`. Replay stops at the first frame that is incomplete, fails its checksum or
+    /// fails to deserialize, and the log is truncated to the last complete frame so that new
+    /// appends don't follow garbage.
+    pub fn replay<K, V>(path: &Path, comparator: Arc<dyn Comparator<K>>, encoding: Encoding) -> Result<MemTable<K, V>>
+    where
+        K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+        V: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        Self::replay_into(path, MemTable::with_comparator_and_encoding(comparator, encoding))
+    }
+
+    /// Like `replay`, into `memtable`, whose sequence numbers the replayed writes continue
+    pub(crate) fn replay_into<K, V>(path: &Path, mut memtable: MemTable<K, V>) -> Result<MemTable<K, V>>
+    where
+        K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+        V: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(memtable),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        if buf.is_empty() {
+            return Ok(memtable);
+        }
+        if !buf.starts_with(&MAGIC) {
+            replay_unframed(&buf, &mut memtable)?;
+            rewrite(path, &memtable)?;
+            return Ok(memtable);
+        }
+
+        let position = read_frames(&buf, &mut memtable)?;
+        if position < buf.len() {
+            file.set_len(position as u64)?;
+        }
+
+        Ok(memtable)
+    }
+
+    /// Like `replay`, but leaves the log untouched: a damaged tail is skipped rather than
+    /// truncated, and a log in the unframed format isn't rewritten
+    pub fn read<K, V>(path: &Path, comparator: Arc<dyn Comparator<K>>, encoding: Encoding) -> Result<MemTable<K, V>>
+    where
+        K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+        V: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        Self::read_into(path, MemTable::with_comparator_and_encoding(comparator, encoding))
+    }
+
+    /// Like `read`, into `memtable`, whose sequence numbers the replayed writes continue
+    pub(crate) fn read_into<K, V>(path: &Path, mut memtable: MemTable<K, V>) -> Result<MemTable<K, V>>
+    where
+        K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+        V: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let buf = match std::fs::read(path) {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(memtable),
+            Err(e) => return Err(e.into()),
+        };
+        if buf.starts_with(&MAGIC) {
+            read_frames(&buf, &mut memtable)?;
+        } else if !buf.is_empty() {
+            replay_unframed(&buf, &mut memtable)?;
+        }
+        Ok(memtable)
+    }
+}
+
+/// Applies the frames of a framed log to `memtable`, returning the length of the log up to
+/// the first incomplete or damaged frame
+fn read_frames<K, V>(buf: &[u8], memtable: &mut MemTable<K, V>) -> Result<usize>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+{
+    let mut position = MAGIC.len();
+    while let Some((record, len)) = read_frame::<K, V>(&buf[position..]) {
+        match record {
+            Record::Write(key, entry) => apply(memtable, key, entry)?,
+            Record::Batch(entries) => {
+                memtable.apply_batch(entries)?;
+            }
+            Record::DeleteRange(start, end) => {
+                memtable.delete_range(start, end)?;
+            }
+        }
+        position += len;
+    }
+    Ok(position)
+}
+
+/// Serializes a record into a frame
+fn frame<K, V>(record: &Record<K, V>) -> Result<Vec<u8>>
+where
+    K: serde::Serialize,
+    V: serde::Serialize,
+{
+    let len = bincode::serialized_size(record)? as usize;
+    let mut frame = Vec::with_capacity(4 + len + 4);
+    frame.extend_from_slice(&(len as u32).to_le_bytes());
+    bincode::serialize_into(&mut frame, record)?;
+    let crc = crc32fast::hash(&frame[4..]);
+    frame.extend_from_slice(&crc.to_le_bytes());
+    Ok(frame)
+}
+
+/// Reads the frame at the start of `data`, returning its record and length, or `None` if it
+/// is incomplete or damaged
+fn read_frame<K, V>(data: &[u8]) -> Option<(Record<K, V>, usize)>
+where
+    K: serde::de::DeserializeOwned,
+    V: serde::de::DeserializeOwned,
+{
+    let len = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    let record = data.get(4..4 + len)?;
+    let crc = u32::from_le_bytes(data.get(4 + len..8 + len)?.try_into().ok()?);
+    if crc32fast::hash(record) != crc {
+        return None;
+    }
+    Some((bincode::deserialize(record).ok()?, 8 + len))
+}
+
+fn apply<K, V>(memtable: &mut MemTable<K, V>, key: K, entry: Entry<V>) -> Result<()>
+where
+    K: Ord + serde::Serialize + Clone,
+    V: serde::Serialize,
+{
+    match entry {
+        Entry::Value(value) => memtable.put(key, value)?,
+        Entry::Tombstone => memtable.delete(key)?,
+        Entry::Expiring { value, expires_at } => memtable.put_with_expiry(key, value, expires_at)?,
+        // Operands applied onto a value are logged as the merged value, so these only append
+        Entry::Merge(operands) => memtable.append_operands(key, operands)?,
+    };
+    Ok(())
+}
+
+/// Replays a log of bare `(key, entry)` records, as written before frames existed, up to the
+/// first record that fails to deserialize
+fn replay_unframed<K, V>(buf: &[u8], memtable: &mut MemTable<K, V>) -> Result<()>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned + Clone,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+{
+    let mut cursor = std::io::Cursor::new(buf);
+    while (cursor.position() as usize) < buf.len() {
+        let record = bincode::deserialize_from::<_, K>(&mut cursor)
+            .and_then(|key| Ok((key, bincode::deserialize_from::<_, Entry<V>>(&mut cursor)?)));
+        match record {
+            Ok((key, entry)) => apply(memtable, key, entry)?,
+            Err(_) => break,
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the log at `path` with one holding the memtable's entries in the current format
+fn rewrite<K, V>(path: &Path, memtable: &MemTable<K, V>) -> Result<()>
+where
+    K: Ord + serde::Serialize + Clone,
+    V: serde::Serialize,
+{
+    let mut log = MAGIC.to_vec();
+    let entries: Vec<_> = memtable.entries().map(|(key, entry)| (key, entry.as_ref())).collect();
+    log.extend(frame(&Record::Batch(entries))?);
+
+    let tmp_path = crate::tmp_path(path);
+    let mut tmp = File::create(&tmp_path)?;
+    tmp.write_all(&log)?;
+    tmp.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    crate::sync_parent_dir(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::NaturalOrder;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_wal_replay() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("wal.log");
+
+        let mut wal = Wal::open(&path)?;
+        wal.append(&1, &Entry::Value("one".to_string()))?;
+        wal.append(&2, &Entry::Value("two".to_string()))?;
+        wal.append(&1, &Entry::<String>::Tombstone)?;
+        wal.append(&12, &Entry::Value("twelve".to_string()))?;
+        wal.append_delete_range(&10, &20)?;
+        wal.append(&15, &Entry::Value("fifteen".to_string()))?;
+
+        let memtable = Wal::replay::<i32, String>(&path, Arc::new(NaturalOrder), Encoding::default())?;
+        assert_eq!(memtable.get_entry(&1), Some(&Entry::Tombstone));
+        assert_eq!(memtable.get(&2), Some(&"two".to_string()));
+        assert_eq!(memtable.get_entry(&12), Some(&Entry::Tombstone));
+        assert_eq!(memtable.get(&15), Some(&"fifteen".to_string()));
+        assert_eq!(memtable.range_tombstones().len(), 1);
+
+        wal.reset()?;
+        let memtable = Wal::replay::<i32, String>(&path, Arc::new(NaturalOrder), Encoding::default())?;
+        assert!(memtable.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wal_missing_file() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("wal.log");
+
+        let memtable = Wal::replay::<i32, String>(&path, Arc::new(NaturalOrder), Encoding::default())?;
+        assert!(memtable.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wal_torn_write() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("wal.log");
+
+        let mut wal = Wal::open(&path)?;
+        wal.append(&1, &Entry::Value("one".to_string()))?;
+        wal.append(&2, &Entry::Value("two".to_string()))?;
+        let full_len = std::fs::metadata(&path)?.len();
+        // Chop the last record in half to simulate a crash mid-append
+        std::fs::OpenOptions::new().write(true).open(&path)?.set_len(full_len - 4)?;
+
+        let memtable = Wal::replay::<i32, String>(&path, Arc::new(NaturalOrder), Encoding::default())?;
+        assert_eq!(memtable.get(&1), Some(&"one".to_string()));
+        assert_eq!(memtable.get(&2), None);
+
+        // The torn tail is cut off so later appends are readable
+        let mut wal = Wal::open(&path)?;
+        wal.append(&3, &Entry::Value("three".to_string()))?;
+        let memtable = Wal::replay::<i32, String>(&path, Arc::new(NaturalOrder), Encoding::default())?;
+        assert_eq!(memtable.get(&1), Some(&"one".to_string()));
+        assert_eq!(memtable.get(&3), Some(&"three".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wal_torn_batch() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("wal.log");
+
+        let mut wal = Wal::open(&path)?;
+        wal.append(&1, &Entry::Value("one".to_string()))?;
+        wal.append_batch(&[(2, Entry::Value("two".to_string())), (1, Entry::Tombstone)])?;
+        let full_len = std::fs::metadata(&path)?.len();
+
+        let memtable = Wal::replay::<i32, String>(&path, Arc::new(NaturalOrder), Encoding::default())?;
+        assert_eq!(memtable.get_entry(&1), Some(&Entry::Tombstone));
+        assert_eq!(memtable.get(&2), Some(&"two".to_string()));
+
+        // Losing any part of the batch, even just its checksum, loses all of it
+        std::fs::OpenOptions::new().write(true).open(&path)?.set_len(full_len - 1)?;
+        let memtable = Wal::replay::<i32, String>(&path, Arc::new(NaturalOrder), Encoding::default())?;
+        assert_eq!(memtable.get(&1), Some(&"one".to_string()));
+        assert_eq!(memtable.get(&2), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wal_unframed_log_is_rewritten() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("wal.log");
+
+        // A log from before frames existed: bare key and entry records
+        let mut log = Vec::new();
+        bincode::serialize_into(&mut log, &1)?;
+        bincode::serialize_into(&mut log, &Entry::Value("one".to_string()))?;
+        bincode::serialize_into(&mut log, &2)?;
+        bincode::serialize_into(&mut log, &Entry::Expiring { value: "two".to_string(), expires_at: 5 })?;
+        std::fs::write(&path, log)?;
+
+        let memtable = Wal::replay::<i32, String>(&path, Arc::new(NaturalOrder), Encoding::default())?;
+        assert_eq!(memtable.get(&1), Some(&"one".to_string()));
+        assert!(std::fs::read(&path)?.starts_with(&MAGIC));
+
+        let mut wal = Wal::open(&path)?;
+        wal.append(&3, &Entry::Value("three".to_string()))?;
+        let replayed = Wal::replay::<i32, String>(&path, Arc::new(NaturalOrder), Encoding::default())?;
+        assert_eq!(replayed.get(&1), Some(&"one".to_string()));
+        assert_eq!(replayed.get_entry(&2), memtable.get_entry(&2));
+        assert_eq!(replayed.get(&3), Some(&"three".to_string()));
+
+        Ok(())
+    }
+}